@@ -0,0 +1,183 @@
+//! Compare [`extendable_hashmap::HashMap`] against `std`'s and hashbrown's
+//! maps, for various sizes and key types.
+
+use criterion::{
+    criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion,
+};
+use std::{hash::Hash, hint::black_box};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// The operations measured on every map.
+trait Map<K>: Clone + IntoIterator<Item = (K, u64)> {
+    const NAME: &'static str;
+
+    fn new() -> Self;
+    fn insert(&mut self, key: K, value: u64);
+    fn get(&self, key: &K) -> Option<&u64>;
+    fn remove(&mut self, key: &K) -> Option<u64>;
+}
+
+impl<K: Hash + Eq + Clone> Map<K> for extendable_hashmap::HashMap<K, u64> {
+    const NAME: &'static str = "extendable";
+
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn insert(&mut self, key: K, value: u64) {
+        self.insert(key, value);
+    }
+
+    fn get(&self, key: &K) -> Option<&u64> {
+        self.get(key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<u64> {
+        self.remove(key)
+    }
+}
+
+impl<K: Hash + Eq + Clone> Map<K> for std::collections::HashMap<K, u64> {
+    const NAME: &'static str = "std";
+
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn insert(&mut self, key: K, value: u64) {
+        self.insert(key, value);
+    }
+
+    fn get(&self, key: &K) -> Option<&u64> {
+        self.get(key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<u64> {
+        self.remove(key)
+    }
+}
+
+impl<K: Hash + Eq + Clone> Map<K> for hashbrown::HashMap<K, u64> {
+    const NAME: &'static str = "hashbrown";
+
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn insert(&mut self, key: K, value: u64) {
+        self.insert(key, value);
+    }
+
+    fn get(&self, key: &K) -> Option<&u64> {
+        self.get(key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<u64> {
+        self.remove(key)
+    }
+}
+
+fn filled<K: Clone, M: Map<K>>(keys: &[K]) -> M {
+    let mut map = M::new();
+    for (value, key) in keys.iter().enumerate() {
+        map.insert(key.clone(), value as u64);
+    }
+
+    map
+}
+
+/// Benchmark operation `op` of map `M` with `keys`, whose type is
+/// `key_type`.
+fn bench_map<K, M>(c: &mut Criterion, op: &str, key_type: &str, keys: &[K])
+where
+    K: Clone,
+    M: Map<K>,
+{
+    let mut group = c.benchmark_group(format!("{op}/{key_type}"));
+    let id = BenchmarkId::new(M::NAME, keys.len());
+    match op {
+        "insert" => {
+            group.bench_with_input(id, keys, |b, keys| {
+                b.iter_batched(
+                    || keys.to_vec(),
+                    |keys| {
+                        let mut map = M::new();
+                        for (value, key) in keys.into_iter().enumerate() {
+                            map.insert(black_box(key), value as u64);
+                        }
+                        map
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+        "get" => {
+            let map = filled::<K, M>(keys);
+            group.bench_with_input(id, keys, |b, keys| {
+                b.iter(|| {
+                    for key in keys {
+                        black_box(map.get(black_box(key)));
+                    }
+                })
+            });
+        }
+        "remove" => {
+            let map = filled::<K, M>(keys);
+            group.bench_with_input(id, keys, |b, keys| {
+                b.iter_batched(
+                    || map.clone(),
+                    |mut map| {
+                        for key in keys {
+                            black_box(map.remove(black_box(key)));
+                        }
+                        map
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+        "iterate" => {
+            let map = filled::<K, M>(keys);
+            group.bench_with_input(id, keys, |b, _| {
+                b.iter_batched(
+                    || map.clone(),
+                    |map| map.into_iter().map(|(_, v)| v).sum::<u64>(),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+        _ => unreachable!("unknown operation {op}"),
+    }
+    group.finish();
+}
+
+/// Benchmark all the maps and operations with the keys `make_key` returns.
+fn bench_keys<K>(c: &mut Criterion, key_type: &str, make_key: fn(usize) -> K)
+where
+    K: Hash + Eq + Clone,
+{
+    for op in ["insert", "get", "remove", "iterate"] {
+        for size in SIZES {
+            let keys = (0..size).map(make_key).collect::<Vec<_>>();
+            bench_map::<K, extendable_hashmap::HashMap<K, u64>>(
+                c, op, key_type, &keys,
+            );
+            bench_map::<K, std::collections::HashMap<K, u64>>(
+                c, op, key_type, &keys,
+            );
+            bench_map::<K, hashbrown::HashMap<K, u64>>(c, op, key_type, &keys);
+        }
+    }
+}
+
+fn u64_keys(c: &mut Criterion) {
+    bench_keys(c, "u64", |i| i as u64);
+}
+
+fn string_keys(c: &mut Criterion) {
+    bench_keys(c, "string", |i| format!("key-{i}"));
+}
+
+criterion_group!(benches, u64_keys, string_keys);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use extendable_hashmap::{HashMap, HashMapBuilder};
+use std::hint::black_box;
+
+const N: u64 = 10_000;
+
+fn filled() -> HashMap<u64, u64> {
+    let mut map = HashMap::new();
+    for i in 0..N {
+        map.insert(i, i);
+    }
+
+    map
+}
+
+fn insert(c: &mut Criterion) {
+    c.bench_function("insert", |b| {
+        b.iter(|| {
+            let mut map = HashMap::new();
+            for i in 0..N {
+                map.insert(black_box(i), i);
+            }
+            map
+        })
+    });
+}
+
+fn bulk_load(c: &mut Criterion) {
+    c.bench_function("bulk_load", |b| {
+        b.iter(|| {
+            let map: HashMap<u64, u64> = HashMapBuilder::new()
+                .bulk_load((0..N).map(|i| (black_box(i), i)));
+            map
+        })
+    });
+}
+
+fn get(c: &mut Criterion) {
+    let map = filled();
+    c.bench_function("get", |b| {
+        b.iter(|| {
+            for i in 0..N {
+                black_box(map.get(&black_box(i)));
+            }
+        })
+    });
+}
+
+fn remove(c: &mut Criterion) {
+    c.bench_function("remove", |b| {
+        b.iter_batched(
+            filled,
+            |mut map| {
+                for i in 0..N {
+                    black_box(map.remove(&black_box(i)));
+                }
+                map
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn remove_most(c: &mut Criterion) {
+    // a delete-heavy workload, where most buckets get coalesced
+    c.bench_function("remove_most", |b| {
+        b.iter_batched(
+            filled,
+            |mut map| {
+                for i in (0..N).filter(|i| i % 10 != 0) {
+                    black_box(map.remove(&black_box(i)));
+                }
+                map
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, insert, bulk_load, get, remove, remove_most);
+criterion_main!(benches);
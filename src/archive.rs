@@ -0,0 +1,245 @@
+//! Zero-copy archiving of [`HashMap`] with [`rkyv`].
+
+use crate::{
+    hasher::{BitOrder, HashValue, KeyHasher},
+    map::HashMap,
+};
+use rkyv::{
+    bytecheck::CheckBytes,
+    munge::munge,
+    rancor::Fallible,
+    ser::{Allocator, Writer},
+    vec::{ArchivedVec, VecResolver},
+    Archive, Archived, Place, Portable, Serialize,
+};
+use std::hash::Hash;
+
+/// An archived [`HashMap`], which can be queried in place, e.g., in a
+/// memory-mapped file, without deserializing it.
+///
+/// The directory and buckets are archived as they are, the entries of bucket
+/// `i` are `offsets[i]..offsets[i + 1]` of `hashes`, `keys` and `values`, so
+/// a lookup hashes the key, and scans the bucket it indexes. Only maps with
+/// 64-bit hash values and a [`BitOrder`] mapper can be archived.
+///
+/// Validating an archive, e.g., with [`rkyv::access()`], checks that it is
+/// well formed, but not that the entries are stored in the right buckets,
+/// the lookups on a corrupted archive may miss, but are still safe.
+#[derive(Portable, CheckBytes)]
+#[bytecheck(crate = rkyv::bytecheck)]
+#[repr(C)]
+pub struct ArchivedHashMap<K, V> {
+    /// Global depth
+    global_depth: Archived<u32>,
+    /// 1 if the directory is indexed by the least significant bits
+    lsb: Archived<u32>,
+    /// Directory entries, storing the number of the bucket
+    directory: ArchivedVec<Archived<u32>>,
+    /// Start of every bucket in the entries, followed by the number of
+    /// entries
+    offsets: ArchivedVec<Archived<u32>>,
+    /// Hash values of the keys
+    hashes: ArchivedVec<Archived<u64>>,
+    /// Keys
+    keys: ArchivedVec<K>,
+    /// Values, `values[i]` belongs to `keys[i]`
+    values: ArchivedVec<V>,
+}
+
+/// The resolver of [`ArchivedHashMap`].
+pub struct HashMapResolver {
+    directory: VecResolver,
+    offsets: VecResolver,
+    hashes: VecResolver,
+    keys: VecResolver,
+    values: VecResolver,
+}
+
+impl<K, V, S, const B: usize> Archive for HashMap<K, V, S, BitOrder, B>
+where
+    K: Archive,
+    V: Archive,
+    S: KeyHasher<Hash = u64>,
+{
+    type Archived = ArchivedHashMap<K::Archived, V::Archived>;
+    type Resolver = HashMapResolver;
+
+    fn resolve(&self, resolver: Self::Resolver, out: Place<Self::Archived>) {
+        munge!(let ArchivedHashMap {
+            global_depth,
+            lsb,
+            directory,
+            offsets,
+            hashes,
+            keys,
+            values,
+        } = out);
+        (self.global_depth as u32).resolve((), global_depth);
+        ((self.mapper == BitOrder::Lsb) as u32).resolve((), lsb);
+        ArchivedVec::resolve_from_len(
+            self.directories.len(),
+            resolver.directory,
+            directory,
+        );
+        ArchivedVec::resolve_from_len(
+            self.buckets.len() + 1,
+            resolver.offsets,
+            offsets,
+        );
+        ArchivedVec::resolve_from_len(self.len, resolver.hashes, hashes);
+        ArchivedVec::resolve_from_len(self.len, resolver.keys, keys);
+        ArchivedVec::resolve_from_len(self.len, resolver.values, values);
+    }
+}
+
+impl<K, V, S, const B: usize, T> Serialize<T> for HashMap<K, V, S, BitOrder, B>
+where
+    K: Serialize<T>,
+    V: Serialize<T>,
+    S: KeyHasher<Hash = u64>,
+    T: Fallible + Allocator + Writer + ?Sized,
+{
+    fn serialize(
+        &self,
+        serializer: &mut T,
+    ) -> Result<HashMapResolver, T::Error> {
+        // buckets are numbered by their order in the slab, whose indexes may
+        // have holes
+        let mut numbers = vec![0_u32; self.buckets.capacity()];
+        let mut offsets = vec![0_u32];
+        for (number, (idx, bucket)) in self.buckets.iter().enumerate() {
+            numbers[idx] = number as u32;
+            offsets.push(offsets[number] + bucket.len() as u32);
+        }
+        let entries = self
+            .buckets
+            .iter()
+            .flat_map(|(_, bucket)| bucket.iter())
+            .collect::<Vec<_>>();
+
+        Ok(HashMapResolver {
+            directory: ArchivedVec::serialize_from_iter::<u32, _, _>(
+                self.directories.iter().map(|idx| numbers[idx.get()]),
+                serializer,
+            )?,
+            offsets: ArchivedVec::serialize_from_slice(&offsets, serializer)?,
+            hashes: ArchivedVec::serialize_from_iter::<u64, _, _>(
+                entries.iter().map(|(hash, _, _)| **hash),
+                serializer,
+            )?,
+            keys: ArchivedVec::serialize_from_iter::<K, _, _>(
+                entries.iter().map(|(_, key, _)| *key),
+                serializer,
+            )?,
+            values: ArchivedVec::serialize_from_iter::<V, _, _>(
+                entries.iter().map(|(_, _, value)| *value),
+                serializer,
+            )?,
+        })
+    }
+}
+
+impl<K, V> ArchivedHashMap<K, V> {
+    /// Return the number of elements in the map.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Return true if the map contains no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Return the index of the entry whose key is `key`.
+    fn find<Q, S>(&self, key: &Q, hash_builder: &S) -> Option<usize>
+    where
+        Q: Hash + ?Sized,
+        K: PartialEq<Q>,
+        S: KeyHasher<Hash = u64>,
+    {
+        let hash = hash_builder.hash_key(key);
+        let mapped = if self.lsb == 1 {
+            hash.reverse_bits()
+        } else {
+            hash
+        };
+        let global_depth = self.global_depth.to_native().min(u64::BITS);
+        let number = self.directory.get(mapped.prefix(global_depth))?;
+        let start = self.offsets.get(number.to_native() as usize)?;
+        let end = self.offsets.get(number.to_native() as usize + 1)?;
+        let range = start.to_native() as usize..end.to_native() as usize;
+
+        self.hashes
+            .get(range.clone())?
+            .iter()
+            .zip(self.keys.get(range.clone())?)
+            .position(|(h, k)| *h == hash && k == key)
+            .map(|idx| range.start + idx)
+    }
+
+    /// Return the value corresponding to the key, `hash_builder` should be
+    /// the hash builder of the archived map.
+    pub fn get<Q, S>(&self, key: &Q, hash_builder: &S) -> Option<&V>
+    where
+        Q: Hash + ?Sized,
+        K: PartialEq<Q>,
+        S: KeyHasher<Hash = u64>,
+    {
+        self.find(key, hash_builder)
+            .and_then(|idx| self.values.get(idx))
+    }
+
+    /// Return true if the map contains a value for the specified key,
+    /// `hash_builder` should be the hash builder of the archived map.
+    pub fn contains_key<Q, S>(&self, key: &Q, hash_builder: &S) -> bool
+    where
+        Q: Hash + ?Sized,
+        K: PartialEq<Q>,
+        S: KeyHasher<Hash = u64>,
+    {
+        self.find(key, hash_builder).is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::FixedState;
+    use rkyv::{rancor::Error, string::ArchivedString};
+
+    #[test]
+    fn archive_works() {
+        let hash_builder = FixedState::with_seed(7);
+        let mut map =
+            HashMap::with_bit_order_and_hasher(BitOrder::Lsb, hash_builder);
+        for i in 0..1000_u64 {
+            map.insert(i.to_string(), i);
+        }
+        for i in (0..1000_u64).step_by(3) {
+            map.remove(&i.to_string());
+        }
+
+        let bytes = rkyv::to_bytes::<Error>(&map).unwrap();
+        let archived = rkyv::access::<
+            ArchivedHashMap<ArchivedString, Archived<u64>>,
+            Error,
+        >(&bytes)
+        .unwrap();
+        assert_eq!(archived.len(), map.len());
+        for i in 0..1000_u64 {
+            let key = i.to_string();
+            assert_eq!(
+                archived.get(key.as_str(), &hash_builder).copied(),
+                map.get(&key).map(|v| Archived::<u64>::from_native(*v))
+            );
+        }
+        assert!(!archived.contains_key("1000", &hash_builder));
+        assert!(rkyv::access::<
+            ArchivedHashMap<ArchivedString, Archived<u64>>,
+            Error,
+        >(&bytes[..bytes.len() / 2])
+        .is_err());
+    }
+}
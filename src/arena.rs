@@ -0,0 +1,305 @@
+//! The arena the spilled entries of a [`HashMap`](crate::HashMap) are
+//! allocated from.
+
+use allocator_api2::{
+    alloc::{AllocError, Allocator, Global, Layout},
+    boxed::Box,
+    vec::Vec,
+};
+use parking_lot::Mutex;
+use std::{
+    marker::PhantomData,
+    ptr::NonNull,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+/// Blocks larger than this are allocated from the backing allocator rather
+/// than from the chunks.
+const MAX_BLOCK_SIZE: usize = 4096;
+
+/// Number of blocks of the first chunk of a size class, every next chunk of
+/// the class holds twice as many blocks, up to [`MAX_CHUNK_SIZE`] bytes.
+const MIN_CHUNK_BLOCKS: usize = 4;
+
+/// Maximum bytes of a chunk.
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Blocks of the same layout, carved from the chunks of the arena.
+struct SizeClass {
+    /// Layout of the blocks, which are at least as large and aligned as a
+    /// pointer, so that a free block can point to the next free block.
+    layout: Layout,
+    /// The last freed block, which points to the block freed before it.
+    free: Option<NonNull<u8>>,
+    /// The unused blocks of the last chunk start here.
+    cursor: NonNull<u8>,
+    /// Number of the unused blocks of the last chunk.
+    remaining: usize,
+    /// Number of blocks of the next chunk.
+    next_blocks: usize,
+}
+
+/// Return the layout of the blocks serving `layout`.
+fn block_layout(layout: Layout) -> Layout {
+    Layout::from_size_align(
+        layout.size().max(size_of::<usize>()),
+        layout.align().max(align_of::<usize>()),
+    )
+    .expect("Arena: the block layout overflows")
+    .pad_to_align()
+}
+
+/// The state of an arena, guarded by its lock.
+struct Pools<A: Allocator> {
+    /// The backing allocator, which chunks and large blocks come from.
+    alloc: A,
+    classes: Vec<SizeClass, A>,
+    /// The chunks allocated so far, which are freed with the arena.
+    chunks: Vec<(NonNull<u8>, Layout), A>,
+    /// Bytes of the chunks.
+    chunk_bytes: usize,
+    /// Bytes of the live blocks allocated from the backing allocator.
+    large_bytes: usize,
+}
+
+impl<A: Allocator> Pools<A> {
+    fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 || layout.size() > MAX_BLOCK_SIZE {
+            let block = self.alloc.allocate(layout)?;
+            self.large_bytes += layout.size();
+            return Ok(block.cast());
+        }
+
+        let layout = block_layout(layout);
+        let class = match self.classes.iter().position(|c| c.layout == layout) {
+            Some(idx) => &mut self.classes[idx],
+            None => {
+                self.classes.push(SizeClass {
+                    layout,
+                    free: None,
+                    cursor: NonNull::dangling(),
+                    remaining: 0,
+                    next_blocks: MIN_CHUNK_BLOCKS,
+                });
+                self.classes.last_mut().unwrap()
+            }
+        };
+        if let Some(block) = class.free {
+            // SAFETY: a free block is aligned for a pointer and stores the
+            // next free block.
+            class.free = unsafe { block.cast::<Option<NonNull<u8>>>().read() };
+            return Ok(block);
+        }
+        if class.remaining == 0 {
+            let blocks = class.next_blocks;
+            let chunk_layout =
+                Layout::from_size_align(layout.size() * blocks, layout.align())
+                    .map_err(|_| AllocError)?;
+            let chunk = self.alloc.allocate(chunk_layout)?.cast::<u8>();
+            self.chunks.push((chunk, chunk_layout));
+            self.chunk_bytes += chunk_layout.size();
+            class.cursor = chunk;
+            class.remaining = blocks;
+            if layout.size() * blocks * 2 <= MAX_CHUNK_SIZE {
+                class.next_blocks = blocks * 2;
+            }
+        }
+        let block = class.cursor;
+        // SAFETY: `remaining` blocks are left in the chunk from `cursor`, so
+        // the next one starts within the chunk, or right after its end.
+        class.cursor = unsafe { block.add(layout.size()) };
+        class.remaining -= 1;
+
+        Ok(block)
+    }
+
+    /// # Safety
+    /// `block` should have been allocated from these pools with `layout`.
+    unsafe fn deallocate(&mut self, block: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 || layout.size() > MAX_BLOCK_SIZE {
+            self.large_bytes -= layout.size();
+            // SAFETY: `block` was allocated from `alloc` with `layout`.
+            unsafe { self.alloc.deallocate(block, layout) };
+            return;
+        }
+
+        let layout = block_layout(layout);
+        let class = self
+            .classes
+            .iter_mut()
+            .find(|c| c.layout == layout)
+            .expect("Arena: the block isn't allocated from the arena");
+        // SAFETY: the block is at least as large and aligned as a pointer.
+        unsafe { block.cast::<Option<NonNull<u8>>>().write(class.free) };
+        class.free = Some(block);
+    }
+}
+
+impl<A: Allocator> Drop for Pools<A> {
+    fn drop(&mut self) {
+        for &(chunk, layout) in self.chunks.iter() {
+            // SAFETY: the chunk was allocated from `alloc` with `layout`.
+            unsafe { self.alloc.deallocate(chunk, layout) };
+        }
+    }
+}
+
+/// The shared part of an arena.
+struct Inner<A: Allocator> {
+    /// Number of the handles.
+    refs: AtomicUsize,
+    pools: Mutex<Pools<A>>,
+}
+
+/// An arena, which allocates blocks of the same layout from the chunks of
+/// the backing allocator `A`, a freed block is reused by the next allocation
+/// of its layout.
+///
+/// The spilled entry columns of the buckets of a map have few layouts, so
+/// the buckets that splits create and coalescences remove are allocated from
+/// a handful of chunks rather than from `A` one by one. Blocks larger than 4
+/// KiB go to `A` directly.
+///
+/// An `Arena` is a handle, which is cloned into every allocation made from
+/// the arena, the chunks are freed when the last handle is dropped.
+pub(crate) struct Arena<A: Allocator + Clone = Global> {
+    inner: NonNull<Inner<A>>,
+    _marker: PhantomData<Inner<A>>,
+}
+
+// SAFETY: the state is guarded by a lock, and the backing allocator, which
+// is part of the state, is only used under the lock.
+unsafe impl<A: Allocator + Clone + Send> Send for Arena<A> {}
+// SAFETY: see above.
+unsafe impl<A: Allocator + Clone + Send> Sync for Arena<A> {}
+
+impl Arena {
+    /// Create an arena backed by the global allocator.
+    pub(crate) fn new() -> Self {
+        Self::new_in(Global)
+    }
+}
+
+impl<A: Allocator + Clone> Arena<A> {
+    /// Create an arena backed by `alloc`.
+    pub(crate) fn new_in(alloc: A) -> Self {
+        let inner = Inner {
+            refs: AtomicUsize::new(1),
+            pools: Mutex::new(Pools {
+                classes: Vec::new_in(alloc.clone()),
+                chunks: Vec::new_in(alloc.clone()),
+                alloc: alloc.clone(),
+                chunk_bytes: 0,
+                large_bytes: 0,
+            }),
+        };
+        let (inner, _) =
+            Box::into_raw_with_allocator(Box::new_in(inner, alloc));
+
+        Self {
+            // SAFETY: a box is never null.
+            inner: unsafe { NonNull::new_unchecked(inner) },
+            _marker: PhantomData,
+        }
+    }
+
+    fn inner(&self) -> &Inner<A> {
+        // SAFETY: the shared part lives as long as any handle.
+        unsafe { self.inner.as_ref() }
+    }
+
+    /// Return the bytes the arena has allocated from the backing allocator.
+    pub(crate) fn allocated(&self) -> usize {
+        let pools = self.inner().pools.lock();
+        pools.chunk_bytes + pools.large_bytes
+    }
+
+    /// Return a copy of the backing allocator.
+    pub(crate) fn allocator(&self) -> A {
+        self.inner().pools.lock().alloc.clone()
+    }
+}
+
+impl<A: Allocator + Clone> Clone for Arena<A> {
+    fn clone(&self) -> Self {
+        self.inner().refs.fetch_add(1, Ordering::Relaxed);
+        Self {
+            inner: self.inner,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<A: Allocator + Clone> Drop for Arena<A> {
+    fn drop(&mut self) {
+        if self.inner().refs.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // synchronizes with the decrements of the other handles, so that
+        // their deallocations happen before the chunks are freed
+        fence(Ordering::Acquire);
+        let alloc = self.allocator();
+        // SAFETY: this is the last handle, the shared part was boxed in
+        // `alloc` by `new_in()`.
+        drop(unsafe { Box::from_raw_in(self.inner.as_ptr(), alloc) });
+    }
+}
+
+// SAFETY: the blocks are carved from chunks which are only freed with the
+// arena, after every handle, and thus every allocation, is gone. A block is
+// reused only after it's deallocated.
+unsafe impl<A: Allocator + Clone> Allocator for Arena<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.inner().pools.lock().allocate(layout)?;
+        Ok(NonNull::slice_from_raw_parts(block, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: guaranteed by the caller.
+        unsafe { self.inner().pools.lock().deallocate(ptr, layout) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arena_reuses_blocks() {
+        let arena = Arena::new();
+        let mut columns = (0..100)
+            .map(|i| {
+                let mut column = Vec::with_capacity_in(16, arena.clone());
+                column.push(i as u64);
+                column
+            })
+            .collect::<std::vec::Vec<_>>();
+        let allocated = arena.allocated();
+        // 100 blocks of 128 bytes take chunks of 4, 8, 16, 32 and 64 blocks
+        assert_eq!(allocated, 124 * 128);
+
+        columns.truncate(10);
+        for _ in 0..90 {
+            columns.push(Vec::with_capacity_in(16, arena.clone()));
+        }
+        assert_eq!(arena.allocated(), allocated);
+        assert!(columns[..10]
+            .iter()
+            .enumerate()
+            .all(|(i, c)| c[0] == i as u64));
+
+        // growing takes a block of the larger layout, large blocks don't
+        // come from the chunks
+        columns[0].reserve_exact(31);
+        assert_eq!(arena.allocated(), allocated + 4 * 256);
+        let large: Vec<u8, _> = Vec::with_capacity_in(5000, arena.clone());
+        assert_eq!(arena.allocated(), allocated + 4 * 256 + 5000);
+        drop(large);
+        assert_eq!(arena.allocated(), allocated + 4 * 256);
+
+        // the chunks outlive the handle the arena is created with
+        drop(arena);
+        columns[1].extend(2..18);
+        assert!(columns[1].iter().copied().eq(1..18));
+    }
+}
@@ -0,0 +1,540 @@
+use crate::{
+    bucket::{
+        Bucket, BucketPolicy,
+        BucketValue::{EqualTo, Range},
+        BUCKET_CAP,
+    },
+    hasher::{DefaultHashBuilder, HashValue, KeyHasher},
+};
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Formatter},
+    hash::Hash,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+/// A bucket that can be shared and locked across tasks.
+type SharedBucket<K, V, H> = Arc<RwLock<Bucket<K, V, H>>>;
+
+/// The directory of an [`AsyncHashMap`].
+struct Directory<K, V, H> {
+    /// Global depth
+    global_depth: usize,
+    /// Directory entries, `entries[i]` is the bucket that entry `i` points
+    /// to, several entries can point to the same bucket.
+    entries: Vec<SharedBucket<K, V, H>>,
+}
+
+impl<K, V, H: HashValue> Directory<K, V, H> {
+    /// Return the bucket where a key whose hash value is `hash` will go.
+    fn locate(&self, hash: H) -> &SharedBucket<K, V, H> {
+        &self.entries[hash.prefix(self.global_depth as u32)]
+    }
+
+    /// Double the directory and increment the global depth.
+    ///
+    /// With the global depth incremented, entry `idx` is covered by the new
+    /// entries `2 * idx` and `2 * idx + 1`, both of them point to the bucket
+    /// entry `idx` points to.
+    fn double(&mut self) {
+        self.entries = self
+            .entries
+            .iter()
+            .flat_map(|bucket| [Arc::clone(bucket), Arc::clone(bucket)])
+            .collect();
+        self.global_depth += 1;
+    }
+}
+
+/// A concurrent extendible hash map for async code, whose locks are
+/// [`tokio::sync::RwLock`]s.
+///
+/// Every bucket is behind its own lock, and the directory is behind another
+/// one, which is write-locked when a bucket gets split. Waiting for a lock
+/// yields to the executor instead of blocking the thread, so the guards
+/// returned by the map can be held across `.await` points.
+///
+/// # Deadlock
+/// The directory lock is never held while waiting for a bucket lock: a task
+/// locates the bucket of a key under the directory read lock, releases it,
+/// and then locks the bucket, retrying if the bucket got split in between. A
+/// split locks the bucket before the directory. So a guard held across an
+/// `.await` only blocks the tasks accessing its bucket, rather than every
+/// task waiting for the directory.
+///
+/// Calling a method of the map while holding a guard returned by it may
+/// still deadlock, e.g., inserting into the bucket that an [`AsyncRef`]
+/// holds.
+///
+/// # Coalescence
+/// Buckets are never coalesced, so removals don't shrink the map.
+pub struct AsyncHashMap<K, V, S: KeyHasher = DefaultHashBuilder> {
+    /// Number of entries
+    len: AtomicUsize,
+    /// Number of entries a bucket can hold
+    bucket_capacity: usize,
+    /// Directory
+    directory: RwLock<Directory<K, V, S::Hash>>,
+    /// Hash builder
+    hash_builder: S,
+}
+
+impl<K, V, S: KeyHasher> Debug for AsyncHashMap<K, V, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncHashMap")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<K, V, S: KeyHasher + Default> Default for AsyncHashMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V> AsyncHashMap<K, V, DefaultHashBuilder> {
+    /// Create an empty `AsyncHashMap`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K, V, S: KeyHasher> AsyncHashMap<K, V, S> {
+    /// Create an empty `AsyncHashMap` which will use the given hash builder
+    /// to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_bucket_capacity_and_hasher(BUCKET_CAP, hash_builder)
+    }
+
+    /// Create an empty `AsyncHashMap` whose buckets can hold
+    /// `bucket_capacity` entries, `hash_builder` will be used to hash keys.
+    ///
+    /// # Panic
+    /// `bucket_capacity` should be greater than 0.
+    pub fn with_bucket_capacity_and_hasher(
+        bucket_capacity: usize,
+        hash_builder: S,
+    ) -> Self {
+        assert!(bucket_capacity > 0);
+
+        let entries = [0, 1]
+            .map(|bit| {
+                Arc::new(RwLock::new(Bucket::new(bit, 1, bucket_capacity)))
+            })
+            .into();
+
+        Self {
+            len: AtomicUsize::new(0),
+            bucket_capacity,
+            directory: RwLock::new(Directory {
+                global_depth: 1,
+                entries,
+            }),
+            hash_builder,
+        }
+    }
+
+    /// Return a reference to the map's hash builder.
+    #[inline]
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Return the number of elements in the map.
+    ///
+    /// The value is exact once the modifications made by other tasks have
+    /// completed, otherwise it can be outdated.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Return the number of elements in the map without synchronizing with
+    /// other tasks, which is cheap enough for monitoring, but the value may
+    /// lag behind [`len()`](Self::len).
+    #[inline]
+    pub fn approx_len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Return true if the map contains no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clone the bucket where a key whose hash value is `hash` will go, the
+    /// directory is read-locked only while it's located.
+    async fn locate(&self, hash: S::Hash) -> SharedBucket<K, V, S::Hash> {
+        Arc::clone(self.directory.read().await.locate(hash))
+    }
+
+    /// Read-lock the bucket where a key whose hash value is `hash` will go.
+    ///
+    /// The directory isn't locked while waiting for the bucket, which can
+    /// get split meanwhile, and then no longer covers `hash`, in which case
+    /// the bucket is located again.
+    async fn read_bucket(
+        &self,
+        hash: S::Hash,
+    ) -> OwnedRwLockReadGuard<Bucket<K, V, S::Hash>> {
+        loop {
+            let bucket = self.locate(hash).await.read_owned().await;
+            if bucket.covers(hash) {
+                return bucket;
+            }
+        }
+    }
+
+    /// Write-lock the bucket where a key whose hash value is `hash` will go,
+    /// see [`read_bucket()`](Self::read_bucket).
+    async fn write_bucket(
+        &self,
+        hash: S::Hash,
+    ) -> OwnedRwLockWriteGuard<Bucket<K, V, S::Hash>> {
+        loop {
+            let bucket = self.locate(hash).await.write_owned().await;
+            if bucket.covers(hash) {
+                return bucket;
+            }
+        }
+    }
+
+    /// Split the bucket where a key whose hash value is `hash` will go.
+    ///
+    /// The bucket is left untouched if it is no longer full, as other tasks
+    /// can remove entries or split it before it's write-locked.
+    async fn split(&self, hash: S::Hash) {
+        let mut bucket = self.write_bucket(hash).await;
+        if !bucket.is_full(self.bucket_capacity) || !bucket.splittable(hash) {
+            return;
+        }
+        // The bucket is locked before the directory, the directory lock is
+        // never held while waiting for a bucket lock.
+        let mut directory = self.directory.write().await;
+
+        let old_local_depth = bucket.local_depth();
+        if old_local_depth == directory.global_depth {
+            directory.double();
+        }
+
+        let mut new_bucket = Bucket::new(
+            (bucket.prefix << 1) | 1,
+            old_local_depth + 1,
+            self.bucket_capacity,
+        );
+        bucket.push_bit(0);
+
+        // redistribute the entries by the bit after the old bits
+        for (h, k, v) in bucket.take(self.bucket_capacity) {
+            if h.bit(old_local_depth as u32) == 1 {
+                new_bucket.push((h, k, v), BucketPolicy::Unsorted);
+            } else {
+                bucket.push((h, k, v), BucketPolicy::Unsorted);
+            }
+        }
+
+        // the last half of the entries pointing to the old bucket point to
+        // the new one
+        let new_bucket_value = new_bucket.value(directory.global_depth);
+        let new_bucket = Arc::new(RwLock::new(new_bucket));
+        match new_bucket_value {
+            EqualTo(idx) => directory.entries[idx] = new_bucket,
+            Range(range) => {
+                for idx in range {
+                    directory.entries[idx] = Arc::clone(&new_bucket);
+                }
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: KeyHasher> AsyncHashMap<K, V, S> {
+    /// Insert a key-value pair into the map.
+    ///
+    /// If the map did not have this key present, `None` is returned.
+    ///
+    /// If the map did have this key present, the value is updated, and the
+    /// old value is returned. The key is not updated, though.
+    pub async fn insert(&self, key: K, value: V) -> Option<V> {
+        let hash = self.hash_builder.hash_key(&key);
+        loop {
+            let mut bucket = self.write_bucket(hash).await;
+            if let Some(idx) =
+                bucket.position(hash, &key, BucketPolicy::Unsorted)
+            {
+                return Some(std::mem::replace(&mut bucket.values[idx], value));
+            }
+
+            if !bucket.is_full(self.bucket_capacity) || !bucket.splittable(hash)
+            {
+                bucket.push((hash, key, value), BucketPolicy::Unsorted);
+                self.len.fetch_add(1, Ordering::Release);
+                return None;
+            }
+
+            // Splitting write-locks the bucket, release it first.
+            drop(bucket);
+            self.split(hash).await;
+        }
+    }
+
+    /// Return a guard referencing the entry corresponding to the key, the
+    /// bucket of the entry is read-locked until the guard is dropped.
+    pub async fn get<Q>(&self, key: &Q) -> Option<AsyncRef<K, V, S::Hash>>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let bucket = self.read_bucket(hash).await;
+        let idx = bucket.position(hash, key, BucketPolicy::Unsorted)?;
+
+        Some(AsyncRef { bucket, idx })
+    }
+
+    /// Return a guard mutably referencing the entry corresponding to the key,
+    /// the bucket of the entry is write-locked until the guard is dropped.
+    pub async fn get_mut<Q>(
+        &self,
+        key: &Q,
+    ) -> Option<AsyncRefMut<K, V, S::Hash>>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let bucket = self.write_bucket(hash).await;
+        let idx = bucket.position(hash, key, BucketPolicy::Unsorted)?;
+
+        Some(AsyncRefMut { bucket, idx })
+    }
+
+    /// Return true if the map contains a value for the specified key.
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.get(key).await.is_some()
+    }
+
+    /// Remove `key` from the map, return the stored key and its value if it
+    /// was previously in the map.
+    pub async fn remove_entry<Q>(&self, key: &Q) -> Option<(K, V)>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let mut bucket = self.write_bucket(hash).await;
+        let idx = bucket.position(hash, key, BucketPolicy::Unsorted)?;
+        let (_, k, v) = bucket.remove(idx, BucketPolicy::Unsorted);
+        self.len.fetch_sub(1, Ordering::Release);
+
+        Some((k, v))
+    }
+
+    /// Remove `key` from the map, return its value if it was previously in the
+    /// map.
+    pub async fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.remove_entry(key).await.map(|(_, value)| value)
+    }
+}
+
+/// A reference to an entry of an [`AsyncHashMap`], which holds a read lock on
+/// the bucket where the entry lives.
+pub struct AsyncRef<K, V, H = u64> {
+    bucket: OwnedRwLockReadGuard<Bucket<K, V, H>>,
+    /// Index of this entry in the bucket.
+    idx: usize,
+}
+
+impl<K, V, H> AsyncRef<K, V, H> {
+    /// Return a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        &self.bucket.keys[self.idx]
+    }
+
+    /// Return a reference to this entry's value.
+    pub fn value(&self) -> &V {
+        &self.bucket.values[self.idx]
+    }
+
+    /// Return references to this entry's key and value.
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key(), self.value())
+    }
+}
+
+impl<K, V, H> Deref for AsyncRef<K, V, H> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+impl<K: Debug, V: Debug, H> Debug for AsyncRef<K, V, H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncRef")
+            .field("key", self.key())
+            .field("value", self.value())
+            .finish()
+    }
+}
+
+/// A mutable reference to an entry of an [`AsyncHashMap`], which holds a
+/// write lock on the bucket where the entry lives.
+pub struct AsyncRefMut<K, V, H = u64> {
+    bucket: OwnedRwLockWriteGuard<Bucket<K, V, H>>,
+    /// Index of this entry in the bucket.
+    idx: usize,
+}
+
+impl<K, V, H> AsyncRefMut<K, V, H> {
+    /// Return a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        &self.bucket.keys[self.idx]
+    }
+
+    /// Return a reference to this entry's value.
+    pub fn value(&self) -> &V {
+        &self.bucket.values[self.idx]
+    }
+
+    /// Return a mutable reference to this entry's value.
+    pub fn value_mut(&mut self) -> &mut V {
+        &mut self.bucket.values[self.idx]
+    }
+
+    /// Downgrade this guard to an [`AsyncRef`], allowing other tasks to read
+    /// the bucket.
+    pub fn downgrade(self) -> AsyncRef<K, V, H> {
+        AsyncRef {
+            bucket: self.bucket.downgrade(),
+            idx: self.idx,
+        }
+    }
+}
+
+impl<K, V, H> Deref for AsyncRefMut<K, V, H> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+impl<K, V, H> DerefMut for AsyncRefMut<K, V, H> {
+    fn deref_mut(&mut self) -> &mut V {
+        self.value_mut()
+    }
+}
+
+impl<K: Debug, V: Debug, H> Debug for AsyncRefMut<K, V, H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncRefMut")
+            .field("key", self.key())
+            .field("value", self.value())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn async_map_works() {
+        let map = Arc::new(AsyncHashMap::new());
+        let tasks = (0..4)
+            .map(|t| {
+                let map = Arc::clone(&map);
+                tokio::spawn(async move {
+                    for i in (t * 1000)..((t + 1) * 1000) {
+                        assert_eq!(map.insert(i, i).await, None);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(map.len(), 4000);
+        assert_eq!(map.approx_len(), 4000);
+        for i in 0..4000 {
+            assert_eq!(*map.get(&i).await.unwrap(), i);
+        }
+
+        // a guard can be held across an `.await`
+        let mut entry = map.get_mut(&0).await.unwrap();
+        tokio::task::yield_now().await;
+        *entry += 1;
+        assert_eq!(entry.downgrade().pair(), (&0, &1));
+
+        assert_eq!(map.remove(&0).await, Some(1));
+        assert!(!map.contains_key(&0).await);
+        assert_eq!(map.len(), 3999);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn async_map_guard_blocks_its_bucket_only() {
+        use crate::FixedState;
+        use std::hash::BuildHasher;
+
+        let hash_builder = FixedState::default();
+        // the keys outside the bucket of key 0, which starts at depth 1
+        let msb = |key: &u64| hash_builder.hash_one(key) >> 63;
+        let others = (1..)
+            .filter(|key| msb(key) != msb(&0))
+            .take(1000)
+            .collect::<Vec<_>>();
+        let map = Arc::new(AsyncHashMap::with_bucket_capacity_and_hasher(
+            4,
+            hash_builder,
+        ));
+        map.insert(0, 0).await;
+        let guard = map.get_mut(&0).await.unwrap();
+
+        // waits for the guard
+        let blocked = tokio::spawn({
+            let map = Arc::clone(&map);
+            async move { map.insert(0, 1).await }
+        });
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        // splits the other buckets meanwhile
+        tokio::spawn({
+            let map = Arc::clone(&map);
+            let others = others.clone();
+            async move {
+                for key in others {
+                    assert_eq!(map.insert(key, key).await, None);
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(!blocked.is_finished());
+
+        drop(guard);
+        assert_eq!(blocked.await.unwrap(), Some(0));
+        assert_eq!(*map.get(&0).await.unwrap(), 1);
+        for key in others {
+            assert_eq!(*map.get(&key).await.unwrap(), key);
+        }
+    }
+}
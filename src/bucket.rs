@@ -1,55 +1,199 @@
+use crate::{
+    column::{self, Column},
+    hasher::HashValue,
+    util::{match_tag, EMPTY_TAG, GROUP_WIDTH},
+};
+use allocator_api2::alloc::{Allocator, Global};
 use std::{
+    borrow::Borrow,
     fmt::{Debug, Formatter},
     ops::RangeInclusive,
 };
 
+/// Default number of entries a bucket can hold inline.
 pub(crate) const BUCKET_CAP: usize = 3;
 
-/// Bucket, where data is actually stored.
-pub(crate) struct Bucket<K, V> {
-    /// Bits that are unique to this bucket.
+/// Return the maximum depth of the buckets whose hash values are of type `H`,
+/// which is the width of `H`, capped at 64 as the prefix of a bucket is a
+/// `u64`.
+#[inline]
+pub(crate) fn max_depth<H: HashValue>() -> usize {
+    H::BITS.min(u64::BITS) as usize
+}
+
+/// How the entries are arranged within a bucket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BucketPolicy {
+    /// Entries are appended, and probed linearly, a removed entry is replaced
+    /// by the last one, this is the default.
+    #[default]
+    Unsorted,
+    /// Entries are kept sorted by their hash values and probed with binary
+    /// search, which suits large bucket capacities.
+    Sorted,
+}
+
+/// Index of a bucket, this is what directory entries store.
+///
+/// This is the bucket's key in the slab where buckets live, which stays valid
+/// until the bucket itself is removed.
+///
+/// Stored as an `u32` rather than an `usize`, which halves the memory used by
+/// the directory on 64-bit targets, as the number of buckets never gets close
+/// to `u32::MAX`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct BucketIdx(u32);
+
+impl BucketIdx {
+    /// Create a `BucketIdx` from `idx`.
     ///
-    /// # Weight
-    /// Say the global depth is `i`, weight of `bits[index]` is
-    /// `2^(i-index-1)`.
+    /// # Panic
+    /// `idx` should fit in an `u32`.
+    #[inline]
+    pub(crate) fn new(idx: usize) -> Self {
+        Self(u32::try_from(idx).expect("BucketIdx::new(): too many buckets"))
+    }
+
+    /// Return the index as an `usize`.
+    #[inline]
+    pub(crate) fn get(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Debug for BucketIdx {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl PartialEq<usize> for BucketIdx {
+    fn eq(&self, other: &usize) -> bool {
+        self.get() == *other
+    }
+}
+
+/// Bucket, where data is actually stored.
+///
+/// Up to `B` entries are stored inline, a bucket that can hold more entries
+/// spills them to the allocator `A`.
+pub(crate) struct Bucket<
+    K,
+    V,
+    H = u64,
+    const B: usize = BUCKET_CAP,
+    A: Allocator = Global,
+> {
+    /// Bits that are unique to this bucket, the first `depth` bits of the
+    /// hash values of its keys, with the first bit the most significant one.
     ///
     /// # Functionality of this field
-    /// When updating bucket pointers in `directory`, we need to do
-    /// bit-string match to find the corresponding bucket, string
-    /// match is slow, we use numeric value for a faster lookup.
+    /// When updating bucket pointers in `directory`, we need to find the
+    /// directory entries pointing to this bucket, which are the entries whose
+    /// indexes start with the prefix.
     ///
     /// # Example
-    /// Say we have bits `[1]`, and the global depth is `3`, then the
-    /// bits are automatically expanded to `[1, 0, 0]`, and thus
-    /// has value `4`.
+    /// Say we have prefix `0b1` of depth `1`, and the global depth is `3`,
+    /// then the prefix is automatically expanded to `0b100`, and thus has
+    /// value `4`.
+    pub(crate) prefix: u64,
+    /// Local depth, the number of the bits of `prefix`.
+    pub(crate) depth: u8,
+    /// Hash values of the keys stored in this bucket, `hashes[i]` is the
+    /// hash value of `keys[i]`.
+    ///
+    /// The hash value of the key is cached so that:
+    /// 1. Splitting a bucket does not need to rehash the keys
+    /// 2. Lookups can skip the entries with a different hash value without
+    ///    comparing the keys.
+    ///
+    /// # Overflow entries
+    /// A bucket can hold more entries than its capacity when all of them have
+    /// the same hash value, as splitting cannot separate them. The entries
+    /// beyond the capacity are called overflow entries.
+    ///
+    /// # Mutation
+    /// Entries should be added or removed through the methods of `Bucket`,
+    /// which keep `hashes`, `keys`, `values` and `tags` in sync.
+    pub(crate) hashes: Column<H, B, A>,
+    /// Keys stored in this bucket.
     ///
-    /// # local depth
-    /// Local depth equals `self.bits.len()`.
-    pub(crate) bits: Vec<u8>,
-    pub(crate) data: Vec<(K, V)>,
+    /// Keys and values are stored in separate arrays so that probing the keys
+    /// does not drag the values into cache.
+    pub(crate) keys: Column<K, B, A>,
+    /// Values stored in this bucket, `values[i]` belongs to `keys[i]`.
+    pub(crate) values: Column<V, B, A>,
+    /// `tags[i]` is the tag of `hashes[i]`, see [`HashValue::tag()`].
+    ///
+    /// Probing the tags 8 at a time, which are stored contiguously, touches
+    /// far less memory than comparing the entries one by one.
+    tags: Column<u8, B, A>,
+    /// The allocator the entries spill to.
+    alloc: A,
 }
 
-impl<K, V> Debug for Bucket<K, V>
+impl<K, V, H, const B: usize, A: Allocator + Clone> Debug
+    for Bucket<K, V, H, B, A>
 where
     K: Debug,
     V: Debug,
+    H: Debug,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Bucket")
-            .field("local depth", &self.bits.len())
-            .field("bits", &self.bits)
-            .field("data", &self.data)
-            .field("data len", &self.data.len())
+            .field("local depth", &self.depth)
+            .field("prefix", &self.prefix)
+            .field("data", &self.iter().collect::<Vec<_>>())
+            .field("data len", &self.len())
             .finish()
     }
 }
 
+impl<K, V, H, const B: usize, A: Allocator + Clone> Clone
+    for Bucket<K, V, H, B, A>
+where
+    K: Clone,
+    V: Clone,
+    H: Clone,
+{
+    fn clone(&self) -> Self {
+        self.clone_in(self.alloc.clone())
+    }
+}
+
+/// An owning iterator over the `(hash, key, value)` entries of a bucket,
+/// created by [`Bucket::into_entries()`].
+pub(crate) struct Entries<K, V, H, const B: usize, A: Allocator = Global> {
+    hashes: column::IntoIter<H, B, A>,
+    keys: column::IntoIter<K, B, A>,
+    values: column::IntoIter<V, B, A>,
+}
+
+impl<K, V, H, const B: usize, A: Allocator> Iterator
+    for Entries<K, V, H, B, A>
+{
+    type Item = (H, K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some((self.hashes.next()?, self.keys.next()?, self.values.next()?))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+impl<K, V, H, const B: usize, A: Allocator> ExactSizeIterator
+    for Entries<K, V, H, B, A>
+{
+}
+
 /// A bucket's value, this is the **index** of directory entries that pointing
 /// to this bucket.
 ///
 /// Calculated through:
 /// 1. Global depth
-/// 2. Bucket's `bits`
+/// 2. Bucket's `prefix`
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) enum BucketValue {
     /// This bucket's local depth equals to the global depth.
@@ -75,98 +219,479 @@ impl BucketValue {
     }
 }
 
-impl<K, V> Bucket<K, V> {
-    /// Create a bucket with the specified configuration.
+impl<K, V, H, const B: usize> Bucket<K, V, H, B> {
+    /// Create a bucket with the specified configuration, which can hold
+    /// `capacity` entries, spilling to the global allocator.
     ///
     /// # Panic
-    /// All numbers in `bits` should be valid binary numbers, i.e., be
-    /// smaller than 2.
-    pub(crate) fn new(bits: &[u8]) -> Self {
-        // check `bits`
-        bits.iter().for_each(|bit| assert!(*bit < 2));
+    /// Same as [`Bucket::new_in()`].
+    pub(crate) fn new(prefix: u64, depth: usize, capacity: usize) -> Self {
+        Self::new_in(prefix, depth, capacity, Global)
+    }
+}
 
+impl<K, V, H, const B: usize, A: Allocator + Clone> Bucket<K, V, H, B, A> {
+    /// Create a bucket with the specified configuration, which can hold
+    /// `capacity` entries, spilling to `alloc`.
+    ///
+    /// # Panic
+    /// `depth` should be at most 64, and `prefix` should have no more than
+    /// `depth` bits.
+    pub(crate) fn new_in(
+        prefix: u64,
+        depth: usize,
+        capacity: usize,
+        alloc: A,
+    ) -> Self {
+        assert!(depth <= u64::BITS as usize);
+        assert_eq!(prefix.checked_shr(depth as u32).unwrap_or(0), 0);
+
+        Self {
+            prefix,
+            depth: depth as u8,
+            hashes: Column::with_capacity_in(capacity, &alloc),
+            keys: Column::with_capacity_in(capacity, &alloc),
+            values: Column::with_capacity_in(capacity, &alloc),
+            tags: Column::with_capacity_in(capacity, &alloc),
+            alloc,
+        }
+    }
+
+    /// Clone the bucket, keeping its capacity, the clone spills to `alloc`.
+    pub(crate) fn clone_in(&self, alloc: A) -> Self
+    where
+        K: Clone,
+        V: Clone,
+        H: Clone,
+    {
         Self {
-            bits: bits.to_vec(),
-            data: Vec::with_capacity(BUCKET_CAP),
+            prefix: self.prefix,
+            depth: self.depth,
+            hashes: self.hashes.clone_in(&alloc),
+            keys: self.keys.clone_in(&alloc),
+            values: self.values.clone_in(&alloc),
+            tags: self.tags.clone_in(&alloc),
+            alloc,
+        }
+    }
+
+    /// Move the spilled entries to `alloc`, keeping the capacity and the
+    /// order of the entries.
+    pub(crate) fn move_to(&mut self, alloc: A)
+    where
+        H: HashValue,
+    {
+        let capacity = self.keys.capacity();
+        let empty =
+            Self::new_in(self.prefix, self.local_depth(), capacity, alloc);
+        for entry in std::mem::replace(self, empty).into_entries() {
+            self.push(entry, BucketPolicy::Unsorted);
         }
     }
 
-    /// Return `true` if this `key` is included in this bucket.
-    pub(crate) fn contains(&self, key: &K) -> bool
+    /// Return the index of the entry whose key equals to `key`, `hash` should
+    /// be the hash value of `key`, the entries are arranged under `policy`.
+    pub(crate) fn position<Q>(
+        &self,
+        hash: H,
+        key: &Q,
+        policy: BucketPolicy,
+    ) -> Option<usize>
     where
-        K: Eq,
+        H: HashValue,
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
     {
-        self.data.iter().any(|(k, _)| k == key)
+        match policy {
+            BucketPolicy::Unsorted => {
+                let tag = hash.tag();
+                for (group_idx, tags) in
+                    self.tags.chunks(GROUP_WIDTH).enumerate()
+                {
+                    let mut group = [EMPTY_TAG; GROUP_WIDTH];
+                    group[..tags.len()].copy_from_slice(tags);
+
+                    let mut mask = match_tag(group, tag);
+                    while mask != 0 {
+                        let idx = group_idx * GROUP_WIDTH
+                            + mask.trailing_zeros() as usize / 8;
+                        if self.hashes[idx] == hash
+                            && self.keys[idx].borrow() == key
+                        {
+                            return Some(idx);
+                        }
+                        mask &= mask - 1;
+                    }
+                }
+
+                None
+            }
+            BucketPolicy::Sorted => {
+                let start = self.hashes.partition_point(|h| *h < hash);
+                self.hashes[start..]
+                    .iter()
+                    .take_while(|h| **h == hash)
+                    .zip(&self.keys[start..])
+                    .position(|(_, k)| k.borrow() == key)
+                    .map(|idx| start + idx)
+            }
+        }
+    }
+
+    /// Return the number of entries stored in this bucket.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Return an iterator over the `(hash, key, value)` entries.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&H, &K, &V)> {
+        self.hashes
+            .iter()
+            .zip(self.keys.iter())
+            .zip(self.values.iter())
+            .map(|((h, k), v)| (h, k, v))
+    }
+
+    /// Consume this bucket, return an iterator over its `(hash, key, value)`
+    /// entries.
+    pub(crate) fn into_entries(self) -> Entries<K, V, H, B, A> {
+        Entries {
+            hashes: self.hashes.into_iter(),
+            keys: self.keys.into_iter(),
+            values: self.values.into_iter(),
+        }
+    }
+
+    /// Store `entry` under `policy`, return its index.
+    pub(crate) fn push(
+        &mut self,
+        (hash, key, value): (H, K, V),
+        policy: BucketPolicy,
+    ) -> usize
+    where
+        H: HashValue,
+    {
+        let idx = match policy {
+            BucketPolicy::Unsorted => self.len(),
+            BucketPolicy::Sorted => self.hashes.partition_point(|h| *h <= hash),
+        };
+        self.tags.insert(idx, hash.tag(), &self.alloc);
+        self.hashes.insert(idx, hash, &self.alloc);
+        self.keys.insert(idx, key, &self.alloc);
+        self.values.insert(idx, value, &self.alloc);
+        idx
+    }
+
+    /// Remove and return the `idx`th entry, the entries are arranged under
+    /// `policy`.
+    ///
+    /// The last entry takes the place of the removed one if the entries are
+    /// unsorted, otherwise the order of the remaining entries is preserved.
+    pub(crate) fn remove(
+        &mut self,
+        idx: usize,
+        policy: BucketPolicy,
+    ) -> (H, K, V) {
+        match policy {
+            BucketPolicy::Unsorted => {
+                self.tags.swap_remove(idx);
+                (
+                    self.hashes.swap_remove(idx),
+                    self.keys.swap_remove(idx),
+                    self.values.swap_remove(idx),
+                )
+            }
+            BucketPolicy::Sorted => {
+                self.tags.remove(idx);
+                (
+                    self.hashes.remove(idx),
+                    self.keys.remove(idx),
+                    self.values.remove(idx),
+                )
+            }
+        }
+    }
+
+    /// Move the entries whose hash values satisfy `moved` to `other` under
+    /// `policy`, the remaining entries keep their order.
+    ///
+    /// The entries are partitioned in place, nothing is allocated unless
+    /// `other` runs out of room.
+    pub(crate) fn split_off(
+        &mut self,
+        other: &mut Self,
+        policy: BucketPolicy,
+        mut moved: impl FnMut(H) -> bool,
+    ) where
+        H: HashValue,
+    {
+        // move the remaining entries to the front, in their order
+        let mut kept = 0;
+        for idx in 0..self.len() {
+            if !moved(self.hashes[idx]) {
+                self.tags.swap(kept, idx);
+                self.hashes.swap(kept, idx);
+                self.keys.swap(kept, idx);
+                self.values.swap(kept, idx);
+                kept += 1;
+            }
+        }
+        while self.len() > kept {
+            self.tags.pop();
+            let entry = (
+                self.hashes.pop().unwrap(),
+                self.keys.pop().unwrap(),
+                self.values.pop().unwrap(),
+            );
+            other.push(entry, policy);
+        }
+    }
+
+    /// Take all the entries out, leaving the bucket, whose prefix is kept,
+    /// empty with room for `capacity` entries.
+    pub(crate) fn take(&mut self, capacity: usize) -> Entries<K, V, H, B, A> {
+        let empty = Self::new_in(
+            self.prefix,
+            self.local_depth(),
+            capacity,
+            self.alloc.clone(),
+        );
+        std::mem::replace(self, empty).into_entries()
+    }
+
+    /// Sort the entries by their hash values.
+    pub(crate) fn sort(&mut self)
+    where
+        H: HashValue,
+    {
+        let capacity = self.keys.capacity();
+        let mut entries = self.take(capacity).collect::<Vec<_>>();
+        entries.sort_by_key(|(h, _, _)| *h);
+        for entry in entries {
+            self.push(entry, BucketPolicy::Unsorted);
+        }
     }
 
     /// Return the bucket's local depth.
     #[inline]
     pub(crate) fn local_depth(&self) -> usize {
-        self.bits.len()
+        self.depth as usize
+    }
+
+    /// Append `bit` to the prefix, incrementing the local depth.
+    pub(crate) fn push_bit(&mut self, bit: u64) {
+        assert!(self.depth < u64::BITS as u8 && bit < 2);
+        self.prefix = (self.prefix << 1) | bit;
+        self.depth += 1;
+    }
+
+    /// Remove the last bit of the prefix and return it, decrementing the
+    /// local depth.
+    pub(crate) fn pop_bit(&mut self) -> u64 {
+        assert!(self.depth > 0);
+        let bit = self.prefix & 1;
+        self.prefix >>= 1;
+        self.depth -= 1;
+        bit
     }
 
     /// Given the global depth, calculate this bucket's value.
     pub(crate) fn value(&self, global_depth: usize) -> BucketValue {
-        let local_depth = self.bits.len();
+        let local_depth = self.local_depth();
+        let value = self.prefix as usize;
         if local_depth == global_depth {
-            assert_eq!(self.bits.len(), global_depth);
-
-            let value: usize = self
-                .bits
-                .iter()
-                .rev()
-                .map(|u_8| *u_8 as usize)
-                .enumerate()
-                .fold(0, |acc, (idx, x)| {
-                    acc + (2_usize.pow(
-                        idx.try_into().expect("Should be in range of u32?"),
-                    )) * x
-                });
-
             BucketValue::EqualTo(value)
         } else {
             assert!(local_depth < global_depth);
 
-            let start: usize =
-                self.bits.iter().map(|u_8| *u_8 as usize).enumerate().fold(
-                    0,
-                    |acc, (idx, x)| {
-                        acc + (2_usize.pow((global_depth - idx - 1) as u32)) * x
-                    },
-                );
-            let end: usize = start
-                + 2_usize.pow((global_depth - self.bits.len()) as u32)
-                - 1;
+            let shift = global_depth - local_depth;
+            let start = value << shift;
+            let end = start + (1 << shift) - 1;
 
             BucketValue::Range(RangeInclusive::new(start, end))
         }
     }
 
-    /// Return true if this bucket is full.
+    /// Return true if this bucket, which is full, can be split to make room
+    /// for a new entry whose hash value is `hash`.
+    ///
+    /// Splitting cannot separate the entries if they all have the same hash
+    /// value as the new one, or the bucket has no more bits to split on, see
+    /// [`max_depth()`].
+    pub(crate) fn splittable(&self, hash: H) -> bool
+    where
+        H: HashValue,
+    {
+        self.local_depth() < max_depth::<H>()
+            && !self.hashes.iter().all(|h| *h == hash)
+    }
+
+    /// Return true if a key whose hash value is `hash` belongs to this
+    /// bucket, i.e., the first bits of `hash` are the prefix of this bucket.
+    pub(crate) fn covers(&self, hash: H) -> bool
+    where
+        H: HashValue,
+    {
+        hash.prefix(self.depth as u32) as u64 == self.prefix
+    }
+
+    /// Return true if this bucket, which can hold `capacity` entries, is
+    /// full.
     #[inline]
-    pub(crate) fn is_full(&self) -> bool {
-        self.data.len() == self.data.capacity()
+    pub(crate) fn is_full(&self, capacity: usize) -> bool {
+        self.len() >= capacity
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::arena::Arena;
+
+    #[test]
+    #[should_panic]
+    fn bucket_new_invalid_prefix() {
+        Bucket::<(), ()>::new(0b100, 2, BUCKET_CAP);
+    }
+
+    #[test]
+    fn bucket_idx() {
+        let idx = BucketIdx::new(42);
+        assert_eq!(idx.get(), 42);
+        assert_eq!(idx, 42);
+        assert_eq!(format!("{:?}", [idx]), "[42]");
+    }
 
     #[test]
     #[should_panic]
-    fn bucket_new_invalid_bit() {
-        Bucket::<(), ()>::new(&[3, 1]);
+    fn bucket_idx_overflow() {
+        BucketIdx::new(u32::MAX as usize + 1);
+    }
+
+    #[test]
+    fn bucket_sorted_policy() {
+        let mut bucket: Bucket<i32, i32> = Bucket::new(1, 1, 8);
+        for (hash, key) in [(3, 0), (1, 1), (2, 2), (1, 3)] {
+            bucket.push((hash, key, key), BucketPolicy::Sorted);
+        }
+        assert_eq!(bucket.hashes[..], [1, 1, 2, 3]);
+        assert_eq!(bucket.keys[..], [1, 3, 2, 0]);
+
+        assert_eq!(bucket.position(1, &3, BucketPolicy::Sorted), Some(1));
+        assert_eq!(bucket.position(3, &0, BucketPolicy::Sorted), Some(3));
+        assert_eq!(bucket.position(2, &0, BucketPolicy::Sorted), None);
+        assert_eq!(bucket.position(1, &3, BucketPolicy::Unsorted), Some(1));
+
+        // the sorted entries stay sorted
+        bucket.remove(0, BucketPolicy::Sorted);
+        assert_eq!(bucket.hashes[..], [1, 2, 3]);
+        assert_eq!(bucket.position(3, &0, BucketPolicy::Sorted), Some(2));
+    }
+
+    #[test]
+    fn bucket_tags() {
+        let mut bucket: Bucket<u64, u64> = Bucket::new(1, 1, 64);
+        for i in 0..20 {
+            bucket.push((i, i, i), BucketPolicy::Unsorted);
+        }
+        for i in 0..20 {
+            let idx = bucket.position(i, &i, BucketPolicy::Unsorted);
+            assert_eq!(idx, Some(i as usize));
+        }
+        assert_eq!(bucket.position(20, &20, BucketPolicy::Unsorted), None);
+
+        // the last entry takes the place of the removed one
+        let (h, _, _) = bucket.remove(3, BucketPolicy::Unsorted);
+        assert_eq!(h, 3);
+        assert_eq!(bucket.position(19, &19, BucketPolicy::Unsorted), Some(3));
+        assert_eq!(bucket.position(4, &4, BucketPolicy::Unsorted), Some(4));
+        assert_eq!(bucket.tags.len(), bucket.len());
+
+        let entries = bucket.take(BUCKET_CAP);
+        assert_eq!(entries.len(), 19);
+        let mut entries = entries.collect::<Vec<_>>();
+        entries.sort();
+        assert!(entries
+            .into_iter()
+            .eq((0..20).filter(|i| *i != 3).map(|i| (i, i, i))));
+        assert_eq!((bucket.prefix, bucket.local_depth()), (1, 1));
+        assert!(bucket.tags.is_empty());
+    }
+
+    #[test]
+    fn bucket_split_off() {
+        let mut bucket: Bucket<u64, u64> = Bucket::new(0, 0, 8);
+        for i in 0..8 {
+            bucket.push((i, i, i), BucketPolicy::Sorted);
+        }
+        let mut other = Bucket::new(1, 1, 8);
+        bucket.split_off(&mut other, BucketPolicy::Sorted, |h| h % 3 == 0);
+
+        assert_eq!(bucket.hashes[..], [1, 2, 4, 5, 7]);
+        assert_eq!(bucket.tags.len(), bucket.len());
+        assert!(bucket.iter().all(|(h, k, v)| h == k && k == v));
+        assert_eq!(other.hashes[..], [0, 3, 6]);
+        assert_eq!(other.position(3, &3, BucketPolicy::Sorted), Some(1));
+    }
+
+    #[test]
+    fn bucket_move_to() {
+        let arena = Arena::new();
+        let mut bucket: Bucket<u64, u64, u64, BUCKET_CAP, Arena> =
+            Bucket::new_in(0b10, 2, 64, arena.clone());
+        for i in 0..10 {
+            bucket.push((i, i, i), BucketPolicy::Unsorted);
+        }
+        let other = Arena::new();
+        bucket.move_to(other.clone());
+        assert!(bucket.iter().all(|(h, k, v)| h == k && k == v));
+        assert!(bucket.iter().map(|(h, _, _)| *h).eq(0..10));
+        assert_eq!((bucket.prefix, bucket.local_depth()), (0b10, 2));
+        assert_eq!(bucket.keys.capacity(), 64);
+        assert!(other.allocated() > 0);
+
+        // the blocks freed by the move are taken by the next bucket
+        let allocated = arena.allocated();
+        let _next: Bucket<u64, u64, u64, BUCKET_CAP, Arena> =
+            Bucket::new_in(0, 0, 64, arena.clone());
+        assert_eq!(arena.allocated(), allocated);
     }
 
     #[test]
     fn bucket_value() {
-        let bucket: Bucket<(), ()> = Bucket::new(&[1, 1]);
+        let bucket: Bucket<(), ()> = Bucket::new(0b11, 2, BUCKET_CAP);
 
         assert_eq!(
             bucket.value(3),
             BucketValue::Range(RangeInclusive::new(6, 7))
         );
         assert_eq!(bucket.value(2), BucketValue::EqualTo(3));
+
+        let mut bucket = bucket;
+        bucket.push_bit(0);
+        assert_eq!((bucket.prefix, bucket.local_depth()), (0b110, 3));
+        assert!(bucket.covers(0b110 << 61));
+        assert!(!bucket.covers(0b111 << 61));
+        assert_eq!(bucket.pop_bit(), 0);
+        assert_eq!(bucket.value(3), BucketValue::Range(6..=7));
+    }
+
+    #[test]
+    fn bucket_clone_keeps_capacity() {
+        let mut bucket: Bucket<i32, i32> = Bucket::new(1, 1, BUCKET_CAP);
+        bucket.push((0, 1, 1), BucketPolicy::Unsorted);
+
+        let clone = bucket.clone();
+        assert_eq!((clone.prefix, clone.depth), (bucket.prefix, bucket.depth));
+        assert!(clone.iter().eq(bucket.iter()));
+        assert_eq!(clone.keys.capacity(), BUCKET_CAP);
+        assert!(matches!(clone.keys, Column::Inline(_)));
+        assert!(!clone.is_full(BUCKET_CAP));
+
+        let mut bucket: Bucket<i32, i32> = Bucket::new(1, 1, 64);
+        bucket.push((0, 1, 1), BucketPolicy::Unsorted);
+        assert!(matches!(bucket.values, Column::Heap(_)));
+        assert_eq!(bucket.clone().values.capacity(), 64);
     }
 }
@@ -0,0 +1,489 @@
+//! A builder of [`HashMap`], which can bulk load entries.
+
+use crate::{
+    arena::Arena,
+    bucket::{max_depth, Bucket, BucketIdx, BucketPolicy, BUCKET_CAP},
+    counters::Counters,
+    hasher::{
+        BitOrder, DefaultHashBuilder, DirectoryMapper, HashValue, KeyHasher,
+    },
+    map::{count_depths, CoalescePolicy, HashMap, HALVING_HYSTERESIS},
+    observer::{MapEvent, Observer},
+    slab::Slab,
+};
+use allocator_api2::{
+    alloc::{Allocator, Global},
+    vec::Vec as AllocVec,
+};
+use std::{hash::Hash, ops::Range};
+
+/// A builder of [`HashMap`].
+///
+/// Besides creating empty maps with the given options, the builder can
+/// [`bulk_load()`](Self::bulk_load) a map from a large number of entries
+/// much faster than inserting them one at a time.
+///
+/// ```
+/// use extendable_hashmap::{BucketPolicy, HashMapBuilder};
+///
+/// let map = HashMapBuilder::new()
+///     .bucket_capacity(16)
+///     .bucket_policy(BucketPolicy::Sorted)
+///     .bulk_load((0..1000).map(|i| (i, i * 2)));
+/// assert_eq!(map.len(), 1000);
+/// assert_eq!(map.get(&10), Some(&20));
+/// ```
+#[derive(Debug, Clone)]
+pub struct HashMapBuilder<
+    S = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+> {
+    hash_builder: S,
+    mapper: M,
+    bucket_capacity: usize,
+    bucket_policy: BucketPolicy,
+    coalesce_policy: CoalescePolicy,
+    halving_hysteresis: usize,
+    max_global_depth: Option<usize>,
+    observer: Option<Observer>,
+}
+
+impl HashMapBuilder {
+    /// Create a builder with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S: Default, M: Default, const B: usize> Default
+    for HashMapBuilder<S, M, B>
+{
+    fn default() -> Self {
+        Self {
+            hash_builder: S::default(),
+            mapper: M::default(),
+            bucket_capacity: B,
+            bucket_policy: BucketPolicy::default(),
+            coalesce_policy: CoalescePolicy::default(),
+            halving_hysteresis: HALVING_HYSTERESIS,
+            max_global_depth: None,
+            observer: None,
+        }
+    }
+}
+
+impl<S, M, const B: usize> HashMapBuilder<S, M, B> {
+    /// Use `hash_builder` to hash keys.
+    pub fn hasher<T>(self, hash_builder: T) -> HashMapBuilder<T, M, B> {
+        HashMapBuilder {
+            hash_builder,
+            mapper: self.mapper,
+            bucket_capacity: self.bucket_capacity,
+            bucket_policy: self.bucket_policy,
+            coalesce_policy: self.coalesce_policy,
+            halving_hysteresis: self.halving_hysteresis,
+            max_global_depth: self.max_global_depth,
+            observer: self.observer,
+        }
+    }
+
+    /// Map hash values to directory indexes with `mapper`.
+    pub fn mapper<T>(self, mapper: T) -> HashMapBuilder<S, T, B> {
+        HashMapBuilder {
+            hash_builder: self.hash_builder,
+            mapper,
+            bucket_capacity: self.bucket_capacity,
+            bucket_policy: self.bucket_policy,
+            coalesce_policy: self.coalesce_policy,
+            halving_hysteresis: self.halving_hysteresis,
+            max_global_depth: self.max_global_depth,
+            observer: self.observer,
+        }
+    }
+
+    /// Set the number of entries a bucket can hold, which defaults to `B`.
+    ///
+    /// # Panic
+    /// `bucket_capacity` should be greater than 0.
+    pub fn bucket_capacity(mut self, bucket_capacity: usize) -> Self {
+        assert!(bucket_capacity > 0);
+        self.bucket_capacity = bucket_capacity;
+
+        self
+    }
+
+    /// Set how the entries are arranged within a bucket, see
+    /// [`HashMap::set_bucket_policy()`].
+    pub fn bucket_policy(mut self, bucket_policy: BucketPolicy) -> Self {
+        self.bucket_policy = bucket_policy;
+
+        self
+    }
+
+    /// Set when removals merge buckets, see
+    /// [`HashMap::set_coalesce_policy()`].
+    ///
+    /// # Panic
+    /// The fill ratio of [`CoalescePolicy::Threshold`] should be within
+    /// `[0, 1]`.
+    pub fn coalesce_policy(mut self, coalesce_policy: CoalescePolicy) -> Self {
+        coalesce_policy.check();
+        self.coalesce_policy = coalesce_policy;
+
+        self
+    }
+
+    /// Set the number of the empty levels the directory keeps below its
+    /// deepest bucket, see [`HashMap::set_halving_hysteresis()`].
+    pub fn halving_hysteresis(mut self, halving_hysteresis: usize) -> Self {
+        self.halving_hysteresis = halving_hysteresis;
+
+        self
+    }
+
+    /// Set the maximum global depth, see [`HashMap::set_max_global_depth()`].
+    ///
+    /// # Panic
+    /// `max_global_depth` should be greater than 0.
+    pub fn max_global_depth(mut self, max_global_depth: usize) -> Self {
+        assert!(max_global_depth > 0);
+        self.max_global_depth = Some(max_global_depth);
+
+        self
+    }
+
+    /// Set the observer of the structural changes of the built maps, see
+    /// [`HashMap::set_observer()`].
+    pub fn observer<F>(mut self, observer: F) -> Self
+    where
+        F: Fn(MapEvent) + Send + Sync + 'static,
+    {
+        self.observer = Some(Observer::new(observer));
+
+        self
+    }
+}
+
+impl<S: KeyHasher, M: DirectoryMapper<S::Hash>, const B: usize>
+    HashMapBuilder<S, M, B>
+{
+    /// Return the maximum global depth of the built maps.
+    ///
+    /// # Panic
+    /// The maximum global depth should not be greater than the width of the
+    /// hash values or 64.
+    fn max_depth(&self) -> usize {
+        let max_global_depth =
+            self.max_global_depth.unwrap_or(max_depth::<S::Hash>());
+        assert!(max_global_depth <= max_depth::<S::Hash>());
+
+        max_global_depth
+    }
+
+    /// Build an empty map.
+    pub fn build<K, V>(self) -> HashMap<K, V, S, M, B> {
+        self.build_in(Global)
+    }
+
+    /// Build an empty map, which allocates from `alloc`.
+    pub fn build_in<K, V, A: Allocator + Clone>(
+        self,
+        alloc: A,
+    ) -> HashMap<K, V, S, M, B, A> {
+        self.bulk_load_sorted(Vec::new(), alloc)
+    }
+
+    /// Build a map holding the entries of `iter`, a key appearing more than
+    /// once takes its last value.
+    ///
+    /// All the keys are hashed up front, and sorted by the bits indexing the
+    /// directory, so the final layout is computed at once: a group of
+    /// entries that don't fit into a bucket is divided by the next bit until
+    /// every group does, and each group is written into its bucket directly.
+    /// No bucket is ever split and no entry is ever moved, unlike inserting
+    /// the entries one at a time.
+    pub fn bulk_load<K, V, I>(self, iter: I) -> HashMap<K, V, S, M, B>
+    where
+        K: Hash + Eq,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.bulk_load_in(iter, Global)
+    }
+
+    /// Build a map holding the entries of `iter`, which allocates from
+    /// `alloc`, see [`bulk_load()`](Self::bulk_load).
+    pub fn bulk_load_in<K, V, I, A>(
+        self,
+        iter: I,
+        alloc: A,
+    ) -> HashMap<K, V, S, M, B, A>
+    where
+        K: Hash + Eq,
+        I: IntoIterator<Item = (K, V)>,
+        A: Allocator + Clone,
+    {
+        let mut entries = iter
+            .into_iter()
+            .map(|(k, v)| (self.hash_builder.hash_key(&k), k, v))
+            .collect::<Vec<_>>();
+        // stable, so a duplicated key is followed by its later values
+        entries.sort_by_key(|(hash, _, _)| self.mapper.map(*hash));
+
+        self.bulk_load_sorted(dedup_sorted(entries), alloc)
+    }
+
+    /// Build a map holding `entries`, which are sorted by their mapped hash
+    /// values, and have distinct keys, the map allocates from `alloc`.
+    fn bulk_load_sorted<K, V, A: Allocator + Clone>(
+        self,
+        entries: Vec<(S::Hash, K, V)>,
+        alloc: A,
+    ) -> HashMap<K, V, S, M, B, A> {
+        let max_global_depth = self.max_depth();
+        let arena = Arena::new_in(alloc);
+        let len = entries.len();
+        let (global_depth, directories, buckets) = layout(
+            entries,
+            &self.mapper,
+            self.bucket_capacity,
+            self.bucket_policy,
+            max_global_depth,
+            &arena,
+        );
+
+        HashMap {
+            len,
+            global_depth,
+            bucket_capacity: self.bucket_capacity,
+            max_global_depth,
+            bucket_policy: self.bucket_policy,
+            coalesce_policy: self.coalesce_policy,
+            halving_hysteresis: self.halving_hysteresis,
+            directories: directories.into(),
+            depth_counts: count_depths(&buckets, global_depth),
+            buckets,
+            hash_builder: self.hash_builder,
+            mapper: self.mapper,
+            observer: self.observer,
+            counters: Counters::default(),
+            arena,
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > HashMap<K, V, S, M, B, A>
+{
+    /// Fill this map, which should be empty, with `entries`, which are
+    /// sorted by their mapped hash values, and have distinct keys, like
+    /// [`HashMapBuilder::bulk_load()`] does, keeping the options of the map.
+    pub(crate) fn load_sorted(&mut self, entries: Vec<(S::Hash, K, V)>) {
+        assert!(self.is_empty());
+
+        let len = entries.len();
+        let (global_depth, directories, buckets) = layout(
+            entries,
+            &self.mapper,
+            self.bucket_capacity,
+            self.bucket_policy,
+            self.max_global_depth,
+            &self.arena,
+        );
+        self.len = len;
+        self.global_depth = global_depth;
+        self.directories = directories.into();
+        self.depth_counts = count_depths(&buckets, global_depth);
+        self.buckets = buckets;
+    }
+}
+
+/// Drop the duplicated keys of `entries`, which are sorted by their mapped
+/// hash values, a key appearing more than once takes its last value.
+pub(crate) fn dedup_sorted<H: PartialEq, K: Eq, V>(
+    entries: Vec<(H, K, V)>,
+) -> Vec<(H, K, V)> {
+    // the duplicated keys share a hash value
+    let mut deduped: Vec<(H, K, V)> = Vec::with_capacity(entries.len());
+    let mut group_start = 0;
+    for (hash, key, value) in entries {
+        if deduped.last().is_none_or(|(h, _, _)| *h != hash) {
+            group_start = deduped.len();
+        }
+        match deduped[group_start..]
+            .iter_mut()
+            .find(|(_, k, _)| *k == key)
+        {
+            Some(entry) => entry.2 = value,
+            None => deduped.push((hash, key, value)),
+        }
+    }
+
+    deduped
+}
+
+/// The global depth, the directory and the buckets of a map.
+type Layout<K, V, H, const B: usize, A> = (
+    usize,
+    AllocVec<BucketIdx, A>,
+    Slab<Bucket<K, V, H, B, Arena<A>>, A>,
+);
+
+/// Lay out `entries`, which are sorted by their mapped hash values, and have
+/// distinct keys, into buckets of `bucket_capacity` entries spilling to
+/// `arena`, whose directory is at most `max_global_depth` deep.
+fn layout<K, V, H, M, const B: usize, A>(
+    entries: Vec<(H, K, V)>,
+    mapper: &M,
+    bucket_capacity: usize,
+    bucket_policy: BucketPolicy,
+    max_global_depth: usize,
+    arena: &Arena<A>,
+) -> Layout<K, V, H, B, A>
+where
+    H: HashValue,
+    M: DirectoryMapper<H>,
+    A: Allocator + Clone,
+{
+    let mapped = entries
+        .iter()
+        .map(|(hash, _, _)| mapper.map(*hash))
+        .collect::<Vec<_>>();
+    let mut leaves = Vec::new();
+    partition(
+        &mapped,
+        bucket_capacity,
+        max_global_depth,
+        0..mapped.len(),
+        (0, 0),
+        &mut leaves,
+    );
+
+    let global_depth = leaves
+        .iter()
+        .map(|(_, depth, _): &(u64, usize, _)| *depth)
+        .max()
+        .unwrap_or(0);
+    let alloc = arena.allocator();
+    let mut directories =
+        AllocVec::with_capacity_in(1 << global_depth, alloc.clone());
+    directories.resize(1 << global_depth, BucketIdx::new(0));
+    let mut buckets = Slab::with_capacity_in(leaves.len(), alloc);
+    let mut entries = entries.into_iter();
+    for (prefix, depth, range) in leaves {
+        let mut bucket =
+            Bucket::new_in(prefix, depth, bucket_capacity, arena.clone());
+        for entry in entries.by_ref().take(range.len()) {
+            bucket.push(entry, bucket_policy);
+        }
+
+        let shift = global_depth - depth;
+        let value = prefix as usize;
+        let bucket_idx = BucketIdx::new(buckets.insert(bucket));
+        directories[value << shift..(value + 1) << shift].fill(bucket_idx);
+    }
+
+    (global_depth, directories, buckets)
+}
+
+/// Divide `mapped[range]`, whose first `depth` bits are `prefix`, into
+/// buckets, and append the prefix, the depth and the range of every bucket
+/// to `leaves`.
+///
+/// Like [`HashMap`] does, a group is kept in one bucket, beyond its capacity,
+/// if all its hash values are equal, or `max_global_depth` is reached.
+fn partition<H: HashValue>(
+    mapped: &[H],
+    bucket_capacity: usize,
+    max_global_depth: usize,
+    range: Range<usize>,
+    (prefix, depth): (u64, usize),
+    leaves: &mut Vec<(u64, usize, Range<usize>)>,
+) {
+    let group = &mapped[range.clone()];
+    let fits = group.len() <= bucket_capacity
+        || group.first() == group.last()
+        || depth >= max_global_depth;
+    if fits {
+        leaves.push((prefix, depth, range));
+        return;
+    }
+
+    let mid =
+        range.start + group.partition_point(|hash| hash.bit(depth as u32) == 0);
+    for (range, bit) in [(range.start..mid, 0), (mid..range.end, 1)] {
+        partition(
+            mapped,
+            bucket_capacity,
+            max_global_depth,
+            range,
+            ((prefix << 1) | bit, depth + 1),
+            leaves,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::FixedState;
+
+    #[test]
+    fn bulk_load_works() {
+        let hash_builder = FixedState::with_seed(3);
+        let entries = (0..10000_u64).map(|i| (i % 8000, i)).collect::<Vec<_>>();
+        let map = HashMapBuilder::new()
+            .hasher(hash_builder)
+            .mapper(BitOrder::Lsb)
+            .bulk_load(entries.clone());
+
+        let mut expected =
+            HashMap::with_bit_order_and_hasher(BitOrder::Lsb, hash_builder);
+        for (k, v) in entries {
+            expected.insert(k, v);
+        }
+        assert_eq!(map.len(), 8000);
+        map.validate().unwrap();
+        assert_eq!(map, expected);
+        assert_eq!(map.get(&1), Some(&8001));
+        for (_, bucket) in map.buckets.iter() {
+            assert!(bucket.len() <= map.bucket_capacity());
+        }
+        for (idx, bucket_idx) in map.directories.iter().enumerate() {
+            let bucket = &map.buckets[bucket_idx.get()];
+            let shift = map.global_depth - bucket.local_depth();
+            assert_eq!(idx >> shift, bucket.prefix as usize);
+        }
+
+        // the map keeps working as usual
+        let mut map = map;
+        for i in 8000..9000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 9000);
+        assert_eq!(map.get(&8500), Some(&8500));
+    }
+
+    #[test]
+    fn bulk_load_overflow() {
+        let map = HashMapBuilder::<FixedState, BitOrder, 4>::default()
+            .bucket_capacity(4)
+            .max_global_depth(2)
+            .bulk_load((0..100).map(|i| (i, i)));
+        assert_eq!(map.global_depth, 2);
+        assert_eq!(map.buckets.len(), 4);
+        assert_eq!(map.len(), 100);
+
+        let empty: HashMap<u64, u64> = HashMapBuilder::new().build();
+        assert_eq!(empty.global_depth, 0);
+        assert_eq!(empty.buckets.len(), 1);
+        assert!(empty.is_empty());
+    }
+}
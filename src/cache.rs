@@ -0,0 +1,118 @@
+//! A bounded cache of the bucket pages of a
+//! [`DiskHashMap`](crate::DiskHashMap), evicting the least recently used
+//! page.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// A least recently used cache of clean page images, i.e., images the same
+/// as the ones in the store.
+#[derive(Debug, Default)]
+pub(crate) struct PageCache {
+    /// Maximum number of the cached pages, the cache is disabled if it is 0
+    capacity: usize,
+    /// Image of every cached page, along with its last use
+    pages: HashMap<u64, (u64, Vec<u8>)>,
+    /// Cached pages by their last uses, the least recently used one first
+    uses: BTreeMap<u64, u64>,
+    /// Counter of the uses
+    clock: u64,
+}
+
+impl PageCache {
+    /// Return the maximum number of the cached pages.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Return the number of the cached pages.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Return the image of page `page_no`, marking it the most recently
+    /// used page, or `None` if it is not cached.
+    pub(crate) fn get(&mut self, page_no: u64) -> Option<&[u8]> {
+        self.clock += 1;
+        let (used, image) = self.pages.get_mut(&page_no)?;
+        self.uses.remove(used);
+        self.uses.insert(self.clock, page_no);
+        *used = self.clock;
+
+        Some(image)
+    }
+
+    /// Cache `image` of page `page_no` as the most recently used page,
+    /// evicting the least recently used page if the cache is full.
+    pub(crate) fn insert(&mut self, page_no: u64, image: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        if let Some((used, _)) = self.pages.insert(page_no, (self.clock, image))
+        {
+            self.uses.remove(&used);
+        }
+        self.uses.insert(self.clock, page_no);
+        while self.pages.len() > self.capacity {
+            self.evict();
+        }
+    }
+
+    /// Evict the least recently used page, return false if the cache is
+    /// empty.
+    pub(crate) fn evict(&mut self) -> bool {
+        let Some((_, page_no)) = self.uses.pop_first() else {
+            return false;
+        };
+        self.pages.remove(&page_no);
+
+        true
+    }
+
+    /// Remove page `page_no` from the cache.
+    pub(crate) fn remove(&mut self, page_no: u64) {
+        if let Some((used, _)) = self.pages.remove(&page_no) {
+            self.uses.remove(&used);
+        }
+    }
+
+    /// Set the maximum number of the cached pages, evicting the least
+    /// recently used pages beyond it.
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.pages.len() > capacity {
+            self.evict();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn page_cache_evicts_least_recently_used() {
+        let mut cache = PageCache::default();
+        cache.set_capacity(2);
+        cache.insert(1, vec![1]);
+        cache.insert(2, vec![2]);
+        assert_eq!(cache.get(1), Some(&[1][..]));
+        cache.insert(3, vec![3]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(1), Some(&[1][..]));
+
+        // a cached page is replaced
+        cache.insert(3, vec![4]);
+        assert_eq!(cache.get(3), Some(&[4][..]));
+        assert_eq!(cache.len(), 2);
+
+        cache.set_capacity(1);
+        assert_eq!(cache.get(1), None);
+        cache.set_capacity(0);
+        cache.insert(5, vec![5]);
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -0,0 +1,258 @@
+//! Directory checkpoints of a [`DiskHashMap`](crate::DiskHashMap).
+//!
+//! Without a checkpoint, the directory is rebuilt from the headers of all the
+//! bucket pages when the map is opened, which reads the whole file. A
+//! checkpoint records the directory, so that it is loaded instead, in the
+//! file at the same path as the map with `.dir` appended:
+//!
+//! | field        | size |
+//! |--------------|------|
+//! | magic        | 8    |
+//! | version      | 4    |
+//! | global depth | 4    |
+//! | pages        | 8    |
+//! | buckets      | 8    |
+//! | bucket       | 16   |
+//! | ...          |      |
+//! | checksum     | 4    |
+//!
+//! where the buckets are in the order of the directory entries pointing to
+//! them, every bucket is its page number (8 bytes), local depth (4 bytes) and
+//! number of records (4 bytes), and the CRC-32C checksum covers the rest of
+//! the checkpoint. All the integers are in little endian.
+//!
+//! A checkpoint is replaced atomically, by writing a temporary file and
+//! renaming it.
+
+use crate::util::crc32c;
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Magic bytes at the start of a checkpoint.
+const MAGIC: &[u8; 8] = b"EXTHDIR\0";
+/// Version of the checkpoint format.
+const VERSION: u32 = 1;
+/// Length of the checkpoint header in bytes.
+const HEADER_LEN: usize = 32;
+/// Length of a bucket in bytes.
+const BUCKET_LEN: usize = 16;
+
+/// Return the path of the directory checkpoint of the map stored at `path`,
+/// which is `path` with `.dir` appended.
+pub(crate) fn checkpoint_path(path: &Path) -> PathBuf {
+    let mut checkpoint_path = OsString::from(path.as_os_str());
+    checkpoint_path.push(".dir");
+
+    checkpoint_path.into()
+}
+
+/// The directory of a map, along with the number of records of every page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Checkpoint {
+    /// Global depth
+    pub(crate) global_depth: u32,
+    /// Directory entries, storing page numbers
+    pub(crate) directory: Vec<u64>,
+    /// Number of records of every page, indexed by the page number, its
+    /// length is the number of pages, including the file header
+    pub(crate) page_lens: Vec<u32>,
+}
+
+/// Encode the checkpoint of `directory`, whose global depth is
+/// `global_depth`, and `page_lens`, the number of records of every page.
+fn encode(global_depth: u32, directory: &[u64], page_lens: &[u32]) -> Vec<u8> {
+    let mut buckets = Vec::new();
+    let mut idx = 0;
+    while idx < directory.len() {
+        let page_no = directory[idx];
+        // a bucket is pointed to by `2^(global_depth - local_depth)`
+        // consecutive entries
+        let run = directory[idx..]
+            .iter()
+            .take_while(|entry| **entry == page_no)
+            .count();
+        buckets.push((page_no, global_depth - run.ilog2()));
+        idx += run;
+    }
+
+    let mut buf =
+        Vec::with_capacity(HEADER_LEN + buckets.len() * BUCKET_LEN + 4);
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+    buf.extend_from_slice(&global_depth.to_le_bytes());
+    buf.extend_from_slice(&(page_lens.len() as u64).to_le_bytes());
+    buf.extend_from_slice(&(buckets.len() as u64).to_le_bytes());
+    for (page_no, depth) in buckets {
+        buf.extend_from_slice(&page_no.to_le_bytes());
+        buf.extend_from_slice(&depth.to_le_bytes());
+        buf.extend_from_slice(&page_lens[page_no as usize].to_le_bytes());
+    }
+    let checksum = crc32c(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+
+    buf
+}
+
+/// Atomically replace the checkpoint at `path` with the checkpoint of
+/// `directory`, whose global depth is `global_depth`, and `page_lens`.
+pub(crate) fn write_checkpoint(
+    path: &Path,
+    global_depth: u32,
+    directory: &[u64],
+    page_lens: &[u32],
+) -> io::Result<()> {
+    let mut tmp_path = OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&encode(global_depth, directory, page_lens))?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    sync_parent(path)
+}
+
+/// Sync the parent directory of `path`, so that a file renamed to `path`
+/// is durable.
+pub(crate) fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Remove the checkpoint at `path`, if any.
+pub(crate) fn remove_checkpoint(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+impl Checkpoint {
+    /// Decode a checkpoint, return `None` if it is malformed.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let (body, checksum) = buf.split_last_chunk::<4>()?;
+        if body.len() < HEADER_LEN
+            || crc32c(body) != u32::from_le_bytes(*checksum)
+            || &body[0..8] != MAGIC
+        {
+            return None;
+        }
+        let read_u32 = |at: usize| {
+            u32::from_le_bytes(body[at..at + 4].try_into().unwrap())
+        };
+        let read_u64 = |at: usize| {
+            u64::from_le_bytes(body[at..at + 8].try_into().unwrap())
+        };
+        if read_u32(8) != VERSION {
+            return None;
+        }
+        let global_depth = read_u32(12);
+        let page_count = usize::try_from(read_u64(16)).ok()?;
+        let n_buckets = usize::try_from(read_u64(24)).ok()?;
+        if global_depth >= usize::BITS
+            || body.len() - HEADER_LEN != n_buckets.checked_mul(BUCKET_LEN)?
+        {
+            return None;
+        }
+
+        let buckets = body[HEADER_LEN..]
+            .as_chunks::<BUCKET_LEN>()
+            .0
+            .iter()
+            .map(|bucket| {
+                let page_no =
+                    u64::from_le_bytes(bucket[0..8].try_into().unwrap());
+                let depth =
+                    u32::from_le_bytes(bucket[8..12].try_into().unwrap());
+                let records =
+                    u32::from_le_bytes(bucket[12..16].try_into().unwrap());
+                (page_no, depth, records)
+            })
+            .collect::<Vec<_>>();
+        // the buckets cover the directory exactly once, check it before
+        // allocating the directory
+        if buckets.iter().any(|(page_no, depth, _)| {
+            *depth > global_depth
+                || *page_no == 0
+                || *page_no >= page_count as u64
+        }) {
+            return None;
+        }
+        let covered = buckets
+            .iter()
+            .map(|(_, depth, _)| 1_u128 << (global_depth - depth))
+            .sum::<u128>();
+        if covered != 1 << global_depth {
+            return None;
+        }
+
+        let mut directory = Vec::with_capacity(1 << global_depth);
+        let mut page_lens = vec![0; page_count];
+        for (page_no, depth, records) in buckets {
+            let run = 1_usize << (global_depth - depth);
+            // the entries of a bucket start at a multiple of their number
+            if directory.len() % run != 0 {
+                return None;
+            }
+            directory.resize(directory.len() + run, page_no);
+            page_lens[page_no as usize] = records;
+        }
+
+        Some(Self {
+            global_depth,
+            directory,
+            page_lens,
+        })
+    }
+
+    /// Read the checkpoint at `path`, return `None` if it doesn't exist, or
+    /// is malformed.
+    pub(crate) fn read(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(buf) => Ok(Self::decode(&buf)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        // page 1 covers 00 and 01, page 3 covers 10, page 2 covers 11
+        let checkpoint = Checkpoint {
+            global_depth: 2,
+            directory: vec![1, 1, 3, 2],
+            page_lens: vec![0, 10, 20, 30],
+        };
+        let buf = encode(
+            checkpoint.global_depth,
+            &checkpoint.directory,
+            &checkpoint.page_lens,
+        );
+        assert_eq!(buf.len(), HEADER_LEN + 3 * BUCKET_LEN + 4);
+        assert_eq!(Checkpoint::decode(&buf), Some(checkpoint));
+
+        // corrupted or truncated
+        let mut corrupted = buf.clone();
+        corrupted[HEADER_LEN] ^= 1;
+        assert_eq!(Checkpoint::decode(&corrupted), None);
+        assert_eq!(Checkpoint::decode(&buf[..buf.len() - 1]), None);
+        assert_eq!(Checkpoint::decode(&[]), None);
+    }
+}
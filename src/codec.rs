@@ -0,0 +1,292 @@
+//! Encoding of the keys and values stored by
+//! [`DiskHashMap`](crate::DiskHashMap).
+//!
+//! A map encodes its keys and values with a [`Codec`], which is
+//! [`DefaultCodec`] by default, encoding the types implementing [`Encode`].
+//! A custom codec can store types that don't implement [`Encode`], e.g., the
+//! types of other crates, or encode them differently.
+//!
+//! Unlike the map, which requires the `disk` feature, this module is always
+//! available, as [`Encode`] also encodes the entries of snapshots and
+//! streams.
+
+use std::io;
+
+/// A type that can be stored in a [`DiskHashMap`](crate::DiskHashMap).
+///
+/// The encoding of a key should be canonical, i.e., equal keys are encoded
+/// into the same bytes, as the map hashes and compares the encoded keys.
+pub trait Encode: Sized {
+    /// Identifier of the encoding, which is recorded in the file header of a
+    /// [`DiskHashMap`](crate::DiskHashMap) and checked when the file is
+    /// opened, so that a file isn't read as another key or value type.
+    ///
+    /// The built-in implementations use the identifiers from 1 to 255, the
+    /// default is 0.
+    const CODEC_ID: u32 = 0;
+
+    /// Append the encoding of `self` to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decode a value from `bytes`, which are the bytes appended by
+    /// [`encode()`](Encode::encode).
+    fn decode(bytes: &[u8]) -> io::Result<Self>;
+}
+
+/// The encoding of the keys `K` and values `V` of a
+/// [`DiskHashMap`](crate::DiskHashMap).
+///
+/// The encoding of a key should be canonical, i.e., equal keys are encoded
+/// into the same bytes, as the map hashes and compares the encoded keys. An
+/// encoded key or value may be of a fixed or variable length, as the map
+/// records the length of every one of them, like
+/// [`encode_entry()`](Codec::encode_entry) does.
+pub trait Codec<K, V> {
+    /// Identifier of the key encoding, which is recorded in the file header
+    /// of a [`DiskHashMap`](crate::DiskHashMap) and checked when the file is
+    /// opened, see [`Encode::CODEC_ID`].
+    const KEY_CODEC_ID: u32 = 0;
+    /// Identifier of the value encoding, see
+    /// [`KEY_CODEC_ID`](Codec::KEY_CODEC_ID).
+    const VALUE_CODEC_ID: u32 = 0;
+
+    /// Append the encoding of `key` to `buf`.
+    fn encode_key(&self, key: &K, buf: &mut Vec<u8>);
+
+    /// Decode a key from `bytes`, which are the bytes appended by
+    /// [`encode_key()`](Codec::encode_key).
+    fn decode_key(&self, bytes: &[u8]) -> io::Result<K>;
+
+    /// Append the encoding of `value` to `buf`.
+    fn encode_value(&self, value: &V, buf: &mut Vec<u8>);
+
+    /// Decode a value from `bytes`, which are the bytes appended by
+    /// [`encode_value()`](Codec::encode_value).
+    fn decode_value(&self, bytes: &[u8]) -> io::Result<V>;
+
+    /// Append an entry to `buf`, as the length of the encoded key (4 bytes,
+    /// little endian), the encoded key, the length of the encoded value and
+    /// the encoded value.
+    ///
+    /// # Panic
+    /// The encoded key and value should be shorter than 4 GiB.
+    fn encode_entry(&self, key: &K, value: &V, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.extend_from_slice(&[0; 4]);
+        self.encode_key(key, buf);
+        let len = u32::try_from(buf.len() - start - 4)
+            .expect("encoded key is too large");
+        buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+
+        let start = buf.len();
+        buf.extend_from_slice(&[0; 4]);
+        self.encode_value(value, buf);
+        let len = u32::try_from(buf.len() - start - 4)
+            .expect("encoded value is too large");
+        buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    /// Decode an entry appended by [`encode_entry()`](Codec::encode_entry)
+    /// from the start of `bytes`, return it along with the bytes following
+    /// it.
+    fn decode_entry<'a>(
+        &self,
+        bytes: &'a [u8],
+    ) -> io::Result<(K, V, &'a [u8])> {
+        let (key, rest) = split_prefixed(bytes)?;
+        let (value, rest) = split_prefixed(rest)?;
+
+        Ok((self.decode_key(key)?, self.decode_value(value)?, rest))
+    }
+}
+
+/// Split the bytes prefixed by their length from the start of `bytes`,
+/// return them along with the bytes following them.
+fn split_prefixed(bytes: &[u8]) -> io::Result<(&[u8], &[u8])> {
+    let (len, rest) = bytes
+        .split_first_chunk::<4>()
+        .ok_or(io::ErrorKind::UnexpectedEof)?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(rest.split_at(len))
+}
+
+/// The codec encoding the keys and values with their [`Encode`]
+/// implementations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DefaultCodec;
+
+impl<K: Encode, V: Encode> Codec<K, V> for DefaultCodec {
+    const KEY_CODEC_ID: u32 = K::CODEC_ID;
+    const VALUE_CODEC_ID: u32 = V::CODEC_ID;
+
+    #[inline]
+    fn encode_key(&self, key: &K, buf: &mut Vec<u8>) {
+        key.encode(buf);
+    }
+
+    #[inline]
+    fn decode_key(&self, bytes: &[u8]) -> io::Result<K> {
+        K::decode(bytes)
+    }
+
+    #[inline]
+    fn encode_value(&self, value: &V, buf: &mut Vec<u8>) {
+        value.encode(buf);
+    }
+
+    #[inline]
+    fn decode_value(&self, bytes: &[u8]) -> io::Result<V> {
+        V::decode(bytes)
+    }
+}
+
+/// Return the error of decoding `bytes` that are not a valid `T`.
+pub(crate) fn invalid_data<T>(bytes: &[u8]) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "{} bytes are not a valid {}",
+            bytes.len(),
+            std::any::type_name::<T>()
+        ),
+    )
+}
+
+macro_rules! impl_encode_for_int {
+    ($($ty:ty = $id:literal),*) => {
+        $(
+            /// Encoded in little endian.
+            impl Encode for $ty {
+                const CODEC_ID: u32 = $id;
+
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> io::Result<Self> {
+                    bytes
+                        .try_into()
+                        .map(Self::from_le_bytes)
+                        .map_err(|_| invalid_data::<Self>(bytes))
+                }
+            }
+        )*
+    };
+}
+
+impl_encode_for_int!(
+    u8 = 1,
+    u16 = 2,
+    u32 = 3,
+    u64 = 4,
+    u128 = 5,
+    i8 = 6,
+    i16 = 7,
+    i32 = 8,
+    i64 = 9,
+    i128 = 10
+);
+
+/// Encoded as its UTF-8 bytes.
+impl Encode for String {
+    const CODEC_ID: u32 = 11;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| invalid_data::<Self>(bytes))
+    }
+}
+
+/// Encoded as is.
+impl Encode for Vec<u8> {
+    const CODEC_ID: u32 = 12;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Encoded as is.
+impl Encode for Box<[u8]> {
+    const CODEC_ID: u32 = 13;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        Ok(bytes.into())
+    }
+}
+
+/// Encoded as its UTF-8 bytes.
+impl Encode for Box<str> {
+    const CODEC_ID: u32 = 14;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        std::str::from_utf8(bytes)
+            .map(Into::into)
+            .map_err(|_| invalid_data::<Self>(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip<T: Encode + PartialEq + std::fmt::Debug>(value: T) {
+        let mut buf = Vec::new();
+        value.encode(&mut buf);
+        assert_eq!(T::decode(&buf).unwrap(), value);
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        round_trip(u8::MAX);
+        round_trip(-1_i64);
+        round_trip(u128::MAX - 1);
+        round_trip(String::from("extendable"));
+        round_trip(vec![0_u8, 1, 2]);
+        round_trip(Box::<[u8]>::from([3_u8, 4]));
+        round_trip(Box::<str>::from("hashing"));
+
+        assert!(u32::decode(&[0, 1]).is_err());
+        assert!(String::decode(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn codec_entries() {
+        let mut buf = Vec::new();
+        DefaultCodec.encode_entry(&1_u32, &String::from("one"), &mut buf);
+        DefaultCodec.encode_entry(&2_u32, &String::new(), &mut buf);
+        assert_eq!(buf.len(), (4 + 4 + 4 + 3) + (4 + 4 + 4));
+
+        let (key, value, rest): (u32, String, _) =
+            DefaultCodec.decode_entry(&buf).unwrap();
+        assert_eq!((key, value.as_str()), (1, "one"));
+        let (key, value, rest): (u32, String, _) =
+            DefaultCodec.decode_entry(rest).unwrap();
+        assert_eq!((key, value.as_str(), rest), (2, "", &[][..]));
+
+        // the first entry is truncated
+        let err = Codec::<u32, String>::decode_entry(&DefaultCodec, &buf[..14])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
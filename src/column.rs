@@ -0,0 +1,151 @@
+//! A column of the entries of a bucket.
+
+use allocator_api2::{alloc::Allocator, vec::Vec};
+use arrayvec::ArrayVec;
+use std::ops::{Deref, DerefMut};
+
+/// Elements of a column, up to `B` of them are stored inline, more are
+/// spilled to the allocator `A`.
+///
+/// Unlike a `Vec`, the methods that may spill take the allocator, which a
+/// column doesn't store until it has spilled.
+pub(crate) enum Column<T, const B: usize, A: Allocator> {
+    Inline(ArrayVec<T, B>),
+    Heap(Vec<T, A>),
+}
+
+impl<T, const B: usize, A: Allocator + Clone> Column<T, B, A> {
+    /// Create an empty column with room for `capacity` elements, which are
+    /// allocated from `alloc` if they don't fit inline.
+    pub(crate) fn with_capacity_in(capacity: usize, alloc: &A) -> Self {
+        if capacity <= B {
+            Column::Inline(ArrayVec::new())
+        } else {
+            Column::Heap(Vec::with_capacity_in(capacity, alloc.clone()))
+        }
+    }
+
+    /// Return the number of elements the column can hold without
+    /// allocating.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        match self {
+            Column::Inline(_) => B,
+            Column::Heap(vec) => vec.capacity(),
+        }
+    }
+
+    /// Insert `value` at `idx`, spilling the elements to `alloc` if the
+    /// inline storage is full.
+    pub(crate) fn insert(&mut self, idx: usize, value: T, alloc: &A) {
+        match self {
+            Column::Inline(array) if array.is_full() => {
+                let mut vec =
+                    Vec::with_capacity_in((2 * B).max(4), alloc.clone());
+                vec.extend(array.drain(..));
+                vec.insert(idx, value);
+                *self = Column::Heap(vec);
+            }
+            Column::Inline(array) => array.insert(idx, value),
+            Column::Heap(vec) => vec.insert(idx, value),
+        }
+    }
+
+    /// Remove and return the element at `idx`, the last element takes its
+    /// place.
+    pub(crate) fn swap_remove(&mut self, idx: usize) -> T {
+        match self {
+            Column::Inline(array) => array.swap_remove(idx),
+            Column::Heap(vec) => vec.swap_remove(idx),
+        }
+    }
+
+    /// Remove and return the element at `idx`, shifting the elements after
+    /// it.
+    pub(crate) fn remove(&mut self, idx: usize) -> T {
+        match self {
+            Column::Inline(array) => array.remove(idx),
+            Column::Heap(vec) => vec.remove(idx),
+        }
+    }
+
+    /// Remove and return the last element.
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        match self {
+            Column::Inline(array) => array.pop(),
+            Column::Heap(vec) => vec.pop(),
+        }
+    }
+
+    /// Clone the column, keeping its capacity, the spilled elements are
+    /// allocated from `alloc`.
+    pub(crate) fn clone_in(&self, alloc: &A) -> Self
+    where
+        T: Clone,
+    {
+        let mut clone = Self::with_capacity_in(self.capacity(), alloc);
+        match &mut clone {
+            Column::Inline(array) => array.extend(self.iter().cloned()),
+            Column::Heap(vec) => vec.extend(self.iter().cloned()),
+        }
+        clone
+    }
+}
+
+impl<T, const B: usize, A: Allocator> Deref for Column<T, B, A> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        match self {
+            Column::Inline(array) => array,
+            Column::Heap(vec) => vec,
+        }
+    }
+}
+
+impl<T, const B: usize, A: Allocator> DerefMut for Column<T, B, A> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self {
+            Column::Inline(array) => array,
+            Column::Heap(vec) => vec,
+        }
+    }
+}
+
+impl<T, const B: usize, A: Allocator> IntoIterator for Column<T, B, A> {
+    type Item = T;
+    type IntoIter = IntoIter<T, B, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            Column::Inline(array) => IntoIter::Inline(array.into_iter()),
+            Column::Heap(vec) => IntoIter::Heap(vec.into_iter()),
+        }
+    }
+}
+
+/// An owning iterator over the elements of a [`Column`].
+pub(crate) enum IntoIter<T, const B: usize, A: Allocator> {
+    Inline(arrayvec::IntoIter<T, B>),
+    Heap(allocator_api2::vec::IntoIter<T, A>),
+}
+
+impl<T, const B: usize, A: Allocator> Iterator for IntoIter<T, B, A> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            IntoIter::Inline(iter) => iter.next(),
+            IntoIter::Heap(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            IntoIter::Inline(iter) => iter.size_hint(),
+            IntoIter::Heap(iter) => iter.size_hint(),
+        }
+    }
+}
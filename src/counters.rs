@@ -0,0 +1,110 @@
+//! Counters of the structural maintenance of a [`HashMap`].
+
+use crate::{
+    hasher::{DirectoryMapper, KeyHasher},
+    map::HashMap,
+};
+use allocator_api2::alloc::Allocator;
+
+/// Counters of the structural maintenance of a [`HashMap`], returned by
+/// [`HashMap::counters()`], for attributing time to splits and merges
+/// rather than to probing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub(crate) splits: u64,
+    pub(crate) cascading_splits: u64,
+    pub(crate) merges: u64,
+    pub(crate) moved_entries: u64,
+}
+
+impl Counters {
+    /// Return the number of bucket splits.
+    #[inline]
+    pub fn splits(&self) -> u64 {
+        self.splits
+    }
+
+    /// Return the number of insertions that split more than one bucket,
+    /// because the bucket where the new entry goes was still full after a
+    /// split.
+    #[inline]
+    pub fn cascading_splits(&self) -> u64 {
+        self.cascading_splits
+    }
+
+    /// Return the number of bucket merges.
+    #[inline]
+    pub fn merges(&self) -> u64 {
+        self.merges
+    }
+
+    /// Return the number of entries moved to another bucket by splits and
+    /// merges, the entries are moved with their cached hash values, without
+    /// rehashing their keys.
+    #[inline]
+    pub fn moved_entries(&self) -> u64 {
+        self.moved_entries
+    }
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> HashMap<K, V, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Return the counters of the splits and merges since the map was
+    /// created, or since the last [`reset_counters()`](Self::reset_counters).
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::HashMap;
+    ///
+    /// let mut map = HashMap::with_bucket_capacity(4);
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    /// assert!(map.counters().splits() > 0);
+    ///
+    /// map.reset_counters();
+    /// assert_eq!(map.counters().splits(), 0);
+    /// ```
+    #[inline]
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Reset the counters returned by [`counters()`](Self::counters) to 0.
+    #[inline]
+    pub fn reset_counters(&mut self) {
+        self.counters = Counters::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counters_track_splits_and_merges() {
+        let mut map = HashMap::with_bucket_capacity(4);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        let counters = *map.counters();
+        // every split adds a bucket
+        assert_eq!(counters.splits() as usize, map.bucket_count() - 1);
+        assert!(counters.cascading_splits() <= counters.splits());
+        assert_eq!(counters.merges(), 0);
+        assert!(counters.moved_entries() > 0);
+
+        map.reset_counters();
+        let buckets = map.bucket_count();
+        for i in 0..990 {
+            map.remove(&i);
+        }
+        let counters = *map.counters();
+        assert_eq!(counters.splits(), 0);
+        assert_eq!(counters.merges() as usize, buckets - map.bucket_count());
+        assert!(counters.moved_entries() > 0);
+    }
+}
@@ -0,0 +1,165 @@
+//! The directory of a [`HashMap`](crate::HashMap).
+
+use crate::bucket::BucketIdx;
+use allocator_api2::{
+    alloc::{Allocator, Global},
+    vec::Vec,
+};
+use std::{
+    fmt::{Debug, Formatter},
+    ops::{Deref, DerefMut},
+};
+
+/// The directory, `2^global_depth` slots storing the indexes of the buckets
+/// they point to.
+///
+/// The single slot of global depth 0 is stored inline, so that a map isn't
+/// allocated a directory until its first split. The other directories are
+/// allocated from `A`.
+#[derive(Clone)]
+pub(crate) enum Directory<A: Allocator = Global> {
+    /// The slot of global depth 0.
+    Inline([BucketIdx; 1]),
+    /// The slots of global depth 1 or more.
+    Heap(Vec<BucketIdx, A>),
+}
+
+impl<A: Allocator + Clone> Directory<A> {
+    /// Return the number of slots the allocated storage can hold, which is 0
+    /// if nothing is allocated.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        match self {
+            Directory::Inline(_) => 0,
+            Directory::Heap(slots) => slots.capacity(),
+        }
+    }
+
+    /// Double the directory, slot `idx` becomes the slots `2 * idx` and
+    /// `2 * idx + 1`, both of them point to the bucket `idx` pointed to.
+    ///
+    /// The directory of a single slot is allocated from `alloc`.
+    pub(crate) fn double(&mut self, alloc: &A) {
+        match self {
+            Directory::Inline([bucket_idx]) => {
+                let mut slots = Vec::with_capacity_in(2, alloc.clone());
+                slots.extend([*bucket_idx; 2]);
+                *self = Directory::Heap(slots);
+            }
+            Directory::Heap(slots) => {
+                // The slots are expanded in place from the back, so that
+                // every old slot is read before getting overwritten.
+                //
+                // [0, 1] => [00, 01, 10, 11] ([0, 1] => [0, 0, 1, 1])
+                let old_len = slots.len();
+                slots.resize(old_len * 2, BucketIdx::new(0));
+                for idx in (0..old_len).rev() {
+                    let bucket_idx = slots[idx];
+                    slots[2 * idx] = bucket_idx;
+                    slots[2 * idx + 1] = bucket_idx;
+                }
+            }
+        }
+    }
+
+    /// Halve the directory, slot `idx` takes the bucket of the slots
+    /// `2 * idx` and `2 * idx + 1`, which should point to the same bucket.
+    ///
+    /// The storage is kept, except that the directory of a single slot is
+    /// stored inline again.
+    ///
+    /// # Panic
+    /// The directory should have more than 1 slot.
+    pub(crate) fn halve(&mut self) {
+        let Directory::Heap(slots) = self else {
+            panic!("Directory::halve(): the directory has a single slot");
+        };
+        let new_len = slots.len() / 2;
+        for idx in 0..new_len {
+            let bucket_idx = slots[2 * idx];
+            debug_assert_eq!(bucket_idx, slots[2 * idx + 1]);
+            slots[idx] = bucket_idx;
+        }
+        slots.truncate(new_len);
+        if new_len == 1 {
+            *self = Directory::Inline([slots[0]]);
+        }
+    }
+
+    /// Shrink the allocated storage, keeping room for `min_len` slots.
+    pub(crate) fn shrink_to(&mut self, min_len: usize) {
+        if let Directory::Heap(slots) = self {
+            slots.shrink_to(min_len);
+        }
+    }
+}
+
+impl<A: Allocator> From<Vec<BucketIdx, A>> for Directory<A> {
+    /// Take `slots` as the directory, a single slot is stored inline.
+    fn from(slots: Vec<BucketIdx, A>) -> Self {
+        match slots.as_slice() {
+            [bucket_idx] => Directory::Inline([*bucket_idx]),
+            _ => Directory::Heap(slots),
+        }
+    }
+}
+
+impl<A: Allocator> Deref for Directory<A> {
+    type Target = [BucketIdx];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Directory::Inline(slot) => slot,
+            Directory::Heap(slots) => slots,
+        }
+    }
+}
+
+impl<A: Allocator> DerefMut for Directory<A> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Directory::Inline(slot) => slot,
+            Directory::Heap(slots) => slots,
+        }
+    }
+}
+
+impl<A: Allocator> PartialEq for Directory<A> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<A: Allocator> Eq for Directory<A> {}
+
+impl<A: Allocator> Debug for Directory<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn directory_double_and_halve() {
+        let mut directory = Directory::Inline([BucketIdx::new(7)]);
+        assert_eq!(directory.capacity(), 0);
+
+        directory.double(&Global);
+        assert_eq!(*directory, [BucketIdx::new(7); 2]);
+        directory[1] = BucketIdx::new(3);
+        directory.double(&Global);
+        assert_eq!(*directory, [7, 7, 3, 3].map(BucketIdx::new));
+
+        directory.halve();
+        assert_eq!(*directory, [7, 3].map(BucketIdx::new));
+        directory[1] = BucketIdx::new(7);
+        directory.halve();
+        assert!(matches!(directory, Directory::Inline([idx]) if idx == 7));
+        assert_eq!(directory.capacity(), 0);
+    }
+}
@@ -0,0 +1,2221 @@
+#[cfg(feature = "mmap")]
+use crate::store::MmapStore;
+use crate::{
+    checkpoint::{
+        checkpoint_path, remove_checkpoint, sync_parent, write_checkpoint,
+        Checkpoint,
+    },
+    codec::{Codec, DefaultCodec, Encode},
+    flusher::{Flusher, Shared},
+    hasher::FixedState,
+    options::DiskOptions,
+    page::{
+        is_valid_page_size, prefix, Compression, Page, Record,
+        DEFAULT_PAGE_SIZE,
+    },
+    store::{
+        read_at, set_open_flags, write_at, BucketStore, FileStore, WriteMode,
+    },
+    stream::{read_record, write_record},
+    wal::{wal_path, Durability, EntryKind, Wal},
+};
+use parking_lot::Mutex;
+use std::{
+    ffi::OsString,
+    fmt::{Debug, Formatter},
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, Hasher},
+    io::{self, Read, Write},
+    marker::PhantomData,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Magic bytes at the start of the file.
+const MAGIC: &[u8; 8] = b"EXTHMAP\0";
+/// Version of the file format.
+const VERSION: u32 = 3;
+/// Length of the file header in bytes, the rest of page 0 is zeroed.
+const HEADER_LEN: usize = 32;
+/// Length of the write-ahead log in bytes, beyond which the map checkpoints.
+pub(crate) const WAL_CHECKPOINT_LEN: u64 = 16 << 20;
+/// Number of splits after which the map checkpoints its directory.
+const DIRECTORY_CHECKPOINT_SPLITS: u32 = 64;
+
+/// Return the page size recorded in the header of `file`, if it is a valid
+/// one.
+fn read_page_size(file: &File) -> io::Result<Option<usize>> {
+    let mut buf = [0; HEADER_LEN];
+    if file.metadata()?.len() < HEADER_LEN as u64 {
+        return Ok(None);
+    }
+    read_at(file, 0, &mut buf)?;
+    let page_size = u32::from_le_bytes(buf[12..16].try_into().unwrap());
+
+    Ok(
+        (&buf[0..8] == MAGIC && is_valid_page_size(page_size as usize))
+            .then_some(page_size as usize),
+    )
+}
+
+/// Return the hasher id of `hash_builder`, which is recorded in the file
+/// header.
+fn hasher_id<S: BuildHasher>(hash_builder: &S) -> u64 {
+    hash_bytes(hash_builder, MAGIC)
+}
+
+/// Hash `bytes`, e.g., an encoded key, by `hash_builder`.
+///
+/// The bytes are written as they are by [`Hasher::write()`], rather than
+/// hashed by the [`Hash`](std::hash::Hash) implementation of `[u8]`, whose
+/// output is not specified by std.
+fn hash_bytes<S: BuildHasher>(hash_builder: &S, bytes: &[u8]) -> u64 {
+    let mut hasher = hash_builder.build_hasher();
+    hasher.write(bytes);
+
+    hasher.finish()
+}
+
+/// Upgrade the file header `header` of format version `version` to the next
+/// version, return the next version.
+///
+/// Every upgrade is a step of a forward migration, a file is upgraded to
+/// [`VERSION`] by applying the steps from its version one by one.
+fn upgrade_header(
+    header: &mut [u8; HEADER_LEN],
+    version: u32,
+    hasher_id: u64,
+    key_codec_id: u32,
+    value_codec_id: u32,
+) -> io::Result<u32> {
+    match version {
+        // version 1 has pages of 4 KiB, which are recorded in the header
+        // already, the buckets of version 2 are the same
+        1 => {
+            header[16..24].copy_from_slice(&hasher_id.to_le_bytes());
+            header[24..28].copy_from_slice(&key_codec_id.to_le_bytes());
+            header[28..32].copy_from_slice(&value_codec_id.to_le_bytes());
+        }
+        // the header of version 3 is the same, its pages are checksummed by
+        // `add_checksums()`
+        2 => {}
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported format version",
+            ))
+        }
+    }
+    header[8..12].copy_from_slice(&(version + 1).to_le_bytes());
+
+    Ok(version + 1)
+}
+
+/// Rewrite the file at `path`, opened as `file`, whose bucket pages have no
+/// checksums, i.e., of a version before 3, into a file whose header is
+/// `header` and pages are checksummed, replacing it.
+///
+/// A page left no room for its checksum is divided like a split, the
+/// directory is rebuilt from the pages when the map is opened afterwards.
+fn add_checksums(
+    path: &Path,
+    file: &File,
+    header: &[u8; HEADER_LEN],
+) -> io::Result<()> {
+    let page_size =
+        u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+    if !is_valid_page_size(page_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported page size",
+        ));
+    }
+
+    // the log holds images of pages without checksums, write them before
+    // the pages are rewritten, and empty it
+    let mut wal = Wal::open(&wal_path(path))?;
+    wal.replay(page_size, |page_no, image| {
+        write_at(file, page_no * page_size as u64, image)
+    })?;
+    file.sync_all()?;
+    wal.truncate()?;
+    remove_checkpoint(&checkpoint_path(path))?;
+
+    let mut tmp_path = OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    let tmp = File::create(&tmp_path)?;
+    let written = write_checksummed(file, &tmp, header, page_size);
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written?;
+    fs::rename(&tmp_path, path)?;
+
+    sync_parent(path)
+}
+
+/// Write the header `header` and the pages of `file`, which are `page_size`
+/// bytes long and have no checksums, checksummed to `tmp`, see
+/// [`add_checksums()`].
+fn write_checksummed(
+    file: &File,
+    tmp: &File,
+    header: &[u8; HEADER_LEN],
+    page_size: usize,
+) -> io::Result<()> {
+    let mut buf = vec![0; page_size];
+    buf[..HEADER_LEN].copy_from_slice(header);
+    write_at(tmp, 0, &buf)?;
+    let page_count = file.metadata()?.len() / page_size as u64;
+    let mut next_page_no = page_count;
+    for page_no in 1..page_count {
+        read_at(file, page_no * page_size as u64, &mut buf)?;
+        let page = Page::decode(&buf)?;
+        if !page.records.iter().all(|r| r.fits_in_page(page_size)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "entry doesn't fit into a checksummed page",
+            ));
+        }
+        let mut buckets = Vec::new();
+        match page.encode_image(Compression::None, page_size) {
+            Some(image) => buckets.push((page, image)),
+            None => divide(page, Compression::None, page_size, &mut buckets)?,
+        }
+        // the last bucket takes the place of the page, like a split
+        let (_, image) = buckets.pop().unwrap();
+        write_at(tmp, page_no * page_size as u64, &image)?;
+        for (_, image) in buckets {
+            write_at(tmp, next_page_no * page_size as u64, &image)?;
+            next_page_no += 1;
+        }
+    }
+
+    tmp.sync_all()
+}
+
+/// Divide the records of `page` between its two halves, and those of a half
+/// whose page doesn't fit further, append every bucket along with its image
+/// compressed by `compression` to `buckets`, a page is `page_size` bytes
+/// long.
+///
+/// A half usually fits, but a compressed one may not.
+fn divide(
+    page: Page,
+    compression: Compression,
+    page_size: usize,
+    buckets: &mut Vec<(Page, Vec<u8>)>,
+) -> io::Result<()> {
+    if page.depth == u64::BITS {
+        return Err(io::Error::other(
+            "too many entries with the same hash value",
+        ));
+    }
+
+    let depth = page.depth + 1;
+    let mut low = Page::new(depth, page.prefix << 1);
+    let mut high = Page::new(depth, (page.prefix << 1) | 1);
+    for record in page.records {
+        if prefix(record.hash, depth) & 1 == 1 {
+            high.records.push(record);
+        } else {
+            low.records.push(record);
+        }
+    }
+
+    for half in [high, low] {
+        match half.encode_image(compression, page_size) {
+            Some(image) => buckets.push((half, image)),
+            None => divide(half, compression, page_size, buckets)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// An extendible hash map stored in a file, which requires the `disk`
+/// feature.
+///
+/// The file is a sequence of pages, page 0 is the file header, and every
+/// other page is a bucket. A bucket page records its local depth and the
+/// prefix shared by the hash values of its entries, so the directory, which
+/// maps prefixes to bucket pages, can always be rebuilt from the bucket
+/// pages.
+///
+/// Keys and values are encoded by the [`Codec`] `C`, which defaults to
+/// [`DefaultCodec`], encoding them in their [`Encode`]d form, and the bytes
+/// of the encoded keys are hashed by `S`, which defaults to [`FixedState`],
+/// as the hash values must not change between runs, or across Rust
+/// releases. Every encoded key and value is
+/// stored along with its length, so they can be of fixed or variable
+/// lengths. An entry should fit into a page.
+///
+/// # File header
+/// The file header records:
+///
+/// | field          | size |
+/// |----------------|------|
+/// | magic          | 8    |
+/// | format version | 4    |
+/// | page size      | 4    |
+/// | hasher id      | 8    |
+/// | key codec id   | 4    |
+/// | value codec id | 4    |
+///
+/// The page size is a power of two from 4 KiB to 64 KiB, chosen when the
+/// file is created by [`open_with_page_size()`](Self::open_with_page_size),
+/// and defaults to 4 KiB. The hasher id is the hash value of the magic bytes,
+/// and the codec ids are the [`KEY_CODEC_ID`](Codec::KEY_CODEC_ID) and
+/// [`VALUE_CODEC_ID`](Codec::VALUE_CODEC_ID) of `C`, which are checked when
+/// the file is opened, so that a file isn't opened with another hasher, key
+/// encoding or value encoding.
+///
+/// # Format version
+/// The format version changes with the layout of the file, a file of an
+/// older version can't be opened, and should be upgraded by
+/// [`migrate()`](Self::migrate) first. Version 1 doesn't record the hasher
+/// id and the codec ids, they are recorded by the migration, so a file
+/// should be migrated with the hasher and codec it was created with.
+///
+/// The pages of versions 1 and 2 have no checksums, the migration writes
+/// their log to the file, and rewrites the pages with checksums into a new
+/// file replacing it. A page left no room for its checksum is divided into
+/// new buckets, and the compressed pages are rewritten uncompressed.
+///
+/// # Checksums
+/// The last 4 bytes of every bucket page are the CRC-32C checksum of the
+/// rest of it, which is verified whenever the page is read from the store,
+/// a page whose checksum mismatches is reported as a
+/// [`CorruptPage`](crate::CorruptPage) error.
+/// [`verify()`](Self::verify) scans the whole file, and reports the ranges
+/// of the damaged pages. The log entries and the directory checkpoint are
+/// checksummed as well, a damaged log entry ends the replay, and a damaged
+/// checkpoint is ignored, the directory is rebuilt from the pages instead.
+///
+/// # Durability
+/// Every modification, including a split, is appended to a write-ahead log,
+/// the file at the same path with `.wal` appended, as the new images of the
+/// pages it modifies, before the pages are written to the file. The log is
+/// synced following the [`Durability`] policy, which is
+/// [`Always`](Durability::Always) by default, and the pages are held in
+/// memory until their log entries are synced.
+///
+/// After a crash, [`recover()`](Self::recover) replays the log, bringing the
+/// pages back to the state after the last synced modification. Opening a map
+/// that isn't read-only recovers it as well.
+///
+/// [`sync()`](Self::sync) checkpoints the map: it writes all the pages,
+/// syncs the file, and empties the log, which also happens when the log
+/// grows beyond 16 MiB.
+///
+/// The durability policy, and how the pages are written to the file, see
+/// [`WriteMode`], can be chosen when the map is opened by [`DiskOptions`].
+///
+/// # Background flushing
+/// Once their log entries are synced, the modified pages are written to the
+/// file by the modification itself, unless a background flusher is started
+/// by [`start_flusher()`](Self::start_flusher), or by
+/// [`DiskOptions::flush_interval()`]. The pages are then left dirty, see
+/// [`dirty_pages()`](Self::dirty_pages), and the flusher writes them, and
+/// empties the log once it grows beyond 16 MiB, so a modification is not
+/// blocked on the page writes. If the flusher falls behind and the log grows
+/// beyond 32 MiB, the modification checkpoints the map itself.
+///
+/// [`flush()`](Self::flush) writes the dirty pages at once, and
+/// [`sync_all()`](Self::sync_all) checkpoints the map. An error the flusher
+/// stops at is returned by the next modification.
+///
+/// # Page cache
+/// The bucket pages are read on demand, only the directory is held in
+/// memory, so a map can be bigger than the memory, and looking up a key
+/// reads exactly one page. With
+/// [`set_cache_capacity()`](Self::set_cache_capacity), or
+/// [`DiskOptions::cache_capacity()`], the recently used pages are also
+/// cached, up to the capacity, which bounds the cached clean pages and the
+/// dirty pages together. Once the capacity is reached, the least recently
+/// used clean page is evicted, or if every page is dirty, the dirty pages
+/// are written back. The cache is disabled by default, and a map opened
+/// read-only should not cache pages if its file is updated.
+///
+/// # Directory checkpoint
+/// Rebuilding the directory reads every page of the file, so the directory
+/// is also checkpointed, along with the number of entries of every bucket,
+/// to the file at the same path with `.dir` appended. It is much smaller
+/// than the pages, and is checkpointed by every [`sync()`](Self::sync), and
+/// by [`checkpoint_directory()`](Self::checkpoint_directory), which happens
+/// every 64 splits, while the pages are written lazily.
+///
+/// Opening the map loads the directory checkpoint, and replays the log on
+/// top of it: the header of every replayed page re-points the directory
+/// entries it covers, as the page may be newer than the checkpoint. If the
+/// checkpoint is missing, malformed, or doesn't match the file, it is stale,
+/// and the directory is rebuilt from the bucket pages instead.
+///
+/// # Storage
+/// The pages are kept in a [`BucketStore`] `T`, which is a [`FileStore`] for
+/// the maps opened by path. A map can also run over a store users provide,
+/// e.g., a [`MemoryStore`](crate::MemoryStore), object storage or a block
+/// device, by [`with_store()`](Self::with_store). Such a map has no
+/// write-ahead log or directory checkpoint, its pages are written to the
+/// store as they are modified, and are made durable by
+/// [`sync()`](Self::sync), which syncs the store. Stores are specific to
+/// this map, [`HashMap`](crate::HashMap) doesn't run over them.
+///
+/// # Memory map
+/// With the `mmap` feature, which is only available on Unix, a map opened by
+/// [`open_mmap()`](Self::open_mmap) keeps its pages in an
+/// [`MmapStore`](crate::MmapStore), which reads the pages through a shared
+/// memory map of the file rather than read syscalls. The pages are read in
+/// place, so opening such a map is unsafe: while it's open, the file should
+/// only be modified through the map, and should not be truncated.
+///
+/// # Read-only sharing
+/// [`open_read_only()`](Self::open_read_only), or
+/// [`open_mmap_read_only()`](Self::open_mmap_read_only) with the `mmap`
+/// feature, opens a map that can't be modified, so that several processes
+/// can share one file, e.g., a file produced by a writer and served by many
+/// readers. A read-only map opens no write-ahead log and takes no lock, and
+/// every modification returns a
+/// [`PermissionDenied`](io::ErrorKind::PermissionDenied) error.
+///
+/// The file should not be modified while it is opened read-only, a reader
+/// may see pages being written, which is reported as an
+/// [`InvalidData`](io::ErrorKind::InvalidData) error, and doesn't see the
+/// buckets added after it opens the file. For a reader whose pages are
+/// memory mapped, modifying the file is undefined behavior. A map whose log
+/// isn't empty can't be opened read-only, it should be recovered first.
+///
+/// # Compression
+/// With the `lz4` or `zstd` feature, the bucket pages can be compressed, see
+/// [`Compression`] and [`set_compression()`](Self::set_compression). A
+/// compressed page holds more entries than an uncompressed one, so the map
+/// splits less, and the file and the log are smaller, at the cost of
+/// compressing a page on every modification, and decompressing it on every
+/// read. A split bucket is divided until the pages of its buckets fit.
+///
+/// # Coalescence
+/// Buckets are never coalesced, so removals don't shrink the file.
+pub struct DiskHashMap<K, V, S = FixedState, T = FileStore, C = DefaultCodec> {
+    /// Store of the pages, along with the write-ahead log and the pages not
+    /// written to the store yet, which are shared with the flusher
+    shared: Arc<Mutex<Shared<T>>>,
+    /// Background flusher, if it is started
+    flusher: Option<Flusher>,
+    /// Path of the directory checkpoint, `None` if the map is not opened by
+    /// path
+    checkpoint_path: Option<PathBuf>,
+    /// Size of a page in bytes
+    page_size: usize,
+    /// Global depth
+    global_depth: u32,
+    /// Directory entries, `directory[i]` is the number of the bucket page
+    /// that entry `i` points to.
+    directory: Vec<u64>,
+    /// Number of entries
+    len: usize,
+    /// Number of entries of every bucket page, indexed by the page number
+    page_lens: Vec<u32>,
+    /// Number of splits since the last directory checkpoint
+    splits: u32,
+    /// Hash builder
+    hash_builder: S,
+    /// Codec of the keys and values
+    codec: C,
+    /// True if the map can't be modified
+    read_only: bool,
+    /// When the write-ahead log is synced
+    durability: Durability,
+    /// Compression of the written pages
+    compression: Compression,
+    marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V, S, T: BucketStore, C> Debug for DiskHashMap<K, V, S, T, C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DiskHashMap")
+            .field("len", &self.len)
+            .field("global depth", &self.global_depth)
+            .field("pages", &self.shared.lock().store.page_count())
+            .field("page size", &self.page_size)
+            .finish()
+    }
+}
+
+impl<K: Encode, V: Encode> DiskHashMap<K, V, FixedState> {
+    /// Open the map stored in the file at `path`, which is created if it
+    /// doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with_hasher(path, FixedState::default())
+    }
+
+    /// Open the map stored in the file at `path`, which is created with pages
+    /// of `page_size` bytes if it doesn't exist.
+    ///
+    /// # Error
+    /// An [`InvalidInput`](io::ErrorKind::InvalidInput) error is returned if
+    /// `page_size` is not a power of two from 4 KiB to 64 KiB, or the file
+    /// exists and has another page size.
+    pub fn open_with_page_size<P: AsRef<Path>>(
+        path: P,
+        page_size: usize,
+    ) -> io::Result<Self> {
+        Self::open_with_page_size_and_hasher(
+            path,
+            page_size,
+            FixedState::default(),
+        )
+    }
+
+    /// Upgrade the file at `path` to the current format version, return
+    /// true if it was of an older version, see the
+    /// [format version](Self#format-version) section.
+    ///
+    /// The file is upgraded in place, and its write-ahead log, if any, is
+    /// replayed when the map is opened afterwards.
+    ///
+    /// # Error
+    /// An [`InvalidData`](io::ErrorKind::InvalidData) error is returned if
+    /// the file is not a map, or of a newer version.
+    pub fn migrate<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+        Self::migrate_with_hasher(path, &FixedState::default())
+    }
+
+    /// Open the map stored in the file at `path` read-only, the file should
+    /// exist, see the [read-only sharing](Self#read-only-sharing) section.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_read_only_with_hasher(path, FixedState::default())
+    }
+
+    /// Recover the map stored in the file at `path` after a crash, by
+    /// replaying its write-ahead log, and open it, the file should exist.
+    pub fn recover<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::recover_with_hasher(path, FixedState::default())
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<K: Encode, V: Encode> DiskHashMap<K, V, FixedState, MmapStore> {
+    /// Open the map stored in the file at `path` with its pages memory
+    /// mapped, the file is created if it doesn't exist.
+    ///
+    /// # Safety
+    /// See [`MmapStore::open()`].
+    pub unsafe fn open_mmap<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        // SAFETY: guaranteed by the caller.
+        unsafe { Self::open_mmap_with_hasher(path, FixedState::default()) }
+    }
+
+    /// Open the map stored in the file at `path` read-only, with its pages
+    /// memory mapped, the file should exist.
+    ///
+    /// # Safety
+    /// See [`MmapStore::open()`], the file should not be modified at all
+    /// while the map is open.
+    pub unsafe fn open_mmap_read_only<P: AsRef<Path>>(
+        path: P,
+    ) -> io::Result<Self> {
+        // SAFETY: guaranteed by the caller.
+        unsafe {
+            Self::open_mmap_read_only_with_hasher(path, FixedState::default())
+        }
+    }
+}
+
+impl<K: Encode, V: Encode, S: BuildHasher> DiskHashMap<K, V, S> {
+    /// Open the map stored in the file at `path`, which is created if it
+    /// doesn't exist, `hash_builder` will be used to hash keys.
+    ///
+    /// A map should always be opened with the hash builder it was created
+    /// with.
+    pub fn open_with_hasher<P: AsRef<Path>>(
+        path: P,
+        hash_builder: S,
+    ) -> io::Result<Self> {
+        Self::open_with_options(
+            path,
+            DiskOptions::new().hasher(hash_builder),
+            FileStore::with_write_mode,
+        )
+    }
+
+    /// Open the map stored in the file at `path`, which is created with pages
+    /// of `page_size` bytes if it doesn't exist, `hash_builder` will be used
+    /// to hash keys.
+    pub fn open_with_page_size_and_hasher<P: AsRef<Path>>(
+        path: P,
+        page_size: usize,
+        hash_builder: S,
+    ) -> io::Result<Self> {
+        Self::open_with_options(
+            path,
+            DiskOptions::new().hasher(hash_builder).page_size(page_size),
+            FileStore::with_write_mode,
+        )
+    }
+
+    /// Open the map stored in the file at `path` read-only, the file should
+    /// exist, `hash_builder` will be used to hash keys.
+    pub fn open_read_only_with_hasher<P: AsRef<Path>>(
+        path: P,
+        hash_builder: S,
+    ) -> io::Result<Self> {
+        Self::open_with_options(
+            path,
+            DiskOptions::new().hasher(hash_builder).read_only(true),
+            FileStore::with_write_mode,
+        )
+    }
+
+    /// Recover the map stored in the file at `path` after a crash, by
+    /// replaying its write-ahead log, and open it, the file should exist,
+    /// `hash_builder` will be used to hash keys.
+    pub fn recover_with_hasher<P: AsRef<Path>>(
+        path: P,
+        hash_builder: S,
+    ) -> io::Result<Self> {
+        Self::open_with_options(
+            path,
+            DiskOptions::new().hasher(hash_builder).create(false),
+            FileStore::with_write_mode,
+        )
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<K: Encode, V: Encode, S: BuildHasher> DiskHashMap<K, V, S, MmapStore> {
+    /// Open the map stored in the file at `path` with its pages memory
+    /// mapped, the file is created if it doesn't exist, `hash_builder` will
+    /// be used to hash keys.
+    ///
+    /// # Safety
+    /// See [`open_mmap()`](Self::open_mmap).
+    pub unsafe fn open_mmap_with_hasher<P: AsRef<Path>>(
+        path: P,
+        hash_builder: S,
+    ) -> io::Result<Self> {
+        Self::open_with_options(
+            path,
+            DiskOptions::new().hasher(hash_builder),
+            // SAFETY: guaranteed by the caller.
+            |file, page_size, _| unsafe { MmapStore::new(file, page_size) },
+        )
+    }
+
+    /// Open the map stored in the file at `path` read-only, with its pages
+    /// memory mapped, the file should exist, `hash_builder` will be used to
+    /// hash keys.
+    ///
+    /// # Safety
+    /// See [`open_mmap_read_only()`](Self::open_mmap_read_only).
+    pub unsafe fn open_mmap_read_only_with_hasher<P: AsRef<Path>>(
+        path: P,
+        hash_builder: S,
+    ) -> io::Result<Self> {
+        Self::open_with_options(
+            path,
+            DiskOptions::new().hasher(hash_builder).read_only(true),
+            // SAFETY: guaranteed by the caller.
+            |file, page_size, _| unsafe { MmapStore::new(file, page_size) },
+        )
+    }
+}
+
+impl<K: Encode, V: Encode, S: BuildHasher, T: BucketStore>
+    DiskHashMap<K, V, S, T>
+{
+    /// Create a map over `store`, or open the map it holds if it isn't
+    /// empty, `hash_builder` will be used to hash keys.
+    ///
+    /// The map has no write-ahead log, see the [storage](Self#storage)
+    /// section.
+    ///
+    /// # Error
+    /// An [`InvalidInput`](io::ErrorKind::InvalidInput) error is returned if
+    /// the page size of `store` is not a power of two from 4 KiB to 64 KiB.
+    pub fn with_store(store: T, hash_builder: S) -> io::Result<Self> {
+        Self::with_store_and_codec(store, hash_builder, DefaultCodec)
+    }
+}
+
+impl<K, V, S: BuildHasher, C: Codec<K, V>> DiskHashMap<K, V, S, FileStore, C> {
+    /// Upgrade the file at `path` to the current format version, return
+    /// true if it was of an older version, `hash_builder` and `C` are
+    /// recorded in the file header if the older version doesn't record them,
+    /// see [`migrate()`](DiskHashMap::migrate).
+    pub fn migrate_with_hasher<P: AsRef<Path>>(
+        path: P,
+        hash_builder: &S,
+    ) -> io::Result<bool> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut header = [0; HEADER_LEN];
+        if file.metadata()?.len() < HEADER_LEN as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a DiskHashMap file",
+            ));
+        }
+        read_at(&file, 0, &mut header)?;
+        if &header[0..8] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a DiskHashMap file",
+            ));
+        }
+
+        let mut version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version == VERSION {
+            return Ok(false);
+        }
+        let checksummed = version >= 3;
+        while version < VERSION {
+            version = upgrade_header(
+                &mut header,
+                version,
+                hasher_id(hash_builder),
+                C::KEY_CODEC_ID,
+                C::VALUE_CODEC_ID,
+            )?;
+        }
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported format version",
+            ));
+        }
+        if checksummed {
+            write_at(&file, 0, &header)?;
+            file.sync_all()?;
+        } else {
+            add_checksums(path, &file, &header)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Open the map stored in the file at `path`, which is created if it
+    /// doesn't exist, `hash_builder` will be used to hash keys, and `codec`
+    /// to encode keys and values.
+    ///
+    /// A map should always be opened with the hash builder and the codec it
+    /// was created with.
+    pub fn open_with_hasher_and_codec<P: AsRef<Path>>(
+        path: P,
+        hash_builder: S,
+        codec: C,
+    ) -> io::Result<Self> {
+        Self::open_with_options(
+            path,
+            DiskOptions::new().hasher(hash_builder).codec(codec),
+            FileStore::with_write_mode,
+        )
+    }
+}
+
+impl<K, V, S: BuildHasher, T: BucketStore, C: Codec<K, V>>
+    DiskHashMap<K, V, S, T, C>
+{
+    /// Create a map over `store`, or open the map it holds if it isn't
+    /// empty, `hash_builder` will be used to hash keys, and `codec` to encode
+    /// keys and values, see [`with_store()`](DiskHashMap::with_store).
+    pub fn with_store_and_codec(
+        store: T,
+        hash_builder: S,
+        codec: C,
+    ) -> io::Result<Self> {
+        if !is_valid_page_size(store.page_size()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "page size should be a power of two from 4 KiB to 64 KiB",
+            ));
+        }
+
+        let mut map = Self::new(store, hash_builder, codec, None, None, false);
+        if map.shared.lock().store.page_count() == 0 {
+            map.create()?;
+        }
+        map.read_header(None)?;
+        map.load()?;
+
+        Ok(map)
+    }
+
+    /// Create a map over `store`, before its pages are read.
+    fn new(
+        store: T,
+        hash_builder: S,
+        codec: C,
+        checkpoint_path: Option<PathBuf>,
+        wal: Option<Wal>,
+        read_only: bool,
+    ) -> Self {
+        Self {
+            page_size: store.page_size(),
+            shared: Arc::new(Mutex::new(Shared::new(store, wal))),
+            flusher: None,
+            checkpoint_path,
+            global_depth: 0,
+            directory: vec![1],
+            len: 0,
+            page_lens: vec![0; 2],
+            splits: 0,
+            hash_builder,
+            codec,
+            read_only,
+            durability: Durability::default(),
+            compression: Compression::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Open the map stored in the file at `path` with `options`, the file is
+    /// used as a store by `new_store`.
+    pub(crate) fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        options: DiskOptions<S, C>,
+        new_store: fn(File, usize, WriteMode) -> io::Result<T>,
+    ) -> io::Result<Self> {
+        let page_size = options.page_size;
+        if page_size.is_some_and(|page_size| !is_valid_page_size(page_size)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "page size should be a power of two from 4 KiB to 64 KiB",
+            ));
+        }
+        let path = path.as_ref();
+        let read_only = options.read_only;
+        let mut open_options = OpenOptions::new();
+        open_options
+            .read(true)
+            .write(!read_only)
+            .create(options.create && !read_only)
+            .truncate(false);
+        set_open_flags(&mut open_options, options.write_mode)?;
+        let file = open_options.open(path)?;
+        let wal = if read_only {
+            // the log can't be replayed by a read-only map
+            match File::open(wal_path(path)) {
+                Ok(wal) if wal.metadata()?.len() > 0 => {
+                    return Err(io::Error::other(
+                        "DiskHashMap should be recovered before it is \
+                         opened read-only",
+                    ));
+                }
+                _ => None,
+            }
+        } else {
+            Some(Wal::open(&wal_path(path))?)
+        };
+
+        // the store of an existing file has the page size in its header,
+        // which is read through another handle, as `file` may be opened for
+        // direct I/O
+        let file_page_size = read_page_size(&File::open(path)?)?;
+        let store = new_store(
+            file,
+            file_page_size.or(page_size).unwrap_or(DEFAULT_PAGE_SIZE),
+            options.write_mode,
+        )?;
+
+        let mut map = Self::new(
+            store,
+            options.hash_builder,
+            options.codec,
+            Some(checkpoint_path(path)),
+            wal,
+            read_only,
+        );
+        map.durability = options.durability;
+        map.compression = options.compression;
+        map.set_cache_capacity(options.cache_capacity)?;
+        if map.shared.lock().store.page_count() == 0 && !read_only {
+            map.create()?;
+        }
+        map.read_header(page_size)?;
+        let checkpoint = Checkpoint::read(&checkpoint_path(path))?;
+        let (replayed, restored) = map.replay(checkpoint)?;
+        if !restored {
+            map.load()?;
+        }
+        if !read_only && (replayed > 0 || !restored) {
+            // write the replayed pages, and a fresh directory checkpoint
+            map.sync_all()?;
+        }
+
+        Ok(map)
+    }
+
+    /// Return an error if the map is opened read-only.
+    fn check_writable(&self) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "DiskHashMap is opened read-only",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Initialize an empty file, with the file header and an empty bucket.
+    fn create(&mut self) -> io::Result<()> {
+        let mut buf = vec![0; self.page_size];
+        buf[0..8].copy_from_slice(MAGIC);
+        buf[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        buf[12..16].copy_from_slice(&(self.page_size as u32).to_le_bytes());
+        buf[16..24]
+            .copy_from_slice(&hasher_id(&self.hash_builder).to_le_bytes());
+        buf[24..28].copy_from_slice(&C::KEY_CODEC_ID.to_le_bytes());
+        buf[28..32].copy_from_slice(&C::VALUE_CODEC_ID.to_le_bytes());
+        let shared = &mut *self.shared.lock();
+        let header_no = shared.store.allocate_page()?;
+        shared.store.write_page(header_no, &buf)?;
+        let image = Page::new(0, 0)
+            .encode_image(Compression::None, self.page_size)
+            .unwrap();
+        let bucket_no = shared.store.allocate_page()?;
+        shared.store.write_page(bucket_no, &image)?;
+        shared.store.sync()?;
+
+        // a log or a directory checkpoint left by a removed file doesn't
+        // belong to this one
+        if let Some(checkpoint_path) = &self.checkpoint_path {
+            remove_checkpoint(checkpoint_path)?;
+        }
+        match &mut shared.wal {
+            Some(wal) => wal.truncate(),
+            None => Ok(()),
+        }
+    }
+
+    /// Restore the directory from `checkpoint`, if any, and write the pages
+    /// logged by the write-ahead log to the file, re-pointing the directory
+    /// to them.
+    ///
+    /// Return the number of the replayed log entries, and whether the
+    /// directory is restored, which is false if there is no checkpoint, or it
+    /// is stale.
+    fn replay(
+        &mut self,
+        checkpoint: Option<Checkpoint>,
+    ) -> io::Result<(usize, bool)> {
+        let mut restored = false;
+        if let Some(checkpoint) = checkpoint {
+            self.global_depth = checkpoint.global_depth;
+            self.directory = checkpoint.directory;
+            self.page_lens = checkpoint.page_lens;
+            restored = true;
+        }
+
+        let Some(wal) = self.shared.lock().wal.take() else {
+            return Ok((0, restored && self.check_directory()?));
+        };
+        let replayed = wal.replay(self.page_size, |page_no, image| {
+            self.shared.lock().write_page(page_no, image)?;
+            if restored {
+                restored = Page::decode(image)
+                    .is_ok_and(|page| self.repoint(page_no, &page));
+            }
+            Ok(())
+        });
+        self.shared.lock().wal = Some(wal);
+        let replayed = replayed?;
+
+        Ok((replayed, restored && self.check_directory()?))
+    }
+
+    /// Point the directory entries covered by `page`, the image of the bucket
+    /// page `page_no`, to the page, return false if the page doesn't fit
+    /// into the directory.
+    fn repoint(&mut self, page_no: u64, page: &Page) -> bool {
+        if page.depth >= usize::BITS
+            || page.prefix.checked_shr(page.depth).unwrap_or(0) != 0
+        {
+            return false;
+        }
+        while self.global_depth < page.depth {
+            self.directory = self
+                .directory
+                .iter()
+                .flat_map(|page_no| [*page_no, *page_no])
+                .collect();
+            self.global_depth += 1;
+        }
+        for idx in self.entries_of(page.depth, page.prefix) {
+            self.directory[idx] = page_no;
+        }
+        let Ok(page_idx) = usize::try_from(page_no) else {
+            return false;
+        };
+        if page_idx >= self.page_lens.len() {
+            self.page_lens.resize(page_idx + 1, 0);
+        }
+        self.page_lens[page_idx] = page.records.len() as u32;
+
+        true
+    }
+
+    /// Check the restored directory against the store, and count the
+    /// entries, return false if the directory is stale.
+    fn check_directory(&mut self) -> io::Result<bool> {
+        let page_count = self.page_lens.len() as u64;
+        if self.shared.lock().store.page_count() != page_count
+            || self
+                .directory
+                .iter()
+                .any(|page_no| *page_no == 0 || *page_no >= page_count)
+        {
+            return Ok(false);
+        }
+
+        self.len = self.page_lens.iter().map(|len| *len as usize).sum();
+
+        Ok(true)
+    }
+
+    /// Validate the file header, whose page size should be the one of the
+    /// store, and `page_size` if it is specified.
+    fn read_header(&mut self, page_size: Option<usize>) -> io::Result<()> {
+        let invalid =
+            |reason| io::Error::new(io::ErrorKind::InvalidData, reason);
+        let mismatched =
+            |reason| io::Error::new(io::ErrorKind::InvalidInput, reason);
+
+        let shared = self.shared.lock();
+        if shared.store.page_count() == 0 {
+            return Err(invalid("not a DiskHashMap file"));
+        }
+        let buf = shared.store.get_page(0)?;
+        let read_u32 =
+            |at: usize| u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+        if &buf[0..8] != MAGIC {
+            return Err(invalid("not a DiskHashMap file"));
+        }
+        if read_u32(8) < VERSION {
+            return Err(invalid(
+                "file has an older format version, it should be migrated",
+            ));
+        }
+        if read_u32(8) != VERSION {
+            return Err(invalid("unsupported format version"));
+        }
+        if read_u32(12) as usize != self.page_size {
+            return Err(invalid("unsupported page size"));
+        }
+        if page_size.is_some_and(|page_size| page_size != self.page_size) {
+            return Err(mismatched("file has another page size"));
+        }
+        if buf[16..24] != hasher_id(&self.hash_builder).to_le_bytes() {
+            return Err(mismatched("file is created with another hasher"));
+        }
+        if read_u32(24) != C::KEY_CODEC_ID || read_u32(28) != C::VALUE_CODEC_ID
+        {
+            return Err(mismatched("file is created with another codec"));
+        }
+
+        Ok(())
+    }
+
+    /// Rebuild the directory from the bucket pages.
+    fn load(&mut self) -> io::Result<()> {
+        let invalid =
+            |reason| io::Error::new(io::ErrorKind::InvalidData, reason);
+
+        let page_count = self.shared.lock().store.page_count();
+        self.len = 0;
+        self.page_lens = vec![0; page_count as usize];
+        let mut buckets = Vec::new();
+        for page_no in 1..page_count {
+            let page = self.read(page_no)?;
+            self.len += page.records.len();
+            self.page_lens[page_no as usize] = page.records.len() as u32;
+            buckets.push((page_no, page.depth, page.prefix));
+        }
+
+        self.global_depth = buckets
+            .iter()
+            .map(|(_, depth, _)| *depth)
+            .max()
+            .unwrap_or(0);
+        if self.global_depth >= usize::BITS {
+            return Err(invalid("global depth is too large"));
+        }
+        // the buckets cover the directory exactly once if they don't overlap,
+        // check it before allocating the directory
+        let covered = buckets
+            .iter()
+            .map(|(_, depth, _)| 1_u128 << (self.global_depth - depth))
+            .sum::<u128>();
+        if covered != 1 << self.global_depth {
+            return Err(invalid("buckets don't cover every prefix once"));
+        }
+        self.directory = vec![0; 1 << self.global_depth];
+        for (page_no, depth, prefix) in buckets {
+            for idx in self.entries_of(depth, prefix) {
+                if self.directory[idx] != 0 {
+                    return Err(invalid("buckets overlap"));
+                }
+                self.directory[idx] = page_no;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the indexes of the directory entries pointing to the bucket
+    /// whose local depth is `depth` and prefix is `prefix`.
+    fn entries_of(&self, depth: u32, prefix: u64) -> std::ops::Range<usize> {
+        let shift = self.global_depth - depth;
+
+        (prefix << shift) as usize..((prefix + 1) << shift) as usize
+    }
+
+    /// Return the number of the bucket page where a key whose hash value is
+    /// `hash` will go.
+    #[inline]
+    fn page_of(&self, hash: u64) -> u64 {
+        self.directory[prefix(hash, self.global_depth) as usize]
+    }
+
+    /// Read and decode the bucket page `page_no`.
+    fn read(&self, page_no: u64) -> io::Result<Page> {
+        self.shared.lock().read(page_no)
+    }
+
+    /// Log the bucket pages `pages` modified by a `kind` modification, every
+    /// one of them is its page number, number of records and image, then
+    /// write them to the store once the log is synced, or at once if the map
+    /// has no log. With a background flusher, the synced pages are left
+    /// dirty for it.
+    fn commit(
+        &mut self,
+        kind: EntryKind,
+        pages: Vec<(u64, u32, Vec<u8>)>,
+    ) -> io::Result<()> {
+        let mut images = Vec::with_capacity(pages.len());
+        for (page_no, records, image) in pages {
+            let page_idx = page_no as usize;
+            if page_idx >= self.page_lens.len() {
+                self.page_lens.resize(page_idx + 1, 0);
+            }
+            self.page_lens[page_idx] = records;
+            images.push((page_no, image));
+        }
+        let entry = images
+            .iter()
+            .map(|(page_no, image)| (*page_no, image.as_slice()))
+            .collect::<Vec<_>>();
+
+        let mut shared = self.shared.lock();
+        if let Some(err) = shared.error.take() {
+            drop(shared);
+            self.flusher = None;
+            return Err(err);
+        }
+        let Some(wal) = shared.wal.as_mut() else {
+            for (page_no, image) in entry {
+                shared.write_page(page_no, image)?;
+            }
+            return Ok(());
+        };
+        let synced = wal.append(kind, &entry, self.durability)?;
+        let wal_len = wal.len();
+        shared.pending.extend(images);
+        if synced {
+            shared.synced()?;
+            if self.flusher.is_none() {
+                shared.write_dirty()?;
+            }
+        }
+        drop(shared);
+        // the flusher empties the log, unless it falls too far behind
+        let checkpoint_len = match self.flusher {
+            Some(_) => 2 * WAL_CHECKPOINT_LEN,
+            None => WAL_CHECKPOINT_LEN,
+        };
+        if wal_len > checkpoint_len {
+            self.sync_all()?;
+        }
+
+        Ok(())
+    }
+
+    /// Encode `key` and hash it.
+    fn hash_key(&self, key: &K) -> (u64, Vec<u8>) {
+        let mut bytes = Vec::new();
+        self.codec.encode_key(key, &mut bytes);
+
+        (hash_bytes(&self.hash_builder, &bytes), bytes)
+    }
+
+    /// Return the number of elements in the map.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if the map contains no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the global depth.
+    #[inline]
+    pub fn global_depth(&self) -> u32 {
+        self.global_depth
+    }
+
+    /// Return the size of a page in bytes.
+    #[inline]
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Return a reference to the map's [`BuildHasher`].
+    #[inline]
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Return the durability policy of the write-ahead log.
+    #[inline]
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Set the durability policy of the write-ahead log.
+    #[inline]
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Return the compression of the written pages.
+    #[inline]
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Set the compression of the pages written afterwards, see the
+    /// [compression](Self#compression) section.
+    #[inline]
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Return the capacity of the page cache in pages, 0 if it is disabled.
+    #[inline]
+    pub fn cache_capacity(&self) -> usize {
+        self.shared.lock().cache.capacity()
+    }
+
+    /// Set the capacity of the page cache to `pages` pages, see the
+    /// [page cache](Self#page-cache) section, 0 disables the cache.
+    ///
+    /// Shrinking the cache may write back dirty pages, whose error is
+    /// returned.
+    pub fn set_cache_capacity(&mut self, pages: usize) -> io::Result<()> {
+        self.shared.lock().set_cache_capacity(pages)
+    }
+
+    /// Checkpoint the map, same as [`sync_all()`](Self::sync_all).
+    #[inline]
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+
+    /// Checkpoint the map: write all the modified pages to the store, sync
+    /// it, checkpoint the directory, and empty the write-ahead log.
+    ///
+    /// A map without a log only syncs the store.
+    pub fn sync_all(&mut self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let shared = &mut *self.shared.lock();
+        let Some(checkpoint_path) = &self.checkpoint_path else {
+            return shared.store.sync();
+        };
+        if shared.wal.is_none() {
+            return shared.store.sync();
+        }
+
+        shared.empty_wal(|| {
+            write_checkpoint(
+                checkpoint_path,
+                self.global_depth,
+                &self.directory,
+                &self.page_lens,
+            )
+        })?;
+        self.splits = 0;
+
+        Ok(())
+    }
+
+    /// Sync the write-ahead log and write the modified pages to the store,
+    /// without syncing the store or emptying the log.
+    ///
+    /// Once it returns, the modifications are durable, and no page is left
+    /// dirty, see [`dirty_pages()`](Self::dirty_pages).
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let mut shared = self.shared.lock();
+        shared.sync_wal()?;
+        shared.write_dirty()
+    }
+
+    /// Return the number of the modified pages not written to the store yet.
+    pub fn dirty_pages(&self) -> usize {
+        let shared = self.shared.lock();
+
+        shared.pending.len() + shared.dirty.len()
+    }
+
+    /// Start a thread writing the dirty pages to the store every `interval`,
+    /// and emptying the write-ahead log once it grows too long, see the
+    /// [background flushing](Self#background-flushing) section.
+    ///
+    /// A started flusher is replaced. It is stopped when the map is dropped.
+    ///
+    /// # Error
+    /// An [`Unsupported`](io::ErrorKind::Unsupported) error is returned if
+    /// the map has no write-ahead log.
+    pub fn start_flusher(&mut self, interval: Duration) -> io::Result<()>
+    where
+        T: Send + 'static,
+    {
+        let Some(checkpoint_path) = self.checkpoint_path.clone() else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "map has no write-ahead log",
+            ));
+        };
+        if self.shared.lock().wal.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "map has no write-ahead log",
+            ));
+        }
+
+        self.flusher = None;
+        self.flusher = Some(Flusher::start(
+            Arc::clone(&self.shared),
+            checkpoint_path,
+            interval,
+        )?);
+
+        Ok(())
+    }
+
+    /// Stop the background flusher, if it is started.
+    pub fn stop_flusher(&mut self) {
+        self.flusher = None;
+    }
+
+    /// Scan the bucket pages in the store, and return the ranges of the
+    /// numbers of the damaged pages, whose checksums mismatch, or which are
+    /// malformed, see the [checksums](Self#checksums) section.
+    ///
+    /// The ranges are sorted, and consecutive damaged pages are reported as
+    /// one range, an empty result means no damage is found.
+    pub fn verify(&self) -> io::Result<Vec<Range<u64>>> {
+        let page_count = self.shared.lock().store.page_count();
+        let mut damaged: Vec<Range<u64>> = Vec::new();
+        for page_no in 1..page_count {
+            // the lock is released between pages, so the flusher isn't
+            // blocked for the whole scan
+            let image =
+                self.shared.lock().store.get_page(page_no)?.into_owned();
+            if Page::decode_verified(page_no, &image).is_ok() {
+                continue;
+            }
+            match damaged.last_mut() {
+                Some(range) if range.end == page_no => range.end += 1,
+                _ => damaged.push(page_no..page_no + 1),
+            }
+        }
+
+        Ok(damaged)
+    }
+
+    /// Checkpoint the directory, without syncing the pages.
+    ///
+    /// The write-ahead log is synced first, so the pages the checkpoint
+    /// points to can always be recovered. Replaying the log after a crash
+    /// brings the directory from the checkpoint up to date. A map without a
+    /// log has no directory checkpoint.
+    pub fn checkpoint_directory(&mut self) -> io::Result<()> {
+        let mut shared = self.shared.lock();
+        let (Some(_), Some(checkpoint_path)) =
+            (&shared.wal, &self.checkpoint_path)
+        else {
+            return Ok(());
+        };
+
+        shared.sync_wal()?;
+        write_checkpoint(
+            checkpoint_path,
+            self.global_depth,
+            &self.directory,
+            &self.page_lens,
+        )?;
+        self.splits = 0;
+
+        Ok(())
+    }
+
+    /// Split the bucket page `page_no`, which holds `page`.
+    ///
+    /// The entries whose next bit is 0 stay in the page, the other ones are
+    /// moved to a new page appended to the file.
+    fn split(&mut self, page_no: u64, page: Page) -> io::Result<()> {
+        let mut buckets = Vec::new();
+        divide(page, self.compression, self.page_size, &mut buckets)?;
+        let depth = buckets.iter().map(|(page, _)| page.depth).max().unwrap();
+        if depth > self.global_depth {
+            if depth >= usize::BITS {
+                return Err(io::Error::other("directory is too large"));
+            }
+            // entry `idx` is covered by the new entries from `idx << shift`
+            // to `(idx + 1) << shift`
+            let shift = depth - self.global_depth;
+            self.directory = self
+                .directory
+                .iter()
+                .flat_map(|page_no| std::iter::repeat_n(*page_no, 1 << shift))
+                .collect();
+            self.global_depth = depth;
+        }
+
+        // the last bucket takes the place of the split one, and is written
+        // after the new pages
+        let mut pages = Vec::with_capacity(buckets.len());
+        let mut bits = Vec::with_capacity(buckets.len());
+        let last = buckets.len() - 1;
+        for (idx, (page, image)) in buckets.into_iter().enumerate() {
+            let bucket_no = if idx == last {
+                page_no
+            } else {
+                self.shared.lock().store.allocate_page()?
+            };
+            pages.push((bucket_no, page.records.len() as u32, image));
+            bits.push((bucket_no, page.depth, page.prefix));
+        }
+        self.commit(EntryKind::Split, pages)?;
+        for (bucket_no, depth, prefix) in bits {
+            for idx in self.entries_of(depth, prefix) {
+                self.directory[idx] = bucket_no;
+            }
+        }
+
+        self.splits += 1;
+        if self.splits >= DIRECTORY_CHECKPOINT_SPLITS {
+            self.checkpoint_directory()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<K, V, S: BuildHasher, T: BucketStore, C: Codec<K, V>>
+    DiskHashMap<K, V, S, T, C>
+{
+    /// Return the value corresponding to the key.
+    pub fn get(&self, key: &K) -> io::Result<Option<V>> {
+        let (hash, key) = self.hash_key(key);
+        let page = self.read(self.page_of(hash))?;
+
+        page.position(hash, &key)
+            .map(|idx| self.codec.decode_value(&page.records[idx].value))
+            .transpose()
+    }
+
+    /// Return true if the map contains a value for the specified key.
+    pub fn contains_key(&self, key: &K) -> io::Result<bool> {
+        let (hash, key) = self.hash_key(key);
+        let page = self.read(self.page_of(hash))?;
+
+        Ok(page.position(hash, &key).is_some())
+    }
+
+    /// Insert a key-value pair into the map.
+    ///
+    /// If the map did not have this key present, `None` is returned.
+    ///
+    /// If the map did have this key present, the value is updated, and the
+    /// old value is returned.
+    ///
+    /// # Error
+    /// An [`InvalidInput`](io::ErrorKind::InvalidInput) error is returned if
+    /// the entry doesn't fit into a page, and a
+    /// [`PermissionDenied`](io::ErrorKind::PermissionDenied) error if the map
+    /// is opened read-only.
+    pub fn insert(&mut self, key: K, value: V) -> io::Result<Option<V>> {
+        self.check_writable()?;
+        let (hash, key) = self.hash_key(&key);
+        let mut encoded_value = Vec::new();
+        self.codec.encode_value(&value, &mut encoded_value);
+        let record = Record {
+            hash,
+            key,
+            value: encoded_value,
+        };
+        if !record.fits_in_page(self.page_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "entry doesn't fit into a page",
+            ));
+        }
+
+        let page_no = self.page_of(hash);
+        let mut page = self.read(page_no)?;
+        // The new value may be larger than the old one, take the old record
+        // out, and insert the new one as if it was absent.
+        let old = page
+            .position(hash, &record.key)
+            .map(|idx| page.records.swap_remove(idx).value);
+        page.records.push(record);
+
+        match page.encode_image(self.compression, self.page_size) {
+            Some(image) => {
+                let records = page.records.len() as u32;
+                self.commit(
+                    EntryKind::Insert,
+                    vec![(page_no, records, image)],
+                )?;
+            }
+            None => {
+                if page.records.iter().all(|r| r.hash == hash) {
+                    return Err(io::Error::other(
+                        "too many entries with the same hash value",
+                    ));
+                }
+                // the new record goes into the buckets the page splits into
+                self.split(page_no, page)?;
+            }
+        }
+        if old.is_none() {
+            self.len += 1;
+        }
+
+        old.map(|old| self.codec.decode_value(&old)).transpose()
+    }
+
+    /// Write the entries of the map to `writer` as a stream of records, see
+    /// the [`stream`](crate::stream) module, return the number of the
+    /// written entries.
+    ///
+    /// The entries are read one bucket page at a time, and written as they
+    /// are encoded in the pages, so `writer` should be buffered, e.g., by a
+    /// [`BufWriter`](std::io::BufWriter).
+    pub fn export_entries<W: Write>(&self, mut writer: W) -> io::Result<usize> {
+        let mut entries = 0;
+        // the directory entries pointing to a bucket are consecutive
+        let mut last_page_no = None;
+        for &page_no in self.directory.iter() {
+            if last_page_no.replace(page_no) == Some(page_no) {
+                continue;
+            }
+            for record in self.read(page_no)?.records {
+                write_record(&mut writer, &record.key, &record.value)?;
+                entries += 1;
+            }
+        }
+        writer.flush()?;
+
+        Ok(entries)
+    }
+
+    /// Insert the entries read from `reader`, a stream of records written by
+    /// [`export_entries()`](Self::export_entries) or
+    /// [`HashMap::export_entries()`](crate::HashMap::export_entries), until
+    /// it ends, return the number of the read entries.
+    ///
+    /// Every entry is decoded by the codec before it is inserted, and an
+    /// entry whose key is present updates its value. The records are read
+    /// one at a time, so `reader` should be buffered, e.g., by a
+    /// [`BufReader`](std::io::BufReader).
+    ///
+    /// # Error
+    /// An [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error is returned
+    /// if the stream ends in the middle of a record, and the errors of
+    /// decoding and [`insert()`](Self::insert) are returned as they are. The
+    /// entries read before the error stay in the map.
+    pub fn import_entries<R: Read>(
+        &mut self,
+        mut reader: R,
+    ) -> io::Result<usize> {
+        self.check_writable()?;
+        let mut key = Vec::new();
+        let mut value = Vec::new();
+        let mut entries = 0;
+        while read_record(&mut reader, &mut key, &mut value)? {
+            let (key, value) = (
+                self.codec.decode_key(&key)?,
+                self.codec.decode_value(&value)?,
+            );
+            self.insert(key, value)?;
+            entries += 1;
+        }
+
+        Ok(entries)
+    }
+
+    /// Remove `key` from the map, return its value if it was previously in
+    /// the map.
+    pub fn remove(&mut self, key: &K) -> io::Result<Option<V>> {
+        self.check_writable()?;
+        let (hash, key) = self.hash_key(key);
+        let page_no = self.page_of(hash);
+        let mut page = self.read(page_no)?;
+        let Some(idx) = page.position(hash, &key) else {
+            return Ok(None);
+        };
+
+        let record = page.records.swap_remove(idx);
+        match page.encode_image(self.compression, self.page_size) {
+            Some(image) => {
+                let records = page.records.len() as u32;
+                self.commit(
+                    EntryKind::Remove,
+                    vec![(page_no, records, image)],
+                )?;
+            }
+            // fewer records may compress worse
+            None => self.split(page_no, page)?,
+        }
+        self.len -= 1;
+
+        self.codec.decode_value(&record.value).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CorruptPage;
+    use std::path::PathBuf;
+
+    /// A file in the temporary directory, which is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "extendable_hashmap-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = std::fs::remove_file(&path);
+
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(wal_path(&self.0));
+            let _ = std::fs::remove_file(checkpoint_path(&self.0));
+        }
+    }
+
+    #[test]
+    fn disk_map_works() {
+        let file = TempFile::new("disk_map_works");
+        let mut map = DiskHashMap::open(&file.0).unwrap();
+        assert!(map.is_empty());
+        map.set_durability(Durability::Batched(100));
+        for i in 0..10000_u64 {
+            assert_eq!(map.insert(i, i.to_string()).unwrap(), None);
+        }
+        assert_eq!(
+            map.insert(0, "zero".to_string()).unwrap(),
+            Some("0".into())
+        );
+        assert_eq!(map.len(), 10000);
+        assert!(map.global_depth() > 0);
+        for i in 1..10000 {
+            assert_eq!(map.get(&i).unwrap(), Some(i.to_string()));
+        }
+        assert_eq!(map.get(&10000).unwrap(), None);
+        for i in (0..10000).step_by(2) {
+            assert!(map.remove(&i).unwrap().is_some());
+        }
+        assert_eq!(map.remove(&0).unwrap(), None);
+        map.sync().unwrap();
+        let global_depth = map.global_depth();
+        drop(map);
+
+        // the directory is rebuilt from the pages
+        let map = DiskHashMap::<u64, String>::open(&file.0).unwrap();
+        assert_eq!(map.len(), 5000);
+        assert_eq!(map.global_depth(), global_depth);
+        for i in 0..10000 {
+            assert_eq!(map.contains_key(&i).unwrap(), i % 2 == 1);
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn disk_map_mmap() {
+        type Map = DiskHashMap<u64, u64, FixedState, MmapStore>;
+        let file = TempFile::new("disk_map_mmap");
+        // SAFETY: the file is only modified through `map`, and not while
+        // `reader` is open.
+        assert!(unsafe { Map::open_mmap_read_only(&file.0) }.is_err());
+
+        let mut map = unsafe { Map::open_mmap(&file.0) }.unwrap();
+        for i in 0..5000_u64 {
+            assert_eq!(map.insert(i, i).unwrap(), None);
+        }
+        for i in 0..5000 {
+            assert_eq!(map.get(&i).unwrap(), Some(i));
+        }
+        map.sync().unwrap();
+
+        let mut reader = unsafe { Map::open_mmap_read_only(&file.0) }.unwrap();
+        assert_eq!(reader.len(), 5000);
+        for i in 0..5000 {
+            assert_eq!(reader.get(&i).unwrap(), Some(i));
+        }
+        let err = reader.insert(0, 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(reader.remove(&0).unwrap_err().kind(), err.kind());
+
+        drop(reader);
+
+        map.insert(0, 1).unwrap();
+        map.sync().unwrap();
+        let reader = unsafe { Map::open_mmap_read_only(&file.0) }.unwrap();
+        assert_eq!(reader.get(&0).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn disk_map_recovers() {
+        let file = TempFile::new("disk_map_recovers");
+        let mut map = DiskHashMap::open(&file.0).unwrap();
+        for i in 0..1000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        // tear a page, which is restored from the log
+        map.shared
+            .lock()
+            .store
+            .write_page(1, &[0xff; DEFAULT_PAGE_SIZE])
+            .unwrap();
+        drop(map);
+        assert!(DiskHashMap::<u64, u64>::recover(&file.0).is_ok());
+
+        let mut map = DiskHashMap::open(&file.0).unwrap();
+        map.sync().unwrap();
+        map.set_durability(Durability::Manual);
+        for i in 1000..3000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        map.remove(&0).unwrap();
+        // crash before the pending pages are written
+        std::mem::forget(map);
+
+        let map = DiskHashMap::<u64, u64>::recover(&file.0).unwrap();
+        assert_eq!(map.len(), 2999);
+        assert_eq!(map.get(&0).unwrap(), None);
+        for i in 1..3000 {
+            assert_eq!(map.get(&i).unwrap(), Some(i));
+        }
+        assert_eq!(std::fs::metadata(wal_path(&file.0)).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn disk_map_checkpoints_directory() {
+        let file = TempFile::new("disk_map_checkpoints_directory");
+        let mut map = DiskHashMap::open(&file.0).unwrap();
+        map.set_durability(Durability::Batched(100));
+        for i in 0..2000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        map.sync().unwrap();
+        let stale = std::fs::read(checkpoint_path(&file.0)).unwrap();
+
+        // the directory is checkpointed, the pages are left in the log
+        for i in 2000..4000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        map.checkpoint_directory().unwrap();
+        for i in 4000..6000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        map.remove(&0).unwrap();
+        let global_depth = map.global_depth();
+        std::mem::forget(map);
+
+        // the log is replayed on top of the checkpoint
+        let map = DiskHashMap::<u64, u64>::open(&file.0).unwrap();
+        assert_eq!(map.len(), 5999);
+        assert_eq!(map.global_depth(), global_depth);
+        for i in 1..6000 {
+            assert_eq!(map.get(&i).unwrap(), Some(i));
+        }
+        drop(map);
+
+        // a stale checkpoint is ignored, and the directory is rebuilt
+        std::fs::write(checkpoint_path(&file.0), stale).unwrap();
+        let map = DiskHashMap::<u64, u64>::open(&file.0).unwrap();
+        assert_eq!(map.len(), 5999);
+        assert_eq!(map.global_depth(), global_depth);
+        assert_eq!(map.get(&0).unwrap(), None);
+        assert_eq!(map.get(&5999).unwrap(), Some(5999));
+    }
+
+    #[test]
+    fn disk_map_with_store() {
+        let store = crate::MemoryStore::new(DEFAULT_PAGE_SIZE);
+        let mut map =
+            DiskHashMap::with_store(store, FixedState::default()).unwrap();
+        for i in 0..3000_u64 {
+            map.insert(i, i.to_string()).unwrap();
+        }
+        map.remove(&0).unwrap();
+        map.sync().unwrap();
+
+        // the directory is rebuilt from the store
+        let store =
+            Arc::try_unwrap(map.shared).ok().unwrap().into_inner().store;
+        let map = DiskHashMap::<u64, String, _, _>::with_store(
+            store,
+            FixedState::default(),
+        )
+        .unwrap();
+        assert_eq!(map.len(), 2999);
+        assert!(map.global_depth() > 0);
+        assert_eq!(map.get(&0).unwrap(), None);
+        for i in 1..3000 {
+            assert_eq!(map.get(&i).unwrap(), Some(i.to_string()));
+        }
+
+        let store = crate::MemoryStore::new(1000);
+        let err = DiskHashMap::<u64, u64, _, _>::with_store(
+            store,
+            FixedState::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn disk_map_with_codec() {
+        /// Encodes `(u16, u16)` keys in big endian, and `usize` values as
+        /// that many bytes.
+        struct PairCodec;
+
+        impl Codec<(u16, u16), usize> for PairCodec {
+            const KEY_CODEC_ID: u32 = 100;
+            const VALUE_CODEC_ID: u32 = 101;
+
+            fn encode_key(&self, key: &(u16, u16), buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&key.0.to_be_bytes());
+                buf.extend_from_slice(&key.1.to_be_bytes());
+            }
+
+            fn decode_key(&self, bytes: &[u8]) -> io::Result<(u16, u16)> {
+                let bytes = <[u8; 4]>::try_from(bytes)
+                    .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+                Ok((
+                    u16::from_be_bytes([bytes[0], bytes[1]]),
+                    u16::from_be_bytes([bytes[2], bytes[3]]),
+                ))
+            }
+
+            fn encode_value(&self, value: &usize, buf: &mut Vec<u8>) {
+                buf.resize(buf.len() + value, b'x');
+            }
+
+            fn decode_value(&self, bytes: &[u8]) -> io::Result<usize> {
+                Ok(bytes.len())
+            }
+        }
+
+        let file = TempFile::new("disk_map_with_codec");
+        let mut map = DiskHashMap::open_with_hasher_and_codec(
+            &file.0,
+            FixedState::default(),
+            PairCodec,
+        )
+        .unwrap();
+        for i in 0..2000_u16 {
+            map.insert((i, i + 1), i as usize % 100).unwrap();
+        }
+        assert_eq!(map.insert((1, 2), 7).unwrap(), Some(1));
+        drop(map);
+
+        let map = DiskHashMap::open_with_hasher_and_codec(
+            &file.0,
+            FixedState::default(),
+            PairCodec,
+        )
+        .unwrap();
+        assert_eq!(map.len(), 2000);
+        assert_eq!(map.get(&(1, 2)).unwrap(), Some(7));
+        assert_eq!(map.get(&(1999, 2000)).unwrap(), Some(99));
+        assert_eq!(map.get(&(2, 2)).unwrap(), None);
+        drop(map);
+
+        // the file is created with another codec
+        let err = DiskHashMap::<u32, u64>::open(&file.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn disk_map_compression() {
+        #[cfg(feature = "lz4")]
+        let compression = Compression::Lz4;
+        #[cfg(not(feature = "lz4"))]
+        let compression = Compression::Zstd(3);
+
+        let mut pages = Vec::new();
+        for compression in [Compression::None, compression] {
+            let file = TempFile::new("disk_map_compression");
+            let mut map = DiskHashMap::open(&file.0).unwrap();
+            map.set_compression(compression);
+            for i in 0..5000_u64 {
+                map.insert(i, format!("{:0>100}", i)).unwrap();
+            }
+            for i in 0..1000 {
+                map.remove(&i).unwrap();
+            }
+            map.sync().unwrap();
+            pages.push(map.shared.lock().store.page_count());
+            drop(map);
+
+            // the pages are readable whatever the compression is
+            let mut map = DiskHashMap::<u64, String>::open(&file.0).unwrap();
+            assert_eq!(map.compression(), Compression::None);
+            assert_eq!(map.len(), 4000);
+            for i in 0..5000 {
+                let value = (i >= 1000).then(|| format!("{:0>100}", i));
+                assert_eq!(map.get(&i).unwrap(), value);
+            }
+            map.insert(0, String::new()).unwrap();
+            assert_eq!(map.get(&0).unwrap(), Some(String::new()));
+        }
+        assert!(pages[1] < pages[0] / 2, "{:?}", pages);
+    }
+
+    #[test]
+    fn disk_map_options() {
+        let file = TempFile::new("disk_map_options");
+        let write_modes = [
+            WriteMode::Buffered,
+            WriteMode::Sync,
+            #[cfg(all(feature = "direct-io", target_os = "linux"))]
+            WriteMode::Direct,
+        ];
+        for write_mode in write_modes {
+            let mut map = DiskOptions::new()
+                .page_size(8 << 10)
+                .durability(Durability::Batched(10))
+                .write_mode(write_mode)
+                .open(&file.0)
+                .unwrap();
+            assert_eq!(map.durability(), Durability::Batched(10));
+            for i in 0..2000_u64 {
+                map.insert(i, i.to_string()).unwrap();
+            }
+            map.sync().unwrap();
+            drop(map);
+
+            let map = DiskHashMap::<u64, String>::open(&file.0).unwrap();
+            assert_eq!(map.page_size(), 8 << 10);
+            assert_eq!(map.len(), 2000);
+            assert_eq!(map.get(&1999).unwrap(), Some(1999.to_string()));
+            drop(map);
+            std::fs::remove_file(&file.0).unwrap();
+        }
+
+        let err = DiskOptions::new()
+            .create(false)
+            .open::<u64, u64>(&file.0)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn disk_map_migrates() {
+        let file = TempFile::new("disk_map_migrates");
+        let mut map = DiskHashMap::open(&file.0).unwrap();
+        for i in 0..1000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        map.sync().unwrap();
+        drop(map);
+        assert!(!DiskHashMap::<u64, u64>::migrate(&file.0).unwrap());
+
+        // a file of version 1 has no hasher id or codec ids
+        let mut bytes = std::fs::read(&file.0).unwrap();
+        bytes[8..12].copy_from_slice(&1_u32.to_le_bytes());
+        bytes[16..32].fill(0);
+        std::fs::write(&file.0, &bytes).unwrap();
+        let err = DiskHashMap::<u64, u64>::open(&file.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        assert!(DiskHashMap::<u64, u64>::migrate(&file.0).unwrap());
+        let map = DiskHashMap::<u64, u64>::open(&file.0).unwrap();
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.get(&999).unwrap(), Some(999));
+        drop(map);
+
+        // a newer version can't be migrated
+        let mut bytes = std::fs::read(&file.0).unwrap();
+        bytes[8..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        std::fs::write(&file.0, &bytes).unwrap();
+        let err = DiskHashMap::<u64, u64>::migrate(&file.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn disk_map_page_size() {
+        let file = TempFile::new("disk_map_page_size");
+        let err =
+            DiskHashMap::<u64, Vec<u8>>::open_with_page_size(&file.0, 5000)
+                .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let mut map =
+            DiskHashMap::open_with_page_size(&file.0, 64 << 10).unwrap();
+        for i in 0..100_u64 {
+            map.insert(i, vec![i as u8; 10000]).unwrap();
+        }
+        map.sync().unwrap();
+        drop(map);
+
+        let map = DiskHashMap::<u64, Vec<u8>>::open(&file.0).unwrap();
+        assert_eq!(map.page_size(), 64 << 10);
+        assert_eq!(map.get(&99).unwrap(), Some(vec![99; 10000]));
+        drop(map);
+
+        // another page size, hasher, or codec
+        for err in [
+            DiskHashMap::<u64, Vec<u8>>::open_with_page_size(&file.0, 4096)
+                .unwrap_err(),
+            DiskHashMap::<u64, Vec<u8>, _>::open_with_hasher(
+                &file.0,
+                FixedState::with_seed(1),
+            )
+            .unwrap_err(),
+            DiskHashMap::<u32, Vec<u8>>::open(&file.0).unwrap_err(),
+        ] {
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn disk_map_rejects_bad_input() {
+        let file = TempFile::new("disk_map_rejects_bad_input");
+        let mut map = DiskHashMap::open(&file.0).unwrap();
+        let err = map.insert(0_u8, vec![0_u8; DEFAULT_PAGE_SIZE]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        drop(map);
+
+        std::fs::write(&file.0, vec![0; DEFAULT_PAGE_SIZE]).unwrap();
+        let err = DiskHashMap::<u8, u8>::open(&file.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn disk_map_flusher() {
+        let file = TempFile::new("disk_map_flusher");
+        let mut map = DiskOptions::new()
+            .flush_interval(Duration::from_millis(1))
+            .open(&file.0)
+            .unwrap();
+        for i in 0..2000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        while map.dirty_pages() > 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(map.get(&1999).unwrap(), Some(1999));
+
+        map.stop_flusher();
+        map.insert(2000, 2000).unwrap();
+        assert_eq!(map.dirty_pages(), 0);
+        map.set_durability(Durability::Manual);
+        map.insert(2001, 2001).unwrap();
+        assert_eq!(map.dirty_pages(), 1);
+        map.flush().unwrap();
+        assert_eq!(map.dirty_pages(), 0);
+        drop(map);
+
+        let map = DiskHashMap::<u64, u64>::open(&file.0).unwrap();
+        assert_eq!(map.len(), 2002);
+        assert_eq!(map.get(&2001).unwrap(), Some(2001));
+
+        let mut map = DiskHashMap::with_store(
+            crate::MemoryStore::new(DEFAULT_PAGE_SIZE),
+            FixedState::default(),
+        )
+        .unwrap();
+        map.insert(0_u64, 0_u64).unwrap();
+        let err = map.start_flusher(Duration::from_millis(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        map.sync_all().unwrap();
+    }
+
+    #[test]
+    fn disk_map_read_only() {
+        let file = TempFile::new("disk_map_read_only");
+        let err = DiskHashMap::<u64, u64>::open_read_only(&file.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let mut map = DiskHashMap::open(&file.0).unwrap();
+        for i in 0..3000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        map.set_durability(Durability::Manual);
+        map.insert(3000, 3000).unwrap();
+        // the log isn't empty
+        assert!(DiskHashMap::<u64, u64>::open_read_only(&file.0).is_err());
+        map.sync().unwrap();
+        drop(map);
+
+        let mut readers = [
+            DiskHashMap::<u64, u64>::open_read_only(&file.0).unwrap(),
+            DiskOptions::new().read_only(true).open(&file.0).unwrap(),
+        ];
+        for reader in &mut readers {
+            assert_eq!(reader.len(), 3001);
+            assert_eq!(reader.get(&3000).unwrap(), Some(3000));
+            let err = reader.insert(0, 1).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+            assert_eq!(reader.remove(&0).unwrap_err().kind(), err.kind());
+            reader.sync().unwrap();
+        }
+        assert_eq!(readers[0].get(&0).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn disk_map_page_cache() {
+        let file = TempFile::new("disk_map_page_cache");
+        let mut map = DiskOptions::new()
+            .cache_capacity(4)
+            .durability(Durability::Manual)
+            .open(&file.0)
+            .unwrap();
+        assert_eq!(map.cache_capacity(), 4);
+        for i in 0..5000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        // syncing the log makes the pages dirty, which are written back
+        // beyond the capacity
+        map.checkpoint_directory().unwrap();
+        assert!(map.dirty_pages() <= 4);
+        for i in 0..5000 {
+            assert_eq!(map.get(&i).unwrap(), Some(i));
+        }
+        // a cached page is never stale
+        map.set_durability(Durability::Always);
+        for i in 0..5000 {
+            map.insert(i, i + 1).unwrap();
+            assert_eq!(map.get(&i).unwrap(), Some(i + 1));
+        }
+        map.set_cache_capacity(0).unwrap();
+        assert_eq!(map.get(&4999).unwrap(), Some(5000));
+        drop(map);
+
+        let map = DiskHashMap::<u64, u64>::open(&file.0).unwrap();
+        assert_eq!(map.len(), 5000);
+        assert_eq!(map.get(&0).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn disk_map_checksums() {
+        let file = TempFile::new("disk_map_checksums");
+        let mut map = DiskHashMap::open(&file.0).unwrap();
+        for i in 0..5000_u64 {
+            map.insert(i, i).unwrap();
+        }
+        map.sync().unwrap();
+        assert_eq!(map.verify().unwrap(), []);
+        let key = (0..5000).find(|i| map.page_of(map.hash_key(i).0) == 2);
+        drop(map);
+
+        let mut bytes = std::fs::read(&file.0).unwrap();
+        for page_no in [2, 3, 5] {
+            bytes[page_no * DEFAULT_PAGE_SIZE + 100] ^= 1;
+        }
+        std::fs::write(&file.0, &bytes).unwrap();
+        // the directory is loaded from its checkpoint
+        let map = DiskHashMap::<u64, u64>::open(&file.0).unwrap();
+        assert_eq!(map.verify().unwrap(), [2..4, 5..6]);
+        let err = map.get(&key.unwrap()).unwrap_err();
+        let corrupt = err.get_ref().unwrap().downcast_ref::<CorruptPage>();
+        assert_eq!(corrupt.map(CorruptPage::page_no), Some(2));
+    }
+
+    #[test]
+    fn disk_map_migrates_pages_without_checksums() {
+        let file = TempFile::new("disk_map_migrates_pages_without_checksums");
+        let map = DiskHashMap::<u64, Vec<u8>>::open(&file.0).unwrap();
+        drop(map);
+
+        // a page of version 2 full to its last byte, 126 records of 32 bytes
+        // and one of 48 bytes
+        let mut page = Page::new(0, 0);
+        for i in 0..127_u64 {
+            let key = i.to_le_bytes().to_vec();
+            page.records.push(Record {
+                hash: hash_bytes(&FixedState::default(), &key),
+                key,
+                value: vec![i as u8; if i == 0 { 24 } else { 8 }],
+            });
+        }
+        let mut bytes = std::fs::read(&file.0).unwrap();
+        bytes[8..12].copy_from_slice(&2_u32.to_le_bytes());
+        page.encode(&mut bytes[DEFAULT_PAGE_SIZE..2 * DEFAULT_PAGE_SIZE]);
+        std::fs::write(&file.0, &bytes).unwrap();
+
+        assert!(DiskHashMap::<u64, Vec<u8>>::migrate(&file.0).unwrap());
+        let map = DiskHashMap::<u64, Vec<u8>>::open(&file.0).unwrap();
+        assert_eq!(map.len(), 127);
+        assert_eq!(map.global_depth(), 1);
+        assert_eq!(map.verify().unwrap(), []);
+        for i in 0..127_u64 {
+            let len = if i == 0 { 24 } else { 8 };
+            assert_eq!(map.get(&i).unwrap(), Some(vec![i as u8; len]));
+        }
+    }
+
+    #[test]
+    fn disk_map_exports_entries() {
+        let file = TempFile::new("disk_map_exports_entries");
+        let mut map = DiskHashMap::open(&file.0).unwrap();
+        for i in 0..3000_u64 {
+            map.insert(i, i.to_string()).unwrap();
+        }
+        let mut bytes = Vec::new();
+        assert_eq!(map.export_entries(&mut bytes).unwrap(), 3000);
+
+        // into an in-memory map, and back into another disk-backed map
+        let mut mem_map = crate::HashMap::<u64, String>::new();
+        assert_eq!(mem_map.import_entries(bytes.as_slice()).unwrap(), 3000);
+        assert_eq!(mem_map.get(&2999), Some(&2999.to_string()));
+        let mut exported = Vec::new();
+        mem_map.export_entries(&mut exported).unwrap();
+        let other_file = TempFile::new("disk_map_exports_entries_other");
+        let mut other =
+            DiskHashMap::<u64, String>::open(&other_file.0).unwrap();
+        assert_eq!(other.import_entries(exported.as_slice()).unwrap(), 3000);
+        for i in 0..3000 {
+            assert_eq!(other.get(&i).unwrap(), Some(i.to_string()));
+        }
+
+        let err = other.import_entries(&bytes[..5]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,145 @@
+//! Rendering of the structure of a [`HashMap`] in the
+//! [DOT](https://graphviz.org/doc/info/lang.html) language of Graphviz.
+
+use crate::{
+    hasher::{DirectoryMapper, KeyHasher},
+    map::HashMap,
+    util::format_bits,
+};
+use allocator_api2::alloc::Allocator;
+use std::{
+    fmt::Debug,
+    io::{self, Write},
+};
+
+/// Escape `label` for a field of a record label.
+fn escape(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '{' | '}' | '|' | '<' | '>' | '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> HashMap<K, V, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Write the structure of the map to `writer` as a Graphviz graph, see
+    /// [`to_dot()`](Self::to_dot).
+    pub fn write_dot<W: Write>(&self, mut writer: W) -> io::Result<()>
+    where
+        K: Debug,
+        V: Debug,
+    {
+        writeln!(writer, "digraph HashMap {{")?;
+        writeln!(writer, "    rankdir=LR;")?;
+        writeln!(writer, "    node [shape=record];")?;
+
+        // the directory is a column of slots, labeled by their bits
+        let slots = (0..self.directories.len())
+            .map(|idx| {
+                format!(
+                    "<d{idx}> {}",
+                    format_bits(idx as u64, self.global_depth)
+                )
+            })
+            .collect::<Vec<_>>();
+        writeln!(
+            writer,
+            "    directory [label=\"{{global depth {}|{}}}\"];",
+            self.global_depth,
+            slots.join("|")
+        )?;
+
+        for (bucket_idx, bucket) in self.buckets.iter() {
+            let mut fields = vec![format!(
+                "prefix {} (local depth {})",
+                format_bits(bucket.prefix, bucket.local_depth()),
+                bucket.local_depth()
+            )];
+            fields.extend(
+                bucket
+                    .iter()
+                    .map(|(_, k, v)| escape(&format!("{k:?}: {v:?}"))),
+            );
+            writeln!(
+                writer,
+                "    bucket{bucket_idx} [label=\"{{{}}}\"];",
+                fields.join("|")
+            )?;
+        }
+
+        for (idx, bucket_idx) in self.directories.iter().enumerate() {
+            writeln!(
+                writer,
+                "    directory:d{idx} -> bucket{};",
+                bucket_idx.get()
+            )?;
+        }
+        writeln!(writer, "}}")?;
+
+        writer.flush()
+    }
+
+    /// Render the structure of the map as a Graphviz graph in the DOT
+    /// language: the directory slots, labeled by their bits, point to the
+    /// buckets, labeled by their prefixes and entries.
+    ///
+    /// The graph can be rendered by Graphviz, e.g., `dot -Tsvg map.dot`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::HashMap;
+    ///
+    /// let mut map = HashMap::new();
+    /// map.insert(1, "a");
+    ///
+    /// let dot = map.to_dot();
+    /// assert!(dot.starts_with("digraph HashMap {"));
+    /// assert!(dot.contains("1: \\\"a\\\""));
+    /// ```
+    pub fn to_dot(&self) -> String
+    where
+        K: Debug,
+        V: Debug,
+    {
+        let mut dot = Vec::new();
+        self.write_dot(&mut dot)
+            .expect("writing to a Vec should not fail");
+
+        String::from_utf8(dot).expect("the graph should be valid UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn to_dot_works() {
+        let mut map = HashMap::with_bucket_capacity(2);
+        for i in 0..20 {
+            map.insert(i, format!("v|{i}"));
+        }
+
+        let dot = map.to_dot();
+        assert!(dot.starts_with("digraph HashMap {\n"));
+        assert!(dot.ends_with("}\n"));
+        // an edge per directory slot, a node per bucket
+        assert_eq!(dot.matches(" -> bucket").count(), map.directories.len());
+        assert_eq!(dot.matches("(local depth ").count(), map.buckets.len());
+        assert!(dot.contains(&format!("global depth {}", map.global_depth)));
+        // the separators in the entries are escaped
+        assert!(dot.contains("\\\"v\\|3\\\""));
+    }
+}
@@ -0,0 +1,389 @@
+use crate::{
+    bucket::BUCKET_CAP,
+    hasher::{BitOrder, DefaultHashBuilder, DirectoryMapper, KeyHasher},
+    map::HashMap,
+};
+use allocator_api2::alloc::{Allocator, Global};
+use std::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    hash::Hash,
+};
+
+/// A view into an occupied entry in a [`HashMap`].
+pub struct OccupiedEntry<
+    'a,
+    K,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    map: &'a mut HashMap<K, V, S, M, B, A>,
+    /// Index of the bucket where this entry lives.
+    bucket_idx: usize,
+    /// Index of this entry in the bucket.
+    idx: usize,
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Debug for OccupiedEntry<'_, K, V, S, M, B, A>
+where
+    K: Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+impl<
+        'a,
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > OccupiedEntry<'a, K, V, S, M, B, A>
+{
+    pub(crate) fn new(
+        map: &'a mut HashMap<K, V, S, M, B, A>,
+        bucket_idx: usize,
+        idx: usize,
+    ) -> Self {
+        Self {
+            map,
+            bucket_idx,
+            idx,
+        }
+    }
+
+    /// Return a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        &self.map.buckets[self.bucket_idx].keys[self.idx]
+    }
+
+    /// Return a reference to this entry's value.
+    pub fn get(&self) -> &V {
+        &self.map.buckets[self.bucket_idx].values[self.idx]
+    }
+
+    /// Return a mutable reference to this entry's value.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.buckets[self.bucket_idx].values[self.idx]
+    }
+
+    /// Convert this entry into a mutable reference to its value, which
+    /// lives as long as the map borrow.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.buckets[self.bucket_idx].values[self.idx]
+    }
+
+    /// Set the value of this entry, return the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Remove this entry from the map, return the key and value.
+    pub fn remove_entry(self) -> (K, V) {
+        self.map.remove_at(self.bucket_idx, self.idx)
+    }
+
+    /// Remove this entry from the map, return the value.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+}
+
+/// A view into a single entry in a [`HashMap`], which may either be vacant or
+/// occupied, created by [`HashMap::entry_ref()`].
+///
+/// Different from a normal entry, this is created from a borrowed key, which
+/// will only be converted to an owned key when the vacant entry is inserted.
+pub enum EntryRef<
+    'a,
+    'b,
+    K,
+    Q: ?Sized,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, K, V, S, M, B, A>),
+    /// A vacant entry.
+    Vacant(VacantEntryRef<'a, 'b, K, Q, V, S, M, B, A>),
+}
+
+impl<
+        K,
+        Q,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Debug for EntryRef<'_, '_, K, Q, V, S, M, B, A>
+where
+    K: Debug,
+    Q: Debug + ?Sized,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryRef::Occupied(entry) => {
+                f.debug_tuple("EntryRef").field(entry).finish()
+            }
+            EntryRef::Vacant(entry) => {
+                f.debug_tuple("EntryRef").field(entry).finish()
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        K,
+        Q,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > EntryRef<'a, '_, K, Q, V, S, M, B, A>
+where
+    K: Hash,
+    Q: ToOwned<Owned = K> + ?Sized,
+{
+    /// Ensure a value is in the entry by inserting `default` if it is vacant,
+    /// return a mutable reference to the value in the entry.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            EntryRef::Occupied(entry) => entry.into_mut(),
+            EntryRef::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Ensure a value is in the entry by inserting the result of `default` if
+    /// it is vacant, return a mutable reference to the value in the entry.
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut V
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            EntryRef::Occupied(entry) => entry.into_mut(),
+            EntryRef::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Ensure a value is in the entry by inserting the default value if it is
+    /// vacant, return a mutable reference to the value in the entry.
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Provide in-place mutable access to an occupied entry before any
+    /// potential inserts into the map.
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        if let EntryRef::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+
+        self
+    }
+}
+
+/// A view into a vacant entry in a [`HashMap`], it is part of the
+/// [`EntryRef`] enum.
+pub struct VacantEntryRef<
+    'a,
+    'b,
+    K,
+    Q: ?Sized,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    map: &'a mut HashMap<K, V, S, M, B, A>,
+    /// Index of the bucket where this entry will go.
+    bucket_idx: usize,
+    /// Hash value of `key`.
+    hash: S::Hash,
+    key: &'b Q,
+}
+
+impl<
+        K,
+        Q,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Debug for VacantEntryRef<'_, '_, K, Q, V, S, M, B, A>
+where
+    Q: Debug + ?Sized,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("VacantEntryRef").field(&self.key).finish()
+    }
+}
+
+impl<
+        'a,
+        'b,
+        K,
+        Q: ?Sized,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > VacantEntryRef<'a, 'b, K, Q, V, S, M, B, A>
+{
+    pub(crate) fn new(
+        map: &'a mut HashMap<K, V, S, M, B, A>,
+        bucket_idx: usize,
+        hash: S::Hash,
+        key: &'b Q,
+    ) -> Self {
+        Self {
+            map,
+            bucket_idx,
+            hash,
+            key,
+        }
+    }
+
+    /// Return a reference to the borrowed key of this entry.
+    pub fn key(&self) -> &'b Q {
+        self.key
+    }
+}
+
+impl<
+        'a,
+        K,
+        Q,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > VacantEntryRef<'a, '_, K, Q, V, S, M, B, A>
+where
+    K: Hash,
+    Q: ToOwned<Owned = K> + ?Sized,
+{
+    /// Insert `value` with an owned key converted from the borrowed key,
+    /// return a mutable reference to the value.
+    pub fn insert(self, value: V) -> &'a mut V {
+        let (bucket_idx, idx) = self.map.insert_absent(
+            self.bucket_idx,
+            self.hash,
+            self.key.to_owned(),
+            value,
+        );
+
+        &mut self.map.buckets[bucket_idx].values[idx]
+    }
+}
+
+/// The error returned by [`HashMap::try_insert()`] when the key already
+/// exists.
+///
+/// Contains the occupied entry, and the value that was not inserted.
+pub struct OccupiedError<
+    'a,
+    K,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    /// The entry in the map that was already occupied.
+    pub entry: OccupiedEntry<'a, K, V, S, M, B, A>,
+    /// The value which was not inserted, because the entry was already
+    /// occupied.
+    pub value: V,
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Debug for OccupiedError<'_, K, V, S, M, B, A>
+where
+    K: Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OccupiedError")
+            .field("key", self.entry.key())
+            .field("old_value", self.entry.get())
+            .field("new_value", &self.value)
+            .finish()
+    }
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Display for OccupiedError<'_, K, V, S, M, B, A>
+where
+    K: Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to insert {:?}, key {:?} already exists with value {:?}",
+            self.value,
+            self.entry.key(),
+            self.entry.get(),
+        )
+    }
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Error for OccupiedError<'_, K, V, S, M, B, A>
+where
+    K: Debug,
+    V: Debug,
+{
+}
@@ -0,0 +1,257 @@
+//! Background flushing of the pages of a
+//! [`DiskHashMap`](crate::DiskHashMap).
+//!
+//! The store, the write-ahead log and the pages not written to the store yet
+//! are shared by a map and its flusher. A modification appends its pages to
+//! the log, and once the log is synced, the pages are dirty, i.e., they are
+//! written to the store by the flusher rather than the map. The flusher
+//! writes one dirty page at a time, so the map is only blocked for a page
+//! write at most.
+//!
+//! Once the log grows beyond its checkpoint length, the flusher empties it:
+//! it syncs the store, and removes the directory checkpoint before it
+//! truncates the log, as the checkpoint may be older than the log, and the
+//! directory of the map is not shared. The directory is checkpointed again
+//! by the map later, and a map opened without a checkpoint rebuilds it from
+//! the bucket pages.
+
+use crate::{
+    cache::PageCache, checkpoint::remove_checkpoint, disk::WAL_CHECKPOINT_LEN,
+    page::Page, store::BucketStore, wal::Wal,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    io, mem,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// The state shared by a map and its flusher.
+pub(crate) struct Shared<T> {
+    /// Store of the pages
+    pub(crate) store: T,
+    /// Write-ahead log, `None` if the map is opened read-only, or not opened
+    /// by path
+    pub(crate) wal: Option<Wal>,
+    /// Images of the pages whose log entries are not synced yet
+    pub(crate) pending: BTreeMap<u64, Vec<u8>>,
+    /// Images of the pages whose log entries are synced, which are not
+    /// written to the store yet
+    pub(crate) dirty: BTreeMap<u64, Vec<u8>>,
+    /// Cache of the clean pages, whose capacity also bounds the dirty pages
+    pub(crate) cache: PageCache,
+    /// Error the flusher stopped at, which is returned by the next
+    /// modification of the map
+    pub(crate) error: Option<io::Error>,
+}
+
+impl<T: BucketStore> Shared<T> {
+    /// Share `store` and `wal`.
+    pub(crate) fn new(store: T, wal: Option<Wal>) -> Self {
+        Self {
+            store,
+            wal,
+            pending: BTreeMap::new(),
+            dirty: BTreeMap::new(),
+            cache: PageCache::default(),
+            error: None,
+        }
+    }
+
+    /// Read and decode the bucket page `page_no`, which may not be written to
+    /// the store yet, caching it if it is read from the store.
+    pub(crate) fn read(&mut self, page_no: u64) -> io::Result<Page> {
+        if let Some(image) = self.pending.get(&page_no) {
+            return Page::decode(image);
+        }
+        if let Some(image) = self.dirty.get(&page_no) {
+            return Page::decode(image);
+        }
+        if let Some(image) = self.cache.get(page_no) {
+            return Page::decode(image);
+        }
+
+        let image = self.store.get_page(page_no)?;
+        let page = Page::decode_verified(page_no, &image)?;
+        if self.cache.capacity() > 0 {
+            let image = image.into_owned();
+            self.cache.insert(page_no, image);
+            self.make_room()?;
+        }
+
+        Ok(page)
+    }
+
+    /// Write `image` to the page `page_no` of the store, and cache it.
+    pub(crate) fn write_page(
+        &mut self,
+        page_no: u64,
+        image: &[u8],
+    ) -> io::Result<()> {
+        self.store.write_page(page_no, image)?;
+        if self.cache.capacity() > 0 {
+            self.cache.insert(page_no, image.to_vec());
+        }
+
+        Ok(())
+    }
+
+    /// Sync the write-ahead log, so the pending pages become dirty.
+    pub(crate) fn sync_wal(&mut self) -> io::Result<()> {
+        if let Some(wal) = &mut self.wal {
+            wal.sync()?;
+        }
+        self.synced()
+    }
+
+    /// Mark the pending pages dirty, once their log entries are synced.
+    pub(crate) fn synced(&mut self) -> io::Result<()> {
+        let pending = mem::take(&mut self.pending);
+        self.dirty.extend(pending);
+        self.make_room()
+    }
+
+    /// Set the capacity of the page cache to `capacity` pages.
+    pub(crate) fn set_cache_capacity(
+        &mut self,
+        capacity: usize,
+    ) -> io::Result<()> {
+        self.cache.set_capacity(capacity);
+        self.make_room()
+    }
+
+    /// Evict the clean pages, then write back the dirty ones, until they fit
+    /// in the capacity of the page cache, if it is enabled.
+    fn make_room(&mut self) -> io::Result<()> {
+        let capacity = self.cache.capacity();
+        while capacity > 0 && self.cache.len() + self.dirty.len() > capacity {
+            if self.cache.evict() {
+                continue;
+            }
+            // there is no room to cache a written back page
+            let Some((page_no, image)) = self.dirty.pop_first() else {
+                break;
+            };
+            if let Err(err) = self.store.write_page(page_no, &image) {
+                self.dirty.insert(page_no, image);
+                return Err(err);
+            }
+            self.cache.remove(page_no);
+        }
+
+        Ok(())
+    }
+
+    /// Write the dirty pages to the store.
+    pub(crate) fn write_dirty(&mut self) -> io::Result<()> {
+        while self.write_one_dirty()? {}
+
+        Ok(())
+    }
+
+    /// Write a dirty page to the store, return false if there is none.
+    fn write_one_dirty(&mut self) -> io::Result<bool> {
+        let Some((page_no, image)) = self.dirty.pop_first() else {
+            return Ok(false);
+        };
+        if let Err(err) = self.store.write_page(page_no, &image) {
+            self.dirty.insert(page_no, image);
+            return Err(err);
+        }
+        // the page stays in memory, but becomes clean
+        self.cache.insert(page_no, image);
+
+        Ok(true)
+    }
+
+    /// Write all the pages, sync the store, call `checkpoint` to checkpoint
+    /// the directory, then empty the write-ahead log.
+    pub(crate) fn empty_wal(
+        &mut self,
+        checkpoint: impl FnOnce() -> io::Result<()>,
+    ) -> io::Result<()> {
+        // the pages may only be written after their log entries are synced
+        self.sync_wal()?;
+        self.write_dirty()?;
+        self.store.sync()?;
+        // the log is only emptied once the directory checkpoint is as new as
+        // the file
+        checkpoint()?;
+        self.wal.as_mut().map_or(Ok(()), Wal::truncate)
+    }
+}
+
+/// A thread flushing the pages of a map in the background, which is stopped
+/// when dropped.
+pub(crate) struct Flusher {
+    /// Sender whose drop stops the thread
+    stop: Option<Sender<()>>,
+    /// The thread
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Flusher {
+    /// Start flushing `shared` every `interval`, the directory checkpoint of
+    /// the map is at `checkpoint_path`.
+    pub(crate) fn start<T: BucketStore + Send + 'static>(
+        shared: Arc<Mutex<Shared<T>>>,
+        checkpoint_path: PathBuf,
+        interval: Duration,
+    ) -> io::Result<Self> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name("disk-hashmap-flusher".into())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) =
+                    stopped.recv_timeout(interval)
+                {
+                    if let Err(err) = flush(&shared, &checkpoint_path) {
+                        shared.lock().error = Some(err);
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Write the dirty pages of `shared`, and empty its write-ahead log if it
+/// is too long, removing the directory checkpoint at `checkpoint_path`.
+fn flush<T: BucketStore>(
+    shared: &Mutex<Shared<T>>,
+    checkpoint_path: &Path,
+) -> io::Result<()> {
+    // the lock is released between pages, so the map is not blocked for the
+    // whole flush
+    while shared.lock().write_one_dirty()? {}
+
+    let mut shared = shared.lock();
+    if shared
+        .wal
+        .as_ref()
+        .is_some_and(|wal| wal.len() > WAL_CHECKPOINT_LEN)
+    {
+        shared.empty_wal(|| remove_checkpoint(checkpoint_path))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,333 @@
+use crate::{
+    bucket::{Bucket, BucketPolicy, BUCKET_CAP},
+    hasher::HashValue,
+    primitives::AtomicUsize,
+    sync::{BucketCell, WriteLock},
+};
+use std::{
+    fmt::{Debug, Formatter},
+    ops::{Deref, DerefMut},
+    sync::{atomic::Ordering, Arc},
+};
+
+/// A reference to an entry of a [`SyncHashMap`](crate::SyncHashMap).
+///
+/// This holds a snapshot of the bucket where the entry lives rather than a
+/// lock, so it never blocks the writers, and it won't see their updates.
+pub struct Ref<K, V, H = u64, const B: usize = BUCKET_CAP> {
+    bucket: Arc<Bucket<K, V, H, B>>,
+    /// Index of this entry in the bucket.
+    idx: usize,
+}
+
+impl<K, V, H, const B: usize> Ref<K, V, H, B> {
+    pub(crate) fn new(bucket: Arc<Bucket<K, V, H, B>>, idx: usize) -> Self {
+        Self { bucket, idx }
+    }
+
+    /// Return a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        &self.bucket.keys[self.idx]
+    }
+
+    /// Return a reference to this entry's value.
+    pub fn value(&self) -> &V {
+        &self.bucket.values[self.idx]
+    }
+
+    /// Return references to this entry's key and value.
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key(), self.value())
+    }
+}
+
+impl<K, V, H, const B: usize> Clone for Ref<K, V, H, B> {
+    /// Clone the guard, which shares the snapshot of the bucket rather than
+    /// copying it.
+    fn clone(&self) -> Self {
+        Self {
+            bucket: Arc::clone(&self.bucket),
+            idx: self.idx,
+        }
+    }
+}
+
+impl<K, V, H, const B: usize> Deref for Ref<K, V, H, B> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+impl<K: Debug, V: Debug, H, const B: usize> Debug for Ref<K, V, H, B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ref")
+            .field("key", self.key())
+            .field("value", self.value())
+            .finish()
+    }
+}
+
+/// A mutable reference to an entry of a [`SyncHashMap`](crate::SyncHashMap),
+/// which holds the write lock of the bucket where the entry lives.
+///
+/// Modifications are made to a private copy of the bucket, which is published
+/// when this guard is dropped. Until then, other writers of the bucket block,
+/// and readers see the old value.
+pub struct RefMut<K, V, H = u64, const B: usize = BUCKET_CAP> {
+    /// The bucket the copy will be published to.
+    cell: Arc<BucketCell<K, V, H, B>>,
+    /// Write lock of `cell`, released after the copy gets published.
+    _lock: WriteLock,
+    /// The private copy of the bucket, `None` once it is published.
+    bucket: Option<Bucket<K, V, H, B>>,
+    /// Index of this entry in the bucket.
+    idx: usize,
+}
+
+impl<K, V, H, const B: usize> RefMut<K, V, H, B> {
+    pub(crate) fn new(
+        cell: Arc<BucketCell<K, V, H, B>>,
+        lock: WriteLock,
+        bucket: Bucket<K, V, H, B>,
+        idx: usize,
+    ) -> Self {
+        Self {
+            cell,
+            _lock: lock,
+            bucket: Some(bucket),
+            idx,
+        }
+    }
+
+    fn bucket(&self) -> &Bucket<K, V, H, B> {
+        self.bucket.as_ref().expect("RefMut: bucket is published")
+    }
+
+    fn bucket_mut(&mut self) -> &mut Bucket<K, V, H, B> {
+        self.bucket.as_mut().expect("RefMut: bucket is published")
+    }
+
+    /// Return a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        &self.bucket().keys[self.idx]
+    }
+
+    /// Return a reference to this entry's value.
+    pub fn value(&self) -> &V {
+        &self.bucket().values[self.idx]
+    }
+
+    /// Return a mutable reference to this entry's value.
+    pub fn value_mut(&mut self) -> &mut V {
+        let idx = self.idx;
+        &mut self.bucket_mut().values[idx]
+    }
+
+    /// Return a reference to this entry's key and a mutable reference to its
+    /// value.
+    pub fn pair_mut(&mut self) -> (&K, &mut V) {
+        let idx = self.idx;
+        let bucket = self.bucket_mut();
+        (&bucket.keys[idx], &mut bucket.values[idx])
+    }
+
+    /// Publish the modifications, and convert this guard to a [`Ref`].
+    pub fn downgrade(mut self) -> Ref<K, V, H, B> {
+        let bucket = self.publish().expect("RefMut: bucket is published");
+
+        Ref::new(bucket, self.idx)
+    }
+
+    /// Publish the private copy if it hasn't been published, return it.
+    fn publish(&mut self) -> Option<Arc<Bucket<K, V, H, B>>> {
+        let bucket = Arc::new(self.bucket.take()?);
+        self.cell.publish(Arc::clone(&bucket));
+
+        Some(bucket)
+    }
+}
+
+impl<K, V, H, const B: usize> Drop for RefMut<K, V, H, B> {
+    fn drop(&mut self) {
+        // fields, including the lock, are dropped after this
+        self.publish();
+    }
+}
+
+impl<K, V, H, const B: usize> Deref for RefMut<K, V, H, B> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+impl<K, V, H, const B: usize> DerefMut for RefMut<K, V, H, B> {
+    fn deref_mut(&mut self) -> &mut V {
+        self.value_mut()
+    }
+}
+
+impl<K: Debug, V: Debug, H, const B: usize> Debug for RefMut<K, V, H, B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RefMut")
+            .field("key", self.key())
+            .field("value", self.value())
+            .finish()
+    }
+}
+
+/// A view into a single entry of a [`SyncHashMap`](crate::SyncHashMap), which
+/// may either be vacant or occupied, created by
+/// [`SyncHashMap::entry()`](crate::SyncHashMap::entry).
+///
+/// The bucket of the entry is write-locked until the entry, or the guard it
+/// gets converted to, is dropped, so read-modify-write operations done
+/// through it are atomic.
+pub enum SyncEntry<'a, K, V, H = u64, const B: usize = BUCKET_CAP> {
+    /// An occupied entry.
+    Occupied(RefMut<K, V, H, B>),
+    /// A vacant entry.
+    Vacant(SyncVacantEntry<'a, K, V, H, B>),
+}
+
+impl<K: Debug, V: Debug, H, const B: usize> Debug
+    for SyncEntry<'_, K, V, H, B>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncEntry::Occupied(entry) => {
+                f.debug_tuple("SyncEntry").field(entry).finish()
+            }
+            SyncEntry::Vacant(entry) => {
+                f.debug_tuple("SyncEntry").field(entry).finish()
+            }
+        }
+    }
+}
+
+impl<K, V, H, const B: usize> SyncEntry<'_, K, V, H, B>
+where
+    K: Clone,
+    V: Clone,
+    H: HashValue,
+{
+    /// Return a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        match self {
+            SyncEntry::Occupied(entry) => entry.key(),
+            SyncEntry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Ensure a value is in the entry by inserting `default` if it is vacant,
+    /// return a guard mutably referencing the entry.
+    pub fn or_insert(self, default: V) -> RefMut<K, V, H, B> {
+        match self {
+            SyncEntry::Occupied(entry) => entry,
+            SyncEntry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Ensure a value is in the entry by inserting the result of `default` if
+    /// it is vacant, return a guard mutably referencing the entry.
+    pub fn or_insert_with<F>(self, default: F) -> RefMut<K, V, H, B>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            SyncEntry::Occupied(entry) => entry,
+            SyncEntry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Ensure a value is in the entry by inserting the default value if it is
+    /// vacant, return a guard mutably referencing the entry.
+    pub fn or_default(self) -> RefMut<K, V, H, B>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Provide in-place mutable access to an occupied entry before any
+    /// potential inserts into the map.
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        if let SyncEntry::Occupied(entry) = &mut self {
+            f(entry.value_mut());
+        }
+
+        self
+    }
+}
+
+/// A view into a vacant entry of a [`SyncHashMap`](crate::SyncHashMap), it is
+/// part of the [`SyncEntry`] enum.
+///
+/// The bucket where the entry will go is write-locked, and has room for it.
+pub struct SyncVacantEntry<'a, K, V, H = u64, const B: usize = BUCKET_CAP> {
+    /// The bucket where the entry will go.
+    cell: Arc<BucketCell<K, V, H, B>>,
+    /// Write lock of `cell`.
+    lock: WriteLock,
+    /// Length of the map, incremented on insertion.
+    len: &'a AtomicUsize,
+    /// Hash value of `key`.
+    hash: H,
+    key: K,
+}
+
+impl<K: Debug, V, H, const B: usize> Debug for SyncVacantEntry<'_, K, V, H, B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SyncVacantEntry").field(&self.key).finish()
+    }
+}
+
+impl<'a, K, V, H, const B: usize> SyncVacantEntry<'a, K, V, H, B> {
+    pub(crate) fn new(
+        cell: Arc<BucketCell<K, V, H, B>>,
+        lock: WriteLock,
+        len: &'a AtomicUsize,
+        hash: H,
+        key: K,
+    ) -> Self {
+        Self {
+            cell,
+            lock,
+            len,
+            hash,
+            key,
+        }
+    }
+
+    /// Return a reference to the key of this entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Take the ownership of the key, releasing the bucket.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+impl<K: Clone, V: Clone, H: HashValue, const B: usize>
+    SyncVacantEntry<'_, K, V, H, B>
+{
+    /// Insert `value`, return a guard mutably referencing the entry.
+    ///
+    /// The entry becomes visible to the readers once the guard is dropped.
+    pub fn insert(self, value: V) -> RefMut<K, V, H, B> {
+        let mut bucket = Bucket::clone(&self.cell.snapshot());
+        let idx =
+            bucket.push((self.hash, self.key, value), BucketPolicy::Unsorted);
+        self.len.fetch_add(1, Ordering::Release);
+
+        RefMut::new(self.cell, self.lock, bucket, idx)
+    }
+}
@@ -0,0 +1,426 @@
+//! Hashing related stuff.
+//!
+//! # Default hasher
+//!
+//! The default hasher used by [`HashMap::new()`](crate::HashMap::new).
+//!
+//! By default, this is [`std::collections::hash_map::RandomState`], i.e.,
+//! SipHash, which is resistant to HashDoS attacks but relatively slow for
+//! small keys. Two cargo features are provided to swap it for a faster one:
+//!
+//! * `ahash`: use [`ahash::RandomState`]
+//! * `fxhash`: use [`fxhash::FxBuildHasher`]
+//!
+//! If both are enabled, `ahash` takes precedence.
+//!
+//! # Hash width
+//!
+//! The map consumes hash values through the [`KeyHasher`] trait, which is
+//! implemented for every [`BuildHasher`] with 64-bit hash values. Hashers
+//! producing 128-bit hash values can be used by implementing [`KeyHasher`]
+//! with `Hash = u128`.
+//!
+//! # Determinism
+//!
+//! The layout of a map, i.e., its directory and buckets, is a pure function
+//! of its hash values and the sequence of operations applied to it. With a
+//! hasher that does not depend on any random state, e.g., [`FixedState`] used
+//! by [`HashMap::with_seed()`](crate::HashMap::with_seed), the layout is a
+//! pure function of the sequence of operations and the seed, given that the
+//! [`Hash`] implementation of the keys is deterministic.
+
+use std::{
+    fmt::Debug,
+    hash::{BuildHasher, Hash, Hasher},
+};
+
+/// The default hash builder of [`HashMap`](crate::HashMap).
+#[cfg(feature = "ahash")]
+pub type DefaultHashBuilder = ahash::RandomState;
+
+/// The default hash builder of [`HashMap`](crate::HashMap).
+#[cfg(all(feature = "fxhash", not(feature = "ahash")))]
+pub type DefaultHashBuilder = fxhash::FxBuildHasher;
+
+/// The default hash builder of [`HashMap`](crate::HashMap).
+#[cfg(not(any(feature = "ahash", feature = "fxhash")))]
+pub type DefaultHashBuilder = std::collections::hash_map::RandomState;
+
+// `fxhash` is unused when `ahash` is also enabled.
+#[cfg(all(feature = "ahash", feature = "fxhash"))]
+use fxhash as _;
+
+/// Which bits of the hash values are used to index the directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Use the most significant bits, this is the default.
+    #[default]
+    Msb,
+    /// Use the least significant bits, which suits the hashers whose high
+    /// bits are of low entropy, e.g., `fxhash` for small integer keys.
+    Lsb,
+}
+
+/// A [`BuildHasher`] with a fixed seed, used by
+/// [`HashMap::with_seed()`](crate::HashMap::with_seed).
+///
+/// Unlike [`DefaultHashBuilder`], which may be randomly keyed, hashers built
+/// by a `FixedState` produce the same hash values for the same seed, so that
+/// the layout of a map is reproducible: it's a pure function of the
+/// sequence of operations and the seed, given that the [`Hash`]
+/// implementation of the keys is deterministic.
+///
+/// The hashers are [`FixedHasher`]s, see it for the hashing algorithm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FixedState {
+    seed: u64,
+}
+
+impl FixedState {
+    /// Create a `FixedState` with `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Return the seed.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl BuildHasher for FixedState {
+    type Hasher = FixedHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        FixedHasher::with_keys(self.seed, 0)
+    }
+}
+
+/// The [`Hasher`] built by [`FixedState`], which implements SipHash-1-3.
+///
+/// The hasher is keyed by `(seed, 0)`, and hashes the bytes written to it,
+/// with the integers written as their little-endian bytes, and `usize` and
+/// `isize` as 64-bit integers. The hash value of a sequence of writes is
+/// thus the same on every platform and every Rust release.
+#[derive(Debug, Clone)]
+pub struct FixedHasher {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    /// Bytes written but not compressed yet, in little-endian order.
+    tail: u64,
+    /// Number of the bytes in `tail`.
+    ntail: usize,
+    /// Number of the bytes written.
+    len: usize,
+}
+
+impl FixedHasher {
+    /// Create a hasher keyed by `k0` and `k1`.
+    fn with_keys(k0: u64, k1: u64) -> Self {
+        Self {
+            v0: k0 ^ 0x736f_6d65_7073_6575,
+            v1: k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: k0 ^ 0x6c79_6765_6e65_7261,
+            v3: k1 ^ 0x7465_6462_7974_6573,
+            tail: 0,
+            ntail: 0,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    /// Compress a word of 8 bytes, with 1 round.
+    #[inline]
+    fn compress(&mut self, word: u64) {
+        self.v3 ^= word;
+        self.round();
+        self.v0 ^= word;
+    }
+}
+
+impl Hasher for FixedHasher {
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.len = self.len.wrapping_add(bytes.len());
+
+        // fill the tail first
+        if self.ntail > 0 {
+            let n = bytes.len().min(8 - self.ntail);
+            for (i, byte) in bytes[..n].iter().enumerate() {
+                self.tail |= u64::from(*byte) << (8 * (self.ntail + i));
+            }
+            self.ntail += n;
+            bytes = &bytes[n..];
+            if self.ntail < 8 {
+                return;
+            }
+            self.compress(self.tail);
+            self.tail = 0;
+            self.ntail = 0;
+        }
+
+        let (words, rest) = bytes.as_chunks::<8>();
+        for word in words {
+            self.compress(u64::from_le_bytes(*word));
+        }
+        for (i, byte) in rest.iter().enumerate() {
+            self.tail |= u64::from(*byte) << (8 * i);
+        }
+        self.ntail = rest.len();
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    #[inline]
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let last = ((self.len as u64 & 0xff) << 56) | self.tail;
+        state.compress(last);
+        state.v2 ^= 0xff;
+        for _ in 0..3 {
+            state.round();
+        }
+
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+/// A hash value, whose bits are used to index the directory.
+///
+/// Implemented for `u64`, which is what [`BuildHasher`] produces, and `u128`
+/// for hashers with wider outputs. The width bounds the depths: the global
+/// depth can never exceed [`HashValue::BITS`], nor 64, as the prefixes of
+/// the buckets are `u64`s.
+pub trait HashValue: Copy + Ord + Debug {
+    /// Number of bits of this hash value.
+    const BITS: u32;
+
+    /// Return the `idx`th most significant bit, `idx` starts from 0.
+    fn bit(self, idx: u32) -> usize;
+
+    /// Return the `n` most significant bits, as the low bits of the returned
+    /// value, which is 0 if `n` is 0.
+    ///
+    /// This indexes the directory, whose global depth is `n`, so `n` should
+    /// not exceed [`usize::BITS`]. The default implementation collects the
+    /// bits one by one, the implementations for `u64` and `u128` shift.
+    #[inline]
+    fn prefix(self, n: u32) -> usize {
+        (0..n).fold(0, |acc, idx| (acc << 1) | self.bit(idx))
+    }
+
+    /// Reverse the order of bits.
+    fn reverse_bits(self) -> Self;
+
+    /// Return a 7-bit fragment of this hash value, which is used to probe
+    /// buckets before comparing the hash values and keys.
+    ///
+    /// All the bits contribute to the fragment, as the bits used to index the
+    /// directory are shared by the entries of a bucket.
+    fn tag(self) -> u8;
+}
+
+impl HashValue for u64 {
+    const BITS: u32 = u64::BITS;
+
+    #[inline]
+    fn bit(self, idx: u32) -> usize {
+        ((self >> (Self::BITS - idx - 1)) & 1) as usize
+    }
+    #[inline]
+    fn prefix(self, n: u32) -> usize {
+        // a shift by the whole width overflows
+        self.checked_shr(Self::BITS - n).unwrap_or(0) as usize
+    }
+    #[inline]
+    fn reverse_bits(self) -> Self {
+        u64::reverse_bits(self)
+    }
+
+    #[inline]
+    fn tag(self) -> u8 {
+        // Fibonacci hashing, the high bits of the product depend on all the
+        // bits of `self`.
+        (self.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 57) as u8
+    }
+}
+
+impl HashValue for u128 {
+    const BITS: u32 = u128::BITS;
+
+    #[inline]
+    fn bit(self, idx: u32) -> usize {
+        ((self >> (Self::BITS - idx - 1)) & 1) as usize
+    }
+    #[inline]
+    fn prefix(self, n: u32) -> usize {
+        self.checked_shr(Self::BITS - n).unwrap_or(0) as usize
+    }
+    #[inline]
+    fn reverse_bits(self) -> Self {
+        u128::reverse_bits(self)
+    }
+
+    #[inline]
+    fn tag(self) -> u8 {
+        ((self as u64) ^ ((self >> 64) as u64)).tag()
+    }
+}
+
+/// Hash keys into [`HashValue`]s, this is what [`HashMap`](crate::HashMap)
+/// uses to hash keys.
+///
+/// Every [`BuildHasher`] implements this trait with `u64` hash values,
+/// implement it directly to use hashers with wider outputs, e.g., 128-bit
+/// hashers.
+pub trait KeyHasher {
+    /// Type of the hash values.
+    type Hash: HashValue;
+
+    /// Calculate the hash value of `key`.
+    fn hash_key<Q>(&self, key: &Q) -> Self::Hash
+    where
+        Q: Hash + ?Sized;
+}
+
+impl<S: BuildHasher> KeyHasher for S {
+    type Hash = u64;
+
+    #[inline]
+    fn hash_key<Q>(&self, key: &Q) -> Self::Hash
+    where
+        Q: Hash + ?Sized,
+    {
+        self.hash_one(key)
+    }
+}
+
+/// Map hash values to the bits used to index the directory.
+///
+/// With global depth `d`, the directory index of a hash value `hash` is the
+/// `d` most significant bits of `self.map(hash)`. `map` must be a pure
+/// function, and should preserve the entropy of the hash values, e.g., to
+/// apply Fibonacci hashing:
+///
+/// ```
+/// use extendable_hashmap::{hasher::DirectoryMapper, HashMap};
+///
+/// struct Fibonacci;
+/// impl DirectoryMapper<u64> for Fibonacci {
+///     fn map(&self, hash: u64) -> u64 {
+///         hash.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+///     }
+/// }
+///
+/// let mut map = HashMap::with_mapper_and_hasher(
+///     Fibonacci,
+///     std::collections::hash_map::RandomState::new(),
+/// );
+/// map.insert(1, 1);
+/// assert_eq!(map.get(&1), Some(&1));
+/// ```
+///
+/// [`BitOrder`] is the default implementation.
+pub trait DirectoryMapper<H: HashValue> {
+    /// Map `hash` to the bits used to index the directory.
+    fn map(&self, hash: H) -> H;
+}
+
+impl<H: HashValue> DirectoryMapper<H> for BitOrder {
+    #[inline]
+    fn map(&self, hash: H) -> H {
+        match self {
+            BitOrder::Msb => hash,
+            BitOrder::Lsb => hash.reverse_bits(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bit_order_map() {
+        let hash = 0b110_u64;
+        assert_eq!(BitOrder::Msb.map(hash), hash);
+        assert_eq!(BitOrder::Lsb.map(hash), 0b011 << 61);
+        assert_eq!(BitOrder::Lsb.map(1_u128), 1 << 127);
+    }
+
+    #[test]
+    fn fixed_hasher_is_siphash_1_3() {
+        let hash = |seed, chunks: &[&[u8]]| {
+            let mut hasher = FixedState::with_seed(seed).build_hasher();
+            for chunk in chunks {
+                hasher.write(chunk);
+            }
+            hasher.finish()
+        };
+
+        // SipHash-1-3 keyed by (0, 0)
+        assert_eq!(hash(0, &[]), 0xd1fb_a762_150c_532c);
+        assert_eq!(hash(0, &[b"hello world"]), 0xb1b1_f2e7_07e4_ac8a);
+        // the tail is carried across writes
+        assert_eq!(
+            hash(0, &[b"hel", b"lo", b" world"]),
+            hash(0, &[b"hello world"])
+        );
+        assert_ne!(hash(1, &[b"hello world"]), hash(0, &[b"hello world"]));
+
+        let mut hasher = FixedState::with_seed(0).build_hasher();
+        hasher.write_usize(1);
+        assert_eq!(hasher.finish(), hash(0, &[&1_u64.to_le_bytes()]));
+    }
+
+    #[test]
+    fn tag_is_7_bits() {
+        for hash in [0, 1, u64::MAX, 1 << 63, 0x1234_5678_9ABC_DEF0] {
+            assert!(hash.tag() < 0x80);
+            assert!((hash as u128).tag() < 0x80);
+        }
+        // hash values differing in their low bits only have different tags
+        assert_ne!(1_u64.tag(), 2_u64.tag());
+    }
+}
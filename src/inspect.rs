@@ -0,0 +1,157 @@
+//! Inspecting the buckets of a [`HashMap`].
+
+use crate::{
+    arena::Arena,
+    bucket::{Bucket, BucketIdx, BUCKET_CAP},
+    hasher::{DirectoryMapper, KeyHasher},
+    map::HashMap,
+    slab::Slab,
+};
+use allocator_api2::alloc::{Allocator, Global};
+use std::iter::FusedIterator;
+
+/// The shape of a bucket of a [`HashMap`], yielded by
+/// [`HashMap::bucket_infos()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketInfo {
+    prefix: u64,
+    local_depth: usize,
+    len: usize,
+}
+
+impl BucketInfo {
+    /// Return the prefix of the bucket, i.e., the first
+    /// [`local_depth()`](Self::local_depth) bits of the mapped hash values
+    /// of its entries, the most significant one first, the local depth is
+    /// never greater than 64, even for 128-bit hash values.
+    #[inline]
+    pub fn prefix(&self) -> u64 {
+        self.prefix
+    }
+
+    /// Return the local depth of the bucket, the bucket is pointed to by
+    /// `2^(global_depth - local_depth)` directory entries.
+    #[inline]
+    pub fn local_depth(&self) -> usize {
+        self.local_depth
+    }
+
+    /// Return the number of entries of the bucket.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if the bucket has no entries.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// An iterator over the [`BucketInfo`]s of a [`HashMap`], ordered by the
+/// bucket prefixes, created by [`HashMap::bucket_infos()`].
+///
+/// `H` is the type of the hash values, see [`KeyHasher`].
+pub struct BucketInfos<
+    'a,
+    K,
+    V,
+    H = u64,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    directories: std::slice::Iter<'a, BucketIdx>,
+    buckets: &'a Slab<Bucket<K, V, H, B, Arena<A>>, A>,
+    /// The bucket of the last visited directory entry.
+    last: Option<BucketIdx>,
+}
+
+impl<K, V, H, const B: usize, A: Allocator + Clone> Iterator
+    for BucketInfos<'_, K, V, H, B, A>
+{
+    type Item = BucketInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the directory entries pointing to a bucket are contiguous
+        let bucket_idx = self
+            .directories
+            .by_ref()
+            .find(|bucket_idx| Some(**bucket_idx) != self.last)?;
+        self.last = Some(*bucket_idx);
+        let bucket = &self.buckets[bucket_idx.get()];
+
+        Some(BucketInfo {
+            prefix: bucket.prefix,
+            local_depth: bucket.local_depth(),
+            len: bucket.len(),
+        })
+    }
+}
+
+impl<K, V, H, const B: usize, A: Allocator + Clone> FusedIterator
+    for BucketInfos<'_, K, V, H, B, A>
+{
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> HashMap<K, V, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Return an iterator over the prefix, the local depth and the number of
+    /// entries of every bucket, ordered by the bucket prefixes.
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::HashMap;
+    ///
+    /// let mut map = HashMap::with_bucket_capacity(4);
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// assert_eq!(map.bucket_infos().count(), map.bucket_count());
+    /// let slots = map
+    ///     .bucket_infos()
+    ///     .map(|info| 1 << (map.global_depth() - info.local_depth()))
+    ///     .sum::<usize>();
+    /// assert_eq!(slots, map.directory_len());
+    /// ```
+    pub fn bucket_infos(&self) -> BucketInfos<'_, K, V, S::Hash, B, A> {
+        BucketInfos {
+            directories: self.directories.iter(),
+            buckets: &self.buckets,
+            last: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bucket_infos_follow_the_directory() {
+        let mut map = HashMap::with_bucket_capacity(4);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.global_depth(), map.global_depth);
+        assert_eq!(map.directory_len(), 1 << map.global_depth());
+        assert_eq!(map.bucket_count(), map.buckets.len());
+
+        let infos = map.bucket_infos().collect::<Vec<_>>();
+        assert_eq!(infos.len(), map.bucket_count());
+        assert_eq!(infos.iter().map(BucketInfo::len).sum::<usize>(), 1000);
+        // the prefixes, shifted to the global depth, are the first directory
+        // entries pointing to the buckets
+        let mut slot = 0;
+        for info in &infos {
+            let shift = map.global_depth() - info.local_depth();
+            assert_eq!((info.prefix() << shift) as usize, slot);
+            slot += 1 << shift;
+        }
+        assert_eq!(slot, map.directory_len());
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{
+    arena::Arena,
+    bucket::{Bucket, Entries, BUCKET_CAP},
+    hasher::{BitOrder, DefaultHashBuilder, DirectoryMapper, KeyHasher},
+    map::HashMap,
+    slab,
+};
+use allocator_api2::alloc::{Allocator, Global};
+use std::iter::FusedIterator;
+
+/// A lazy iterator removing and yielding the entries that match a predicate,
+/// created by [`HashMap::extract_if()`].
+///
+/// Coalescence is deferred until this iterator is dropped, so that bucket
+/// indexes stay valid during iteration.
+pub struct ExtractIf<
+    'a,
+    K,
+    V,
+    F,
+    S = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+    F: FnMut(&K, &mut V) -> bool,
+{
+    map: &'a mut HashMap<K, V, S, M, B, A>,
+    /// Index of the bucket being scanned.
+    bucket_idx: usize,
+    /// Index of the next entry to check in the bucket being scanned.
+    idx: usize,
+    pred: F,
+}
+
+impl<'a, K, V, F, S, M, const B: usize, A: Allocator + Clone>
+    ExtractIf<'a, K, V, F, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+    F: FnMut(&K, &mut V) -> bool,
+{
+    pub(crate) fn new(map: &'a mut HashMap<K, V, S, M, B, A>, pred: F) -> Self {
+        Self {
+            map,
+            bucket_idx: 0,
+            idx: 0,
+            pred,
+        }
+    }
+}
+
+impl<K, V, F, S, M, const B: usize, A: Allocator + Clone> Iterator
+    for ExtractIf<'_, K, V, F, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+    F: FnMut(&K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.bucket_idx < self.map.buckets.capacity() {
+            // skip the vacant slots of the removed buckets
+            let Some(bucket) = self.map.buckets.get_mut(self.bucket_idx) else {
+                self.bucket_idx += 1;
+                continue;
+            };
+            while self.idx < bucket.len() {
+                let k = &bucket.keys[self.idx];
+                let v = &mut bucket.values[self.idx];
+                if (self.pred)(k, v) {
+                    self.map.len -= 1;
+                    let (_, k, v) =
+                        bucket.remove(self.idx, self.map.bucket_policy);
+                    return Some((k, v));
+                }
+                self.idx += 1;
+            }
+
+            self.bucket_idx += 1;
+            self.idx = 0;
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.map.len))
+    }
+}
+
+impl<K, V, F, S, M, const B: usize, A: Allocator + Clone> Drop
+    for ExtractIf<'_, K, V, F, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+    F: FnMut(&K, &mut V) -> bool,
+{
+    fn drop(&mut self) {
+        // A coalescence removes a bucket, which may be one that hasn't been
+        // checked, so check that the bucket still exists.
+        for bucket_idx in 0..self.map.buckets.capacity() {
+            if self.map.buckets.contains(bucket_idx) {
+                self.map.coalesce(bucket_idx);
+            }
+        }
+    }
+}
+
+/// An owning iterator over the entries of a [`HashMap`], in arbitrary order.
+///
+/// `H` is the type of the hash values, see [`KeyHasher`].
+pub struct IntoIter<
+    K,
+    V,
+    H = u64,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    buckets: slab::IntoIter<Bucket<K, V, H, B, Arena<A>>, A>,
+    /// Entries of the bucket being consumed.
+    data: Entries<K, V, H, B, Arena<A>>,
+    /// Number of the remaining entries.
+    len: usize,
+}
+
+impl<K, V, H, const B: usize, A: Allocator + Clone> IntoIter<K, V, H, B, A> {
+    pub(crate) fn new<S, M>(map: HashMap<K, V, S, M, B, A>) -> Self
+    where
+        S: KeyHasher<Hash = H>,
+    {
+        Self {
+            buckets: map.buckets.into_iter(),
+            data: Bucket::new_in(0, 0, 0, map.arena.clone()).into_entries(),
+            len: map.len,
+        }
+    }
+}
+
+impl<K, V, H, const B: usize, A: Allocator + Clone> Iterator
+    for IntoIter<K, V, H, B, A>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((_, k, v)) = self.data.next() {
+                self.len -= 1;
+                return Some((k, v));
+            }
+            self.data = self.buckets.next()?.1.into_entries();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V, H, const B: usize, A: Allocator + Clone> ExactSizeIterator
+    for IntoIter<K, V, H, B, A>
+{
+}
+
+impl<K, V, H, const B: usize, A: Allocator + Clone> FusedIterator
+    for IntoIter<K, V, H, B, A>
+{
+}
+
+impl<K, V, S: KeyHasher, M, const B: usize, A: Allocator + Clone> IntoIterator
+    for HashMap<K, V, S, M, B, A>
+{
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, S::Hash, B, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
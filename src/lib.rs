@@ -1,7 +1,97 @@
-#![feature(vec_push_within_capacity)]
+// `Allocator` itself is stable, the allocator parameters of the std
+// collections, e.g., of `vec::IntoIter`, are still behind `allocator_ext`.
+#![cfg_attr(feature = "allocator_api", feature(allocator_ext))]
 
+#[cfg(feature = "rkyv")]
+mod archive;
+mod arena;
+#[cfg(feature = "async")]
+mod async_map;
 mod bucket;
+mod builder;
+#[cfg(feature = "disk")]
+mod cache;
+#[cfg(feature = "disk")]
+mod checkpoint;
+pub mod codec;
+mod column;
+mod counters;
+mod directory;
+#[cfg(feature = "disk")]
+mod disk;
+mod dot;
+mod entry;
+#[cfg(feature = "disk")]
+mod flusher;
+mod guard;
+pub mod hasher;
+mod inspect;
+mod iter;
+mod lock;
+mod macros;
 mod map;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod observer;
+#[cfg(feature = "disk")]
+mod options;
+#[cfg(feature = "disk")]
+mod page;
+#[cfg(feature = "rayon")]
+mod par;
+mod primitives;
+pub mod raw_entry;
+#[cfg(feature = "serde")]
+mod serde;
+mod sharded;
+mod slab;
+mod snapshot;
+mod stats;
+#[cfg(feature = "disk")]
+mod store;
+pub mod stream;
+mod sync;
+mod usage;
 pub(crate) mod util;
+mod validate;
+#[cfg(feature = "disk")]
+mod wal;
+#[cfg(any(feature = "bincode", feature = "postcard"))]
+mod wire;
 
-pub use map::HashMap;
+#[cfg(feature = "rkyv")]
+pub use archive::{ArchivedHashMap, HashMapResolver};
+#[cfg(feature = "async")]
+pub use async_map::{AsyncHashMap, AsyncRef, AsyncRefMut};
+pub use bucket::BucketPolicy;
+pub use builder::HashMapBuilder;
+pub use counters::Counters;
+#[cfg(feature = "disk")]
+pub use disk::DiskHashMap;
+pub use entry::{EntryRef, OccupiedEntry, OccupiedError, VacantEntryRef};
+pub use guard::{Ref, RefMut, SyncEntry, SyncVacantEntry};
+pub use hasher::{BitOrder, DefaultHashBuilder, FixedState};
+pub use inspect::{BucketInfo, BucketInfos};
+pub use iter::{ExtractIf, IntoIter};
+pub use lock::{LockPolicy, RawShardLock, ShardReadGuard, ShardWriteGuard};
+pub use map::{CoalescePolicy, HashMap};
+pub use observer::MapEvent;
+#[cfg(feature = "disk")]
+pub use options::DiskOptions;
+#[cfg(feature = "disk")]
+pub use page::{Compression, CorruptPage};
+#[cfg(feature = "rayon")]
+pub use par::{ParIter, ParIterMut};
+pub use sharded::{
+    ShardedEntry, ShardedHashMap, ShardedOccupiedEntry, ShardedVacantEntry,
+};
+pub use stats::Stats;
+#[cfg(feature = "mmap")]
+pub use store::MmapStore;
+#[cfg(feature = "disk")]
+pub use store::{BucketStore, FileStore, MemoryStore, WriteMode};
+pub use sync::{LockStriping, SyncHashMap};
+pub use usage::MemoryUsage;
+pub use validate::InvariantViolation;
+#[cfg(feature = "disk")]
+pub use wal::Durability;
@@ -0,0 +1,370 @@
+//! The shard locks of a [`ShardedHashMap`](crate::ShardedHashMap), whose
+//! fairness is configurable.
+
+#[cfg(feature = "upgradable")]
+use lock_api::RawRwLockUpgrade;
+use lock_api::{GuardNoSend, RawRwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A guard referencing a part of a read-locked shard.
+pub type ShardReadGuard<'a, T> =
+    lock_api::MappedRwLockReadGuard<'a, RawShardLock, T>;
+
+/// A guard referencing a part of a write-locked shard.
+pub type ShardWriteGuard<'a, T> =
+    lock_api::MappedRwLockWriteGuard<'a, RawShardLock, T>;
+
+/// Which of the readers and the writers of a shard go first when both of them
+/// are waiting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Neither readers nor writers starve, the lock is handed to the waiting
+    /// threads fairly, this is the default, backed by [`parking_lot`]'s
+    /// `RwLock`.
+    #[default]
+    Fair,
+    /// Readers acquire the lock whenever no writer holds it, even if writers
+    /// are waiting, so reads are never delayed by an insert storm, while
+    /// writers can starve under a steady stream of reads.
+    ReaderPreferring,
+    /// Once a writer is waiting, new readers wait until no writer is waiting,
+    /// so writes are never delayed by a read storm, while readers can starve
+    /// under a steady stream of writes.
+    WriterPreferring,
+}
+
+/// The raw lock of a shard, which follows a [`LockPolicy`].
+///
+/// The fair lock parks the waiting threads, the other ones spin and then
+/// yield, which suits the short critical sections of the shards.
+pub struct RawShardLock(Inner);
+
+enum Inner {
+    Fair(parking_lot::RawRwLock),
+    Preferring(PreferringLock),
+}
+
+impl RawShardLock {
+    /// Create an unlocked lock following `policy`.
+    pub(crate) fn new(policy: LockPolicy) -> Self {
+        match policy {
+            LockPolicy::Fair => Self::INIT,
+            LockPolicy::ReaderPreferring => {
+                Self(Inner::Preferring(PreferringLock::new(false)))
+            }
+            LockPolicy::WriterPreferring => {
+                Self(Inner::Preferring(PreferringLock::new(true)))
+            }
+        }
+    }
+}
+
+// SAFETY: both locks provide mutual exclusion between a writer and the
+// others, and the upgradable reader excludes the other upgradable readers.
+unsafe impl RawRwLock for RawShardLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self(Inner::Fair(parking_lot::RawRwLock::INIT));
+
+    type GuardMarker = GuardNoSend;
+
+    fn lock_shared(&self) {
+        match &self.0 {
+            Inner::Fair(lock) => lock.lock_shared(),
+            Inner::Preferring(lock) => lock.lock_shared(),
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        match &self.0 {
+            Inner::Fair(lock) => lock.try_lock_shared(),
+            Inner::Preferring(lock) => lock.try_lock_shared(),
+        }
+    }
+
+    unsafe fn unlock_shared(&self) {
+        match &self.0 {
+            Inner::Fair(lock) => lock.unlock_shared(),
+            Inner::Preferring(lock) => lock.unlock_shared(),
+        }
+    }
+
+    fn lock_exclusive(&self) {
+        match &self.0 {
+            Inner::Fair(lock) => lock.lock_exclusive(),
+            Inner::Preferring(lock) => lock.lock_exclusive(),
+        }
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        match &self.0 {
+            Inner::Fair(lock) => lock.try_lock_exclusive(),
+            Inner::Preferring(lock) => lock.try_lock_exclusive(),
+        }
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        match &self.0 {
+            Inner::Fair(lock) => lock.unlock_exclusive(),
+            Inner::Preferring(lock) => lock.unlock_exclusive(),
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        match &self.0 {
+            Inner::Fair(lock) => lock.is_locked(),
+            Inner::Preferring(lock) => lock.state.load(Ordering::Relaxed) != 0,
+        }
+    }
+}
+
+#[cfg(feature = "upgradable")]
+// SAFETY: an upgradable reader excludes writers and the other upgradable
+// readers, and is upgraded once the readers are gone.
+unsafe impl RawRwLockUpgrade for RawShardLock {
+    fn lock_upgradable(&self) {
+        match &self.0 {
+            Inner::Fair(lock) => lock.lock_upgradable(),
+            Inner::Preferring(lock) => lock.lock_upgradable(),
+        }
+    }
+
+    fn try_lock_upgradable(&self) -> bool {
+        match &self.0 {
+            Inner::Fair(lock) => lock.try_lock_upgradable(),
+            Inner::Preferring(lock) => lock.try_lock_upgradable(),
+        }
+    }
+
+    unsafe fn unlock_upgradable(&self) {
+        match &self.0 {
+            Inner::Fair(lock) => lock.unlock_upgradable(),
+            Inner::Preferring(lock) => lock.unlock_upgradable(),
+        }
+    }
+
+    unsafe fn upgrade(&self) {
+        match &self.0 {
+            Inner::Fair(lock) => lock.upgrade(),
+            Inner::Preferring(lock) => lock.upgrade(),
+        }
+    }
+
+    unsafe fn try_upgrade(&self) -> bool {
+        match &self.0 {
+            Inner::Fair(lock) => lock.try_upgrade(),
+            Inner::Preferring(lock) => lock.try_upgrade(),
+        }
+    }
+}
+
+/// Set while a writer holds the lock.
+const WRITER: usize = 1;
+/// Set while an upgradable reader holds the lock.
+#[cfg(feature = "upgradable")]
+const UPGRADABLE: usize = 1 << 1;
+/// One reader holding the lock, the readers are counted by the rest bits.
+const READER: usize = 1 << 2;
+
+/// A spinning read-write lock which prefers either the readers or the
+/// writers.
+struct PreferringLock {
+    /// `WRITER`, `UPGRADABLE` and the number of readers
+    state: AtomicUsize,
+    /// Number of writers waiting for the lock, only used if the writers are
+    /// preferred
+    waiting_writers: AtomicUsize,
+    /// True if the writers are preferred
+    prefer_writers: bool,
+}
+
+/// Wait a bit before trying to acquire a lock again, spin for the first
+/// `SPINS` tries, and then yield the CPU.
+fn backoff(tries: &mut u32) {
+    const SPINS: u32 = 64;
+
+    if *tries < SPINS {
+        *tries += 1;
+        std::hint::spin_loop();
+    } else {
+        std::thread::yield_now();
+    }
+}
+
+impl PreferringLock {
+    fn new(prefer_writers: bool) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            waiting_writers: AtomicUsize::new(0),
+            prefer_writers,
+        }
+    }
+
+    /// Return true if a new reader should let the waiting writers go first.
+    fn writers_first(&self) -> bool {
+        self.prefer_writers && self.waiting_writers.load(Ordering::Relaxed) > 0
+    }
+
+    /// Try to set the bits of `state` to `f(state)` if `f` allows, retrying
+    /// only if other readers change `state` in the meantime.
+    fn try_update(&self, f: impl Fn(usize) -> Option<usize>) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        while let Some(new) = f(state) {
+            match self.state.compare_exchange_weak(
+                state,
+                new,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => state = current,
+            }
+        }
+
+        false
+    }
+
+    /// Run `f` as a writer, which stops the new readers in the meantime if
+    /// the writers are preferred.
+    fn as_writer<R>(&self, f: impl FnOnce() -> R) -> R {
+        if !self.prefer_writers {
+            return f();
+        }
+
+        self.waiting_writers.fetch_add(1, Ordering::Relaxed);
+        let ret = f();
+        self.waiting_writers.fetch_sub(1, Ordering::Relaxed);
+
+        ret
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        !self.writers_first()
+            && self.try_update(|state| {
+                (state & WRITER == 0).then(|| state + READER)
+            })
+    }
+
+    fn lock_shared(&self) {
+        let mut tries = 0;
+        while !self.try_lock_shared() {
+            backoff(&mut tries);
+        }
+    }
+
+    fn unlock_shared(&self) {
+        self.state.fetch_sub(READER, Ordering::Release);
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn lock_exclusive(&self) {
+        self.as_writer(|| {
+            let mut tries = 0;
+            while !self.try_lock_exclusive() {
+                backoff(&mut tries);
+            }
+        });
+    }
+
+    fn unlock_exclusive(&self) {
+        self.state.fetch_sub(WRITER, Ordering::Release);
+    }
+
+    #[cfg(feature = "upgradable")]
+    fn try_lock_upgradable(&self) -> bool {
+        !self.writers_first()
+            && self.try_update(|state| {
+                (state & (WRITER | UPGRADABLE) == 0)
+                    .then_some(state | UPGRADABLE)
+            })
+    }
+
+    #[cfg(feature = "upgradable")]
+    fn lock_upgradable(&self) {
+        let mut tries = 0;
+        while !self.try_lock_upgradable() {
+            backoff(&mut tries);
+        }
+    }
+
+    #[cfg(feature = "upgradable")]
+    fn unlock_upgradable(&self) {
+        self.state.fetch_sub(UPGRADABLE, Ordering::Release);
+    }
+
+    #[cfg(feature = "upgradable")]
+    fn try_upgrade(&self) -> bool {
+        self.state
+            .compare_exchange(
+                UPGRADABLE,
+                WRITER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    #[cfg(feature = "upgradable")]
+    fn upgrade(&self) {
+        self.as_writer(|| {
+            let mut tries = 0;
+            while !self.try_upgrade() {
+                backoff(&mut tries);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lock_api::RwLock;
+
+    #[test]
+    fn shard_lock_policies() {
+        for policy in [
+            LockPolicy::Fair,
+            LockPolicy::ReaderPreferring,
+            LockPolicy::WriterPreferring,
+        ] {
+            let lock = RwLock::from_raw(RawShardLock::new(policy), 0);
+            let read = lock.read();
+            let read_too = lock.read();
+            assert!(lock.try_write().is_none());
+            drop((read, read_too));
+
+            let mut write = lock.write();
+            *write += 1;
+            assert!(lock.try_read().is_none());
+            assert!(lock.try_write().is_none());
+            drop(write);
+
+            assert!(!lock.is_locked());
+            assert_eq!(*lock.read(), 1);
+        }
+    }
+
+    #[test]
+    fn writer_preferring_lock_stops_new_readers() {
+        let lock = RwLock::from_raw(
+            RawShardLock::new(LockPolicy::WriterPreferring),
+            0,
+        );
+        let read = lock.read();
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| *lock.write() += 1);
+            while lock.try_read().is_some() {
+                std::thread::yield_now();
+            }
+            // a writer is waiting, new readers wait as well
+            drop(read);
+            writer.join().unwrap();
+        });
+
+        assert_eq!(*lock.read(), 1);
+    }
+}
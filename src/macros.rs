@@ -0,0 +1,23 @@
+/// Create a [`HashMap`](crate::HashMap) containing the given `key => value`
+/// pairs, the directory is sized for the number of pairs up front.
+///
+/// # Example
+///
+/// ```
+/// use extendable_hashmap::ehm;
+///
+/// let map = ehm! {
+///     "a" => 1,
+///     "b" => 2,
+/// };
+/// assert_eq!(map["a"], 1);
+/// ```
+#[macro_export]
+macro_rules! ehm {
+    () => {
+        $crate::HashMap::new()
+    };
+    ($($key:expr => $value:expr),+ $(,)?) => {
+        $crate::HashMap::from([$(($key, $value)),+])
+    };
+}
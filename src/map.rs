@@ -1,42 +1,237 @@
 use crate::{
+    arena::Arena,
     bucket::{
-        Bucket, BucketValue,
+        max_depth, Bucket, BucketIdx, BucketPolicy,
         BucketValue::{EqualTo, Range},
         BUCKET_CAP,
     },
-    util::{bits_to_value, get_first_n_bits},
+    counters::Counters,
+    directory::Directory,
+    entry::{EntryRef, OccupiedEntry, OccupiedError, VacantEntryRef},
+    hasher::{
+        BitOrder, DefaultHashBuilder, DirectoryMapper, FixedState, HashValue,
+        KeyHasher,
+    },
+    iter::ExtractIf,
+    observer::{MapEvent, Observer},
+    raw_entry::{RawEntryBuilder, RawEntryBuilderMut},
+    slab::Slab,
+    util::format_bits,
 };
+use allocator_api2::alloc::{Allocator, Global};
 use std::{
     borrow::Borrow,
-    collections::hash_map::DefaultHasher,
-    fmt::{Debug, Formatter},
-    hash::{Hash, Hasher},
+    fmt::{Debug, Display, Formatter},
+    hash::{BuildHasher, Hash},
+    ops::Index,
 };
 
+/// Return the minimum global depth that is needed to hold `capacity`
+/// elements in buckets that can hold `bucket_capacity` entries, 0 if a single
+/// bucket is enough.
+fn depth_for_capacity(capacity: usize, bucket_capacity: usize) -> usize {
+    let n_buckets = capacity.div_ceil(bucket_capacity).max(1);
+
+    n_buckets.next_power_of_two().trailing_zeros() as usize
+}
+
+/// Count the buckets of every local depth up to `global_depth`, which is
+/// not smaller than the local depths, the counts are indexed by the local
+/// depth.
+pub(crate) fn count_depths<K, V, H, const B: usize, A: Allocator + Clone>(
+    buckets: &Slab<Bucket<K, V, H, B, Arena<A>>, A>,
+    global_depth: usize,
+) -> Vec<usize> {
+    let mut depth_counts = vec![0; global_depth + 1];
+    for (_, bucket) in buckets.iter() {
+        depth_counts[bucket.local_depth()] += 1;
+    }
+
+    depth_counts
+}
+
+/// Calculate the hash value of `key` using `hash_builder`.
+pub(crate) fn make_hash<Q, S>(hash_builder: &S, key: &Q) -> S::Hash
+where
+    Q: Hash + ?Sized,
+    S: KeyHasher,
+{
+    hash_builder.hash_key(key)
+}
+
+/// Default number of the empty levels a directory keeps below its deepest
+/// bucket, see [`HashMap::set_halving_hysteresis()`].
+pub(crate) const HALVING_HYSTERESIS: usize = 1;
+
+/// When removals merge a bucket with its sibling bucket, i.e., the bucket
+/// whose prefix differs in the last bit only.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CoalescePolicy {
+    /// Merge the buckets once their entries fit into one bucket, which keeps
+    /// the directory as small as possible, this is the default.
+    #[default]
+    Always,
+    /// Never merge the buckets, so that removing and reinserting entries
+    /// doesn't merge and split the same buckets back and forth, at the cost
+    /// of keeping the buckets and the directory of the largest size the map
+    /// has reached, until [`HashMap::compact()`].
+    Never,
+    /// Merge the buckets once their entries take no more than this fraction
+    /// of a bucket's capacity, e.g., `Threshold(0.5)` leaves half of the
+    /// merged bucket for the reinsertions before it gets split again.
+    Threshold(f64),
+}
+
+impl CoalescePolicy {
+    /// Return true if 2 buckets holding `len` entries in total should be
+    /// merged into a bucket that can hold `bucket_capacity` entries.
+    fn should_merge(self, len: usize, bucket_capacity: usize) -> bool {
+        len < bucket_capacity
+            && match self {
+                CoalescePolicy::Always => true,
+                CoalescePolicy::Never => false,
+                CoalescePolicy::Threshold(fill_ratio) => {
+                    len as f64 <= fill_ratio * bucket_capacity as f64
+                }
+            }
+    }
+
+    /// Panic if the fill ratio of [`CoalescePolicy::Threshold`] is not within
+    /// `[0, 1]`.
+    pub(crate) fn check(self) {
+        if let CoalescePolicy::Threshold(fill_ratio) = self {
+            assert!(
+                (0.0..=1.0).contains(&fill_ratio),
+                "the fill ratio should be within [0, 1], got {fill_ratio}"
+            );
+        }
+    }
+}
+
 /// A map backed by Extendable Hashing.
-pub struct HashMap<K, V> {
+///
+/// Keys are hashed by `S`, which defaults to [`DefaultHashBuilder`], see
+/// [`KeyHasher`] for the hashers that can be used. Hash values are mapped to
+/// directory indexes by `M`, which defaults to [`BitOrder`], see
+/// [`DirectoryMapper`].
+///
+/// Up to `B` entries of a bucket are stored inline, without a separate heap
+/// allocation. `B` is also the bucket capacity of the maps created by
+/// [`HashMap::default()`], e.g., `HashMap::<K, V, DefaultHashBuilder,
+/// BitOrder, 8>::default()`.
+///
+/// # Allocation
+/// An empty map has a single bucket, pointed to by the single directory
+/// entry of global depth 0, which is stored inline, the directory is
+/// allocated when the bucket is split, and stored inline again once the
+/// buckets coalesce back into one.
+///
+/// The map allocates from `A`, which defaults to the global allocator: the
+/// directory is a `Vec`, and the buckets live in a slab. A bucket holding
+/// more than `B` entries spills them to 4 arrays, of hash values, keys,
+/// values and tags, which are allocated from an arena owned by the map: the
+/// arrays of the same size are carved from shared chunks of `A`, and the
+/// arrays freed by coalescences are reused by the next splits rather than
+/// returned to `A`. The chunks are released by
+/// [`shrink_to_fit()`](HashMap::shrink_to_fit), which moves the spilled
+/// entries into a new arena, and when the map is dropped.
+/// [`memory_usage()`](HashMap::memory_usage) reports what each part takes.
+///
+/// A map is created in another allocator by [`HashMap::new_in()`] or
+/// [`HashMapBuilder::build_in()`](crate::HashMapBuilder::build_in). `A`
+/// implements the `Allocator` trait of
+/// [`allocator-api2`](https://docs.rs/allocator-api2), which is the one of
+/// the standard library with the `allocator_api` feature (nightly only).
+///
+/// # Iteration order
+/// The order in which the entries are visited, e.g., by
+/// [`HashMap::into_iter()`](IntoIterator::into_iter) and
+/// [`HashMap::extract_if()`], is unspecified. It may change whenever the map
+/// is modified, e.g., removing an entry may move another entry of its bucket
+/// into its place.
+///
+/// # Tracing
+/// With the `tracing` feature, bucket splits, coalescences and directory
+/// resizes are wrapped in `DEBUG` level [`tracing`](https://docs.rs/tracing)
+/// spans, named `split`, `coalesce`, `double_directory` and
+/// `halve_directory`, recording the prefix, the local and global depths,
+/// and the number of entries involved.
+///
+/// # Metrics
+/// With the `metrics` feature, the structural changes are counted through
+/// the [`metrics`](https://docs.rs/metrics) facade, by the counters
+/// `splits_total`, `merges_total`, `directory_doublings_total` and
+/// `directory_halvings_total`, and the gauge `current_global_depth`, which
+/// is set whenever a directory is resized. The metrics are shared by all
+/// the maps.
+pub struct HashMap<
+    K,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
     /// The number of elements
-    len: usize,
+    pub(crate) len: usize,
     /// Global depth
-    global_depth: usize,
+    pub(crate) global_depth: usize,
+    /// Number of entries a bucket can hold
+    pub(crate) bucket_capacity: usize,
+    /// The directory won't grow beyond this depth
+    pub(crate) max_global_depth: usize,
+    /// How the entries are arranged within a bucket
+    pub(crate) bucket_policy: BucketPolicy,
+    /// When removals merge buckets
+    pub(crate) coalesce_policy: CoalescePolicy,
+    /// Number of the empty levels kept below the deepest bucket before
+    /// removals halve the directory
+    pub(crate) halving_hysteresis: usize,
     /// Directory entries, storing the index of its
     /// corresponding bucket.
-    directories: Vec<usize>,
-    /// Buckets
-    buckets: Vec<Bucket<K, V>>,
+    pub(crate) directories: Directory<A>,
+    /// Buckets, stored in a slab so that a bucket never moves and its index
+    /// stays valid when other buckets are removed.
+    pub(crate) buckets: Slab<Bucket<K, V, S::Hash, B, Arena<A>>, A>,
+    /// Number of buckets of every local depth, `depth_counts[d]` is the
+    /// number of buckets whose local depth is `d`.
+    pub(crate) depth_counts: Vec<usize>,
+    /// Hash builder
+    pub(crate) hash_builder: S,
+    /// Maps hash values to the bits used to index the directory
+    pub(crate) mapper: M,
+    /// Callback of the structural changes
+    pub(crate) observer: Option<Observer>,
+    /// Counters of the splits and merges
+    pub(crate) counters: Counters,
+    /// The arena the spilled entries of the buckets are allocated from
+    pub(crate) arena: Arena<A>,
 }
 
-impl<K, V> Debug for HashMap<K, V>
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Debug for HashMap<K, V, S, M, B, A>
 where
     K: Debug,
     V: Debug,
 {
+    /// With `{:#?}`, print a table with a row per bucket: the directory
+    /// slots pointing to it, its prefix and its entries.
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            return self.fmt_table(f);
+        }
+
         writeln!(f, "Extendable HashMap")?;
         writeln!(f, "len: {}", self.len)?;
         writeln!(f, "global depth: {}", self.global_depth)?;
         writeln!(f, "directories: {:?}", self.directories)?;
-        for (idx, bucket) in self.buckets.iter().enumerate() {
+        for (idx, bucket) in self.buckets.iter() {
             writeln!(f, "{:5} {:?}", idx, bucket)?;
         }
 
@@ -44,386 +239,3106 @@ where
     }
 }
 
-impl<K, V> Default for HashMap<K, V> {
-    fn default() -> Self {
-        let bucket0 = Bucket::new(&[0]);
-        let bucket1 = Bucket::new(&[1]);
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > HashMap<K, V, S, M, B, A>
+where
+    K: Debug,
+    V: Debug,
+{
+    /// Print the summary line, then a row per bucket, ordered by the bucket
+    /// prefixes, e.g.,
+    ///
+    /// ```text
+    /// HashMap with 3 entries, global depth 2, 3 buckets
+    /// slots  prefix  entries
+    /// 00-01  0       {1: "a", 3: "c"}
+    /// 10     10      {2: "b"}
+    /// 11     11      {}
+    /// ```
+    fn fmt_table(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{self}")?;
+
+        let global_depth = self.global_depth;
+        let slots_width = (2 * global_depth + 1).max("slots".len());
+        let prefix_width = global_depth.max("prefix".len());
+        writeln!(
+            f,
+            "{:slots_width$}  {:prefix_width$}  entries",
+            "slots", "prefix"
+        )?;
+
+        // the directory entries pointing to a bucket are contiguous
+        let mut slot = 0;
+        while slot < self.directories.len() {
+            let bucket = &self.buckets[self.directories[slot].get()];
+            let last = slot + (1 << (global_depth - bucket.local_depth())) - 1;
+            let slots = if last == slot {
+                format_bits(slot as u64, global_depth)
+            } else {
+                format!(
+                    "{}-{}",
+                    format_bits(slot as u64, global_depth),
+                    format_bits(last as u64, global_depth)
+                )
+            };
+            write!(
+                f,
+                "{slots:slots_width$}  {:prefix_width$}  {{",
+                format_bits(bucket.prefix, bucket.local_depth())
+            )?;
+            for (idx, (_, k, v)) in bucket.iter().enumerate() {
+                if idx > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{k:?}: {v:?}")?;
+            }
+            writeln!(f, "}}")?;
+            slot = last + 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl<K, V, S: KeyHasher, M, const B: usize, A: Allocator + Clone> Display
+    for HashMap<K, V, S, M, B, A>
+{
+    /// Print a summary line: the number of entries, the global depth and the
+    /// number of buckets.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "HashMap with {} entries, global depth {}, {} buckets",
+            self.len,
+            self.global_depth,
+            self.buckets.len()
+        )
+    }
+}
 
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Clone for HashMap<K, V, S, M, B, A>
+where
+    K: Clone,
+    V: Clone,
+    S: Clone,
+    M: Clone,
+{
+    /// Clone the map, the clone has the same global depth, directory entries
+    /// and buckets as `self`.
+    fn clone(&self) -> Self {
+        let arena = Arena::new_in(self.arena.allocator());
         Self {
-            len: 0,
-            global_depth: 1,
-            directories: vec![0, 1],
-            buckets: vec![bucket0, bucket1],
+            len: self.len,
+            global_depth: self.global_depth,
+            bucket_capacity: self.bucket_capacity,
+            max_global_depth: self.max_global_depth,
+            bucket_policy: self.bucket_policy,
+            coalesce_policy: self.coalesce_policy,
+            halving_hysteresis: self.halving_hysteresis,
+            directories: self.directories.clone(),
+            buckets: self
+                .buckets
+                .map_in(self.buckets.allocator().clone(), |bucket| {
+                    bucket.clone_in(arena.clone())
+                }),
+            depth_counts: self.depth_counts.clone(),
+            hash_builder: self.hash_builder.clone(),
+            mapper: self.mapper.clone(),
+            observer: self.observer.clone(),
+            counters: self.counters,
+            arena,
+        }
+    }
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > PartialEq for HashMap<K, V, S, M, B, A>
+where
+    K: Eq + Hash,
+    V: PartialEq,
+    S: KeyHasher,
+{
+    /// Two maps are equal if they contain the same `(key, value)` pairs, their
+    /// internal layouts (global depth, directory, buckets) do not matter.
+    fn eq(&self, other: &Self) -> bool {
+        if self.len != other.len {
+            return false;
+        }
+
+        self.buckets
+            .iter()
+            .flat_map(|(_, bucket)| bucket.iter())
+            .all(|(_, k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Eq for HashMap<K, V, S, M, B, A>
+where
+    K: Eq + Hash,
+    V: Eq,
+    S: KeyHasher,
+{
+}
+
+impl<K, V, const N: usize> From<[(K, V); N]> for HashMap<K, V>
+where
+    K: Eq + Hash,
+{
+    /// Create a map from an array of `(key, value)` pairs, the directory is
+    /// sized for `N` elements up front.
+    fn from(arr: [(K, V); N]) -> Self {
+        let mut map = Self::with_capacity(N);
+        for (k, v) in arr {
+            map.insert(k, v);
+        }
+
+        map
+    }
+}
+
+impl<K, V, S> From<std::collections::HashMap<K, V, S>> for HashMap<K, V>
+where
+    K: Eq + Hash,
+{
+    /// Move the entries of a `std::collections::HashMap` into a new map, which
+    /// uses the default hasher.
+    fn from(std_map: std::collections::HashMap<K, V, S>) -> Self {
+        let mut map = Self::with_capacity(std_map.len());
+        for (k, v) in std_map {
+            map.insert(k, v);
         }
+
+        map
+    }
+}
+
+impl<K, V, S1, M, S2, const B: usize, A: Allocator + Clone>
+    From<HashMap<K, V, S1, M, B, A>> for std::collections::HashMap<K, V, S2>
+where
+    K: Eq + Hash,
+    S1: KeyHasher,
+    M: DirectoryMapper<S1::Hash>,
+    S2: BuildHasher + Default,
+{
+    fn from(map: HashMap<K, V, S1, M, B, A>) -> Self {
+        let mut std_map =
+            Self::with_capacity_and_hasher(map.len(), S2::default());
+        std_map.extend(map);
+
+        std_map
+    }
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Default for HashMap<K, V, S, M, B, A>
+where
+    S: Default,
+    M: Default,
+    A: Default,
+{
+    fn default() -> Self {
+        Self::with_global_depth(0, B, S::default(), M::default(), A::default())
+    }
+}
+
+impl<
+        K,
+        Q,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Index<&Q> for HashMap<K, V, S, M, B, A>
+where
+    K: Borrow<Q> + Hash,
+    Q: Hash + Eq + ?Sized,
+    S: KeyHasher,
+{
+    type Output = V;
+
+    /// Returns a reference to the value corresponding to the supplied key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not present in the map.
+    fn index(&self, key: &Q) -> &Self::Output {
+        self.get(key)
+            .expect("HashMap::index(): key not found in the map")
     }
 }
 
-impl<K, V> HashMap<K, V> {
+impl<K, V> HashMap<K, V, DefaultHashBuilder> {
     /// Create an empty `HashMap`.
     pub fn new() -> Self {
         Self::default()
     }
 
-    /// Return the number of elements in the map.
-    #[inline]
-    pub fn len(&self) -> usize {
-        self.len
+    /// Create an empty `HashMap` that can hold at least `capacity` elements
+    /// without splitting buckets, provided that the hash values are evenly
+    /// distributed.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
     }
 
-    /// Return true if this map is empty.
-    #[inline]
-    pub fn is_empty(&self) -> bool {
-        self.len == 0
+    /// Create an empty `HashMap` whose buckets can hold `bucket_capacity`
+    /// entries.
+    ///
+    /// A larger bucket capacity results in a smaller directory, at the cost
+    /// of scanning more entries per lookup.
+    ///
+    /// # Panic
+    /// `bucket_capacity` should be greater than 0.
+    pub fn with_bucket_capacity(bucket_capacity: usize) -> Self {
+        Self::with_bucket_capacity_and_hasher(
+            bucket_capacity,
+            DefaultHashBuilder::default(),
+        )
     }
 
-    /// Return the number of elements the map can hold
-    /// without reallocating.
-    #[inline]
-    pub fn capacity(&self) -> usize {
-        self.directories.len() * BUCKET_CAP
+    /// Create an empty `HashMap` which indexes the directory with the
+    /// `bit_order` bits of the hash values.
+    pub fn with_bit_order(bit_order: BitOrder) -> Self {
+        Self::with_bit_order_and_hasher(
+            bit_order,
+            DefaultHashBuilder::default(),
+        )
     }
 }
 
-impl<K: Hash, V> HashMap<K, V> {
-    /// Locate the bucket where `key` will go.
-    fn locate_bucket<Q>(&self, key: &Q) -> usize
-    where
-        K: Borrow<Q>,
-        Q: Hash,
-    {
-        let mut default_hasher = DefaultHasher::new();
-        key.hash(&mut default_hasher);
-        let hash_res = default_hasher.finish();
-
-        // Use the reverse last `self.global` bits
-        //
-        // NOTE: we need to ensure the following guarantee:
-        // Say the global depth is 1, and the hashing bits are `[0]`, after
-        // we increment the global depth to 2, the hashing bits have to be
-        // either `[0, 0]` or `[0, 1]`
-        let first_bits = get_first_n_bits(self.global_depth, hash_res);
-        let directory_idx = bits_to_value(first_bits.as_slice());
+impl<K, V, S: KeyHasher> HashMap<K, V, S> {
+    /// Create an empty `HashMap` which will use the given hash builder to hash
+    /// keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_global_depth(
+            0,
+            BUCKET_CAP,
+            hash_builder,
+            BitOrder::default(),
+            Global,
+        )
+    }
 
-        self.directories[directory_idx]
+    /// Create an empty `HashMap` that can hold at least `capacity` elements
+    /// without splitting buckets, provided that the hash values are evenly
+    /// distributed, `hash_builder` will be used to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self::with_global_depth(
+            depth_for_capacity(capacity, BUCKET_CAP),
+            BUCKET_CAP,
+            hash_builder,
+            BitOrder::default(),
+            Global,
+        )
     }
 
-    /// Split a bucket.
+    /// Create an empty `HashMap` whose buckets can hold `bucket_capacity`
+    /// entries, `hash_builder` will be used to hash keys.
     ///
-    /// Under awful cases, this function will be called recursively until the
-    /// `(key, value)` has been successfully inserted into the map.
-    fn split(&mut self, key: K, value: V, bucket_to_split: usize) {
-        let mut_ref_bucket = self.buckets.get_mut(bucket_to_split).unwrap();
+    /// # Panic
+    /// `bucket_capacity` should be greater than 0.
+    pub fn with_bucket_capacity_and_hasher(
+        bucket_capacity: usize,
+        hash_builder: S,
+    ) -> Self {
+        Self::with_global_depth(
+            0,
+            bucket_capacity,
+            hash_builder,
+            BitOrder::default(),
+            Global,
+        )
+    }
 
-        let old_local_depth = mut_ref_bucket.local_depth();
-        let old_global_depth = self.global_depth;
-        assert!(old_local_depth <= old_global_depth);
+    /// Create an empty `HashMap` which indexes the directory with the
+    /// `bit_order` bits of the hash values, `hash_builder` will be used to
+    /// hash keys.
+    pub fn with_bit_order_and_hasher(
+        bit_order: BitOrder,
+        hash_builder: S,
+    ) -> Self {
+        Self::with_mapper_and_hasher(bit_order, hash_builder)
+    }
 
-        let bucket_value = mut_ref_bucket.value(old_global_depth);
-        let mut bucket_slice = mut_ref_bucket.bits.clone();
-        mut_ref_bucket.bits.push(0);
-        bucket_slice.push(1);
-        let new_bucket = Bucket::new(bucket_slice.as_slice());
-        let new_bucket_idx = self.buckets.len();
-        self.buckets.push(new_bucket);
+    /// Return which bits of the hash values are used to index the directory.
+    #[inline]
+    pub fn bit_order(&self) -> BitOrder {
+        self.mapper
+    }
+}
 
-        if old_local_depth < old_global_depth {
-            let last_half_directory_indexes =
-                // this bucket_value needs to be calculated before incrementing
-                // the local depth because we are redistributing the old pointers.
-                bucket_value.last_half_range().unwrap();
+impl<K, V, S: KeyHasher, M: DirectoryMapper<S::Hash>> HashMap<K, V, S, M> {
+    /// Create an empty `HashMap` which maps hash values to directory indexes
+    /// with `mapper`, `hash_builder` will be used to hash keys.
+    pub fn with_mapper_and_hasher(mapper: M, hash_builder: S) -> Self {
+        Self::with_global_depth(0, BUCKET_CAP, hash_builder, mapper, Global)
+    }
+}
 
-            // redistribute pointers
-            for idx in last_half_directory_indexes {
-                self.directories[idx] = new_bucket_idx;
-            }
-        } else {
-            self.global_depth += 1;
-            for _ in 0..self.directories.len() {
-                self.directories.push(0);
-            }
-
-            // Redistribute directory pointers
-            //
-            // As you can see, we need to redistribute *all* the pointers. When
-            // expanding the directory entry, we choose to *append* instead of
-            // *insert*ing as inserting to an array is not efficient
-            //
-            // [0, 1] => [00, 01, 10, 11] ([0, 1] => [0, 1, 2, 3])
-            //
-            // Appending is efficient but it will invalidate the pointers stored
-            // in the old directory entries, and thus we have to redistribute
-            // them all.
-            //
-            // What about using a linked list, well, we need fast random access
-            // when locating a bucket.
-            for (bucket_idx, bucket) in self.buckets.iter().enumerate() {
-                let bucket_value = bucket.value(self.global_depth);
-
-                match bucket_value {
-                    EqualTo(idx) => self.directories[idx] = bucket_idx,
-                    Range(range) => {
-                        for idx in range {
-                            self.directories[idx] = bucket_idx;
-                        }
-                    }
-                }
-            }
-        }
+impl<K, V> HashMap<K, V, FixedState> {
+    /// Create an empty `HashMap` whose hasher is seeded with `seed`.
+    ///
+    /// Two maps created with the same seed have the same layout after the
+    /// same sequence of operations, see the [`hasher`](crate::hasher) module
+    /// for the details.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_hasher(FixedState::with_seed(seed))
+    }
+}
 
-        // rehashing the existing items
-        let items_need_rehash = self.buckets[bucket_to_split]
-            .data
-            .drain(..)
-            .collect::<Vec<(K, V)>>();
-        for (k, v) in items_need_rehash {
-            let idx = self.locate_bucket(k.borrow());
-            assert!(idx == bucket_to_split || idx == new_bucket_idx);
+impl<K, V, A: Allocator + Clone>
+    HashMap<K, V, DefaultHashBuilder, BitOrder, BUCKET_CAP, A>
+{
+    /// Create an empty `HashMap` which allocates from `alloc`.
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_hasher_in(DefaultHashBuilder::default(), alloc)
+    }
 
-            self.buckets[idx].data.push((k, v));
-        }
+    /// Create an empty `HashMap` that can hold at least `capacity` elements
+    /// without splitting buckets, provided that the hash values are evenly
+    /// distributed, which allocates from `alloc`.
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        Self::with_capacity_and_hasher_in(
+            capacity,
+            DefaultHashBuilder::default(),
+            alloc,
+        )
+    }
+}
 
-        // after split, try inserting the new item again
-        let idx = self.locate_bucket(key.borrow());
-        assert!(idx == bucket_to_split || idx == new_bucket_idx);
-        // let's do split again.
-        if self.buckets[idx].is_full() {
-            self.split(key, value, idx);
-        } else if self.buckets[idx]
-            .data
-            .push_within_capacity((key, value))
-            .is_err()
-        {
-            panic!("push_within_capacity failed")
-        }
+impl<K, V, S: KeyHasher, A: Allocator + Clone>
+    HashMap<K, V, S, BitOrder, BUCKET_CAP, A>
+{
+    /// Create an empty `HashMap` which will use the given hash builder to hash
+    /// keys, and allocates from `alloc`.
+    pub fn with_hasher_in(hash_builder: S, alloc: A) -> Self {
+        Self::with_global_depth(
+            0,
+            BUCKET_CAP,
+            hash_builder,
+            BitOrder::default(),
+            alloc,
+        )
     }
 
-    /// Insert `value` to this set.
-    pub fn insert(&mut self, key: K, value: V) -> Option<V>
-    where
-        K: Eq,
-    {
-        let bucket_idx = self.locate_bucket(key.borrow());
-        let mut_ref_bucket = self.buckets.get_mut(bucket_idx).unwrap();
+    /// Create an empty `HashMap` that can hold at least `capacity` elements
+    /// without splitting buckets, provided that the hash values are evenly
+    /// distributed, `hash_builder` will be used to hash keys, and the map
+    /// allocates from `alloc`.
+    pub fn with_capacity_and_hasher_in(
+        capacity: usize,
+        hash_builder: S,
+        alloc: A,
+    ) -> Self {
+        Self::with_global_depth(
+            depth_for_capacity(capacity, BUCKET_CAP),
+            BUCKET_CAP,
+            hash_builder,
+            BitOrder::default(),
+            alloc,
+        )
+    }
+}
 
-        // Check existence
-        if mut_ref_bucket.contains(&key) {
-            return Some(value);
-        }
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > HashMap<K, V, S, M, B, A>
+{
+    /// Create an empty `HashMap` with global depth `global_depth`, every
+    /// bucket's local depth equals to the global depth, and can hold
+    /// `bucket_capacity` entries, the map allocates from `alloc`.
+    ///
+    /// # Panic
+    /// `bucket_capacity` should be greater than 0, and `global_depth` should
+    /// not be greater than the width of the hash values.
+    fn with_global_depth(
+        global_depth: usize,
+        bucket_capacity: usize,
+        hash_builder: S,
+        mapper: M,
+        alloc: A,
+    ) -> Self {
+        assert!(bucket_capacity > 0);
 
-        if !mut_ref_bucket.is_full() {
-            if mut_ref_bucket
-                .data
-                .push_within_capacity((key, value))
-                .is_err()
-            {
-                panic!("push_within_capacity failed")
-            }
-        } else {
-            self.split(key, value, bucket_idx);
-        }
-        self.len += 1;
+        let mut map = Self {
+            len: 0,
+            global_depth: 0,
+            bucket_capacity,
+            max_global_depth: max_depth::<S::Hash>(),
+            bucket_policy: BucketPolicy::default(),
+            coalesce_policy: CoalescePolicy::default(),
+            halving_hysteresis: HALVING_HYSTERESIS,
+            // set by `reset()`
+            directories: Directory::Inline([BucketIdx::new(0)]),
+            buckets: Slab::new_in(alloc.clone()),
+            depth_counts: Vec::new(),
+            hash_builder,
+            mapper,
+            observer: None,
+            counters: Counters::default(),
+            arena: Arena::new_in(alloc),
+        };
+        map.reset(global_depth);
 
-        None
+        map
     }
 
-    /// Remove `key` from the map, return its value if it was previously in the
-    /// map.
+    /// Reset the map to an empty one with global depth `global_depth`, every
+    /// bucket's local depth equals to the global depth, return the old
+    /// buckets.
     ///
-    /// # Coalescence
-    ///
-    /// After deletion, we will try to merge the bucket where the `key` was
-    /// removed and its sibling bucket.
-    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
-    where
-        Q: Eq + Hash,
-        K: Borrow<Q>,
-    {
-        let bucket_idx = self.locate_bucket(key);
-        let mut_ref_bucket = self
-            .buckets
-            .get_mut(bucket_idx)
-            .expect("locate_bucket() returns a wrong index");
-        let key_idx = mut_ref_bucket
-            .data
-            .iter()
-            .position(|(k, _)| k.borrow() == key)?;
-        let (_, value) = mut_ref_bucket.data.remove(key_idx);
-        self.len -= 1;
-
-        let immut_ref_bucket = self.buckets.get(bucket_idx).unwrap();
-        // check if we can coalesce it and its sibling bucket and remove the bucket
-        if immut_ref_bucket.local_depth() >= 2 {
-            let mut bucket_bits = immut_ref_bucket
-                .bits
-                .iter()
-                .map(|u8| *u8 as usize)
-                .collect::<Vec<usize>>();
-            let bucket_last_bit = *bucket_bits.last().unwrap();
-            *bucket_bits.last_mut().unwrap() = 1 - bucket_last_bit;
-            bucket_bits.resize(self.global_depth, 0);
-
-            let sibling_idx =
-                self.directories[bits_to_value(bucket_bits.as_slice())];
-            let immut_ref_sibling_bucket =
-                self.buckets.get(sibling_idx).unwrap();
-
-            // sibling bucket exists
-            if immut_ref_sibling_bucket.local_depth()
-                == immut_ref_bucket.local_depth()
-            {
-                // The data of two buckets can fit into one bucket
-                if immut_ref_sibling_bucket.data.len()
-                    + immut_ref_bucket.data.len()
-                    < BUCKET_CAP
-                {
-                    // begin coalescence
-                    let dead_bucket_idx: usize;
-                    let survivor_bucket_idx: usize;
-                    let dead_bucket_value: BucketValue;
-
-                    if bucket_last_bit == 1 {
-                        dead_bucket_idx = bucket_idx;
-                        survivor_bucket_idx = sibling_idx;
-                        dead_bucket_value =
-                            immut_ref_bucket.value(self.global_depth);
-                    } else {
-                        dead_bucket_idx = sibling_idx;
-                        survivor_bucket_idx = bucket_idx;
-                        dead_bucket_value =
-                            immut_ref_sibling_bucket.value(self.global_depth);
-                    }
+    /// # Panic
+    /// `global_depth` should not be greater than the width of the hash values
+    /// or 64.
+    fn reset(
+        &mut self,
+        global_depth: usize,
+    ) -> Slab<Bucket<K, V, S::Hash, B, Arena<A>>, A> {
+        assert!(global_depth <= max_depth::<S::Hash>());
 
-                    let dead_bucket_data_clone: Vec<_> = self
-                        .buckets
-                        .get_mut(dead_bucket_idx)
-                        .unwrap()
-                        .data
-                        .drain(..)
-                        .collect();
-                    let survivor_bucket_mut_ref =
-                        self.buckets.get_mut(survivor_bucket_idx).unwrap();
-
-                    // transfer data
-                    survivor_bucket_mut_ref.data.extend(dead_bucket_data_clone);
-                    // decrease the local depth
-                    survivor_bucket_mut_ref.bits.pop().unwrap();
-                    // update directory entries
-                    match dead_bucket_value {
-                        EqualTo(idx) => {
-                            self.directories[idx] = survivor_bucket_idx
-                        }
-                        Range(range) => {
-                            for idx in range {
-                                self.directories[idx] = survivor_bucket_idx;
-                            }
-                        }
-                    }
-                    // remove the dead bucket
-                    self.buckets.remove(dead_bucket_idx);
-
-                    // directory entries for bucket since index `bucket_idx` are invalidated, update them
-                    // All you need to do is to decrease the invalid "pointers" by 1
-                    if dead_bucket_idx != self.buckets.len() {
-                        self.directories.iter_mut().for_each(|entry| {
-                            assert_ne!(*entry, dead_bucket_idx);
-
-                            if *entry > dead_bucket_idx {
-                                *entry -= 1;
-                            }
-                        });
-                    }
-                }
-            }
+        let n_buckets = 2_usize.pow(global_depth as u32);
+        let alloc = self.buckets.allocator().clone();
+        let mut buckets = Slab::with_capacity_in(n_buckets, alloc.clone());
+        for value in 0..n_buckets {
+            buckets.insert(self.new_bucket(value as u64, global_depth));
         }
+        let mut directories =
+            allocator_api2::vec::Vec::with_capacity_in(n_buckets, alloc);
+        directories.extend((0..n_buckets).map(BucketIdx::new));
 
-        Some(value)
+        self.len = 0;
+        self.global_depth = global_depth;
+        self.directories = directories.into();
+        self.depth_counts = vec![0; global_depth + 1];
+        self.depth_counts[global_depth] = n_buckets;
+        std::mem::replace(&mut self.buckets, buckets)
     }
 
-    /// Returns a reference to the value corresponding to the key.
-    pub fn get<Q>(&self, key: &Q) -> Option<&V>
-    where
-        Q: Eq + Hash,
-        K: Borrow<Q>,
-    {
-        let bucket_idx = self.locate_bucket(key);
-        let bucket = self
-            .buckets
-            .get(bucket_idx)
-            .expect("locate_bucket() returns a wrong index");
+    /// Return a reference to the map's [`BuildHasher`].
+    #[inline]
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
 
-        bucket
-            .data
-            .iter()
-            .find(|(k, _)| k.borrow() == key)
-            .map(|kv| &kv.1)
+    /// Return a reference to the allocator of the map.
+    #[inline]
+    pub fn allocator(&self) -> &A {
+        self.buckets.allocator()
     }
 
-    /// Returns a mutable reference to the value corresponding to the key.
-    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        let bucket_idx = self.locate_bucket(key);
-        let bucket = self
-            .buckets
-            .get_mut(bucket_idx)
-            .expect("locate_bucket() returns a wrong index");
+    /// Return the number of entries a bucket can hold.
+    #[inline]
+    pub fn bucket_capacity(&self) -> usize {
+        self.bucket_capacity
+    }
 
-        bucket
-            .data
-            .iter_mut()
-            .find(|(k, _)| k.borrow() == key)
-            .map(|kv| &mut kv.1)
+    /// Return the global depth, i.e., the number of hash bits used to index
+    /// the directory.
+    #[inline]
+    pub fn global_depth(&self) -> usize {
+        self.global_depth
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// Return the number of directory entries, which is
+    /// `2^global_depth`.
+    #[inline]
+    pub fn directory_len(&self) -> usize {
+        self.directories.len()
+    }
 
-    #[test]
-    fn insert_without_split_works() {
-        let mut map = HashMap::new();
-        assert_eq!(map.insert(1, 1), None);
+    /// Return the number of buckets, several directory entries may point to
+    /// the same bucket.
+    #[inline]
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.len()
+    }
 
-        assert_eq!(map.get(&1), Some(&1));
+    /// Return the maximum global depth, i.e., the directory won't have more
+    /// than `2^max_global_depth` entries.
+    ///
+    /// Defaults to the width of the hash values, capped at 64, as the
+    /// prefixes of the buckets, e.g., [`BucketInfo::prefix()`], are `u64`s.
+    ///
+    /// [`BucketInfo::prefix()`]: crate::BucketInfo::prefix
+    #[inline]
+    pub fn max_global_depth(&self) -> usize {
+        self.max_global_depth
     }
 
-    #[test]
-    fn insert_duplicate_items() {
-        let mut map = HashMap::new();
-        assert_eq!(map.insert(1, 1), None);
-        assert_eq!(map.insert(1, 1), Some(1));
+    /// Set the maximum global depth.
+    ///
+    /// Once the global depth reaches `max_global_depth`, the directory stops
+    /// doubling, and a full bucket whose local depth equals to the global
+    /// depth stores new entries as overflow entries instead of being split.
+    /// This bounds the directory size under skewed hash distributions, at the
+    /// cost of scanning more entries per lookup.
+    ///
+    /// A global depth already greater than `max_global_depth` is not reduced.
+    ///
+    /// # Panic
+    /// `max_global_depth` should be greater than 0, and not greater than the
+    /// width of the hash values or 64.
+    pub fn set_max_global_depth(&mut self, max_global_depth: usize) {
+        assert!(max_global_depth > 0);
+        assert!(max_global_depth <= max_depth::<S::Hash>());
 
-        assert_eq!(map.get(&1), Some(&1));
+        self.max_global_depth = max_global_depth;
     }
 
-    #[test]
-    fn insert_1000_items() {
-        let mut map = HashMap::new();
-        for i in 0..1000 {
-            assert_eq!(map.get(&i), None);
-            assert_eq!(map.insert(i, i), None);
-            assert_eq!(map.get(&i), Some(&i));
+    /// Return how the entries are arranged within a bucket.
+    #[inline]
+    pub fn bucket_policy(&self) -> BucketPolicy {
+        self.bucket_policy
+    }
+
+    /// Set how the entries are arranged within a bucket, the existing entries
+    /// are rearranged accordingly.
+    ///
+    /// [`BucketPolicy::Sorted`] makes lookups binary search the buckets, which
+    /// pays off with large bucket capacities, see
+    /// [`HashMap::with_bucket_capacity()`].
+    pub fn set_bucket_policy(&mut self, bucket_policy: BucketPolicy) {
+        if bucket_policy == BucketPolicy::Sorted
+            && self.bucket_policy != BucketPolicy::Sorted
+        {
+            self.buckets
+                .iter_mut()
+                .for_each(|(_, bucket)| bucket.sort());
         }
 
-        assert_eq!(map.len(), 1000);
+        self.bucket_policy = bucket_policy;
     }
 
-    #[test]
-    fn remove_works() {
-        let mut map = HashMap::new();
+    /// Return when removals merge buckets.
+    #[inline]
+    pub fn coalesce_policy(&self) -> CoalescePolicy {
+        self.coalesce_policy
+    }
+
+    /// Set when removals merge buckets, see [`CoalescePolicy`], the existing
+    /// buckets are merged by the following removals only.
+    ///
+    /// # Panic
+    /// The fill ratio of [`CoalescePolicy::Threshold`] should be within
+    /// `[0, 1]`.
+    pub fn set_coalesce_policy(&mut self, coalesce_policy: CoalescePolicy) {
+        coalesce_policy.check();
+        self.coalesce_policy = coalesce_policy;
+    }
+
+    /// Return the number of the empty levels the directory keeps below its
+    /// deepest bucket before removals halve it.
+    #[inline]
+    pub fn halving_hysteresis(&self) -> usize {
+        self.halving_hysteresis
+    }
+
+    /// Set the number of the empty levels the directory keeps below its
+    /// deepest bucket before removals halve it, which defaults to 1.
+    ///
+    /// Removals halve the directory once its `halving_hysteresis + 1` deepest
+    /// levels have no bucket, so that a split and a coalescence around the
+    /// same bucket don't double and halve the directory back and forth. With
+    /// 0, the directory is halved as soon as it can be, which keeps it as
+    /// small as possible. [`shrink_to_fit()`](Self::shrink_to_fit) halves
+    /// the directory regardless.
+    pub fn set_halving_hysteresis(&mut self, halving_hysteresis: usize) {
+        self.halving_hysteresis = halving_hysteresis;
+    }
+
+    /// Set `observer` to be called with every structural change of the map,
+    /// i.e., bucket splits and coalescences, and directory doublings and
+    /// halvings, see [`MapEvent`], replacing the previous observer.
+    ///
+    /// The observer is called while the map is being modified, it should be
+    /// cheap, e.g., logging the event or updating a metric. The observer is
+    /// shared by the clones of the map.
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::{HashMap, MapEvent};
+    /// use std::sync::{
+    ///     atomic::{AtomicUsize, Ordering},
+    ///     Arc,
+    /// };
+    ///
+    /// let splits = Arc::new(AtomicUsize::new(0));
+    /// let mut map = HashMap::new();
+    /// map.set_observer({
+    ///     let splits = Arc::clone(&splits);
+    ///     move |event| {
+    ///         if let MapEvent::Split { .. } = event {
+    ///             splits.fetch_add(1, Ordering::Relaxed);
+    ///         }
+    ///     }
+    /// });
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    /// assert!(splits.load(Ordering::Relaxed) > 0);
+    /// ```
+    pub fn set_observer<F>(&mut self, observer: F)
+    where
+        F: Fn(MapEvent) + Send + Sync + 'static,
+    {
+        self.observer = Some(Observer::new(observer));
+    }
+
+    /// Remove the observer set by [`set_observer()`](Self::set_observer).
+    pub fn clear_observer(&mut self) {
+        self.observer = None;
+    }
+
+    /// Report `event` to the observer, if any, and to the `metrics` facade
+    /// with the `metrics` feature.
+    #[inline]
+    fn notify(&self, event: MapEvent) {
+        #[cfg(feature = "metrics")]
+        crate::observer::record_metrics(event);
+        if let Some(observer) = &self.observer {
+            observer.notify(event);
+        }
+    }
+
+    /// Return a reference to the map's [`DirectoryMapper`].
+    #[inline]
+    pub fn mapper(&self) -> &M {
+        &self.mapper
+    }
+
+    /// Return the number of elements in the map.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if this map is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the number of elements the map can hold
+    /// without reallocating.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.directories.len() * self.bucket_capacity
+    }
+
+    /// Create a raw immutable entry builder for the map.
+    ///
+    /// See the [`raw_entry`](crate::raw_entry) module for the requirement on
+    /// the hash values.
+    pub fn raw_entry(&self) -> RawEntryBuilder<'_, K, V, S, M, B, A> {
+        RawEntryBuilder::new(self)
+    }
+
+    /// Create a raw mutable entry builder for the map.
+    ///
+    /// See the [`raw_entry`](crate::raw_entry) module for the requirement on
+    /// the hash values.
+    pub fn raw_entry_mut(
+        &mut self,
+    ) -> RawEntryBuilderMut<'_, K, V, S, M, B, A> {
+        RawEntryBuilderMut::new(self)
+    }
+
+    /// Return the `idx`th bit of `hash` used to index the directory,
+    /// `idx` starts from 0.
+    #[inline]
+    fn hash_bit(&self, hash: S::Hash, idx: usize) -> u8 {
+        self.mapper.map(hash).bit(idx as u32) as u8
+    }
+
+    /// Locate the bucket where a key whose hash value is `hash` will go.
+    pub(crate) fn locate_bucket_by_hash(&self, hash: S::Hash) -> usize {
+        // Use the reverse last `self.global` bits
+        //
+        // NOTE: we need to ensure the following guarantee:
+        // Say the global depth is 1, and the hashing bits are `[0]`, after
+        // we increment the global depth to 2, the hashing bits have to be
+        // either `[0, 0]` or `[0, 1]`
+        //
+        // The mapper is applied first, e.g., with `BitOrder::Lsb`, the bits are
+        // taken from the least significant one, which keeps the same
+        // guarantee.
+        let directory_idx =
+            self.mapper.map(hash).prefix(self.global_depth as u32);
+
+        self.directories[directory_idx].get()
+    }
+
+    /// Remove the `idx`th entry of bucket `bucket_idx`, and then try
+    /// coalescing that bucket.
+    pub(crate) fn remove_at(
+        &mut self,
+        bucket_idx: usize,
+        idx: usize,
+    ) -> (K, V) {
+        let (_, k, v) =
+            self.buckets[bucket_idx].remove(idx, self.bucket_policy);
+        self.len -= 1;
+
+        self.coalesce(bucket_idx);
+
+        (k, v)
+    }
+
+    /// Create an iterator which uses a closure to determine if an entry should
+    /// be removed.
+    ///
+    /// If the closure returns true, the entry is removed from the map and
+    /// yielded, entries are removed lazily, i.e., only when the iterator is
+    /// advanced. If the iterator is dropped before being fully consumed, the
+    /// remaining entries are retained.
+    ///
+    /// Buckets are coalesced when the iterator is dropped.
+    pub fn extract_if<F>(
+        &mut self,
+        pred: F,
+    ) -> ExtractIf<'_, K, V, F, S, M, B, A>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        ExtractIf::new(self, pred)
+    }
+
+    /// Return the index of the sibling bucket of bucket `bucket_idx`, whose
+    /// prefix differs from the bucket's only in the last bit, or `None` if
+    /// the local depth of the bucket is 0, i.e., it's the only bucket, or the
+    /// sibling has been split deeper.
+    fn sibling(&self, bucket_idx: usize) -> Option<usize> {
+        let bucket = &self.buckets[bucket_idx];
+        let local_depth = bucket.local_depth();
+        if local_depth == 0 {
+            return None;
+        }
+
+        // the first directory entry pointing to the sibling bucket
+        let sibling_prefix = (bucket.prefix ^ 1) as usize;
+        let sibling_idx = self.directories
+            [sibling_prefix << (self.global_depth - local_depth)]
+            .get();
+
+        (self.buckets[sibling_idx].local_depth() == local_depth)
+            .then_some(sibling_idx)
+    }
+
+    /// Try to coalesce bucket `bucket_idx` and its sibling bucket, return true
+    /// if they are merged.
+    ///
+    /// The merged bucket is coalesced with its own sibling bucket again, and
+    /// so on, until a sibling bucket can't be merged, so that an emptied map
+    /// collapses back towards a single bucket.
+    ///
+    /// NOTE: this will remove buckets, whose indexes will be invalidated, the
+    /// indexes of the other buckets stay valid.
+    pub(crate) fn coalesce(&mut self, mut bucket_idx: usize) -> bool {
+        let mut merged = false;
+        // every merge decrements the local depth, which bounds the loop
+        while let Some(survivor_bucket_idx) =
+            self.coalesce_once(bucket_idx, self.coalesce_policy)
+        {
+            bucket_idx = survivor_bucket_idx;
+            merged = true;
+        }
+
+        // Halve the directory once the levels of the hysteresis below the
+        // deepest one are empty too, rather than once the deepest one is, see
+        // `set_halving_hysteresis()`.
+        while self.global_depth > self.halving_hysteresis
+            && self.depth_counts[self.global_depth - self.halving_hysteresis..]
+                .iter()
+                .all(|count| *count == 0)
+        {
+            self.halve_directory();
+        }
+
+        merged
+    }
+
+    /// Try to merge bucket `bucket_idx` and its sibling bucket, return the
+    /// index of the merged bucket if they are merged.
+    ///
+    /// Two buckets can be merged if they have the same local depth, which
+    /// should be greater than 0, and their data can fit into one bucket, as
+    /// long as `policy` allows.
+    fn coalesce_once(
+        &mut self,
+        bucket_idx: usize,
+        policy: CoalescePolicy,
+    ) -> Option<usize> {
+        let sibling_idx = self.sibling(bucket_idx)?;
+        // The data of two buckets should fit into one bucket
+        if !policy.should_merge(
+            self.buckets[bucket_idx].len() + self.buckets[sibling_idx].len(),
+            self.bucket_capacity,
+        ) {
+            return None;
+        }
+
+        // the bucket whose last bit is 1 is merged into the other one
+        let (dead_bucket_idx, survivor_bucket_idx) =
+            if self.buckets[bucket_idx].prefix & 1 == 1 {
+                (bucket_idx, sibling_idx)
+            } else {
+                (sibling_idx, bucket_idx)
+            };
+        let dead_bucket_value =
+            self.buckets[dead_bucket_idx].value(self.global_depth);
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "coalesce",
+            prefix = self.buckets[survivor_bucket_idx].prefix >> 1,
+            local_depth = self.buckets[survivor_bucket_idx].local_depth() - 1,
+            global_depth = self.global_depth,
+            occupancy = self.buckets[bucket_idx].len()
+                + self.buckets[sibling_idx].len(),
+        )
+        .entered();
+
+        self.counters.merges += 1;
+        self.counters.moved_entries +=
+            self.buckets[dead_bucket_idx].len() as u64;
+        // remove the dead bucket, the other buckets don't move so the
+        // directory entries pointing to them stay valid
+        let dead_bucket = self.buckets.remove(dead_bucket_idx);
+        let survivor_bucket_mut_ref = &mut self.buckets[survivor_bucket_idx];
+        // transfer data
+        for entry in dead_bucket.into_entries() {
+            survivor_bucket_mut_ref.push(entry, self.bucket_policy);
+        }
+        // decrease the local depth
+        survivor_bucket_mut_ref.pop_bit();
+        let local_depth = survivor_bucket_mut_ref.local_depth();
+        self.depth_counts[local_depth + 1] -= 2;
+        self.depth_counts[local_depth] += 1;
+        // update directory entries
+        match dead_bucket_value {
+            EqualTo(idx) => {
+                self.directories[idx] = BucketIdx::new(survivor_bucket_idx)
+            }
+            Range(range) => {
+                self.directories[range]
+                    .fill(BucketIdx::new(survivor_bucket_idx));
+            }
+        }
+        let survivor = &self.buckets[survivor_bucket_idx];
+        self.notify(MapEvent::Coalesce {
+            prefix: survivor.prefix,
+            local_depth: survivor.local_depth(),
+        });
+
+        Some(survivor_bucket_idx)
+    }
+
+    /// Return an empty bucket of `prefix` of local depth `depth`, which
+    /// spills to the arena of the map.
+    fn new_bucket(
+        &self,
+        prefix: u64,
+        depth: usize,
+    ) -> Bucket<K, V, S::Hash, B, Arena<A>> {
+        Bucket::new_in(prefix, depth, self.bucket_capacity, self.arena.clone())
+    }
+
+    /// Halve the directory and decrement the global depth, every bucket's
+    /// local depth should be smaller than the global depth.
+    fn halve_directory(&mut self) {
+        // With the global depth decremented, directory entry `idx` covers the
+        // old entries `2 * idx` and `2 * idx + 1`, both of them point to the
+        // same bucket.
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "halve_directory",
+            global_depth = self.global_depth,
+            slots = self.directories.len(),
+        )
+        .entered();
+        self.directories.halve();
+        self.global_depth -= 1;
+        self.notify(MapEvent::DirectoryHalved {
+            global_depth: self.global_depth,
+        });
+    }
+
+    /// Shrink the directory as much as possible.
+    ///
+    /// As long as every bucket's local depth is smaller than the global depth,
+    /// every bucket is pointed to by at least 2 directory entries, so we can
+    /// halve the directory and decrement the global depth. This is repeated
+    /// until some bucket's local depth equals the global depth, then the freed
+    /// memory is released, the spilled entries are moved into a new arena so
+    /// that the chunks of the old one, which keep the blocks freed by the
+    /// coalescences, are released too.
+    ///
+    /// Removals halve the directory as well, but only while every bucket's
+    /// local depth is smaller than the global depth minus 1, and they don't
+    /// release the freed memory.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// Merge every pair of sibling buckets whose entries fit into one bucket,
+    /// whatever the [`CoalescePolicy`] is, then shrink the map like
+    /// [`shrink_to_fit()`](Self::shrink_to_fit) does, return the number of
+    /// merges.
+    ///
+    /// This is meant for the maps whose removals don't merge the buckets, or
+    /// merge them late, e.g., under [`CoalescePolicy::Never`], after bulk
+    /// removals.
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::{CoalescePolicy, HashMap};
+    ///
+    /// let mut map = HashMap::with_bucket_capacity(4);
+    /// map.set_coalesce_policy(CoalescePolicy::Never);
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    /// let buckets = map.bucket_count();
+    /// for i in 0..1000 {
+    ///     map.remove(&i);
+    /// }
+    /// assert_eq!(map.bucket_count(), buckets);
+    ///
+    /// assert_eq!(map.compact(), buckets - 1);
+    /// assert_eq!(map.bucket_count(), 1);
+    /// assert_eq!(map.global_depth(), 0);
+    /// ```
+    pub fn compact(&mut self) -> usize {
+        let bucket_idxes =
+            self.buckets.iter().map(|(idx, _)| idx).collect::<Vec<_>>();
+        let mut merges = 0;
+        for mut bucket_idx in bucket_idxes {
+            // merged into another bucket already
+            if !self.buckets.contains(bucket_idx) {
+                continue;
+            }
+            // A merged bucket is tried with its sibling bucket again, so the
+            // buckets left can't be merged after a single pass: of 2
+            // siblings, the one that changed last has been tried with the
+            // other.
+            while let Some(survivor_bucket_idx) =
+                self.coalesce_once(bucket_idx, CoalescePolicy::Always)
+            {
+                bucket_idx = survivor_bucket_idx;
+                merges += 1;
+            }
+        }
+        self.shrink_to_fit();
+
+        merges
+    }
+
+    /// Shrink the directory like [`shrink_to_fit()`](Self::shrink_to_fit)
+    /// does, but keep the global depth and the allocations needed to hold
+    /// `min_capacity` elements, i.e., this won't shrink
+    /// [`capacity()`](Self::capacity) below `min_capacity`.
+    ///
+    /// This never grows the map, a map whose capacity is already below
+    /// `min_capacity`, e.g., as removals have halved its directory, is left
+    /// unchanged.
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::HashMap;
+    ///
+    /// // seeded, so that the layout left by the removals is deterministic
+    /// let mut map = HashMap::with_seed(1);
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    /// for i in 0..990 {
+    ///     map.remove(&i);
+    /// }
+    ///
+    /// map.shrink_to(100);
+    /// assert!(map.capacity() >= 100);
+    /// map.shrink_to(0);
+    /// assert!(map.capacity() >= 10);
+    /// ```
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let min_global_depth =
+            depth_for_capacity(min_capacity, self.bucket_capacity);
+        while self.global_depth > min_global_depth
+            && self.depth_counts[self.global_depth] == 0
+        {
+            self.halve_directory();
+        }
+
+        self.directories.shrink_to(1 << min_global_depth);
+        let min_buckets = min_capacity.div_ceil(self.bucket_capacity);
+        if self.buckets.capacity() > min_buckets.max(self.buckets.len()) {
+            self.buckets.shrink_to_fit();
+            self.buckets
+                .reserve(min_buckets.saturating_sub(self.buckets.len()));
+        }
+        if self.arena.allocated() > 0 {
+            let arena = Arena::new_in(self.arena.allocator());
+            for (_, bucket) in self.buckets.iter_mut() {
+                bucket.move_to(arena.clone());
+            }
+            self.arena = arena;
+        }
+    }
+}
+
+impl<
+        K: Hash,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > HashMap<K, V, S, M, B, A>
+{
+    /// Locate the bucket where `key` will go, return the hash value of `key`
+    /// and the bucket index.
+    fn locate_bucket<Q>(&self, key: &Q) -> (S::Hash, usize)
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+
+        (hash, self.locate_bucket_by_hash(hash))
+    }
+
+    /// Split bucket `bucket_to_split`, which is full, to insert `(key, value)`.
+    ///
+    /// Under awful cases, the bucket where `(key, value)` goes is still full
+    /// after a split, then it gets split again, until `(key, value)` has been
+    /// successfully inserted into the map.
+    ///
+    /// Return the bucket index and the slot index where `(key, value)` is
+    /// stored.
+    fn split(
+        &mut self,
+        hash: S::Hash,
+        key: K,
+        value: V,
+        mut bucket_to_split: usize,
+    ) -> (usize, usize) {
+        let mut splits = 0;
+        loop {
+            let bucket = &self.buckets[bucket_to_split];
+            // All the entries have the same hash value as the new one,
+            // splitting cannot separate them, or splitting needs a directory
+            // deeper than allowed, store the new one as an overflow entry.
+            if !bucket.is_full(self.bucket_capacity)
+                || bucket.hashes.iter().all(|h| *h == hash)
+                || bucket.local_depth() >= self.max_global_depth
+            {
+                break;
+            }
+
+            // the bucket where the new item goes may still be full, let's
+            // do split again.
+            bucket_to_split = self.split_once(bucket_to_split, hash);
+            splits += 1;
+        }
+        if splits > 1 {
+            self.counters.cascading_splits += 1;
+        }
+
+        let slot = self.buckets[bucket_to_split]
+            .push((hash, key, value), self.bucket_policy);
+
+        (bucket_to_split, slot)
+    }
+
+    /// Split bucket `bucket_to_split` by the bit after its prefix, return the
+    /// index of the bucket where a key whose hash value is `hash` goes.
+    fn split_once(&mut self, bucket_to_split: usize, hash: S::Hash) -> usize {
+        let old_local_depth = self.buckets[bucket_to_split].local_depth();
+        let goes_to_new_bucket = self.hash_bit(hash, old_local_depth) == 1;
+        let old_global_depth = self.global_depth;
+        assert!(old_local_depth <= old_global_depth);
+        // Two different hash values differ in some bit, they are separated
+        // before all the bits get used, the buckets deeper than the maximum
+        // global depth, which is at most 64, are never split.
+        assert!(
+            old_local_depth < max_depth::<S::Hash>(),
+            "HashMap::split(): hash values have no more bits to split on"
+        );
+        let old_prefix = self.buckets[bucket_to_split].prefix;
+        let mut new_bucket =
+            self.new_bucket((old_prefix << 1) | 1, old_local_depth + 1);
+        let mut_ref_bucket = &mut self.buckets[bucket_to_split];
+
+        // Redistribute the existing items using their cached hash values, no
+        // rehashing is needed. The items share the first `old_local_depth`
+        // bits, so the next bit alone tells which bucket an item goes to.
+        let mapper = &self.mapper;
+        let next_bit =
+            |hash: S::Hash| mapper.map(hash).bit(old_local_depth as u32);
+        let bucket_value = mut_ref_bucket.value(old_global_depth);
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "split",
+            prefix = old_prefix,
+            local_depth = old_local_depth,
+            global_depth = old_global_depth,
+            occupancy = mut_ref_bucket.len(),
+        )
+        .entered();
+        mut_ref_bucket.split_off(&mut new_bucket, self.bucket_policy, |h| {
+            next_bit(h) == 1
+        });
+        mut_ref_bucket.push_bit(0);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            kept = mut_ref_bucket.len(),
+            moved = new_bucket.len(),
+            "bucket split"
+        );
+        self.counters.splits += 1;
+        self.counters.moved_entries += new_bucket.len() as u64;
+        let new_bucket_idx = self.buckets.insert(new_bucket);
+        if self.depth_counts.len() <= old_local_depth + 1 {
+            self.depth_counts.resize(old_local_depth + 2, 0);
+        }
+        self.depth_counts[old_local_depth] -= 1;
+        self.depth_counts[old_local_depth + 1] += 2;
+
+        if old_local_depth < old_global_depth {
+            let last_half_directory_indexes =
+                // this bucket_value needs to be calculated before incrementing
+                // the local depth because we are redistributing the old pointers.
+                bucket_value.last_half_range().unwrap();
+
+            // redistribute pointers
+            for idx in last_half_directory_indexes {
+                self.directories[idx] = BucketIdx::new(new_bucket_idx);
+            }
+        } else {
+            // Double the directory. With the global depth incremented, the
+            // old directory entry `idx` covers the entries `2 * idx` and
+            // `2 * idx + 1`, both of them keep pointing to the same bucket.
+            // The directory of global depth 0 is allocated here.
+            #[cfg(feature = "tracing")]
+            let _span = tracing::debug_span!(
+                "double_directory",
+                global_depth = old_global_depth,
+                slots = self.directories.len(),
+            )
+            .entered();
+            self.directories.double(self.buckets.allocator());
+            self.global_depth += 1;
+
+            // The split bucket was pointed to by a single entry, now covered
+            // by 2 entries, the last of them points to the new bucket.
+            let EqualTo(idx) = bucket_value else {
+                unreachable!("local depth equals the global depth");
+            };
+            self.directories[2 * idx + 1] = BucketIdx::new(new_bucket_idx);
+            self.notify(MapEvent::DirectoryDoubled {
+                global_depth: self.global_depth,
+            });
+        }
+        self.notify(MapEvent::Split {
+            prefix: old_prefix,
+            local_depth: old_local_depth,
+        });
+
+        let target = if goes_to_new_bucket {
+            new_bucket_idx
+        } else {
+            bucket_to_split
+        };
+        debug_assert_eq!(target, self.locate_bucket_by_hash(hash));
+
+        target
+    }
+
+    /// Insert `(key, value)`, which is known to be absent, to the bucket
+    /// `bucket_idx`, the bucket will be split if it is full. `hash` is the
+    /// hash value of `key`.
+    ///
+    /// Return the bucket index and the slot index where `(key, value)` is
+    /// stored.
+    pub(crate) fn insert_absent(
+        &mut self,
+        bucket_idx: usize,
+        hash: S::Hash,
+        key: K,
+        value: V,
+    ) -> (usize, usize) {
+        let mut_ref_bucket = self.buckets.get_mut(bucket_idx).unwrap();
+        let location = if !mut_ref_bucket.is_full(self.bucket_capacity) {
+            let idx =
+                mut_ref_bucket.push((hash, key, value), self.bucket_policy);
+
+            (bucket_idx, idx)
+        } else {
+            self.split(hash, key, value, bucket_idx)
+        };
+        self.len += 1;
+
+        location
+    }
+
+    /// Insert `(key, value)` to this map.
+    ///
+    /// If the map did not have this key present, `None` is returned.
+    ///
+    /// If the map did have this key present, the value is updated, and the
+    /// old value is returned. The key is not updated.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Eq,
+    {
+        let (hash, bucket_idx) = self.locate_bucket(&key);
+        let mut_ref_bucket = self.buckets.get_mut(bucket_idx).unwrap();
+
+        // Check existence
+        if let Some(idx) =
+            mut_ref_bucket.position(hash, &key, self.bucket_policy)
+        {
+            return Some(std::mem::replace(
+                &mut mut_ref_bucket.values[idx],
+                value,
+            ));
+        }
+
+        self.insert_absent(bucket_idx, hash, key, value);
+
+        None
+    }
+
+    /// Return a mutable reference to the value corresponding to `key`, if
+    /// `key` is absent, insert the value computed from `default` first.
+    ///
+    /// The bucket is located only once.
+    pub fn get_or_insert_with<F>(&mut self, key: K, default: F) -> &mut V
+    where
+        K: Eq,
+        F: FnOnce() -> V,
+    {
+        let (hash, bucket_idx) = self.locate_bucket(&key);
+        let existing =
+            self.buckets[bucket_idx].position(hash, &key, self.bucket_policy);
+
+        let (bucket_idx, idx) = match existing {
+            Some(idx) => (bucket_idx, idx),
+            None => self.insert_absent(bucket_idx, hash, key, default()),
+        };
+
+        &mut self.buckets[bucket_idx].values[idx]
+    }
+
+    /// Get the entry of `key` for in-place manipulation.
+    ///
+    /// `key` is only converted to an owned key when the vacant entry is
+    /// inserted, so no allocation happens if the key is present.
+    pub fn entry_ref<'a, 'b, Q>(
+        &'a mut self,
+        key: &'b Q,
+    ) -> EntryRef<'a, 'b, K, Q, V, S, M, B, A>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (hash, bucket_idx) = self.locate_bucket(key);
+        let existing =
+            self.buckets[bucket_idx].position(hash, key, self.bucket_policy);
+
+        match existing {
+            Some(idx) => {
+                EntryRef::Occupied(OccupiedEntry::new(self, bucket_idx, idx))
+            }
+            None => EntryRef::Vacant(VacantEntryRef::new(
+                self, bucket_idx, hash, key,
+            )),
+        }
+    }
+
+    /// Make sure the map has enough capacity for `additional` more elements.
+    ///
+    /// If the capacity is not sufficient, the map is rebuilt with a larger
+    /// global depth, so that the following insertions won't have to split
+    /// buckets one at a time.
+    pub(crate) fn reserve(&mut self, additional: usize)
+    where
+        K: Eq,
+    {
+        let required = self.len + additional;
+        if required <= self.capacity() {
+            return;
+        }
+
+        let global_depth = depth_for_capacity(required, self.bucket_capacity)
+            .min(self.max_global_depth)
+            .max(self.global_depth);
+        let old_buckets = self.reset(global_depth);
+        for (h, k, v) in old_buckets
+            .into_iter()
+            .flat_map(|(_, bucket)| bucket.into_entries())
+        {
+            let bucket_idx = self.locate_bucket_by_hash(h);
+            self.insert_absent(bucket_idx, h, k, v);
+        }
+    }
+
+    /// Move all the elements of `other` into `self`, leaving `other` empty.
+    ///
+    /// The directory is enlarged for the combined length before moving the
+    /// elements, which are then inserted through [`HashMap::insert()`].
+    pub fn append(&mut self, other: &mut Self)
+    where
+        K: Eq,
+    {
+        self.reserve(other.len());
+
+        let other_buckets = other.reset(0);
+        for (_, k, v) in other_buckets
+            .into_iter()
+            .flat_map(|(_, bucket)| bucket.into_entries())
+        {
+            self.insert(k, v);
+        }
+    }
+
+    /// Try inserting `(key, value)` to the map, return a mutable reference to
+    /// the inserted value.
+    ///
+    /// If `key` is already present, nothing is updated, and an error
+    /// containing the occupied entry and `value` is returned.
+    pub fn try_insert(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Result<&mut V, OccupiedError<'_, K, V, S, M, B, A>>
+    where
+        K: Eq,
+    {
+        let (hash, bucket_idx) = self.locate_bucket(&key);
+        let existing =
+            self.buckets[bucket_idx].position(hash, &key, self.bucket_policy);
+
+        match existing {
+            Some(idx) => Err(OccupiedError {
+                entry: OccupiedEntry::new(self, bucket_idx, idx),
+                value,
+            }),
+            None => {
+                let (bucket_idx, idx) =
+                    self.insert_absent(bucket_idx, hash, key, value);
+
+                Ok(&mut self.buckets[bucket_idx].values[idx])
+            }
+        }
+    }
+
+    /// Remove `key` from the map, return its value if it was previously in the
+    /// map.
+    ///
+    /// # Coalescence
+    ///
+    /// After deletion, we will try to merge the bucket where the `key` was
+    /// removed and its sibling bucket.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    /// Remove `key` from the map, return the stored key and its value if it
+    /// was previously in the map.
+    ///
+    /// Coalescence is done in the same way as [`HashMap::remove()`].
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let (bucket_idx, key_idx) = self.find(key)?;
+
+        Some(self.remove_at(bucket_idx, key_idx))
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let (bucket_idx, idx) = self.find(key)?;
+
+        Some(&self.buckets[bucket_idx].values[idx])
+    }
+
+    /// Returns the key-value pair corresponding to the supplied key.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let (bucket_idx, idx) = self.find(key)?;
+        let bucket = &self.buckets[bucket_idx];
+
+        Some((&bucket.keys[idx], &bucket.values[idx]))
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (bucket_idx, idx) = self.find(key)?;
+
+        Some(&mut self.buckets[bucket_idx].values[idx])
+    }
+
+    /// Locate `key`, return the index of the bucket where it is stored and its
+    /// index in that bucket.
+    fn find<Q>(&self, key: &Q) -> Option<(usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (hash, bucket_idx) = self.locate_bucket(key);
+        let idx = self
+            .buckets
+            .get(bucket_idx)
+            .expect("locate_bucket() returns a wrong index")
+            .position(hash, key, self.bucket_policy)?;
+
+        Some((bucket_idx, idx))
+    }
+
+    /// Returns mutable references to the values corresponding to `keys`.
+    ///
+    /// Return `None` if any key is missing, or if some keys refer to the same
+    /// entry.
+    pub fn get_many_mut<Q, const N: usize>(
+        &mut self,
+        keys: [&Q; N],
+    ) -> Option<[&mut V; N]>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut locations = [(0, 0); N];
+        for (location, key) in locations.iter_mut().zip(keys) {
+            *location = self.find(key)?;
+        }
+
+        // aliasing check, an entry is identified by its bucket index and its
+        // index in that bucket.
+        for (i, location) in locations.iter().enumerate() {
+            if locations[..i].contains(location) {
+                return None;
+            }
+        }
+
+        // Borrow every bucket once, the values of a bucket may be stored
+        // inline, so borrowing the bucket again would overlap the values
+        // already handed out. Keys in the same bucket share the pointer to
+        // its values.
+        let buckets: *mut Slab<_, A> = &mut self.buckets;
+        let mut values = [std::ptr::null_mut::<V>(); N];
+        for (i, (bucket_idx, _)) in locations.iter().enumerate() {
+            values[i] = match locations[..i]
+                .iter()
+                .position(|(prev_idx, _)| prev_idx == bucket_idx)
+            {
+                Some(prev) => values[prev],
+                None => {
+                    // SAFETY: the bucket is borrowed for the first time, and
+                    // `get_disjoint_mut()` reaches its slab entry through
+                    // the pointer to the slab's storage, without borrowing
+                    // the other entries, including the buckets whose values
+                    // are pointed to by `values[..i]`.
+                    let [bucket] = unsafe {
+                        (*buckets)
+                            .get_disjoint_mut([*bucket_idx])
+                            .expect("find() returns a wrong index")
+                    };
+                    bucket.values.as_mut_ptr()
+                }
+            };
+        }
+
+        Some(std::array::from_fn(|i| {
+            // SAFETY:
+            // 1. all the locations are valid as they are returned by `find()`
+            // 2. all the locations are distinct, so these mutable references
+            //    never alias
+            unsafe { &mut *values[i].add(locations[i].1) }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::HashMapBuilder;
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn insert_without_split_works() {
+        let mut map = HashMap::new();
+        assert_eq!(map.insert(1, 1), None);
+
+        assert_eq!(map.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn insert_duplicate_items() {
+        let mut map = HashMap::new();
+        assert_eq!(map.insert(1, 1), None);
+        assert_eq!(map.insert(1, 2), Some(1));
+
+        assert_eq!(map.get(&1), Some(&2));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn insert_replaces_during_and_after_splits() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            // this insertion may split a bucket
+            assert_eq!(map.insert(i, i), None);
+            assert_eq!(map.insert(i, i + 1), Some(i));
+        }
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i + 2), Some(i + 1));
+        }
+
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&(i + 2)));
+        }
+        assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn insert_1000_items() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), None);
+            assert_eq!(map.insert(i, i), None);
+            assert_eq!(map.get(&i), Some(&i));
+        }
+
+        assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn remove_works() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            assert!(map.remove(&i).is_none());
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 1000);
+
+        for i in 0..1000 {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn shrink_to_keeps_min_capacity() {
+        let mut map = HashMap::with_bucket_capacity(4);
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+        let global_depth = map.global_depth;
+        for i in 0..9_000 {
+            map.remove(&i);
+        }
+
+        // the directory is not halved below the depth 1000 entries need
+        let min_global_depth = depth_for_capacity(1000, 4);
+        map.shrink_to(1000);
+        map.validate().unwrap();
+        let max_local_depth = map.stats().max_local_depth();
+        assert_eq!(map.global_depth, min_global_depth.max(max_local_depth));
+        assert!(map.global_depth <= global_depth);
+        assert!(map.capacity() >= 1000);
+        assert!(map.directories.capacity() >= 1 << min_global_depth);
+        assert!(map.buckets.capacity() >= 250);
+
+        // a smaller minimum shrinks further
+        for i in 9_000..9_990 {
+            map.remove(&i);
+        }
+        let max_local_depth = map.stats().max_local_depth();
+        map.shrink_to(10);
+        map.validate().unwrap();
+        assert_eq!(
+            map.global_depth,
+            depth_for_capacity(10, 4).max(max_local_depth)
+        );
+        assert!(map.capacity() >= map.len());
+        for i in 9_990..10_000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn shrink_to_below_capacity_is_noop() {
+        let mut map = HashMap::with_bucket_capacity(4);
+        for i in 0..10 {
+            map.insert(i, i);
+        }
+        let capacity = map.capacity();
+        let global_depth = map.global_depth;
+        let buckets = map.bucket_count();
+        assert!(capacity < 1000);
+
+        map.shrink_to(1000);
+        map.validate().unwrap();
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(map.global_depth, global_depth);
+        assert_eq!(map.bucket_count(), buckets);
+        for i in 0..10 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn shrink_to_fit_works() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        let global_depth = map.global_depth;
+        for i in 0..990 {
+            map.remove(&i);
+        }
+
+        map.shrink_to_fit();
+        map.validate().unwrap();
+        assert!(map.global_depth <= global_depth);
+        assert_eq!(map.directories.len(), 2_usize.pow(map.global_depth as _));
+        assert!(map
+            .buckets
+            .iter()
+            .any(|(_, bucket)| bucket.local_depth() == map.global_depth));
+        for i in 990..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+
+        // A freshly split map can be shrunk back after coalescence
+        let mut map = HashMap::new();
+        for i in 0..5 {
+            map.insert(i, i);
+        }
+        for i in 0..5 {
+            map.remove(&i);
+        }
+        map.shrink_to_fit();
+        map.validate().unwrap();
+        assert_eq!(map.global_depth, 0);
+        assert_eq!(map.directories.len(), 1);
+        assert_eq!(map.buckets.len(), 1);
+        assert_eq!(map.memory_usage().directory(), 0);
+    }
+
+    #[test]
+    fn directory_is_allocated_by_the_first_split() {
+        let mut map = HashMap::with_bucket_capacity(4);
+        assert_eq!(map.memory_usage().directory(), 0);
+        for i in 0..4 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.global_depth, 0);
+        assert_eq!(map.memory_usage().directory(), 0);
+
+        map.insert(4, 4);
+        assert!(map.global_depth > 0);
+        assert!(map.memory_usage().directory() > 0);
+        map.validate().unwrap();
+
+        // the buckets coalesce back into one, whose directory is inline
+        for i in 0..5 {
+            map.remove(&i);
+        }
+        map.shrink_to_fit();
+        assert_eq!(map.global_depth, 0);
+        assert!(matches!(map.directories, Directory::Inline(_)));
+        map.validate().unwrap();
+    }
+
+    #[test]
+    fn get_key_value_works() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i.to_string(), i);
+        }
+
+        for i in 0..100 {
+            let key = i.to_string();
+            let (k, v) = map.get_key_value(key.as_str()).unwrap();
+            assert_eq!(k, &key);
+            assert_eq!(*v, i);
+        }
+        assert_eq!(map.get_key_value("100"), None);
+    }
+
+    #[test]
+    fn remove_entry_works() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i.to_string().into_boxed_str(), i);
+        }
+
+        for i in 0..100 {
+            let key = i.to_string();
+            assert_eq!(
+                map.remove_entry(key.as_str()),
+                Some((key.into_boxed_str(), i))
+            );
+        }
+        assert_eq!(map.remove_entry("0"), None);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn try_insert_works() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            assert_eq!(map.try_insert(i, i).ok().copied(), Some(i));
+        }
+
+        for i in 0..100 {
+            let err = map.try_insert(i, i + 1).unwrap_err();
+            assert_eq!(err.entry.key(), &i);
+            assert_eq!(err.entry.get(), &i);
+            assert_eq!(err.value, i + 1);
+        }
+        assert_eq!(map.len(), 100);
+    }
+
+    #[test]
+    fn get_many_mut_works() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+
+        let [a, b] = map.get_many_mut([&1, &99]).unwrap();
+        std::mem::swap(a, b);
+        assert_eq!(map.get(&1), Some(&99));
+        assert_eq!(map.get(&99), Some(&1));
+
+        // aliasing keys
+        assert!(map.get_many_mut([&1, &2, &1]).is_none());
+        // missing key
+        assert!(map.get_many_mut([&1, &100]).is_none());
+    }
+
+    #[test]
+    fn get_many_mut_same_bucket() {
+        // a single bucket, whose values are stored inline
+        let mut map = HashMap::new();
+        for i in 0..3 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.bucket_count(), 1);
+        let [a, b, c] = map.get_many_mut([&2, &0, &1]).unwrap();
+        *a += 10;
+        *b += 10;
+        std::mem::swap(b, c);
+        assert_eq!(map.get(&0), Some(&1));
+        assert_eq!(map.get(&1), Some(&10));
+        assert_eq!(map.get(&2), Some(&12));
+
+        // keys spread over 2 buckets, 3 of them sharing one
+        let mut map = HashMap::with_seed(0);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        let mut keys_by_bucket = BTreeMap::<usize, Vec<i32>>::new();
+        for i in 0..1000 {
+            let (bucket_idx, _) = map.find(&i).unwrap();
+            keys_by_bucket.entry(bucket_idx).or_default().push(i);
+        }
+        let shared = keys_by_bucket.values().find(|k| k.len() >= 3).unwrap();
+        let other =
+            keys_by_bucket.values().find(|k| k[0] != shared[0]).unwrap();
+        let keys = [&shared[0], &other[0], &shared[1], &shared[2]];
+        for value in map.get_many_mut(keys).unwrap() {
+            *value += 1000;
+        }
+        for key in keys {
+            assert_eq!(map[key], key + 1000);
+        }
+        map.validate().unwrap();
+    }
+
+    #[test]
+    fn index_works() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i.to_string(), i);
+        }
+
+        for i in 0..100 {
+            assert_eq!(map[&i.to_string()], i);
+            assert_eq!(map[i.to_string().as_str()], i);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "key not found")]
+    fn index_missing_key() {
+        let map: HashMap<i32, i32> = HashMap::new();
+        let _ = map[&1];
+    }
+
+    #[test]
+    fn clone_preserves_layout() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+
+        let mut clone = map.clone();
+        assert_eq!(clone.len(), map.len());
+        assert_eq!(clone.global_depth, map.global_depth);
+        assert_eq!(clone.directories, map.directories);
+        for ((ka, a), (kb, b)) in clone.buckets.iter().zip(map.buckets.iter()) {
+            assert_eq!(ka, kb);
+            assert_eq!((a.prefix, a.depth), (b.prefix, b.depth));
+            assert!(a.iter().eq(b.iter()));
+        }
+
+        // the clone is fully functional
+        for i in 100..200 {
+            clone.insert(i, i);
+        }
+        for i in 0..200 {
+            assert_eq!(clone.get(&i), Some(&i));
+        }
+        assert_eq!(map.len(), 100);
+    }
+
+    #[test]
+    fn eq_ignores_layout() {
+        let mut map1 = HashMap::new();
+        let mut map2 = HashMap::new();
+        for i in 0..100 {
+            map1.insert(i, i);
+        }
+        for i in (0..200).rev() {
+            map2.insert(i, i);
+        }
+        for i in 100..200 {
+            map2.remove(&i);
+        }
+        assert_eq!(map1, map2);
+
+        *map2.get_mut(&0).unwrap() = 1;
+        assert_ne!(map1, map2);
+        map2.remove(&0);
+        assert_ne!(map1, map2);
+    }
+
+    #[test]
+    fn extract_if_works() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        let buckets = map.buckets.len();
+
+        let mut evens = map.extract_if(|k, _| k % 2 == 0).collect::<Vec<_>>();
+        evens.sort();
+        assert_eq!(
+            evens,
+            (0..1000).step_by(2).map(|i| (i, i)).collect::<Vec<_>>()
+        );
+        assert_eq!(map.len(), 500);
+        assert!(map.buckets.len() < buckets);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), (i % 2 == 1).then_some(&i));
+        }
+
+        // dropped before being fully consumed
+        assert_eq!(map.extract_if(|_, _| true).take(100).count(), 100);
+        assert_eq!(map.len(), 400);
+    }
+
+    #[test]
+    fn remove_only_repoints_dead_bucket() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        for i in 0..1000 {
+            let (before, old_depth) =
+                (map.directories.clone(), map.global_depth);
+            map.remove(&i);
+            map.validate().unwrap();
+            // a directory entry changes only if its bucket got merged away,
+            // entry `idx` covers the old entry `idx << shift` if the
+            // directory got halved
+            let shift = old_depth - map.global_depth;
+            for (idx, new) in map.directories.iter().enumerate() {
+                let old = before[idx << shift];
+                assert!(old == *new || !map.buckets.contains(old.get()));
+            }
+        }
+    }
+
+    #[test]
+    fn coalesce_keeps_bucket_indexes() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        let bucket_of = |map: &HashMap<i32, i32>, key: &i32| {
+            let (bucket_idx, _) = map.find(key).unwrap();
+            let bucket = &map.buckets[bucket_idx];
+            (bucket_idx, (bucket.prefix, bucket.depth))
+        };
+        let before = (0..1000).map(|i| bucket_of(&map, &i)).collect::<Vec<_>>();
+
+        for i in 0..900 {
+            map.remove(&i);
+        }
+        map.validate().unwrap();
+
+        // a bucket that wasn't merged away keeps its index
+        for i in 900..1000 {
+            let (bucket_idx, prefix) = bucket_of(&map, &i);
+            if prefix == before[i as usize].1 {
+                assert_eq!(bucket_idx, before[i as usize].0);
+            }
+        }
+        assert!(map
+            .directories
+            .iter()
+            .all(|bucket_idx| map.buckets.contains(bucket_idx.get())));
+    }
+
+    #[test]
+    fn with_global_depth_works() {
+        let map: HashMap<(), ()> = HashMap::with_global_depth(
+            3,
+            BUCKET_CAP,
+            DefaultHashBuilder::default(),
+            BitOrder::default(),
+            Global,
+        );
+        assert_eq!(map.directories[..], (0..8).collect::<Vec<_>>());
+        for (idx, bucket) in map.buckets.iter() {
+            assert_eq!(bucket.value(3), EqualTo(idx));
+        }
+    }
+
+    #[test]
+    fn append_works() {
+        let mut map1 = HashMap::new();
+        let mut map2 = HashMap::new();
+        for i in 0..100 {
+            map1.insert(i, i);
+        }
+        for i in 100..1000 {
+            map2.insert(i, i);
+        }
+
+        map1.append(&mut map2);
+        map1.validate().unwrap();
+        map2.validate().unwrap();
+        assert!(map2.is_empty());
+        // left as a new map is
+        assert_eq!(map2.global_depth(), 0);
+        assert_eq!(map2.bucket_count(), 1);
+        assert_eq!(map2.get(&100), None);
+        assert_eq!(map1.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(map1.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn get_or_insert_with_works() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            *map.get_or_insert_with(i % 100, || 0) += 1;
+        }
+
+        assert_eq!(map.len(), 100);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(&10));
+        }
+        assert_eq!(*map.get_or_insert_with(0, || unreachable!()), 10);
+    }
+
+    #[test]
+    fn depth_for_capacity_works() {
+        assert_eq!(depth_for_capacity(0, BUCKET_CAP), 0);
+        assert_eq!(depth_for_capacity(BUCKET_CAP, BUCKET_CAP), 0);
+        assert_eq!(depth_for_capacity(BUCKET_CAP + 1, BUCKET_CAP), 1);
+        assert_eq!(depth_for_capacity(BUCKET_CAP * 2, BUCKET_CAP), 1);
+        assert_eq!(depth_for_capacity(BUCKET_CAP * 2 + 1, BUCKET_CAP), 2);
+        assert_eq!(depth_for_capacity(BUCKET_CAP * 1024, BUCKET_CAP), 10);
+        assert_eq!(depth_for_capacity(64 * 1024, 64), 10);
+    }
+
+    #[test]
+    fn from_array_works() {
+        let map = HashMap::from([(1, 1), (2, 2), (3, 3), (1, 4)]);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&1), Some(&4));
+        assert!(map.capacity() >= 4);
+
+        let map = crate::ehm! {
+            "a" => 1,
+            "b" => 2,
+        };
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["a"], 1);
+        assert_eq!(map["b"], 2);
+
+        let map: HashMap<i32, i32> = crate::ehm! {};
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn into_iter_works() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        let iter = map.into_iter();
+        assert_eq!(iter.len(), 1000);
+        let mut items = iter.collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(items, (0..1000).map(|i| (i, i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn std_hashmap_conversions() {
+        let std_map = (0..1000)
+            .map(|i| (i, i))
+            .collect::<std::collections::HashMap<_, _>>();
+
+        let map = HashMap::from(std_map.clone());
+        assert_eq!(map.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+
+        let std_map_2: std::collections::HashMap<_, _> = map.into();
+        assert_eq!(std_map, std_map_2);
+    }
+
+    #[test]
+    fn entry_ref_works() {
+        let mut map: HashMap<String, usize> = HashMap::new();
+        let words = ["a", "b", "c", "a", "b", "a"];
+        for word in words {
+            *map.entry_ref(word).or_default() += 1;
+        }
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["a"], 3);
+        assert_eq!(map["b"], 2);
+        assert_eq!(map["c"], 1);
+
+        map.entry_ref("c").and_modify(|v| *v += 1).or_insert(0);
+        assert_eq!(map["c"], 2);
+        map.entry_ref("d").and_modify(|v| *v += 1).or_insert(0);
+        assert_eq!(map["d"], 0);
+
+        match map.entry_ref("a") {
+            EntryRef::Occupied(entry) => {
+                assert_eq!(entry.remove_entry(), ("a".to_string(), 3))
+            }
+            EntryRef::Vacant(_) => unreachable!(),
+        }
+        assert!(matches!(map.entry_ref("a"), EntryRef::Vacant(_)));
+
+        for i in 0..1000 {
+            map.entry_ref(i.to_string().as_str()).or_insert(i);
+        }
+        for i in 0..1000 {
+            assert_eq!(map[i.to_string().as_str()], i);
+        }
+    }
+
+    #[test]
+    fn custom_hasher_works() {
+        use std::{
+            collections::hash_map::DefaultHasher, hash::BuildHasherDefault,
+        };
+
+        let mut map: HashMap<i32, i32, BuildHasherDefault<DefaultHasher>> =
+            HashMap::default();
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i), None);
+        }
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+            assert_eq!(
+                make_hash(map.hasher(), &i),
+                BuildHasherDefault::<DefaultHasher>::default().hash_one(i)
+            );
+        }
+        for i in 0..1000 {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn with_hasher_is_reproducible() {
+        use std::{
+            collections::hash_map::DefaultHasher, hash::BuildHasherDefault,
+        };
+
+        let hash_builder = BuildHasherDefault::<DefaultHasher>::default();
+        let mut map1 = HashMap::with_hasher(hash_builder.clone());
+        let mut map2 = HashMap::with_capacity_and_hasher(0, hash_builder);
+        for i in 0..1000 {
+            map1.insert(i, i);
+            map2.insert(i, i);
+        }
+
+        assert_eq!(map1.global_depth, map2.global_depth);
+        assert_eq!(map1.directories, map2.directories);
+        for ((ka, a), (kb, b)) in map1.buckets.iter().zip(map2.buckets.iter()) {
+            assert_eq!(ka, kb);
+            assert_eq!((a.prefix, a.depth), (b.prefix, b.depth));
+            assert!(a.iter().eq(b.iter()));
+        }
+
+        let map: HashMap<i32, i32, _> = HashMap::with_capacity_and_hasher(
+            1000,
+            BuildHasherDefault::<DefaultHasher>::default(),
+        );
+        assert!(map.capacity() >= 1000);
+    }
+
+    #[test]
+    fn split_does_not_rehash() {
+        use std::{cell::Cell, collections::hash_map::DefaultHasher, rc::Rc};
+
+        /// A `BuildHasher` counting how many hashers it has built.
+        #[derive(Default)]
+        struct CountingState(Rc<Cell<usize>>);
+        impl BuildHasher for CountingState {
+            type Hasher = DefaultHasher;
+
+            fn build_hasher(&self) -> Self::Hasher {
+                self.0.set(self.0.get() + 1);
+                DefaultHasher::new()
+            }
+        }
+
+        let counter = Rc::new(Cell::new(0));
+        let mut map = HashMap::with_hasher(CountingState(counter.clone()));
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        // every key is hashed exactly once, although there are many splits
+        assert_eq!(counter.get(), 1000);
+        assert!(map.buckets.len() > 2);
+    }
+
+    #[test]
+    fn lookup_hashes_once() {
+        use std::{cell::Cell, collections::hash_map::DefaultHasher, rc::Rc};
+
+        /// A `BuildHasher` counting how many hashers it has built.
+        #[derive(Default)]
+        struct CountingState(Rc<Cell<usize>>);
+        impl BuildHasher for CountingState {
+            type Hasher = DefaultHasher;
+
+            fn build_hasher(&self) -> Self::Hasher {
+                self.0.set(self.0.get() + 1);
+                DefaultHasher::new()
+            }
+        }
+
+        let counter = Rc::new(Cell::new(0));
+        let mut map = HashMap::with_hasher(CountingState(counter.clone()));
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+
+        counter.set(0);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(&i));
+            *map.get_mut(&i).unwrap() += 1;
+            assert!(map.get_key_value(&i).is_some());
+        }
+        assert_eq!(counter.get(), 300);
+
+        counter.set(0);
+        for i in 0..100 {
+            assert_eq!(map.remove(&i), Some(i + 1));
+        }
+        assert_eq!(counter.get(), 100);
+    }
+
+    #[test]
+    fn wide_hash_works() {
+        use std::{collections::hash_map::DefaultHasher, hash::Hasher};
+
+        /// A `KeyHasher` producing 128-bit hash values, with the low 64 bits
+        /// all being 0.
+        struct WideHasher;
+        impl KeyHasher for WideHasher {
+            type Hash = u128;
+
+            fn hash_key<Q>(&self, key: &Q) -> Self::Hash
+            where
+                Q: Hash + ?Sized,
+            {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                (hasher.finish() as u128) << 64
+            }
+        }
+
+        let mut map = HashMap::with_hasher(WideHasher);
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i), None);
+        }
+        assert_eq!(map.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        assert_eq!(map.remove(&0), Some(0));
+        // the prefixes of the buckets are `u64`s
+        assert_eq!(map.max_global_depth(), 64);
+        assert!(std::panic::catch_unwind(|| {
+            HashMap::<u8, u8, _>::with_hasher(WideHasher)
+                .set_max_global_depth(65)
+        })
+        .is_err());
+        assert_eq!(map.into_iter().count(), 999);
+    }
+
+    #[test]
+    fn with_seed_is_deterministic() {
+        let mut map1 = HashMap::with_seed(42);
+        let mut map2 = HashMap::with_seed(42);
+        for i in 0..1000 {
+            map1.insert(i, i);
+            map2.insert(i, i);
+        }
+        for i in (0..1000).step_by(3) {
+            map1.remove(&i);
+            map2.remove(&i);
+        }
+
+        assert_eq!(map1.hasher(), &FixedState::with_seed(42));
+        assert_eq!(map1.global_depth, map2.global_depth);
+        assert_eq!(map1.directories, map2.directories);
+        for ((ka, a), (kb, b)) in map1.buckets.iter().zip(map2.buckets.iter()) {
+            assert_eq!(ka, kb);
+            assert_eq!((a.prefix, a.depth), (b.prefix, b.depth));
+            assert!(a.iter().eq(b.iter()));
+        }
+
+        let map3: HashMap<i32, i32, _> = HashMap::with_seed(43);
+        assert_ne!(map1.hasher().hash_one(1), map3.hasher().hash_one(1));
+    }
+
+    #[test]
+    fn lsb_bit_order_works() {
+        use std::hash::{BuildHasherDefault, Hasher};
+
+        /// A hasher whose hash values are the keys themselves, i.e., the high
+        /// bits are always 0 for small keys.
+        #[derive(Default)]
+        struct IdentityHasher(u64);
+        impl Hasher for IdentityHasher {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+
+            fn write(&mut self, _: &[u8]) {
+                unimplemented!()
+            }
+
+            fn write_u64(&mut self, i: u64) {
+                self.0 = i;
+            }
+        }
+
+        let mut map = HashMap::with_bit_order_and_hasher(
+            BitOrder::Lsb,
+            BuildHasherDefault::<IdentityHasher>::default(),
+        );
+        assert_eq!(map.bit_order(), BitOrder::Lsb);
+        for i in 0..1000_u64 {
+            map.insert(i, i);
+        }
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        // keys with distinct low bits are spread evenly
+        assert!(map.global_depth <= 10);
+        assert_eq!(map.clone().bit_order(), BitOrder::Lsb);
+        assert_eq!(HashMap::<i32, i32>::new().bit_order(), BitOrder::Msb);
+    }
+
+    #[test]
+    fn doubling_keeps_directory_consistent() {
+        for bit_order in [BitOrder::Msb, BitOrder::Lsb] {
+            let mut map = HashMap::with_bit_order(bit_order);
+            for i in 0..5000 {
+                map.insert(i, i);
+                if i % 500 == 0 {
+                    map.validate().unwrap();
+                    // every entry points to the bucket whose prefix is the
+                    // first bits of the entry's index
+                    for (idx, bucket_idx) in map.directories.iter().enumerate()
+                    {
+                        let bucket = &map.buckets[bucket_idx.get()];
+                        let shift = map.global_depth - bucket.local_depth();
+                        assert_eq!(idx >> shift, bucket.prefix as usize);
+                    }
+                }
+            }
+            assert!((0..5000).all(|i| map.get(&i) == Some(&i)));
+        }
+    }
+
+    #[test]
+    fn split_by_next_bit() {
+        for bit_order in [BitOrder::Msb, BitOrder::Lsb] {
+            let mut map = HashMap::with_bit_order(bit_order);
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+
+            // every entry lives in the bucket whose prefix is a prefix of
+            // the entry's hash bits
+            for (_, bucket) in map.buckets.iter() {
+                for hash in bucket.hashes.iter() {
+                    let prefix = (0..bucket.local_depth())
+                        .fold(0, |acc, idx| {
+                            (acc << 1) | map.hash_bit(*hash, idx) as u64
+                        });
+                    assert_eq!(prefix, bucket.prefix);
+                }
+            }
+            map.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn bucket_capacity_works() {
+        let mut map = HashMap::with_bucket_capacity(64);
+        assert_eq!(map.bucket_capacity(), 64);
+        assert_eq!(map.capacity(), 64);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert!(map.buckets.iter().all(|(_, bucket)| bucket.len() <= 64));
+        // a much smaller directory than the one of the default capacity
+        assert!(map.global_depth < 8);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+
+        for i in 0..1000 {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert!(map.buckets.len() < 4);
+        assert_eq!(map.clone().bucket_capacity(), 64);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_bucket_capacity() {
+        HashMap::<i32, i32>::with_bucket_capacity(0);
+    }
+
+    #[test]
+    fn const_bucket_size_works() {
+        let mut map: HashMap<i32, i32, DefaultHashBuilder, BitOrder, 8> =
+            HashMap::default();
+        assert_eq!(map.bucket_capacity(), 8);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        // nothing spills
+        assert_eq!(map.memory_usage().entries(), 0);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        assert_eq!(map.into_iter().count(), 1000);
+    }
+
+    #[test]
+    fn identical_hashes_overflow() {
+        use std::hash::{BuildHasherDefault, Hasher};
+
+        /// A hasher whose hash values are always `self.0`.
+        #[derive(Default)]
+        struct ConstHasher(u64);
+        impl Hasher for ConstHasher {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut map: HashMap<i32, i32, BuildHasherDefault<ConstHasher>> =
+            HashMap::default();
+        for i in 0..100 {
+            assert_eq!(map.insert(i, i), None);
+        }
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.global_depth, 0);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        for i in 0..50 {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert_eq!(map.len(), 50);
+        assert_eq!(map.into_iter().count(), 50);
+    }
+
+    #[test]
+    fn few_distinct_hashes_overflow() {
+        use std::hash::{BuildHasherDefault, Hasher};
+
+        /// A hasher producing only four hash values, differing in the first
+        /// two bits.
+        #[derive(Default)]
+        struct FourHasher(u64);
+        impl Hasher for FourHasher {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+
+            fn write(&mut self, _: &[u8]) {
+                unimplemented!()
+            }
+
+            fn write_i32(&mut self, i: i32) {
+                self.0 = ((i % 4) as u64) << 62;
+            }
+        }
+
+        let mut map: HashMap<i32, i32, BuildHasherDefault<FourHasher>> =
+            HashMap::default();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        // split until the four hash values are separated
+        assert_eq!(map.global_depth, 2);
+        assert_eq!(map.buckets.len(), 4);
+        assert!(map.buckets.iter().all(|(_, bucket)| bucket.len() == 250));
+    }
+
+    #[test]
+    fn max_global_depth_works() {
+        use std::hash::{BuildHasherDefault, Hasher};
+
+        /// A hasher producing only two hash values, differing in the last
+        /// bit.
+        #[derive(Default)]
+        struct ParityHasher(u64);
+        impl Hasher for ParityHasher {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+
+            fn write(&mut self, _: &[u8]) {
+                unimplemented!()
+            }
+
+            fn write_i32(&mut self, i: i32) {
+                self.0 = (i % 2) as u64;
+            }
+        }
+
+        let mut map: HashMap<i32, i32, BuildHasherDefault<ParityHasher>> =
+            HashMap::default();
+        assert_eq!(map.max_global_depth(), 64);
+        map.set_max_global_depth(4);
         for i in 0..1000 {
-            assert!(map.remove(&i).is_none());
             map.insert(i, i);
         }
+        assert_eq!(map.global_depth, 4);
+        assert_eq!(map.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+
+        // reserving space does not grow the directory beyond the limit
+        let mut other = HashMap::new();
+        for i in 0..1000 {
+            other.insert(i, i);
+        }
+        let mut map = HashMap::new();
+        map.set_max_global_depth(2);
+        map.append(&mut other);
+        assert_eq!(map.global_depth, 2);
         assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn sorted_bucket_policy_works() {
+        let mut map = HashMap::with_bucket_capacity(64);
+        for i in 0..500 {
+            map.insert(i, i);
+        }
+        // switching the policy sorts the existing entries
+        map.set_bucket_policy(BucketPolicy::Sorted);
+        assert_eq!(map.bucket_policy(), BucketPolicy::Sorted);
+        for i in 500..1000 {
+            map.insert(i, i);
+        }
+        for i in (0..1000).step_by(2) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+
+        map.validate().unwrap();
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), (i % 2 == 1).then_some(&i));
+        }
+    }
 
+    #[test]
+    fn removal_swaps_in_last_entry() {
+        let mut map = HashMap::with_bucket_capacity(64);
         for i in 0..1000 {
+            map.insert(i, i);
+        }
+        for i in (0..1000).step_by(3) {
             assert_eq!(map.remove(&i), Some(i));
         }
+        // the entries moved into the places of the extracted ones are
+        // checked as well
+        let mut extracted =
+            map.extract_if(|k, _| k % 3 == 1).collect::<Vec<_>>();
+        extracted.sort();
+        assert!(extracted.iter().map(|(k, _)| *k).eq((1..1000).step_by(3)));
 
-        assert_eq!(map.len(), 0);
+        map.validate().unwrap();
+        assert_eq!(map.len(), 333);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), (i % 3 == 2).then_some(&i));
+        }
+    }
+
+    #[test]
+    fn directory_halves_automatically() {
+        let mut map = HashMap::new();
+        for i in 0..100_000 {
+            map.insert(i, i);
+        }
+        let grown_depth = map.global_depth;
+        assert_eq!(map.depth_counts, count_depths(&map.buckets, grown_depth));
+
+        for i in 0..99_990 {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        map.validate().unwrap();
+        assert!(map.global_depth < grown_depth);
+        let (counts, deeper) = map.depth_counts.split_at(map.global_depth + 1);
+        assert_eq!(counts, count_depths(&map.buckets, map.global_depth));
+        assert!(deeper.iter().all(|count| *count == 0));
+        // the 2 deepest levels are never both empty
+        let max_local_depth = map
+            .buckets
+            .iter()
+            .map(|(_, bucket)| bucket.local_depth())
+            .max()
+            .unwrap();
+        assert!(map.global_depth <= max_local_depth + 1);
+        for i in 99_990..100_000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+
+        // without hysteresis, the directory is as small as the buckets allow
+        map.set_halving_hysteresis(0);
+        for i in 99_990..100_000 {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        map.validate().unwrap();
+        assert_eq!((map.global_depth, map.bucket_count()), (0, 1));
+    }
+
+    #[test]
+    fn observer_reports_structural_changes() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut map = HashMapBuilder::new()
+            .bucket_capacity(4)
+            .observer({
+                let events = Arc::clone(&events);
+                move |event| events.lock().unwrap().push(event)
+            })
+            .build();
+        let initial_depth = map.global_depth;
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+        for i in 0..9_990 {
+            map.remove(&i);
+        }
+
+        let events = std::mem::take(&mut *events.lock().unwrap());
+        // a split is reported after the doubling it needs
+        assert_eq!(events[0], MapEvent::DirectoryDoubled { global_depth: 1 });
+        assert!(matches!(events[1], MapEvent::Split { local_depth: 0, .. }));
+        let count =
+            |f: fn(&MapEvent) -> bool| events.iter().filter(|e| f(e)).count();
+        let splits = count(|e| matches!(e, MapEvent::Split { .. }));
+        let merges = count(|e| matches!(e, MapEvent::Coalesce { .. }));
+        let doublings =
+            count(|e| matches!(e, MapEvent::DirectoryDoubled { .. }));
+        let halvings = count(|e| matches!(e, MapEvent::DirectoryHalved { .. }));
+        assert!(merges > 0 && halvings > 0);
+        assert_eq!(map.buckets.len(), 1 + splits - merges);
+        assert_eq!(map.global_depth, initial_depth + doublings - halvings);
+
+        map.clear_observer();
+        map.insert(10_000, 10_000);
+        assert!(map.observer.is_none());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_spans_are_emitted() {
+        use tracing::{
+            span::{Attributes, Id, Record},
+            Event, Metadata, Subscriber,
+        };
+
+        /// Record the names of the created spans.
+        struct SpanNames(Mutex<Vec<&'static str>>);
+
+        impl Subscriber for SpanNames {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name());
+                Id::from_u64(names.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let subscriber = Arc::new(SpanNames(Mutex::new(Vec::new())));
+        tracing::subscriber::with_default(Arc::clone(&subscriber), || {
+            let mut map = HashMap::with_bucket_capacity(4);
+            for i in 0..10_000 {
+                map.insert(i, i);
+            }
+            for i in 0..9_990 {
+                map.remove(&i);
+            }
+        });
+
+        let names = subscriber.0.lock().unwrap();
+        for name in ["split", "coalesce", "double_directory", "halve_directory"]
+        {
+            assert!(names.contains(&name), "no {name} span");
+        }
+    }
+
+    #[test]
+    fn debug_table_and_display() {
+        let mut map = HashMap::with_bucket_capacity(2);
+        assert_eq!(
+            map.to_string(),
+            "HashMap with 0 entries, global depth 0, 1 buckets"
+        );
+
+        for i in 0..20 {
+            map.insert(i, i);
+        }
+        let table = format!("{map:#?}");
+        let mut lines = table.lines();
+        assert_eq!(lines.next(), Some(map.to_string().as_str()));
+        assert!(lines.next().unwrap().starts_with("slots"));
+        // a row per bucket, ordered by the prefixes
+        let rows = lines.collect::<Vec<_>>();
+        assert_eq!(rows.len(), map.bucket_count());
+        for (row, info) in rows.iter().zip(map.bucket_infos()) {
+            let prefix = row.split_whitespace().nth(1).unwrap();
+            assert_eq!(prefix, format_bits(info.prefix(), info.local_depth()));
+            assert_eq!(row.matches(": ").count(), info.len());
+        }
+        // the plain format is unchanged
+        assert!(format!("{map:?}").starts_with("Extendable HashMap\n"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_are_recorded() {
+        use metrics::{
+            Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder,
+            SharedString, Unit,
+        };
+        use std::{
+            collections::BTreeMap,
+            sync::atomic::{AtomicU64, Ordering},
+        };
+
+        /// Store every metric in an `AtomicU64`, keyed by its name.
+        #[derive(Default)]
+        struct AtomicRecorder(Mutex<BTreeMap<String, Arc<AtomicU64>>>);
+
+        impl AtomicRecorder {
+            fn get(&self, name: &str) -> u64 {
+                self.0.lock().unwrap()[name].load(Ordering::Relaxed)
+            }
+
+            fn register(&self, key: &Key) -> Arc<AtomicU64> {
+                let mut metrics = self.0.lock().unwrap();
+                Arc::clone(metrics.entry(key.name().to_string()).or_default())
+            }
+        }
+
+        impl Recorder for AtomicRecorder {
+            fn describe_counter(
+                &self,
+                _: KeyName,
+                _: Option<Unit>,
+                _: SharedString,
+            ) {
+            }
+            fn describe_gauge(
+                &self,
+                _: KeyName,
+                _: Option<Unit>,
+                _: SharedString,
+            ) {
+            }
+            fn describe_histogram(
+                &self,
+                _: KeyName,
+                _: Option<Unit>,
+                _: SharedString,
+            ) {
+            }
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                Counter::from_arc(self.register(key))
+            }
+            fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::from_arc(self.register(key))
+            }
+            fn register_histogram(
+                &self,
+                _: &Key,
+                _: &Metadata<'_>,
+            ) -> Histogram {
+                Histogram::noop()
+            }
+        }
+
+        let recorder = AtomicRecorder::default();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let map = metrics::with_local_recorder(&recorder, || {
+            let mut map = HashMapBuilder::new()
+                .bucket_capacity(4)
+                .observer({
+                    let events = Arc::clone(&events);
+                    move |event| events.lock().unwrap().push(event)
+                })
+                .build();
+            for i in 0..10_000 {
+                map.insert(i, i);
+            }
+            for i in 0..9_990 {
+                map.remove(&i);
+            }
+            map
+        });
+
+        // the metrics count the events the observer sees
+        let events = events.lock().unwrap();
+        let count = |f: fn(&MapEvent) -> bool| {
+            events.iter().filter(|e| f(e)).count() as u64
+        };
+        assert_eq!(
+            recorder.get("splits_total"),
+            count(|e| matches!(e, MapEvent::Split { .. }))
+        );
+        assert_eq!(
+            recorder.get("merges_total"),
+            count(|e| matches!(e, MapEvent::Coalesce { .. }))
+        );
+        assert_eq!(
+            recorder.get("directory_doublings_total"),
+            count(|e| matches!(e, MapEvent::DirectoryDoubled { .. }))
+        );
+        assert_eq!(
+            f64::from_bits(recorder.get("current_global_depth")),
+            map.global_depth() as f64
+        );
+    }
+
+    #[test]
+    fn coalesced_buckets_are_reused() {
+        // buckets of 16 entries spill to the arena
+        let mut map = HashMap::with_bucket_capacity(16);
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+        let allocated = map.memory_usage().entries();
+        assert_eq!(allocated, map.arena.allocated());
+
+        // the blocks of the coalesced buckets are kept
+        for i in 0..9_000 {
+            map.remove(&i);
+        }
+        assert_eq!(map.memory_usage().entries(), allocated);
+
+        // splits take the blocks the coalescences freed
+        for i in 0..9_000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.memory_usage().entries(), allocated);
+        map.validate().unwrap();
+
+        // shrinking moves the entries left into a new arena
+        for i in 0..9_000 {
+            map.remove(&i);
+        }
+        map.shrink_to_fit();
+        assert!(map.memory_usage().entries() < allocated / 4);
+        map.validate().unwrap();
+        for i in 9_000..10_000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    /// An allocator counting the live bytes it has allocated.
+    #[derive(Clone, Default)]
+    struct CountingAlloc(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl CountingAlloc {
+        fn live(&self) -> usize {
+            self.0.load(std::sync::atomic::Ordering::Relaxed)
+        }
+    }
+
+    // SAFETY: the blocks are allocated from the global allocator.
+    unsafe impl Allocator for CountingAlloc {
+        fn allocate(
+            &self,
+            layout: std::alloc::Layout,
+        ) -> Result<std::ptr::NonNull<[u8]>, allocator_api2::alloc::AllocError>
+        {
+            let block = Global.allocate(layout)?;
+            self.0
+                .fetch_add(layout.size(), std::sync::atomic::Ordering::Relaxed);
+            Ok(block)
+        }
+
+        unsafe fn deallocate(
+            &self,
+            ptr: std::ptr::NonNull<u8>,
+            layout: std::alloc::Layout,
+        ) {
+            self.0
+                .fetch_sub(layout.size(), std::sync::atomic::Ordering::Relaxed);
+            // SAFETY: guaranteed by the caller.
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    #[test]
+    fn custom_allocator_works() {
+        let alloc = CountingAlloc::default();
+        let mut map = HashMapBuilder::new()
+            .bucket_capacity(16)
+            .build_in(alloc.clone());
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+        // the directory, the buckets and the spilled entries all come from
+        // the allocator
+        let usage = map.memory_usage();
+        assert!(alloc.live() >= usage.directory() + usage.buckets());
+        assert!(usage.entries() > 0);
+        let live = alloc.live();
+
+        let clone = map.clone();
+        assert_eq!(clone, map);
+        assert!(alloc.live() > live);
+        assert!(clone.into_iter().eq(map.clone()));
+        assert_eq!(alloc.live(), live);
+
+        for i in 0..9_000 {
+            map.remove(&i);
+        }
+        map.shrink_to_fit();
+        assert!(alloc.live() < live / 4);
+        map.validate().unwrap();
+        assert!((9_000..10_000).all(|i| map[&i] == i));
+
+        drop(map);
+        assert_eq!(alloc.live(), 0);
+
+        let loaded = HashMapBuilder::new()
+            .bulk_load_in((0..1000).map(|i| (i, i)), alloc.clone());
+        let mut map =
+            HashMap::with_capacity_in(1000, loaded.allocator().clone());
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map, loaded);
+        drop((map, loaded));
+        assert_eq!(alloc.live(), 0);
+    }
+
+    #[test]
+    fn coalesce_policy_works() {
+        let drained = |policy| {
+            let mut map = HashMapBuilder::new()
+                .bucket_capacity(8)
+                .coalesce_policy(policy)
+                .build();
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+            let buckets = map.buckets.len();
+            for i in 0..990 {
+                map.remove(&i);
+            }
+            map.validate().unwrap();
+            assert_eq!(map.coalesce_policy(), policy);
+            (buckets, map.buckets.len())
+        };
+
+        let (before, never) = drained(CoalescePolicy::Never);
+        assert_eq!(never, before);
+        let (_, always) = drained(CoalescePolicy::Always);
+        let (_, threshold) = drained(CoalescePolicy::Threshold(0.25));
+        assert!(always < threshold && threshold < never);
+        assert!(!CoalescePolicy::Threshold(0.5).should_merge(5, 8));
+        assert!(CoalescePolicy::Threshold(0.5).should_merge(4, 8));
+        assert!(!CoalescePolicy::Always.should_merge(8, 8));
+    }
+
+    #[test]
+    fn coalescence_cascades() {
+        let mut map = HashMap::with_bucket_capacity(4);
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+        for i in 0..9_999 {
+            map.remove(&i);
+        }
+        map.validate().unwrap();
+        // the last entry fits into a single bucket
+        assert_eq!(map.bucket_count(), 1);
+        assert_eq!(map.stats().max_local_depth(), 0);
+        assert!(map.global_depth() <= 2);
+        assert_eq!(map.get(&9_999), Some(&9_999));
+
+        map.remove(&9_999);
+        map.validate().unwrap();
+        assert_eq!(map.bucket_count(), 1);
+    }
+
+    #[test]
+    fn compact_merges_every_fitting_pair() {
+        let mut map = HashMapBuilder::new()
+            .bucket_capacity(8)
+            .coalesce_policy(CoalescePolicy::Never)
+            .build();
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+        for i in 0..9_900 {
+            map.remove(&i);
+        }
+        let buckets = map.bucket_count();
+        let merges = map.counters().merges();
+
+        let compacted = map.compact();
+        assert!(compacted > 0);
+        assert_eq!(compacted, buckets - map.bucket_count());
+        assert_eq!(map.counters().merges() - merges, compacted as u64);
+        map.validate().unwrap();
+        assert_eq!(map.global_depth, map.stats().max_local_depth());
+        // no sibling buckets fit into one bucket
+        for (bucket_idx, bucket) in map.buckets.iter() {
+            if let Some(sibling_idx) = map.sibling(bucket_idx) {
+                assert!(
+                    bucket.len() + map.buckets[sibling_idx].len()
+                        >= map.bucket_capacity
+                );
+            }
+        }
+        for i in 9_900..10_000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        // a compact map has nothing left to merge
+        assert_eq!(map.compact(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "fill ratio")]
+    fn coalesce_policy_invalid_threshold() {
+        HashMap::<u64, u64>::new()
+            .set_coalesce_policy(CoalescePolicy::Threshold(1.5));
     }
 }
@@ -0,0 +1,93 @@
+//! Read-only memory maps of the files of [`DiskHashMap`](crate::DiskHashMap).
+
+#[cfg(not(unix))]
+compile_error!("the `mmap` feature is only available on Unix");
+
+use std::{fs::File, io, os::fd::AsRawFd, ptr::NonNull};
+
+/// A shared, read-only memory map of a whole file.
+///
+/// The map is shared, so writes to the file, by this process or others, are
+/// visible through it. Truncating the file while it is mapped makes accessing
+/// the truncated part fault. This is why creating a map is unsafe.
+pub(crate) struct Mmap {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// SAFETY: the map is read-only, and owned by this value.
+unsafe impl Send for Mmap {}
+// SAFETY: the map is read-only.
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Map the first `len` bytes of `file`, `len` should not be 0.
+    ///
+    /// # Safety
+    /// The mapped bytes should not be modified while they are borrowed from
+    /// [`as_slice()`](Self::as_slice), and the file should not be truncated
+    /// while it is mapped.
+    pub(crate) unsafe fn new(file: &File, len: usize) -> io::Result<Self> {
+        assert!(len > 0);
+
+        // SAFETY: mapping a file doesn't touch any memory of this process.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            ptr: NonNull::new(ptr.cast()).expect("mmap returned null"),
+            len,
+        })
+    }
+
+    /// Return the mapped bytes.
+    #[inline]
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        // SAFETY: the map is `self.len` bytes long, and lives as long as
+        // `self`.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: the map is no longer borrowed.
+        unsafe {
+            libc::munmap(self.ptr.as_ptr().cast(), self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn mmap_works() {
+        let path = std::env::temp_dir().join(format!(
+            "extendable_hashmap-{}-mmap_works",
+            std::process::id()
+        ));
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"extendable").unwrap();
+        let file = File::open(&path).unwrap();
+
+        // SAFETY: the file is only modified by this test, before it's mapped.
+        let mmap = unsafe { Mmap::new(&file, 10) }.unwrap();
+        assert_eq!(mmap.as_slice(), b"extendable");
+        drop(mmap);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
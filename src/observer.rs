@@ -0,0 +1,93 @@
+//! Observing the structural changes of a [`HashMap`](crate::HashMap).
+
+use std::{
+    fmt::{Debug, Formatter},
+    sync::Arc,
+};
+
+/// A structural change of a [`HashMap`](crate::HashMap), reported to the
+/// observer set by [`HashMap::set_observer()`](crate::HashMap::set_observer).
+///
+/// Prefixes are the first bits of the mapped hash values, the most
+/// significant one first, e.g., prefix `0b10` of local depth 3 is the bits
+/// `010`, the local depths are never greater than 64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MapEvent {
+    /// The bucket of `prefix`, whose local depth was `local_depth`, is split
+    /// into the buckets of prefixes `prefix << 1` and `prefix << 1 | 1`.
+    Split {
+        /// Prefix of the split bucket
+        prefix: u64,
+        /// Local depth of the split bucket before the split
+        local_depth: usize,
+    },
+    /// The buckets of prefixes `prefix << 1` and `prefix << 1 | 1` are
+    /// coalesced into the bucket of `prefix`, whose local depth is
+    /// `local_depth`.
+    Coalesce {
+        /// Prefix of the coalesced bucket
+        prefix: u64,
+        /// Local depth of the coalesced bucket
+        local_depth: usize,
+    },
+    /// The directory is doubled, the global depth becomes `global_depth`.
+    DirectoryDoubled {
+        /// Global depth after doubling
+        global_depth: usize,
+    },
+    /// The directory is halved, the global depth becomes `global_depth`.
+    DirectoryHalved {
+        /// Global depth after halving
+        global_depth: usize,
+    },
+}
+
+/// A callback invoked with every [`MapEvent`].
+#[derive(Clone)]
+pub(crate) struct Observer(Arc<dyn Fn(MapEvent) + Send + Sync>);
+
+impl Observer {
+    /// Wrap `callback` as an observer.
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(MapEvent) + Send + Sync + 'static,
+    {
+        Self(Arc::new(callback))
+    }
+
+    /// Report `event` to the callback.
+    #[inline]
+    pub(crate) fn notify(&self, event: MapEvent) {
+        (self.0)(event)
+    }
+}
+
+impl Debug for Observer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+/// Record `event` in the metrics of the [`metrics`] facade: the counters
+/// `splits_total`, `merges_total`, `directory_doublings_total` and
+/// `directory_halvings_total`, and the gauge `current_global_depth`.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_metrics(event: MapEvent) {
+    match event {
+        MapEvent::Split { .. } => {
+            metrics::counter!("splits_total").increment(1)
+        }
+        MapEvent::Coalesce { .. } => {
+            metrics::counter!("merges_total").increment(1)
+        }
+        MapEvent::DirectoryDoubled { global_depth } => {
+            metrics::counter!("directory_doublings_total").increment(1);
+            metrics::gauge!("current_global_depth").set(global_depth as f64);
+        }
+        MapEvent::DirectoryHalved { global_depth } => {
+            metrics::counter!("directory_halvings_total").increment(1);
+            metrics::gauge!("current_global_depth").set(global_depth as f64);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Options of opening a [`DiskHashMap`].
+
+use crate::{
+    codec::{Codec, DefaultCodec},
+    disk::DiskHashMap,
+    hasher::FixedState,
+    page::Compression,
+    store::{FileStore, WriteMode},
+    wal::Durability,
+};
+use std::{hash::BuildHasher, io, path::Path, time::Duration};
+
+/// Options of opening a [`DiskHashMap`], chosen by the durability
+/// requirements of the users.
+///
+/// The options combine the durability of the write-ahead log, which is
+/// synced on every modification, every `n` modifications, or on
+/// [`sync()`](DiskHashMap::sync) only, see [`Durability`], with how the
+/// bucket pages are written, through the page cache, synced on every write,
+/// or bypassing the page cache, see [`WriteMode`].
+///
+/// ```no_run
+/// use extendable_hashmap::{DiskOptions, Durability, WriteMode};
+///
+/// let map = DiskOptions::new()
+///     .page_size(16 << 10)
+///     .durability(Durability::Batched(64))
+///     .write_mode(WriteMode::Sync)
+///     .open::<u64, String>("map.db")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct DiskOptions<S = FixedState, C = DefaultCodec> {
+    pub(crate) hash_builder: S,
+    pub(crate) codec: C,
+    pub(crate) page_size: Option<usize>,
+    pub(crate) create: bool,
+    pub(crate) read_only: bool,
+    pub(crate) durability: Durability,
+    pub(crate) write_mode: WriteMode,
+    pub(crate) compression: Compression,
+    pub(crate) flush_interval: Option<Duration>,
+    pub(crate) cache_capacity: usize,
+}
+
+impl DiskOptions {
+    /// Create the default options, which open a map created if it doesn't
+    /// exist, with the default page size, [`Durability`], [`WriteMode`] and
+    /// [`Compression`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for DiskOptions {
+    fn default() -> Self {
+        Self {
+            hash_builder: FixedState::default(),
+            codec: DefaultCodec,
+            page_size: None,
+            create: true,
+            read_only: false,
+            durability: Durability::default(),
+            write_mode: WriteMode::default(),
+            compression: Compression::default(),
+            flush_interval: None,
+            cache_capacity: 0,
+        }
+    }
+}
+
+impl<S, C> DiskOptions<S, C> {
+    /// Use `hash_builder` to hash keys.
+    pub fn hasher<T>(self, hash_builder: T) -> DiskOptions<T, C> {
+        DiskOptions {
+            hash_builder,
+            codec: self.codec,
+            page_size: self.page_size,
+            create: self.create,
+            read_only: self.read_only,
+            durability: self.durability,
+            write_mode: self.write_mode,
+            compression: self.compression,
+            flush_interval: self.flush_interval,
+            cache_capacity: self.cache_capacity,
+        }
+    }
+
+    /// Use `codec` to encode keys and values.
+    pub fn codec<T>(self, codec: T) -> DiskOptions<S, T> {
+        DiskOptions {
+            hash_builder: self.hash_builder,
+            codec,
+            page_size: self.page_size,
+            create: self.create,
+            read_only: self.read_only,
+            durability: self.durability,
+            write_mode: self.write_mode,
+            compression: self.compression,
+            flush_interval: self.flush_interval,
+            cache_capacity: self.cache_capacity,
+        }
+    }
+
+    /// Create the file with pages of `page_size` bytes, which is also
+    /// checked against an existing file, see
+    /// [`DiskHashMap::open_with_page_size()`].
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = Some(page_size);
+
+        self
+    }
+
+    /// Create the file if it doesn't exist, which defaults to true.
+    pub fn create(mut self, create: bool) -> Self {
+        self.create = create;
+
+        self
+    }
+
+    /// Open the map read-only, which defaults to false, see the
+    /// [read-only sharing](DiskHashMap#read-only-sharing) section. The file
+    /// should exist, and no background flusher is started.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+
+        self
+    }
+
+    /// Set the durability policy of the write-ahead log, see
+    /// [`DiskHashMap::set_durability()`].
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+
+        self
+    }
+
+    /// Set how the bucket pages are written to the file.
+    pub fn write_mode(mut self, write_mode: WriteMode) -> Self {
+        self.write_mode = write_mode;
+
+        self
+    }
+
+    /// Set the compression of the written pages, see
+    /// [`DiskHashMap::set_compression()`].
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+
+        self
+    }
+
+    /// Start a background flusher writing the dirty pages every `interval`,
+    /// see [`DiskHashMap::start_flusher()`].
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = Some(interval);
+
+        self
+    }
+
+    /// Cache up to `pages` pages in memory, see
+    /// [`DiskHashMap::set_cache_capacity()`].
+    pub fn cache_capacity(mut self, pages: usize) -> Self {
+        self.cache_capacity = pages;
+
+        self
+    }
+
+    /// Open the map stored in the file at `path` with these options.
+    ///
+    /// # Error
+    /// Besides the errors of [`DiskHashMap::open()`], an
+    /// [`Unsupported`](io::ErrorKind::Unsupported) error is returned if the
+    /// platform doesn't support the write mode.
+    pub fn open<K, V>(
+        self,
+        path: impl AsRef<Path>,
+    ) -> io::Result<DiskHashMap<K, V, S, FileStore, C>>
+    where
+        S: BuildHasher,
+        C: Codec<K, V>,
+    {
+        // a read-only map has no pages to flush
+        let flush_interval = self.flush_interval.filter(|_| !self.read_only);
+        let mut map = DiskHashMap::open_with_options(
+            path,
+            self,
+            FileStore::with_write_mode,
+        )?;
+        if let Some(interval) = flush_interval {
+            map.start_flusher(interval)?;
+        }
+
+        Ok(map)
+    }
+}
@@ -0,0 +1,575 @@
+//! Bucket pages of a [`DiskHashMap`](crate::DiskHashMap).
+//!
+//! A page starts with a header:
+//!
+//! | field       | size |
+//! |-------------|------|
+//! | local depth | 4    |
+//! | records     | 4    |
+//! | prefix      | 8    |
+//!
+//! followed by the records, each of them is a record header followed by the
+//! encoded key and value:
+//!
+//! | field        | size |
+//! |--------------|------|
+//! | hash value   | 8    |
+//! | key length   | 4    |
+//! | value length | 4    |
+//!
+//! All the integers are in little endian, the unused tail of a page is
+//! zeroed, and the last 4 bytes of a page are the CRC-32C checksum of the
+//! rest of it, which is verified when the page is read from the store.
+//!
+//! A compressed page starts with another header instead:
+//!
+//! | field             | size |
+//! |-------------------|------|
+//! | compression       | 4    |
+//! | compressed length | 4    |
+//! | length            | 4    |
+//!
+//! followed by the compressed bytes of the page above, without its unused
+//! tail and checksum, which is `length` bytes long, up to [`COMPRESSED_PAGE_FACTOR`]
+//! times the page size. The compression has its highest bit set, which
+//! tells it from the local depth of an uncompressed page, and the rest is
+//! the algorithm, 1 for LZ4 and 2 for zstd.
+
+use crate::util::crc32c;
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    io,
+};
+
+/// Default size of a page in bytes.
+pub(crate) const DEFAULT_PAGE_SIZE: usize = 4 << 10;
+/// Minimum size of a page in bytes.
+pub(crate) const MIN_PAGE_SIZE: usize = 4 << 10;
+/// Maximum size of a page in bytes.
+pub(crate) const MAX_PAGE_SIZE: usize = 64 << 10;
+/// Size of a page header in bytes.
+const PAGE_HEADER_LEN: usize = 16;
+/// Size of a page trailer, i.e., the checksum, in bytes.
+const PAGE_TRAILER_LEN: usize = 4;
+/// Size of a record header in bytes.
+const RECORD_HEADER_LEN: usize = 16;
+/// Size of a compressed page header in bytes.
+const COMPRESSED_HEADER_LEN: usize = 12;
+/// Bit set in the first field of a compressed page.
+const COMPRESSED: u32 = 1 << 31;
+/// Algorithm of a page compressed by LZ4.
+const LZ4: u32 = 1;
+/// Algorithm of a page compressed by zstd.
+const ZSTD: u32 = 2;
+/// A compressed page holds up to this many times the page size in bytes.
+pub(crate) const COMPRESSED_PAGE_FACTOR: usize = 4;
+
+/// Compression of the bucket pages of a [`DiskHashMap`](crate::DiskHashMap),
+/// which trades CPU for disk footprint.
+///
+/// A bucket whose records compress well holds more records than fit into
+/// an uncompressed page, up to 4 times as many bytes, so the map splits less
+/// and has fewer pages. A bucket that doesn't shrink is stored uncompressed.
+///
+/// Every page records its compression, so the pages of a file can be
+/// compressed differently, and changing the compression only affects the
+/// pages written afterwards. Reading a compressed page requires the feature
+/// of its algorithm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Don't compress pages, this is the default.
+    #[default]
+    None,
+    /// Compress pages with LZ4, which requires the `lz4` feature.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Compress pages with zstd at the given level, which requires the
+    /// `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+impl Compression {
+    /// Return the number of bytes a bucket can hold with this compression,
+    /// where a page is `page_size` bytes long.
+    #[inline]
+    pub(crate) fn capacity(self, page_size: usize) -> usize {
+        match self {
+            Compression::None => page_size,
+            #[allow(unreachable_patterns)]
+            _ => page_size * COMPRESSED_PAGE_FACTOR,
+        }
+    }
+
+    /// Compress `bytes`, return the algorithm and the compressed bytes, or
+    /// `None` if pages are not compressed, or the compression fails.
+    #[cfg_attr(
+        not(any(feature = "lz4", feature = "zstd")),
+        allow(unused_variables)
+    )]
+    fn compress(self, bytes: &[u8]) -> Option<(u32, Vec<u8>)> {
+        match self {
+            Compression::None => None,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Some((LZ4, lz4_flex::block::compress(bytes))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => {
+                zstd::bulk::compress(bytes, level).ok().map(|c| (ZSTD, c))
+            }
+        }
+    }
+}
+
+/// Decompress the `len` bytes compressed by `algorithm` into `compressed`.
+#[cfg_attr(
+    not(any(feature = "lz4", feature = "zstd")),
+    allow(unused_variables)
+)]
+fn decompress(
+    algorithm: u32,
+    compressed: &[u8],
+    len: usize,
+) -> io::Result<Vec<u8>> {
+    let bytes: io::Result<Vec<u8>> = match algorithm {
+        #[cfg(feature = "lz4")]
+        LZ4 => lz4_flex::block::decompress(compressed, len)
+            .map_err(|_| malformed("corrupted LZ4 data")),
+        #[cfg(not(feature = "lz4"))]
+        LZ4 => Err(malformed("compressed by LZ4, enable the `lz4` feature")),
+        #[cfg(feature = "zstd")]
+        ZSTD => zstd::bulk::decompress(compressed, len)
+            .map_err(|_| malformed("corrupted zstd data")),
+        #[cfg(not(feature = "zstd"))]
+        ZSTD => Err(malformed("compressed by zstd, enable the `zstd` feature")),
+        _ => Err(malformed("unknown compression")),
+    };
+
+    bytes.and_then(|bytes| {
+        if bytes.len() != len {
+            return Err(malformed("decompressed length mismatches"));
+        }
+        Ok(bytes)
+    })
+}
+
+/// Return the first `depth` bits of `hash`, as the low bits of the returned
+/// value.
+#[inline]
+pub(crate) fn prefix(hash: u64, depth: u32) -> u64 {
+    hash.checked_shr(u64::BITS - depth).unwrap_or(0)
+}
+
+/// Return true if `page_size` is a power of two between [`MIN_PAGE_SIZE`] and
+/// [`MAX_PAGE_SIZE`].
+#[inline]
+pub(crate) fn is_valid_page_size(page_size: usize) -> bool {
+    page_size.is_power_of_two()
+        && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+}
+
+/// The error of a bucket page whose checksum mismatches, which is damaged,
+/// e.g., torn by a crash or corrupted by the disk.
+///
+/// It is the inner error of an [`InvalidData`](io::ErrorKind::InvalidData)
+/// error:
+///
+/// ```no_run
+/// use extendable_hashmap::{CorruptPage, DiskHashMap};
+///
+/// let map = DiskHashMap::<u64, u64>::open("map.db")?;
+/// if let Err(err) = map.get(&0) {
+///     if let Some(corrupt) =
+///         err.get_ref().and_then(|err| err.downcast_ref::<CorruptPage>())
+///     {
+///         eprintln!("page {} is damaged", corrupt.page_no());
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorruptPage {
+    page_no: u64,
+}
+
+impl CorruptPage {
+    /// Return the number of the damaged page.
+    #[inline]
+    pub fn page_no(&self) -> u64 {
+        self.page_no
+    }
+}
+
+impl Display for CorruptPage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "checksum of page {} mismatches", self.page_no)
+    }
+}
+
+impl Error for CorruptPage {}
+
+impl From<CorruptPage> for io::Error {
+    fn from(err: CorruptPage) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Return the error of a malformed page.
+fn malformed(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed page: {}", reason),
+    )
+}
+
+/// Read a little-endian `u32` at `at`.
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+/// Read a little-endian `u64` at `at`.
+fn read_u64(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+/// An entry stored in a page, with its key and value encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Record {
+    /// Hash value of the encoded key
+    pub(crate) hash: u64,
+    /// Encoded key
+    pub(crate) key: Vec<u8>,
+    /// Encoded value
+    pub(crate) value: Vec<u8>,
+}
+
+impl Record {
+    /// Return the number of bytes this record takes in a page.
+    #[inline]
+    pub(crate) fn encoded_len(&self) -> usize {
+        RECORD_HEADER_LEN + self.key.len() + self.value.len()
+    }
+
+    /// Return true if this record fits into an empty page of `page_size`
+    /// bytes.
+    #[inline]
+    pub(crate) fn fits_in_page(&self, page_size: usize) -> bool {
+        PAGE_HEADER_LEN + self.encoded_len() + PAGE_TRAILER_LEN <= page_size
+    }
+}
+
+/// A bucket, decoded from a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Page {
+    /// Local depth
+    pub(crate) depth: u32,
+    /// The first `depth` bits shared by the hash values of the records, see
+    /// [`prefix()`]
+    pub(crate) prefix: u64,
+    /// Records
+    pub(crate) records: Vec<Record>,
+}
+
+impl Page {
+    /// Create an empty page.
+    pub(crate) fn new(depth: u32, prefix: u64) -> Self {
+        Self {
+            depth,
+            prefix,
+            records: Vec::new(),
+        }
+    }
+
+    /// Return the number of bytes this page takes when encoded, without the
+    /// unused tail.
+    fn encoded_len(&self) -> usize {
+        PAGE_HEADER_LEN
+            + self.records.iter().map(Record::encoded_len).sum::<usize>()
+    }
+
+    /// Encode this page into an image of `page_size` bytes, compressed by
+    /// `compression` and checksummed, return `None` if it doesn't fit.
+    ///
+    /// The page is stored uncompressed if it doesn't shrink, so it always
+    /// fits if its records fit into an uncompressed page.
+    pub(crate) fn encode_image(
+        &self,
+        compression: Compression,
+        page_size: usize,
+    ) -> Option<Vec<u8>> {
+        let len = self.encoded_len();
+        if len > compression.capacity(page_size) {
+            return None;
+        }
+
+        let usable = page_size - PAGE_TRAILER_LEN;
+        let mut buf = vec![0; len.max(page_size)];
+        self.encode(&mut buf[..len]);
+        if let Some((algorithm, compressed)) = compression.compress(&buf[..len])
+        {
+            if COMPRESSED_HEADER_LEN + compressed.len() < len.min(usable) {
+                buf.truncate(page_size);
+                buf[0..4]
+                    .copy_from_slice(&(COMPRESSED | algorithm).to_le_bytes());
+                buf[4..8]
+                    .copy_from_slice(&(compressed.len() as u32).to_le_bytes());
+                buf[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+                let end = COMPRESSED_HEADER_LEN + compressed.len();
+                buf[COMPRESSED_HEADER_LEN..end].copy_from_slice(&compressed);
+                buf[end..].fill(0);
+                seal(&mut buf);
+                return Some(buf);
+            }
+        }
+        if len > usable {
+            return None;
+        }
+        seal(&mut buf);
+
+        Some(buf)
+    }
+
+    /// Return the index of the record whose key is `key`.
+    pub(crate) fn position(&self, hash: u64, key: &[u8]) -> Option<usize> {
+        self.records
+            .iter()
+            .position(|record| record.hash == hash && record.key == key)
+    }
+
+    /// Encode this page into `buf`, which is a whole page.
+    ///
+    /// # Panic
+    /// The records should fit into `buf`.
+    pub(crate) fn encode(&self, buf: &mut [u8]) {
+        assert!(self.encoded_len() <= buf.len());
+
+        buf[0..4].copy_from_slice(&self.depth.to_le_bytes());
+        buf[4..8].copy_from_slice(&(self.records.len() as u32).to_le_bytes());
+        buf[8..16].copy_from_slice(&self.prefix.to_le_bytes());
+        let mut at = PAGE_HEADER_LEN;
+        for record in self.records.iter() {
+            buf[at..at + 8].copy_from_slice(&record.hash.to_le_bytes());
+            buf[at + 8..at + 12]
+                .copy_from_slice(&(record.key.len() as u32).to_le_bytes());
+            buf[at + 12..at + 16]
+                .copy_from_slice(&(record.value.len() as u32).to_le_bytes());
+            at += RECORD_HEADER_LEN;
+            buf[at..at + record.key.len()].copy_from_slice(&record.key);
+            at += record.key.len();
+            buf[at..at + record.value.len()].copy_from_slice(&record.value);
+            at += record.value.len();
+        }
+        buf[at..].fill(0);
+    }
+
+    /// Decode the page `page_no` read from the store, which is a whole page,
+    /// after verifying its checksum.
+    pub(crate) fn decode_verified(
+        page_no: u64,
+        buf: &[u8],
+    ) -> io::Result<Self> {
+        if !is_sealed(buf) {
+            return Err(CorruptPage { page_no }.into());
+        }
+
+        Self::decode(buf)
+    }
+
+    /// Decode a page from `buf`, which is a whole page, and may be
+    /// compressed, without verifying its checksum.
+    pub(crate) fn decode(buf: &[u8]) -> io::Result<Self> {
+        let tag = read_u32(buf, 0);
+        if tag & COMPRESSED == 0 {
+            return Self::decode_records(buf);
+        }
+
+        let compressed_len = read_u32(buf, 4) as usize;
+        let len = read_u32(buf, 8) as usize;
+        if COMPRESSED_HEADER_LEN + compressed_len > buf.len()
+            || len > buf.len() * COMPRESSED_PAGE_FACTOR
+        {
+            return Err(malformed("compressed page out of bounds"));
+        }
+        let compressed =
+            &buf[COMPRESSED_HEADER_LEN..COMPRESSED_HEADER_LEN + compressed_len];
+
+        Self::decode_records(&decompress(tag & !COMPRESSED, compressed, len)?)
+    }
+
+    /// Decode an uncompressed page from `buf`, which may be shorter than a
+    /// page.
+    fn decode_records(buf: &[u8]) -> io::Result<Self> {
+        if buf.len() < PAGE_HEADER_LEN {
+            return Err(malformed("page header out of bounds"));
+        }
+        let depth = read_u32(buf, 0);
+        if depth > u64::BITS {
+            return Err(malformed("local depth is greater than 64"));
+        }
+        let count = read_u32(buf, 4) as usize;
+        let prefix = read_u64(buf, 8);
+        if prefix.checked_shr(depth).unwrap_or(0) != 0 {
+            return Err(malformed("prefix is longer than the local depth"));
+        }
+
+        // `count` is not trusted yet
+        let mut records =
+            Vec::with_capacity(count.min(buf.len() / RECORD_HEADER_LEN));
+        let mut at = PAGE_HEADER_LEN;
+        for _ in 0..count {
+            if at + RECORD_HEADER_LEN > buf.len() {
+                return Err(malformed("record header out of bounds"));
+            }
+            let hash = read_u64(buf, at);
+            let key_len = read_u32(buf, at + 8) as usize;
+            let value_len = read_u32(buf, at + 12) as usize;
+            at += RECORD_HEADER_LEN;
+            if at + key_len + value_len > buf.len() {
+                return Err(malformed("record out of bounds"));
+            }
+            if self::prefix(hash, depth) != prefix {
+                return Err(malformed("record in the wrong bucket"));
+            }
+            let key = buf[at..at + key_len].to_vec();
+            at += key_len;
+            let value = buf[at..at + value_len].to_vec();
+            at += value_len;
+            records.push(Record { hash, key, value });
+        }
+
+        Ok(Self {
+            depth,
+            prefix,
+            records,
+        })
+    }
+}
+
+/// Write the checksum of `buf`, which is a whole page, to its trailer.
+fn seal(buf: &mut [u8]) {
+    let (body, trailer) = buf.split_at_mut(buf.len() - PAGE_TRAILER_LEN);
+    trailer.copy_from_slice(&crc32c(body).to_le_bytes());
+}
+
+/// Return true if the checksum of `buf`, which is a whole page, matches.
+fn is_sealed(buf: &[u8]) -> bool {
+    let Some((body, trailer)) = buf.split_last_chunk::<PAGE_TRAILER_LEN>()
+    else {
+        return false;
+    };
+
+    crc32c(body) == u32::from_le_bytes(*trailer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn page_round_trip() {
+        let mut page = Page::new(2, 0b10);
+        for i in 0..10_u64 {
+            page.records.push(Record {
+                hash: (0b10 << 62) | i,
+                key: i.to_le_bytes().to_vec(),
+                value: vec![i as u8; i as usize],
+            });
+        }
+
+        let mut buf = vec![0xff; DEFAULT_PAGE_SIZE];
+        page.encode(&mut buf);
+        assert_eq!(Page::decode(&buf).unwrap(), page);
+        assert_eq!(
+            page.position((0b10 << 62) | 3, &3_u64.to_le_bytes()),
+            Some(3)
+        );
+
+        // a record whose hash value has another prefix
+        page.records[0].hash = 0;
+        page.encode(&mut buf);
+        assert!(Page::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn compressed_page_round_trip() {
+        let mut page = Page::new(1, 0);
+        for i in 0..150_u64 {
+            page.records.push(Record {
+                hash: i,
+                key: i.to_le_bytes().to_vec(),
+                value: vec![0; 16],
+            });
+        }
+        // 150 * (16 + 8 + 16) bytes don't fit into an uncompressed page
+        assert_eq!(
+            page.encode_image(Compression::None, DEFAULT_PAGE_SIZE),
+            None
+        );
+
+        let compressions: &[Compression] = &[
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(3),
+        ];
+        for &compression in compressions {
+            let image =
+                page.encode_image(compression, DEFAULT_PAGE_SIZE).unwrap();
+            assert_eq!(image.len(), DEFAULT_PAGE_SIZE);
+            assert_ne!(read_u32(&image, 0) & COMPRESSED, 0);
+            assert_eq!(Page::decode(&image).unwrap(), page);
+
+            // a page that doesn't shrink is stored uncompressed
+            let small = Page::new(0, 0);
+            let image =
+                small.encode_image(compression, DEFAULT_PAGE_SIZE).unwrap();
+            assert_eq!(read_u32(&image, 0), 0);
+            assert_eq!(Page::decode(&image).unwrap(), small);
+        }
+
+        let mut image = vec![0; DEFAULT_PAGE_SIZE];
+        image[0..4].copy_from_slice(&(COMPRESSED | 7).to_le_bytes());
+        assert!(Page::decode(&image).is_err());
+    }
+
+    #[test]
+    fn page_checksum() {
+        let mut page = Page::new(0, 0);
+        page.records.push(Record {
+            hash: 1,
+            key: vec![1; 8],
+            value: vec![2; 8],
+        });
+        let mut image = page
+            .encode_image(Compression::None, DEFAULT_PAGE_SIZE)
+            .unwrap();
+        assert_eq!(Page::decode_verified(1, &image).unwrap(), page);
+
+        // a flipped bit in the unused tail is detected as well
+        image[DEFAULT_PAGE_SIZE / 2] ^= 1;
+        let err = Page::decode_verified(1, &image).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let corrupt = err.get_ref().unwrap().downcast_ref::<CorruptPage>();
+        assert_eq!(corrupt.map(CorruptPage::page_no), Some(1));
+        assert!(Page::decode_verified(1, &[0; 2]).is_err());
+    }
+
+    #[test]
+    fn valid_page_sizes() {
+        assert!(is_valid_page_size(DEFAULT_PAGE_SIZE));
+        assert!(is_valid_page_size(MAX_PAGE_SIZE));
+        assert!(!is_valid_page_size(MIN_PAGE_SIZE / 2));
+        assert!(!is_valid_page_size(MAX_PAGE_SIZE * 2));
+        assert!(!is_valid_page_size(5000));
+    }
+
+    #[test]
+    fn prefix_works() {
+        let hash = 0b1011 << 60;
+        assert_eq!(prefix(hash, 0), 0);
+        assert_eq!(prefix(hash, 1), 0b1);
+        assert_eq!(prefix(hash, 4), 0b1011);
+        assert_eq!(prefix(hash, 64), hash);
+    }
+}
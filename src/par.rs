@@ -0,0 +1,248 @@
+use crate::{
+    builder::dedup_sorted,
+    hasher::{DirectoryMapper, KeyHasher},
+    map::HashMap,
+    raw_entry::RawEntryMut,
+};
+use allocator_api2::alloc::Allocator;
+use rayon::{
+    iter::{
+        plumbing::UnindexedConsumer, FromParallelIterator,
+        IntoParallelIterator, ParallelExtend, ParallelIterator,
+    },
+    slice::ParallelSliceMut,
+};
+use std::{
+    fmt::{Debug, Formatter},
+    hash::Hash,
+};
+
+/// A parallel iterator over the entries of a [`HashMap`], created by
+/// `par_iter()`.
+///
+/// Buckets are the units of work splitting, the entries of a bucket are
+/// visited sequentially.
+pub struct ParIter<'a, K, V> {
+    /// Keys and values of every bucket
+    buckets: Vec<(&'a [K], &'a [V])>,
+}
+
+impl<K: Debug, V: Debug> Debug for ParIter<'_, K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.buckets.iter()).finish()
+    }
+}
+
+impl<'a, K: Sync, V: Sync> ParallelIterator for ParIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.buckets
+            .into_par_iter()
+            .flat_map_iter(|(keys, values)| keys.iter().zip(values))
+            .drive_unindexed(consumer)
+    }
+}
+
+impl<'a, K, V, S: KeyHasher, M, const B: usize, A: Allocator + Clone>
+    IntoParallelIterator for &'a HashMap<K, V, S, M, B, A>
+where
+    K: Sync,
+    V: Sync,
+{
+    type Iter = ParIter<'a, K, V>;
+    type Item = (&'a K, &'a V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        ParIter {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|(_, bucket)| (&bucket.keys[..], &bucket.values[..]))
+                .collect(),
+        }
+    }
+}
+
+/// A parallel iterator over the entries of a [`HashMap`], with mutable
+/// references to the values, created by `par_iter_mut()`.
+///
+/// Buckets are the units of work splitting, the entries of a bucket are
+/// visited sequentially.
+pub struct ParIterMut<'a, K, V> {
+    /// Keys and values of every bucket
+    buckets: Vec<(&'a [K], &'a mut [V])>,
+}
+
+impl<K: Debug, V: Debug> Debug for ParIterMut<'_, K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.buckets.iter()).finish()
+    }
+}
+
+impl<'a, K: Sync, V: Send> ParallelIterator for ParIterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.buckets
+            .into_par_iter()
+            .flat_map_iter(|(keys, values)| keys.iter().zip(values))
+            .drive_unindexed(consumer)
+    }
+}
+
+impl<'a, K, V, S: KeyHasher, M, const B: usize, A: Allocator + Clone>
+    IntoParallelIterator for &'a mut HashMap<K, V, S, M, B, A>
+where
+    K: Sync,
+    V: Send,
+{
+    type Iter = ParIterMut<'a, K, V>;
+    type Item = (&'a K, &'a mut V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        ParIterMut {
+            buckets: self
+                .buckets
+                .iter_mut()
+                .map(|(_, bucket)| (&bucket.keys[..], &mut bucket.values[..]))
+                .collect(),
+        }
+    }
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> ParallelExtend<(K, V)>
+    for HashMap<K, V, S, M, B, A>
+where
+    K: Eq + Hash + Send,
+    V: Send,
+    S: KeyHasher + Sync,
+    S::Hash: Send,
+    M: DirectoryMapper<S::Hash> + Sync,
+{
+    /// Extend the map with the pairs of a parallel iterator.
+    ///
+    /// The keys are hashed in parallel, and then the pairs are sorted in
+    /// parallel by the bits indexing the directory, so that the pairs going
+    /// to the same bucket are adjacent. An empty map is then bulk loaded like
+    /// [`HashMapBuilder::bulk_load()`](crate::HashMapBuilder::bulk_load)
+    /// does, which never splits a bucket, otherwise the pairs are inserted
+    /// into a directory that has been sized for them, which barely splits
+    /// buckets.
+    ///
+    /// As the sorting is stable, the last pair wins if several pairs have the
+    /// same key, like [`HashMap::insert()`].
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        let hash_builder = &self.hash_builder;
+        let mut entries = par_iter
+            .into_par_iter()
+            .map(|(k, v)| (hash_builder.hash_key(&k), k, v))
+            .collect::<Vec<_>>();
+        let mapper = self.mapper();
+        entries.par_sort_by_key(|(h, _, _)| mapper.map(*h));
+
+        if self.is_empty() {
+            self.load_sorted(dedup_sorted(entries));
+            return;
+        }
+
+        self.reserve(entries.len());
+        for (h, k, v) in entries {
+            match self.raw_entry_mut().from_key_hashed_nocheck(h, &k) {
+                RawEntryMut::Occupied(mut entry) => {
+                    entry.insert(v);
+                }
+                RawEntryMut::Vacant(entry) => {
+                    entry.insert_hashed_nocheck(h, k, v);
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone>
+    FromParallelIterator<(K, V)> for HashMap<K, V, S, M, B, A>
+where
+    K: Eq + Hash + Send,
+    V: Send,
+    S: KeyHasher + Default + Sync,
+    S::Hash: Send,
+    M: DirectoryMapper<S::Hash> + Default + Sync,
+    A: Default,
+{
+    /// Build a map from the pairs of a parallel iterator, which is bulk
+    /// loaded, see [`HashMap::par_extend()`](ParallelExtend::par_extend).
+    fn from_par_iter<I>(par_iter: I) -> Self
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        let mut map = Self::default();
+        map.par_extend(par_iter);
+
+        map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rayon::iter::{IntoParallelRefIterator, IntoParallelRefMutIterator};
+
+    #[test]
+    fn par_iter_works() {
+        let mut map = HashMap::new();
+        for i in 0..1000_u64 {
+            map.insert(i, i);
+        }
+
+        assert_eq!(map.par_iter().count(), 1000);
+        assert_eq!(map.par_iter().map(|(_, v)| *v).sum::<u64>(), 499500);
+
+        map.par_iter_mut().for_each(|(k, v)| *v += k);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), Some(&(2 * i)));
+        }
+    }
+
+    #[test]
+    fn from_par_iter_works() {
+        let map: HashMap<u64, u64> =
+            (0..10000).into_par_iter().map(|i| (i, i)).collect();
+        assert_eq!(map.len(), 10000);
+        for i in 0..10000 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        // bulk loaded, no bucket is split
+        map.validate().unwrap();
+        assert_eq!(map.counters().splits(), 0);
+
+        // the last pair wins
+        let mut map: HashMap<u64, u64> =
+            (0..1000).into_par_iter().map(|i| (i % 100, i)).collect();
+        assert_eq!(map.len(), 100);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(&(900 + i)));
+        }
+
+        map.par_extend((100..200).into_par_iter().map(|i| (i, i)));
+        assert_eq!(map.len(), 200);
+        assert_eq!(map.get(&150), Some(&150));
+        map.validate().unwrap();
+
+        // an empty map keeps its options
+        let mut map = HashMap::with_seed(1);
+        map.set_max_global_depth(2);
+        map.par_extend((0..100).into_par_iter().map(|i| (i, i)));
+        map.validate().unwrap();
+        assert_eq!((map.len(), map.global_depth()), (100, 2));
+    }
+}
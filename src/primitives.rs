@@ -0,0 +1,231 @@
+//! The synchronization primitives of [`SyncHashMap`](crate::SyncHashMap).
+//!
+//! Under `cfg(loom)`, they are replaced by models built on the primitives of
+//! `loom`, which can't instrument [`arc_swap`] and [`parking_lot`], so that
+//! loom checks the interleavings of the real `SyncHashMap` code. The models
+//! have the same names and methods as the types they replace, as far as
+//! `SyncHashMap` uses them.
+
+#[cfg(not(loom))]
+pub(crate) use arc_swap::ArcSwap;
+#[cfg(not(loom))]
+pub(crate) use parking_lot::{
+    ArcMutexGuard, Mutex, RawMutex, RwLock, RwLockWriteGuard,
+};
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+#[cfg(loom)]
+pub(crate) use self::model::{
+    ArcMutexGuard, ArcSwap, AtomicBool, AtomicUsize, Mutex, RawMutex, RwLock,
+    RwLockWriteGuard,
+};
+
+#[cfg(loom)]
+mod model {
+    use std::{
+        cell::UnsafeCell,
+        marker::PhantomData,
+        ops::{Deref, DerefMut},
+        sync::{atomic::Ordering, Arc},
+    };
+
+    pub(crate) use loom::sync::atomic::{AtomicBool, AtomicUsize};
+
+    /// An atomic pointer to an `Arc`, modeled as a mutex holding the `Arc`.
+    pub(crate) struct ArcSwap<T>(loom::sync::Mutex<Arc<T>>);
+
+    impl<T> ArcSwap<T> {
+        pub(crate) fn new(ptr: Arc<T>) -> Self {
+            Self(loom::sync::Mutex::new(ptr))
+        }
+
+        pub(crate) fn from_pointee(value: T) -> Self {
+            Self::new(Arc::new(value))
+        }
+
+        /// Return the current pointer, `arc_swap` returns a guard which
+        /// dereferences to it.
+        pub(crate) fn load(&self) -> Arc<T> {
+            self.load_full()
+        }
+
+        pub(crate) fn load_full(&self) -> Arc<T> {
+            Arc::clone(&self.0.lock().unwrap())
+        }
+
+        pub(crate) fn store(&self, ptr: Arc<T>) {
+            *self.0.lock().unwrap() = ptr;
+        }
+    }
+
+    /// Stands for the raw mutex of `parking_lot`, only used as the type
+    /// parameter of [`ArcMutexGuard`].
+    pub(crate) struct RawMutex;
+
+    /// A mutex spinning on a loom atomic, unlike `loom::sync::Mutex`, its
+    /// guard can own an `Arc` of the mutex, as `parking_lot`'s `lock_arc()`
+    /// returns.
+    pub(crate) struct Mutex<T> {
+        locked: AtomicBool,
+        data: UnsafeCell<T>,
+    }
+
+    // SAFETY: the data is only accessed while the lock is held.
+    unsafe impl<T: Send> Sync for Mutex<T> {}
+
+    impl<T> Mutex<T> {
+        pub(crate) fn new(data: T) -> Self {
+            Self {
+                locked: AtomicBool::new(false),
+                data: UnsafeCell::new(data),
+            }
+        }
+
+        fn acquire(&self) {
+            while self
+                .locked
+                .compare_exchange(
+                    false,
+                    true,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+            {
+                loom::thread::yield_now();
+            }
+        }
+
+        fn release(&self) {
+            self.locked.store(false, Ordering::Release);
+        }
+
+        pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+            self.acquire();
+            MutexGuard(self)
+        }
+
+        pub(crate) fn lock_arc(self: &Arc<Self>) -> ArcMutexGuard<RawMutex, T> {
+            self.acquire();
+            ArcMutexGuard(Arc::clone(self), PhantomData)
+        }
+
+        pub(crate) fn is_locked(&self) -> bool {
+            self.locked.load(Ordering::Relaxed)
+        }
+    }
+
+    pub(crate) struct MutexGuard<'a, T>(&'a Mutex<T>);
+
+    impl<T> Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            // SAFETY: the lock is held.
+            unsafe { &*self.0.data.get() }
+        }
+    }
+
+    impl<T> DerefMut for MutexGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            // SAFETY: the lock is held.
+            unsafe { &mut *self.0.data.get() }
+        }
+    }
+
+    impl<T> Drop for MutexGuard<'_, T> {
+        fn drop(&mut self) {
+            self.0.release();
+        }
+    }
+
+    pub(crate) struct ArcMutexGuard<R, T>(Arc<Mutex<T>>, PhantomData<R>);
+
+    impl<R, T> Drop for ArcMutexGuard<R, T> {
+        fn drop(&mut self) {
+            self.0.release();
+        }
+    }
+
+    /// A readers-writer lock spinning on a loom atomic, which holds the
+    /// number of readers, or `WRITER` while a writer holds it.
+    pub(crate) struct RwLock<T> {
+        state: AtomicUsize,
+        _data: PhantomData<T>,
+    }
+
+    const WRITER: usize = usize::MAX;
+
+    impl<T> RwLock<T> {
+        pub(crate) fn new(_data: T) -> Self {
+            Self {
+                state: AtomicUsize::new(0),
+                _data: PhantomData,
+            }
+        }
+
+        pub(crate) fn read(&self) -> RwLockReadGuard<'_, T> {
+            loop {
+                let state = self.state.load(Ordering::Relaxed);
+                if state != WRITER
+                    && self
+                        .state
+                        .compare_exchange(
+                            state,
+                            state + 1,
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        )
+                        .is_ok()
+                {
+                    return RwLockReadGuard(self);
+                }
+                loom::thread::yield_now();
+            }
+        }
+
+        pub(crate) fn write(&self) -> RwLockWriteGuard<'_, T> {
+            while self
+                .state
+                .compare_exchange(
+                    0,
+                    WRITER,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+            {
+                loom::thread::yield_now();
+            }
+            RwLockWriteGuard(self)
+        }
+    }
+
+    pub(crate) struct RwLockReadGuard<'a, T>(&'a RwLock<T>);
+
+    impl<T> Drop for RwLockReadGuard<'_, T> {
+        fn drop(&mut self) {
+            self.0.state.fetch_sub(1, Ordering::Release);
+        }
+    }
+
+    pub(crate) struct RwLockWriteGuard<'a, T>(&'a RwLock<T>);
+
+    impl<'a, T> RwLockWriteGuard<'a, T> {
+        /// Turn the write lock into a read lock, without letting another
+        /// writer in.
+        pub(crate) fn downgrade(guard: Self) -> RwLockReadGuard<'a, T> {
+            let lock = guard.0;
+            std::mem::forget(guard);
+            lock.state.store(1, Ordering::Release);
+            RwLockReadGuard(lock)
+        }
+    }
+
+    impl<T> Drop for RwLockWriteGuard<'_, T> {
+        fn drop(&mut self) {
+            self.0.state.store(0, Ordering::Release);
+        }
+    }
+}
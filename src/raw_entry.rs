@@ -0,0 +1,481 @@
+//! Raw entry API, which allows looking up or inserting entries using a
+//! precomputed hash value and an equality closure.
+//!
+//! The hash value passed to these APIs must be the one produced by the map's
+//! hasher, i.e., [`HashMap::hasher()`], otherwise the entry won't be found, or
+//! will be inserted into a wrong bucket.
+
+use crate::{
+    bucket::BUCKET_CAP,
+    hasher::{BitOrder, DefaultHashBuilder, DirectoryMapper, KeyHasher},
+    map::{make_hash, HashMap},
+};
+use allocator_api2::alloc::{Allocator, Global};
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Formatter},
+    hash::Hash,
+};
+
+/// A builder for computing where in a [`HashMap`] a key-value pair would be
+/// stored, created by [`HashMap::raw_entry()`].
+pub struct RawEntryBuilder<
+    'a,
+    K,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    map: &'a HashMap<K, V, S, M, B, A>,
+}
+
+impl<
+        'a,
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > RawEntryBuilder<'a, K, V, S, M, B, A>
+{
+    pub(crate) fn new(map: &'a HashMap<K, V, S, M, B, A>) -> Self {
+        Self { map }
+    }
+
+    /// Access an entry by key.
+    pub fn from_key<Q>(self, key: &Q) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, key);
+        self.from_key_hashed_nocheck(hash, key)
+    }
+
+    /// Access an entry by a key and its hash value.
+    pub fn from_key_hashed_nocheck<Q>(
+        self,
+        hash: S::Hash,
+        key: &Q,
+    ) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.from_hash(hash, |k| k.borrow() == key)
+    }
+
+    /// Access an entry by hash value, `is_match` is used to tell if a stored
+    /// key is the one we are looking for.
+    pub fn from_hash<F>(
+        self,
+        hash: S::Hash,
+        mut is_match: F,
+    ) -> Option<(&'a K, &'a V)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let bucket_idx = self.map.locate_bucket_by_hash(hash);
+
+        self.map.buckets[bucket_idx]
+            .iter()
+            .find(|(h, k, _)| **h == hash && is_match(k))
+            .map(|(_, k, v)| (k, v))
+    }
+}
+
+/// A builder for computing where in a [`HashMap`] a key-value pair would be
+/// stored, created by [`HashMap::raw_entry_mut()`].
+pub struct RawEntryBuilderMut<
+    'a,
+    K,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    map: &'a mut HashMap<K, V, S, M, B, A>,
+}
+
+impl<
+        'a,
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > RawEntryBuilderMut<'a, K, V, S, M, B, A>
+{
+    pub(crate) fn new(map: &'a mut HashMap<K, V, S, M, B, A>) -> Self {
+        Self { map }
+    }
+
+    /// Create a `RawEntryMut` from the given key.
+    pub fn from_key<Q>(self, key: &Q) -> RawEntryMut<'a, K, V, S, M, B, A>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = make_hash(&self.map.hash_builder, key);
+        self.from_key_hashed_nocheck(hash, key)
+    }
+
+    /// Create a `RawEntryMut` from the given key and its hash value.
+    pub fn from_key_hashed_nocheck<Q>(
+        self,
+        hash: S::Hash,
+        key: &Q,
+    ) -> RawEntryMut<'a, K, V, S, M, B, A>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.from_hash(hash, |k| k.borrow() == key)
+    }
+
+    /// Create a `RawEntryMut` from the given hash value, `is_match` is used to
+    /// tell if a stored key is the one we are looking for.
+    pub fn from_hash<F>(
+        self,
+        hash: S::Hash,
+        mut is_match: F,
+    ) -> RawEntryMut<'a, K, V, S, M, B, A>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let bucket_idx = self.map.locate_bucket_by_hash(hash);
+        let idx = self.map.buckets[bucket_idx]
+            .iter()
+            .position(|(h, k, _)| *h == hash && is_match(k));
+
+        match idx {
+            Some(idx) => RawEntryMut::Occupied(RawOccupiedEntryMut {
+                map: self.map,
+                bucket_idx,
+                idx,
+            }),
+            None => RawEntryMut::Vacant(RawVacantEntryMut {
+                map: self.map,
+                hash,
+            }),
+        }
+    }
+}
+
+/// A view into a single entry in a [`HashMap`], which may either be vacant or
+/// occupied, created by [`RawEntryBuilderMut`].
+pub enum RawEntryMut<
+    'a,
+    K,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    /// An occupied entry.
+    Occupied(RawOccupiedEntryMut<'a, K, V, S, M, B, A>),
+    /// A vacant entry.
+    Vacant(RawVacantEntryMut<'a, K, V, S, M, B, A>),
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Debug for RawEntryMut<'_, K, V, S, M, B, A>
+where
+    K: Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RawEntryMut::Occupied(entry) => {
+                f.debug_tuple("RawEntryMut").field(entry).finish()
+            }
+            RawEntryMut::Vacant(entry) => {
+                f.debug_tuple("RawEntryMut").field(entry).finish()
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        K: Hash,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > RawEntryMut<'a, K, V, S, M, B, A>
+{
+    /// Ensure a value is in the entry by inserting `(key, value)` if it is
+    /// vacant, return mutable references to the key and value in the entry.
+    pub fn or_insert(self, key: K, value: V) -> (&'a mut K, &'a mut V) {
+        match self {
+            RawEntryMut::Occupied(entry) => entry.into_key_value(),
+            RawEntryMut::Vacant(entry) => entry.insert(key, value),
+        }
+    }
+
+    /// Ensure a value is in the entry by inserting the result of `default` if
+    /// it is vacant, return mutable references to the key and value in the
+    /// entry.
+    pub fn or_insert_with<F>(self, default: F) -> (&'a mut K, &'a mut V)
+    where
+        F: FnOnce() -> (K, V),
+    {
+        match self {
+            RawEntryMut::Occupied(entry) => entry.into_key_value(),
+            RawEntryMut::Vacant(entry) => {
+                let (key, value) = default();
+                entry.insert(key, value)
+            }
+        }
+    }
+
+    /// Provide in-place mutable access to an occupied entry before any
+    /// potential inserts into the map.
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut K, &mut V),
+    {
+        if let RawEntryMut::Occupied(entry) = &mut self {
+            let (k, v) = entry.kv_mut();
+            f(k, v);
+        }
+
+        self
+    }
+}
+
+/// A view into an occupied entry in a [`HashMap`], it is part of the
+/// [`RawEntryMut`] enum.
+pub struct RawOccupiedEntryMut<
+    'a,
+    K,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    map: &'a mut HashMap<K, V, S, M, B, A>,
+    /// Index of the bucket where this entry lives.
+    bucket_idx: usize,
+    /// Index of this entry in the bucket.
+    idx: usize,
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Debug for RawOccupiedEntryMut<'_, K, V, S, M, B, A>
+where
+    K: Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RawOccupiedEntryMut")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+impl<
+        'a,
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > RawOccupiedEntryMut<'a, K, V, S, M, B, A>
+{
+    fn kv_mut(&mut self) -> (&mut K, &mut V) {
+        let bucket = &mut self.map.buckets[self.bucket_idx];
+        (&mut bucket.keys[self.idx], &mut bucket.values[self.idx])
+    }
+
+    /// Return a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        &self.map.buckets[self.bucket_idx].keys[self.idx]
+    }
+
+    /// Return a mutable reference to this entry's key.
+    ///
+    /// Modifying the key in a way that changes its hash value or equality
+    /// leaves the map in an inconsistent state.
+    pub fn key_mut(&mut self) -> &mut K {
+        self.kv_mut().0
+    }
+
+    /// Return a reference to this entry's value.
+    pub fn get(&self) -> &V {
+        &self.map.buckets[self.bucket_idx].values[self.idx]
+    }
+
+    /// Return a mutable reference to this entry's value.
+    pub fn get_mut(&mut self) -> &mut V {
+        self.kv_mut().1
+    }
+
+    /// Convert this entry into mutable references to the key and value, which
+    /// live as long as the map borrow.
+    pub fn into_key_value(self) -> (&'a mut K, &'a mut V) {
+        let bucket = &mut self.map.buckets[self.bucket_idx];
+        (&mut bucket.keys[self.idx], &mut bucket.values[self.idx])
+    }
+
+    /// Set the value of this entry, return the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Remove this entry from the map, return the key and value.
+    pub fn remove_entry(self) -> (K, V) {
+        self.map.remove_at(self.bucket_idx, self.idx)
+    }
+}
+
+/// A view into a vacant entry in a [`HashMap`], it is part of the
+/// [`RawEntryMut`] enum.
+pub struct RawVacantEntryMut<
+    'a,
+    K,
+    V,
+    S: KeyHasher = DefaultHashBuilder,
+    M = BitOrder,
+    const B: usize = BUCKET_CAP,
+    A: Allocator + Clone = Global,
+> {
+    map: &'a mut HashMap<K, V, S, M, B, A>,
+    /// The hash value used to look up this entry.
+    hash: S::Hash,
+}
+
+impl<
+        K,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > Debug for RawVacantEntryMut<'_, K, V, S, M, B, A>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RawVacantEntryMut")
+            .field("hash", &self.hash)
+            .finish()
+    }
+}
+
+impl<
+        'a,
+        K: Hash,
+        V,
+        S: KeyHasher,
+        M: DirectoryMapper<S::Hash>,
+        const B: usize,
+        A: Allocator + Clone,
+    > RawVacantEntryMut<'a, K, V, S, M, B, A>
+{
+    /// Insert `(key, value)` into the map, return mutable references to
+    /// them.
+    pub fn insert(self, key: K, value: V) -> (&'a mut K, &'a mut V) {
+        let hash = make_hash(&self.map.hash_builder, &key);
+        self.insert_hashed_nocheck(hash, key, value)
+    }
+
+    /// Insert `(key, value)` into the map with the given hash value, return
+    /// mutable references to them.
+    pub fn insert_hashed_nocheck(
+        self,
+        hash: S::Hash,
+        key: K,
+        value: V,
+    ) -> (&'a mut K, &'a mut V) {
+        let bucket_idx = self.map.locate_bucket_by_hash(hash);
+        let (bucket_idx, idx) =
+            self.map.insert_absent(bucket_idx, hash, key, value);
+        let bucket = &mut self.map.buckets[bucket_idx];
+
+        (&mut bucket.keys[idx], &mut bucket.values[idx])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn raw_entry_works() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i.to_string(), i);
+        }
+
+        let hash = make_hash(map.hasher(), "42");
+        assert_eq!(
+            map.raw_entry().from_hash(hash, |k| k == "42"),
+            Some((&"42".to_string(), &42))
+        );
+        assert_eq!(map.raw_entry().from_key("100"), None);
+        assert_eq!(
+            map.raw_entry().from_key_hashed_nocheck(hash, "42"),
+            Some((&"42".to_string(), &42))
+        );
+    }
+
+    #[test]
+    fn raw_entry_mut_works() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            let hash = make_hash(map.hasher(), &i);
+            let (_, v) = map
+                .raw_entry_mut()
+                .from_key_hashed_nocheck(hash, &i)
+                .or_insert(i, 0);
+            *v += i;
+        }
+        assert_eq!(map.len(), 1000);
+
+        for i in 0..1000 {
+            match map.raw_entry_mut().from_key(&i) {
+                RawEntryMut::Occupied(mut entry) => {
+                    assert_eq!(entry.insert(i + 1), i);
+                }
+                RawEntryMut::Vacant(_) => unreachable!(),
+            }
+        }
+
+        for i in 0..1000 {
+            let hash = make_hash(map.hasher(), &i);
+            match map.raw_entry_mut().from_hash(hash, |k| *k == i) {
+                RawEntryMut::Occupied(entry) => {
+                    assert_eq!(entry.remove_entry(), (i, i + 1));
+                }
+                RawEntryMut::Vacant(_) => unreachable!(),
+            }
+        }
+        assert!(map.is_empty());
+        assert!(matches!(
+            map.raw_entry_mut().from_key(&0),
+            RawEntryMut::Vacant(_)
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{
+    hasher::{DirectoryMapper, KeyHasher},
+    map::HashMap,
+};
+use allocator_api2::alloc::Allocator;
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{fmt::Formatter, hash::Hash, marker::PhantomData};
+
+/// Deserializers may report a size hint that they cannot back, e.g., read
+/// from an untrusted input, so at most this many elements are reserved up
+/// front.
+const MAX_RESERVED: usize = 1 << 16;
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> Serialize
+    for HashMap<K, V, S, M, B, A>
+where
+    K: Serialize,
+    V: Serialize,
+    S: KeyHasher,
+{
+    /// Serialize the map as a map of its `(key, value)` pairs, the internal
+    /// layout (global depth, directory, buckets) is not serialized.
+    fn serialize<T: Serializer>(
+        &self,
+        serializer: T,
+    ) -> Result<T::Ok, T::Error> {
+        // the length is given up front, which some formats, e.g., bincode
+        // and postcard, require
+        let mut map = serializer.serialize_map(Some(self.len))?;
+        for (_, k, v) in
+            self.buckets.iter().flat_map(|(_, bucket)| bucket.iter())
+        {
+            map.serialize_entry(k, v)?;
+        }
+
+        map.end()
+    }
+}
+
+/// Visitor building a [`HashMap`] from a serialized map.
+struct HashMapVisitor<
+    K,
+    V,
+    S: KeyHasher,
+    M,
+    const B: usize,
+    A: Allocator + Clone,
+> {
+    marker: PhantomData<HashMap<K, V, S, M, B, A>>,
+}
+
+impl<'de, K, V, S, M, const B: usize, A> Visitor<'de>
+    for HashMapVisitor<K, V, S, M, B, A>
+where
+    K: Deserialize<'de> + Eq + Hash,
+    V: Deserialize<'de>,
+    S: KeyHasher + Default,
+    M: DirectoryMapper<S::Hash> + Default,
+    A: Allocator + Clone + Default,
+{
+    type Value = HashMap<K, V, S, M, B, A>;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<T: MapAccess<'de>>(
+        self,
+        mut access: T,
+    ) -> Result<Self::Value, T::Error> {
+        let mut map = HashMap::default();
+        // size the directory up front rather than splitting buckets one at a
+        // time
+        map.reserve(access.size_hint().unwrap_or(0).min(MAX_RESERVED));
+        while let Some((k, v)) = access.next_entry()? {
+            map.insert(k, v);
+        }
+
+        Ok(map)
+    }
+}
+
+impl<'de, K, V, S, M, const B: usize, A> Deserialize<'de>
+    for HashMap<K, V, S, M, B, A>
+where
+    K: Deserialize<'de> + Eq + Hash,
+    V: Deserialize<'de>,
+    S: KeyHasher + Default,
+    M: DirectoryMapper<S::Hash> + Default,
+    A: Allocator + Clone + Default,
+{
+    /// Deserialize a map of `(key, value)` pairs, if a key appears more than
+    /// once, the last value wins.
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(HashMapVisitor {
+            marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serde_round_trip() {
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            map.insert(i.to_string(), i);
+        }
+
+        let json = serde_json::to_string(&map).unwrap();
+        let de: HashMap<String, i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(de, map);
+
+        let de: HashMap<String, i32> =
+            serde_json::from_str(r#"{"a": 1, "b": 2, "a": 3}"#).unwrap();
+        assert_eq!(de.len(), 2);
+        assert_eq!(de["a"], 3);
+        assert!(serde_json::from_str::<HashMap<String, i32>>("[1]").is_err());
+    }
+}
@@ -0,0 +1,777 @@
+use crate::{
+    entry::EntryRef,
+    hasher::{DefaultHashBuilder, HashValue, KeyHasher},
+    lock::{LockPolicy, RawShardLock, ShardReadGuard, ShardWriteGuard},
+    map::HashMap,
+    raw_entry::RawEntryMut,
+};
+#[cfg(feature = "upgradable")]
+use lock_api::RwLockUpgradableReadGuard;
+use lock_api::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Formatter},
+    hash::Hash,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A concurrent hash map which partitions the keys across several
+/// independent [`HashMap`] shards, each behind its own read-write lock.
+///
+/// A key goes to the shard picked by the last bits of its hash value, while
+/// the shards index their directories with the first bits, so the keys of a
+/// shard still spread over its directory.
+///
+/// Operations on different shards can run in parallel, the lookup methods
+/// return guards which keep the shard locked until they are dropped. Whether
+/// the readers or the writers of a shard go first is set by a
+/// [`LockPolicy`](crate::LockPolicy).
+///
+/// # Deadlock
+/// Calling a method of the map while holding a guard returned by it may
+/// deadlock, e.g., inserting into the shard that a read guard holds.
+pub struct ShardedHashMap<K, V, S: KeyHasher = DefaultHashBuilder> {
+    /// Shards, the number of shards is a power of 2
+    shards: Box<[Shard<K, V, S>]>,
+    /// Policy of the shard locks
+    lock_policy: LockPolicy,
+    /// Number of entries, updated while the modified shard is write-locked
+    len: AtomicUsize,
+    /// Number of the hash bits used to pick a shard
+    shard_bits: u32,
+    /// Hash builder, every shard has a clone of it
+    hash_builder: S,
+}
+
+/// A shard of a [`ShardedHashMap`].
+type Shard<K, V, S> = RwLock<RawShardLock, HashMap<K, V, S>>;
+
+impl<K, V, S: KeyHasher> Debug for ShardedHashMap<K, V, S>
+where
+    K: Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.shards.iter().map(|shard| shard.read()))
+            .finish()
+    }
+}
+
+/// Return the default number of shards, which is 4 times the available
+/// parallelism, rounded up to a power of 2.
+fn default_shard_amount() -> usize {
+    let parallelism =
+        std::thread::available_parallelism().map_or(1, usize::from);
+
+    (parallelism * 4).next_power_of_two()
+}
+
+impl<K, V, S: KeyHasher + Clone + Default> Default for ShardedHashMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_shards_and_hasher(default_shard_amount(), S::default())
+    }
+}
+
+impl<K, V> ShardedHashMap<K, V, DefaultHashBuilder> {
+    /// Create an empty `ShardedHashMap`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty `ShardedHashMap` with `shard_amount` shards.
+    ///
+    /// # Panic
+    /// `shard_amount` should be a power of 2.
+    pub fn with_shards(shard_amount: usize) -> Self {
+        Self::with_shards_and_hasher(
+            shard_amount,
+            DefaultHashBuilder::default(),
+        )
+    }
+
+    /// Create an empty `ShardedHashMap` whose shard locks follow
+    /// `lock_policy`.
+    pub fn with_lock_policy(lock_policy: LockPolicy) -> Self {
+        Self::with_lock_policy_and_hasher(
+            lock_policy,
+            DefaultHashBuilder::default(),
+        )
+    }
+}
+
+impl<K, V, S: KeyHasher + Clone> ShardedHashMap<K, V, S> {
+    /// Create an empty `ShardedHashMap` which will use the given hash builder
+    /// to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_shards_and_hasher(default_shard_amount(), hash_builder)
+    }
+
+    /// Create an empty `ShardedHashMap` with `shard_amount` shards,
+    /// `hash_builder` will be used to hash keys.
+    ///
+    /// # Panic
+    /// `shard_amount` should be a power of 2, and its base 2 logarithm should
+    /// not be greater than the width of the hash values.
+    pub fn with_shards_and_hasher(
+        shard_amount: usize,
+        hash_builder: S,
+    ) -> Self {
+        Self::with_options(shard_amount, LockPolicy::default(), hash_builder)
+    }
+
+    /// Create an empty `ShardedHashMap` whose shard locks follow
+    /// `lock_policy`, `hash_builder` will be used to hash keys.
+    pub fn with_lock_policy_and_hasher(
+        lock_policy: LockPolicy,
+        hash_builder: S,
+    ) -> Self {
+        Self::with_options(default_shard_amount(), lock_policy, hash_builder)
+    }
+
+    /// Create an empty `ShardedHashMap` with the given options.
+    fn with_options(
+        shard_amount: usize,
+        lock_policy: LockPolicy,
+        hash_builder: S,
+    ) -> Self {
+        assert!(shard_amount.is_power_of_two());
+        let shard_bits = shard_amount.trailing_zeros();
+        assert!(shard_bits <= S::Hash::BITS);
+
+        let shards = (0..shard_amount)
+            .map(|_| {
+                RwLock::from_raw(
+                    RawShardLock::new(lock_policy),
+                    HashMap::with_hasher(hash_builder.clone()),
+                )
+            })
+            .collect();
+
+        Self {
+            shards,
+            lock_policy,
+            len: AtomicUsize::new(0),
+            shard_bits,
+            hash_builder,
+        }
+    }
+}
+
+impl<K, V, S: KeyHasher> ShardedHashMap<K, V, S> {
+    /// Return a reference to the map's hash builder.
+    #[inline]
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Return the policy of the shard locks.
+    #[inline]
+    pub fn lock_policy(&self) -> LockPolicy {
+        self.lock_policy
+    }
+
+    /// Return the number of shards.
+    #[inline]
+    pub fn shard_amount(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Return the number of elements in the map.
+    ///
+    /// The shards are read-locked one at a time, so the returned value can be
+    /// outdated if other threads are modifying the map.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().len()).sum()
+    }
+
+    /// Return the number of elements in the map without locking any shard,
+    /// which is cheap enough for monitoring, but the value may lag behind
+    /// [`len()`](Self::len).
+    #[inline]
+    pub fn approx_len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Return true if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.read().is_empty())
+    }
+
+    /// Return the shard where a key whose hash value is `hash` goes, which
+    /// is picked by the last `self.shard_bits` bits of `hash`.
+    fn shard(&self, hash: S::Hash) -> &Shard<K, V, S> {
+        let idx = (0..self.shard_bits).fold(0, |acc, idx| {
+            (acc << 1) | hash.bit(S::Hash::BITS - self.shard_bits + idx)
+        });
+
+        &self.shards[idx]
+    }
+}
+
+impl<K: Hash + Eq, V, S: KeyHasher> ShardedHashMap<K, V, S> {
+    /// Insert a key-value pair into the map.
+    ///
+    /// If the map did not have this key present, `None` is returned.
+    ///
+    /// If the map did have this key present, the value is updated, and the
+    /// old value is returned. The key is not updated, though.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let hash = self.hash_builder.hash_key(&key);
+        let mut shard = self.shard(hash).write();
+
+        match shard.raw_entry_mut().from_key_hashed_nocheck(hash, &key) {
+            RawEntryMut::Occupied(mut entry) => Some(entry.insert(value)),
+            RawEntryMut::Vacant(entry) => {
+                entry.insert_hashed_nocheck(hash, key, value);
+                self.len.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Insert a key-value pair into the map if the key is absent, otherwise
+    /// leave the map untouched and give `value` back.
+    ///
+    /// The shard is upgradable-read-locked for the existence check, which
+    /// lets readers of the shard proceed, and it gets write-locked only when
+    /// the pair is inserted.
+    #[cfg(feature = "upgradable")]
+    pub fn try_insert(&self, key: K, value: V) -> Result<(), V> {
+        let hash = self.hash_builder.hash_key(&key);
+        let shard = self.shard(hash).upgradable_read();
+        if shard
+            .raw_entry()
+            .from_key_hashed_nocheck(hash, &key)
+            .is_some()
+        {
+            return Err(value);
+        }
+
+        let mut shard = RwLockUpgradableReadGuard::upgrade(shard);
+        match shard.raw_entry_mut().from_key_hashed_nocheck(hash, &key) {
+            RawEntryMut::Occupied(_) => unreachable!(
+                "ShardedHashMap: shard is modified while being locked"
+            ),
+            RawEntryMut::Vacant(entry) => {
+                entry.insert_hashed_nocheck(hash, key, value);
+                self.len.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+
+    /// Return a guard referencing the value corresponding to the key, the
+    /// shard of the entry is read-locked until the guard is dropped.
+    pub fn get<Q>(&self, key: &Q) -> Option<ShardReadGuard<'_, V>>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let shard = self.shard(hash).read();
+
+        RwLockReadGuard::try_map(shard, |shard| {
+            shard
+                .raw_entry()
+                .from_key_hashed_nocheck(hash, key)
+                .map(|(_, v)| v)
+        })
+        .ok()
+    }
+
+    /// Return a guard mutably referencing the value corresponding to the key,
+    /// the shard of the entry is write-locked until the guard is dropped.
+    pub fn get_mut<Q>(&self, key: &Q) -> Option<ShardWriteGuard<'_, V>>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let shard = self.shard(hash).write();
+
+        RwLockWriteGuard::try_map(shard, |shard| {
+            match shard.raw_entry_mut().from_key_hashed_nocheck(hash, key) {
+                RawEntryMut::Occupied(entry) => Some(entry.into_key_value().1),
+                RawEntryMut::Vacant(_) => None,
+            }
+        })
+        .ok()
+    }
+
+    /// Return true if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Remove `key` from the map, return the stored key and its value if it
+    /// was previously in the map.
+    pub fn remove_entry<Q>(&self, key: &Q) -> Option<(K, V)>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let mut shard = self.shard(hash).write();
+
+        match shard.raw_entry_mut().from_key_hashed_nocheck(hash, key) {
+            RawEntryMut::Occupied(entry) => {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                Some(entry.remove_entry())
+            }
+            RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    /// Remove `key` from the map, return its value if it was previously in the
+    /// map.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    /// Get the entry of `key` for in-place manipulation.
+    ///
+    /// The shard of the entry is write-locked until the entry, or the guard
+    /// it gets converted to, is dropped, so read-modify-write operations
+    /// done through it are atomic.
+    pub fn entry(&self, key: K) -> ShardedEntry<'_, K, V, S> {
+        let hash = self.hash_builder.hash_key(&key);
+        let mut shard = self.shard(hash).write();
+        let occupied = matches!(
+            shard.raw_entry_mut().from_key_hashed_nocheck(hash, &key),
+            RawEntryMut::Occupied(_)
+        );
+        let entry = LockedEntry {
+            shard,
+            len: &self.len,
+            hash,
+            key,
+        };
+
+        if occupied {
+            ShardedEntry::Occupied(ShardedOccupiedEntry(entry))
+        } else {
+            ShardedEntry::Vacant(ShardedVacantEntry(entry))
+        }
+    }
+
+    /// Call `f` with the entry of `key`, while the shard of the entry is
+    /// write-locked, return what `f` returns.
+    ///
+    /// Read-modify-write operations done in `f` are atomic, as no other
+    /// thread can access the shard in the meantime.
+    pub fn with_entry_ref<Q, F, R>(&self, key: &Q, f: F) -> R
+    where
+        Q: ToOwned<Owned = K> + Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+        F: FnOnce(EntryRef<'_, '_, K, Q, V, S>) -> R,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let mut shard = self.shard(hash).write();
+        let old_len = shard.len();
+        let ret = f(shard.entry_ref(key));
+        // `f` inserts or removes at most one entry
+        match shard.len().cmp(&old_len) {
+            std::cmp::Ordering::Greater => {
+                self.len.fetch_add(1, Ordering::Relaxed);
+            }
+            std::cmp::Ordering::Less => {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+            }
+            std::cmp::Ordering::Equal => {}
+        }
+
+        ret
+    }
+}
+
+/// The entry of a key in a write-locked shard.
+struct LockedEntry<'a, K, V, S: KeyHasher> {
+    shard: RwLockWriteGuard<'a, RawShardLock, HashMap<K, V, S>>,
+    /// Length of the map, updated on insertion and removal.
+    len: &'a AtomicUsize,
+    /// Hash value of `key`.
+    hash: S::Hash,
+    key: K,
+}
+
+impl<K: Hash + Eq, V, S: KeyHasher> LockedEntry<'_, K, V, S> {
+    /// Return the entry of the key in the shard.
+    fn raw_entry_mut(&mut self) -> RawEntryMut<'_, K, V, S> {
+        self.shard
+            .raw_entry_mut()
+            .from_key_hashed_nocheck(self.hash, &self.key)
+    }
+}
+
+/// Panic as the entry is vacant, which it can't be, as its shard is locked.
+fn vacant() -> ! {
+    unreachable!("ShardedHashMap: shard is modified while being locked")
+}
+
+/// A view into a single entry of a [`ShardedHashMap`], which may either be
+/// vacant or occupied, created by [`ShardedHashMap::entry()`].
+///
+/// The shard of the entry is write-locked until the entry, or the guard it
+/// gets converted to, is dropped.
+pub enum ShardedEntry<'a, K, V, S: KeyHasher = DefaultHashBuilder> {
+    /// An occupied entry.
+    Occupied(ShardedOccupiedEntry<'a, K, V, S>),
+    /// A vacant entry.
+    Vacant(ShardedVacantEntry<'a, K, V, S>),
+}
+
+impl<K, V, S> Debug for ShardedEntry<'_, K, V, S>
+where
+    K: Debug + Hash + Eq,
+    V: Debug,
+    S: KeyHasher,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShardedEntry::Occupied(entry) => {
+                f.debug_tuple("ShardedEntry").field(entry).finish()
+            }
+            ShardedEntry::Vacant(entry) => {
+                f.debug_tuple("ShardedEntry").field(entry).finish()
+            }
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: KeyHasher> ShardedEntry<'a, K, V, S> {
+    /// Return a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        match self {
+            ShardedEntry::Occupied(entry) => entry.key(),
+            ShardedEntry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Ensure a value is in the entry by inserting `default` if it is vacant,
+    /// return a guard mutably referencing the value.
+    pub fn or_insert(self, default: V) -> ShardWriteGuard<'a, V> {
+        match self {
+            ShardedEntry::Occupied(entry) => entry.into_mut(),
+            ShardedEntry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Ensure a value is in the entry by inserting the result of `default` if
+    /// it is vacant, return a guard mutably referencing the value.
+    pub fn or_insert_with<F>(self, default: F) -> ShardWriteGuard<'a, V>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            ShardedEntry::Occupied(entry) => entry.into_mut(),
+            ShardedEntry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Ensure a value is in the entry by inserting the default value if it is
+    /// vacant, return a guard mutably referencing the value.
+    pub fn or_default(self) -> ShardWriteGuard<'a, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Provide in-place mutable access to an occupied entry before any
+    /// potential inserts into the map.
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        if let ShardedEntry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+
+        self
+    }
+}
+
+/// A view into an occupied entry of a [`ShardedHashMap`], it is part of the
+/// [`ShardedEntry`] enum.
+pub struct ShardedOccupiedEntry<'a, K, V, S: KeyHasher = DefaultHashBuilder>(
+    LockedEntry<'a, K, V, S>,
+);
+
+impl<K, V, S> Debug for ShardedOccupiedEntry<'_, K, V, S>
+where
+    K: Debug + Hash + Eq,
+    V: Debug,
+    S: KeyHasher,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShardedOccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: KeyHasher> ShardedOccupiedEntry<'a, K, V, S> {
+    /// Return references to the key and the value in the map.
+    fn pair(&self) -> (&K, &V) {
+        self.0
+            .shard
+            .raw_entry()
+            .from_key_hashed_nocheck(self.0.hash, &self.0.key)
+            .unwrap_or_else(|| vacant())
+    }
+
+    /// Return a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        self.pair().0
+    }
+
+    /// Return a reference to this entry's value.
+    pub fn get(&self) -> &V {
+        self.pair().1
+    }
+
+    /// Return a mutable reference to this entry's value.
+    pub fn get_mut(&mut self) -> &mut V {
+        match self.0.raw_entry_mut() {
+            RawEntryMut::Occupied(entry) => entry.into_key_value().1,
+            RawEntryMut::Vacant(_) => vacant(),
+        }
+    }
+
+    /// Convert this entry to a guard mutably referencing its value.
+    pub fn into_mut(self) -> ShardWriteGuard<'a, V> {
+        let LockedEntry {
+            shard, hash, key, ..
+        } = self.0;
+
+        RwLockWriteGuard::map(shard, |shard| {
+            match shard.raw_entry_mut().from_key_hashed_nocheck(hash, &key) {
+                RawEntryMut::Occupied(entry) => entry.into_key_value().1,
+                RawEntryMut::Vacant(_) => vacant(),
+            }
+        })
+    }
+
+    /// Set the value of this entry, return the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Remove this entry from the map, return the stored key and its value,
+    /// releasing the shard.
+    pub fn remove_entry(mut self) -> (K, V) {
+        let entry = match self.0.raw_entry_mut() {
+            RawEntryMut::Occupied(entry) => entry.remove_entry(),
+            RawEntryMut::Vacant(_) => vacant(),
+        };
+        self.0.len.fetch_sub(1, Ordering::Relaxed);
+
+        entry
+    }
+
+    /// Remove this entry from the map, return its value, releasing the shard.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+}
+
+/// A view into a vacant entry of a [`ShardedHashMap`], it is part of the
+/// [`ShardedEntry`] enum.
+pub struct ShardedVacantEntry<'a, K, V, S: KeyHasher = DefaultHashBuilder>(
+    LockedEntry<'a, K, V, S>,
+);
+
+impl<K: Debug, V, S: KeyHasher> Debug for ShardedVacantEntry<'_, K, V, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ShardedVacantEntry")
+            .field(&self.0.key)
+            .finish()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: KeyHasher> ShardedVacantEntry<'a, K, V, S> {
+    /// Return a reference to the key of this entry.
+    pub fn key(&self) -> &K {
+        &self.0.key
+    }
+
+    /// Take the ownership of the key, releasing the shard.
+    pub fn into_key(self) -> K {
+        self.0.key
+    }
+
+    /// Insert `value`, return a guard mutably referencing it.
+    pub fn insert(self, value: V) -> ShardWriteGuard<'a, V> {
+        let LockedEntry {
+            shard,
+            len,
+            hash,
+            key,
+        } = self.0;
+        len.fetch_add(1, Ordering::Relaxed);
+
+        RwLockWriteGuard::map(shard, |shard| {
+            match shard.raw_entry_mut().from_key_hashed_nocheck(hash, &key) {
+                RawEntryMut::Vacant(entry) => {
+                    entry.insert_hashed_nocheck(hash, key, value).1
+                }
+                RawEntryMut::Occupied(_) => unreachable!(
+                    "ShardedHashMap: shard is modified while being locked"
+                ),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn sharded_map_works() {
+        let map = ShardedHashMap::with_shards(4);
+        assert_eq!(map.shard_amount(), 4);
+        for i in 0..1000 {
+            assert_eq!(map.insert(i, i), None);
+        }
+        assert_eq!(map.insert(0, 1), Some(0));
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.approx_len(), 1000);
+
+        // every shard gets some keys
+        assert!(map.shards.iter().all(|shard| !shard.read().is_empty()));
+
+        *map.get_mut(&1).unwrap() += 1;
+        assert_eq!(*map.get(&1).unwrap(), 2);
+        assert!(map.get(&1000).is_none());
+        assert!(map.get_mut(&1000).is_none());
+
+        for i in 0..1000 {
+            assert!(map.remove(&i).is_some());
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn sharded_map_entry() {
+        let map = ShardedHashMap::with_shards(4);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..100 {
+                        *map.entry(i % 10).or_insert(0) += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), 10);
+        for i in 0..10 {
+            assert_eq!(*map.get(&i).unwrap(), 40);
+        }
+
+        *map.entry(0).and_modify(|v| *v += 1).or_default() += 1;
+        assert_eq!(*map.get(&0).unwrap(), 42);
+        assert_eq!(*map.entry(10).and_modify(|v| *v += 1).or_default(), 0);
+        assert_eq!(map.len(), 11);
+
+        match map.entry(1) {
+            ShardedEntry::Occupied(mut entry) => {
+                assert_eq!((entry.key(), entry.get()), (&1, &40));
+                assert_eq!(entry.insert(1), 40);
+                assert_eq!(entry.remove(), 1);
+            }
+            ShardedEntry::Vacant(_) => unreachable!(),
+        }
+        match map.entry(1) {
+            ShardedEntry::Vacant(entry) => assert_eq!(entry.into_key(), 1),
+            ShardedEntry::Occupied(_) => unreachable!(),
+        }
+        assert_eq!(map.len(), 10);
+    }
+
+    #[cfg(feature = "upgradable")]
+    #[test]
+    fn sharded_map_try_insert() {
+        let map = ShardedHashMap::with_shards(2);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                s.spawn(move || {
+                    for i in 0..100 {
+                        let _ = map.try_insert(i, t);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.approx_len(), 100);
+        let old = *map.get(&0).unwrap();
+        assert_eq!(map.try_insert(0, 4), Err(4));
+        assert_eq!(*map.get(&0).unwrap(), old);
+    }
+
+    #[test]
+    fn sharded_map_lock_policy() {
+        for lock_policy in [
+            LockPolicy::Fair,
+            LockPolicy::ReaderPreferring,
+            LockPolicy::WriterPreferring,
+        ] {
+            let map = ShardedHashMap::with_lock_policy(lock_policy);
+            assert_eq!(map.lock_policy(), lock_policy);
+            thread::scope(|s| {
+                for t in 0..4 {
+                    let map = &map;
+                    s.spawn(move || {
+                        for i in (t * 500)..((t + 1) * 500) {
+                            assert_eq!(map.insert(i, i), None);
+                            assert_eq!(*map.get(&i).unwrap(), i);
+                            #[cfg(feature = "upgradable")]
+                            assert_eq!(map.try_insert(i, 0), Err(0));
+                        }
+                    });
+                }
+            });
+
+            assert_eq!(map.len(), 2000);
+            for i in 0..2000 {
+                assert_eq!(*map.get(&i).unwrap(), i);
+            }
+        }
+    }
+
+    #[test]
+    fn sharded_map_with_entry_ref() {
+        let map: ShardedHashMap<String, usize> = ShardedHashMap::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..100 {
+                        let key = format!("{}", i % 10);
+                        map.with_entry_ref(key.as_str(), |entry| {
+                            *entry.or_insert(0) += 1;
+                        });
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.len(), 10);
+        assert_eq!(map.approx_len(), 10);
+        for i in 0..10 {
+            assert_eq!(*map.get(format!("{}", i).as_str()).unwrap(), 40);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! A slab, the storage of the buckets of a [`HashMap`](crate::HashMap).
+
+use allocator_api2::{
+    alloc::{Allocator, Global},
+    vec::Vec,
+};
+use std::ops::{Index, IndexMut};
+
+enum Entry<T> {
+    /// A vacant slot, storing the key of the next vacant slot, or the length
+    /// of the slab if it's the last one.
+    Vacant(usize),
+    Occupied(T),
+}
+
+/// Pre-allocated storage of values of type `T`, a value gets a key when it's
+/// inserted, which stays valid until the value is removed, regardless of
+/// the other insertions and removals. The slots of the removed values are
+/// reused by the next insertions.
+///
+/// Unlike the `slab` crate, the slots are allocated from `A`.
+pub(crate) struct Slab<T, A: Allocator = Global> {
+    entries: Vec<Entry<T>, A>,
+    /// Number of the occupied slots.
+    len: usize,
+    /// Key of the first vacant slot, `entries.len()` if there is none.
+    next: usize,
+}
+
+impl<T> Slab<T> {
+    /// Create an empty slab with room for `capacity` values.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_in(capacity, Global)
+    }
+}
+
+impl<T, A: Allocator> Slab<T, A> {
+    /// Create an empty slab, which allocates from `alloc`.
+    pub(crate) fn new_in(alloc: A) -> Self {
+        Self::with_capacity_in(0, alloc)
+    }
+
+    /// Create an empty slab with room for `capacity` values, which are
+    /// allocated from `alloc`.
+    pub(crate) fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        Self {
+            entries: Vec::with_capacity_in(capacity, alloc),
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Return the allocator of the slab.
+    #[inline]
+    pub(crate) fn allocator(&self) -> &A {
+        self.entries.allocator()
+    }
+
+    /// Return the number of the values.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return the number of values the slab can hold without reallocating.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    /// Insert `value`, return its key.
+    pub(crate) fn insert(&mut self, value: T) -> usize {
+        let key = self.next;
+        if key == self.entries.len() {
+            self.entries.push(Entry::Occupied(value));
+            self.next = key + 1;
+        } else {
+            match std::mem::replace(
+                &mut self.entries[key],
+                Entry::Occupied(value),
+            ) {
+                Entry::Vacant(next) => self.next = next,
+                Entry::Occupied(_) => unreachable!("Slab: the slot is taken"),
+            }
+        }
+        self.len += 1;
+
+        key
+    }
+
+    /// Remove and return the value of `key`.
+    ///
+    /// # Panic
+    /// `key` should be the key of a value.
+    pub(crate) fn remove(&mut self, key: usize) -> T {
+        let entry = self.entries.get_mut(key).expect("Slab: invalid key");
+        match std::mem::replace(entry, Entry::Vacant(self.next)) {
+            Entry::Occupied(value) => {
+                self.next = key;
+                self.len -= 1;
+                value
+            }
+            vacant => {
+                *entry = vacant;
+                panic!("Slab: invalid key");
+            }
+        }
+    }
+
+    /// Return true if `key` is the key of a value.
+    #[inline]
+    pub(crate) fn contains(&self, key: usize) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Return a reference to the value of `key`.
+    #[inline]
+    pub(crate) fn get(&self, key: usize) -> Option<&T> {
+        match self.entries.get(key) {
+            Some(Entry::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Return a mutable reference to the value of `key`.
+    #[inline]
+    pub(crate) fn get_mut(&mut self, key: usize) -> Option<&mut T> {
+        match self.entries.get_mut(key) {
+            Some(Entry::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Return mutable references to the values of `keys`, `None` if any key
+    /// isn't the key of a value, or if the keys are not distinct.
+    pub(crate) fn get_disjoint_mut<const N: usize>(
+        &mut self,
+        keys: [usize; N],
+    ) -> Option<[&mut T; N]> {
+        let len = self.entries.len();
+        for (i, key) in keys.iter().enumerate() {
+            if *key >= len || keys[..i].contains(key) {
+                return None;
+            }
+        }
+
+        // The slots are reached through the pointer to the storage, no
+        // reference to the other slots is created, so the references the
+        // caller got earlier to the values of the other keys stay valid.
+        let entries = self.entries.as_mut_ptr();
+        // SAFETY: the keys are in bounds and distinct.
+        let entries = keys.map(|key| unsafe { &mut *entries.add(key) });
+        if entries
+            .iter()
+            .any(|entry| matches!(entry, Entry::Vacant(_)))
+        {
+            return None;
+        }
+
+        Some(entries.map(|entry| match entry {
+            Entry::Occupied(value) => value,
+            Entry::Vacant(_) => unreachable!("Slab: the slot is vacant"),
+        }))
+    }
+
+    /// Reserve room for at least `additional` more values.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        let vacant = self.entries.len() - self.len;
+        if additional > vacant {
+            self.entries.reserve(additional - vacant);
+        }
+    }
+
+    /// Release the trailing vacant slots, and as much of the capacity as
+    /// possible.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        while let Some(Entry::Vacant(_)) = self.entries.last() {
+            self.entries.pop();
+        }
+        // relink the vacant slots, some of them are gone
+        self.next = self.entries.len();
+        for (key, entry) in self.entries.iter_mut().enumerate().rev() {
+            if let Entry::Vacant(next) = entry {
+                *next = self.next;
+                self.next = key;
+            }
+        }
+        self.entries.shrink_to_fit();
+    }
+
+    /// Return an iterator over the keys and the values.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(key, entry)| match entry {
+                Entry::Occupied(value) => Some((key, value)),
+                Entry::Vacant(_) => None,
+            })
+    }
+
+    /// Return an iterator over the keys and the mutable values.
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.entries
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(key, entry)| match entry {
+                Entry::Occupied(value) => Some((key, value)),
+                Entry::Vacant(_) => None,
+            })
+    }
+
+    /// Create a slab with the same keys, capacity and vacant slots, whose
+    /// values are mapped from the values of `self` by `f`.
+    pub(crate) fn map_in<U, B: Allocator>(
+        &self,
+        alloc: B,
+        mut f: impl FnMut(&T) -> U,
+    ) -> Slab<U, B> {
+        let mut entries = Vec::with_capacity_in(self.entries.capacity(), alloc);
+        entries.extend(self.entries.iter().map(|entry| match entry {
+            Entry::Vacant(next) => Entry::Vacant(*next),
+            Entry::Occupied(value) => Entry::Occupied(f(value)),
+        }));
+
+        Slab {
+            entries,
+            len: self.len,
+            next: self.next,
+        }
+    }
+}
+
+impl<T, A: Allocator> Index<usize> for Slab<T, A> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, key: usize) -> &T {
+        self.get(key).expect("Slab: invalid key")
+    }
+}
+
+impl<T, A: Allocator> IndexMut<usize> for Slab<T, A> {
+    #[inline]
+    fn index_mut(&mut self, key: usize) -> &mut T {
+        self.get_mut(key).expect("Slab: invalid key")
+    }
+}
+
+impl<T, A: Allocator> IntoIterator for Slab<T, A> {
+    type Item = (usize, T);
+    type IntoIter = IntoIter<T, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            entries: self.entries.into_iter().enumerate(),
+        }
+    }
+}
+
+/// An owning iterator over the keys and the values of a [`Slab`].
+pub(crate) struct IntoIter<T, A: Allocator = Global> {
+    entries: std::iter::Enumerate<allocator_api2::vec::IntoIter<Entry<T>, A>>,
+}
+
+impl<T, A: Allocator> Iterator for IntoIter<T, A> {
+    type Item = (usize, T);
+
+    fn next(&mut self) -> Option<(usize, T)> {
+        self.entries.find_map(|(key, entry)| match entry {
+            Entry::Occupied(value) => Some((key, value)),
+            Entry::Vacant(_) => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn slab_reuses_vacant_slots() {
+        let mut slab = Slab::with_capacity(4);
+        let keys = (0..4)
+            .map(|i| slab.insert(i * 10))
+            .collect::<std::vec::Vec<_>>();
+        assert_eq!(keys, [0, 1, 2, 3]);
+
+        assert_eq!(slab.remove(1), 10);
+        assert_eq!(slab.remove(3), 30);
+        assert!(!slab.contains(1) && slab.get(3).is_none());
+        assert_eq!(slab.len(), 2);
+        // the last removed slot is reused first
+        assert_eq!(slab.insert(50), 3);
+        assert_eq!(slab.insert(60), 1);
+        assert_eq!(slab.insert(70), 4);
+
+        let [a, b] = slab.get_disjoint_mut([4, 0]).unwrap();
+        std::mem::swap(a, b);
+        assert!(slab.get_disjoint_mut([0, 0]).is_none());
+        assert!(slab.get_disjoint_mut([0, 5]).is_none());
+
+        let clone = slab.map_in(Global, |v| v + 1);
+        assert!(clone
+            .iter()
+            .map(|(k, v)| (k, *v))
+            .eq(slab.iter().map(|(k, v)| (k, v + 1))));
+
+        slab.remove(4);
+        slab.remove(2);
+        slab.shrink_to_fit();
+        assert_eq!(slab.capacity(), 4);
+        assert_eq!(slab.insert(80), 2);
+        assert_eq!(slab.insert(90), 4);
+        assert!(slab.into_iter().eq([
+            (0, 70),
+            (1, 60),
+            (2, 80),
+            (3, 50),
+            (4, 90)
+        ]));
+    }
+}
@@ -0,0 +1,357 @@
+//! Binary snapshots of [`HashMap`].
+//!
+//! A snapshot starts with a header:
+//!
+//! | field             | size |
+//! |-------------------|------|
+//! | magic             | 8    |
+//! | version           | 4    |
+//! | hash width        | 4    |
+//! | global depth      | 4    |
+//! | max global depth  | 4    |
+//! | bucket capacity   | 8    |
+//! | bucket policy     | 1    |
+//! | buckets           | 4    |
+//!
+//! followed by the directory, every entry is the 4-byte number of a bucket,
+//! and then the buckets, each of them is its local depth (1 byte), its bits
+//! packed into bytes with the first bit as the most significant one, its
+//! number of entries (4 bytes), and the entries, each of them is the length
+//! of the encoded key (4 bytes), the encoded key, the length of the encoded
+//! value (4 bytes) and the encoded value.
+//!
+//! All the integers are in little endian. Hash values are not stored, they
+//! are recalculated when the snapshot is loaded.
+
+use crate::{
+    arena::Arena,
+    bucket::{max_depth, Bucket, BucketIdx, BucketPolicy},
+    codec::Encode,
+    counters::Counters,
+    hasher::{DirectoryMapper, HashValue, KeyHasher},
+    map::{count_depths, CoalescePolicy, HashMap, HALVING_HYSTERESIS},
+    slab::Slab,
+};
+use allocator_api2::alloc::Allocator;
+use std::{
+    hash::Hash,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+/// Magic bytes at the start of a snapshot.
+const MAGIC: &[u8; 8] = b"EXTHSNAP";
+/// Version of the snapshot format.
+const VERSION: u32 = 1;
+/// Lengths read from a snapshot are not trusted, so at most this many
+/// elements are allocated up front.
+const MAX_RESERVED: usize = 1 << 16;
+
+/// Return the error of a malformed snapshot.
+fn malformed(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed snapshot: {}", reason),
+    )
+}
+
+/// Read `N` bytes.
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+
+    Ok(buf)
+}
+
+/// Read a little-endian `u32`.
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    read_array(reader).map(u32::from_le_bytes)
+}
+
+/// Read a length-prefixed byte string into `buf`, and decode it.
+fn read_encoded<R: Read, T: Encode>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+) -> io::Result<T> {
+    let len = read_u32(reader)? as usize;
+    buf.clear();
+    reader.take(len as u64).read_to_end(buf)?;
+    if buf.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    T::decode(buf)
+}
+
+/// Write the encoding of `value`, prefixed by its length, using `buf` as the
+/// scratch space.
+fn write_encoded<W: Write, T: Encode>(
+    writer: &mut W,
+    value: &T,
+    buf: &mut Vec<u8>,
+) -> io::Result<()> {
+    buf.clear();
+    value.encode(buf);
+    let len = u32::try_from(buf.len())
+        .map_err(|_| io::Error::other("encoded entry is too large"))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(buf)
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> HashMap<K, V, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Write a snapshot of the map, i.e., its global depth, directory and
+    /// buckets, to `writer`.
+    ///
+    /// The snapshot can be restored by [`load_from()`](Self::load_from),
+    /// with the same layout and without splitting any bucket.
+    pub fn save_to<W: Write>(&self, writer: W) -> io::Result<()>
+    where
+        K: Encode,
+        V: Encode,
+    {
+        let mut writer = BufWriter::new(writer);
+        let n_buckets = u32::try_from(self.buckets.len())
+            .map_err(|_| io::Error::other("too many buckets"))?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&S::Hash::BITS.to_le_bytes())?;
+        writer.write_all(&(self.global_depth as u32).to_le_bytes())?;
+        writer.write_all(&(self.max_global_depth as u32).to_le_bytes())?;
+        writer.write_all(&(self.bucket_capacity as u64).to_le_bytes())?;
+        writer.write_all(&[match self.bucket_policy {
+            BucketPolicy::Unsorted => 0,
+            BucketPolicy::Sorted => 1,
+        }])?;
+        writer.write_all(&n_buckets.to_le_bytes())?;
+
+        // buckets are numbered by their order in the slab, whose indexes may
+        // have holes
+        let mut numbers = vec![0_u32; self.buckets.capacity()];
+        for (number, (idx, _)) in self.buckets.iter().enumerate() {
+            numbers[idx] = number as u32;
+        }
+        for bucket_idx in self.directories.iter() {
+            writer.write_all(&numbers[bucket_idx.get()].to_le_bytes())?;
+        }
+
+        let mut buf = Vec::new();
+        for (_, bucket) in self.buckets.iter() {
+            let local_depth = bucket.local_depth();
+            let mut packed = vec![0_u8; local_depth.div_ceil(8)];
+            for idx in 0..local_depth {
+                let bit = (bucket.prefix >> (local_depth - idx - 1)) & 1;
+                packed[idx / 8] |= (bit as u8) << (7 - idx % 8);
+            }
+            writer.write_all(&[bucket.depth])?;
+            writer.write_all(&packed)?;
+            writer.write_all(&(bucket.len() as u32).to_le_bytes())?;
+            for (_, key, value) in bucket.iter() {
+                write_encoded(&mut writer, key, &mut buf)?;
+                write_encoded(&mut writer, value, &mut buf)?;
+            }
+        }
+
+        writer.flush()
+    }
+}
+
+impl<K, V, S, M, const B: usize> HashMap<K, V, S, M, B>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Restore a map from a snapshot written by
+    /// [`save_to()`](Self::save_to).
+    ///
+    /// `mapper` and `hash_builder` should be the ones the map is saved with,
+    /// and the hash builder should produce the same hash values, e.g., a
+    /// [`FixedState`](crate::FixedState) with the same seed.
+    ///
+    /// The observer and the [`CoalescePolicy`] are not saved, the restored
+    /// map has none and the default one.
+    ///
+    /// # Error
+    /// An [`InvalidData`](io::ErrorKind::InvalidData) error is returned if
+    /// the snapshot is malformed, or an entry doesn't belong to the bucket it
+    /// is stored in, which is most likely because of another hash builder.
+    pub fn load_from<R: Read>(
+        reader: R,
+        mapper: M,
+        hash_builder: S,
+    ) -> io::Result<Self>
+    where
+        K: Encode + Hash + Eq,
+        V: Encode,
+    {
+        let mut reader = BufReader::new(reader);
+        if &read_array::<_, 8>(&mut reader)? != MAGIC {
+            return Err(malformed("bad magic bytes"));
+        }
+        if read_u32(&mut reader)? != VERSION {
+            return Err(malformed("unsupported version"));
+        }
+        if read_u32(&mut reader)? != S::Hash::BITS {
+            return Err(malformed("hash values are of another width"));
+        }
+        let global_depth = read_u32(&mut reader)? as usize;
+        let max_global_depth = read_u32(&mut reader)? as usize;
+        if global_depth > max_depth::<S::Hash>()
+            || global_depth >= usize::BITS as usize
+        {
+            return Err(malformed("invalid global depth"));
+        }
+        if max_global_depth == 0 || max_global_depth > max_depth::<S::Hash>() {
+            return Err(malformed("invalid max global depth"));
+        }
+        let bucket_capacity =
+            usize::try_from(u64::from_le_bytes(read_array(&mut reader)?))
+                .map_err(|_| malformed("invalid bucket capacity"))?;
+        if bucket_capacity == 0 {
+            return Err(malformed("invalid bucket capacity"));
+        }
+        let bucket_policy = match read_array::<_, 1>(&mut reader)? {
+            [0] => BucketPolicy::Unsorted,
+            [1] => BucketPolicy::Sorted,
+            _ => return Err(malformed("invalid bucket policy")),
+        };
+        let n_buckets = read_u32(&mut reader)? as usize;
+
+        let dir_len = 1_usize << global_depth;
+        let mut directories =
+            allocator_api2::vec::Vec::with_capacity(dir_len.min(MAX_RESERVED));
+        // number of directory entries pointing to every bucket
+        let mut references = vec![0_usize; n_buckets.min(MAX_RESERVED)];
+        for _ in 0..dir_len {
+            let number = read_u32(&mut reader)? as usize;
+            if number >= n_buckets {
+                return Err(malformed("directory entry out of bounds"));
+            }
+            if number >= references.len() {
+                references.resize(number + 1, 0);
+            }
+            references[number] += 1;
+            directories.push(BucketIdx::new(number));
+        }
+
+        let mut buckets = Slab::with_capacity(n_buckets.min(MAX_RESERVED));
+        let arena = Arena::new();
+        let mut len = 0;
+        let mut buf = Vec::new();
+        for number in 0..n_buckets {
+            let local_depth = read_array::<_, 1>(&mut reader)?[0] as usize;
+            if local_depth > global_depth {
+                return Err(malformed("local depth exceeds the global depth"));
+            }
+            let mut packed = vec![0_u8; local_depth.div_ceil(8)];
+            reader.read_exact(&mut packed)?;
+            let prefix = (0..local_depth).fold(0_u64, |acc, idx| {
+                (acc << 1) | ((packed[idx / 8] >> (7 - idx % 8)) & 1) as u64
+            });
+            // a bucket is pointed to by the entries whose first bits are its
+            // prefix, which are `2^(global_depth - local_depth)` entries
+            if references.get(number).copied().unwrap_or(0)
+                != 1 << (global_depth - local_depth)
+            {
+                return Err(malformed("buckets don't cover the directory"));
+            }
+            let first = (prefix as usize) << (global_depth - local_depth);
+            let last = first + (1 << (global_depth - local_depth));
+            if directories[first..last].iter().any(|idx| *idx != number) {
+                return Err(malformed("buckets don't cover the directory"));
+            }
+
+            let mut bucket =
+                Bucket::new_in(prefix, local_depth, 0, arena.clone());
+            let n_entries = read_u32(&mut reader)?;
+            for _ in 0..n_entries {
+                let key = read_encoded::<_, K>(&mut reader, &mut buf)?;
+                let value = read_encoded::<_, V>(&mut reader, &mut buf)?;
+                let hash = hash_builder.hash_key(&key);
+                if !bucket.covers(mapper.map(hash)) {
+                    return Err(malformed("entry in the wrong bucket"));
+                }
+                if bucket.position(hash, &key, bucket_policy).is_some() {
+                    return Err(malformed("duplicate keys"));
+                }
+                bucket.push((hash, key, value), bucket_policy);
+            }
+            len += n_entries as usize;
+            buckets.insert(bucket);
+        }
+
+        Ok(Self {
+            len,
+            global_depth,
+            bucket_capacity,
+            max_global_depth,
+            bucket_policy,
+            coalesce_policy: CoalescePolicy::default(),
+            halving_hysteresis: HALVING_HYSTERESIS,
+            directories: directories.into(),
+            depth_counts: count_depths(&buckets, global_depth),
+            buckets,
+            hash_builder,
+            mapper,
+            observer: None,
+            counters: Counters::default(),
+            arena,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BitOrder, FixedState};
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut map = HashMap::with_mapper_and_hasher(
+            BitOrder::Lsb,
+            FixedState::with_seed(1),
+        );
+        for i in 0..1000_u64 {
+            map.insert(i, i.to_string());
+        }
+        for i in (0..1000).step_by(3) {
+            map.remove(&i);
+        }
+
+        let mut bytes = Vec::new();
+        map.save_to(&mut bytes).unwrap();
+        let loaded = HashMap::<u64, String, _, _>::load_from(
+            bytes.as_slice(),
+            BitOrder::Lsb,
+            FixedState::with_seed(1),
+        )
+        .unwrap();
+        loaded.validate().unwrap();
+        assert_eq!(loaded, map);
+        assert_eq!(loaded.global_depth, map.global_depth);
+        // bucket indexes may differ, but the layout is the same
+        for (a, b) in loaded.directories.iter().zip(map.directories.iter()) {
+            let (a, b) = (&loaded.buckets[a.get()], &map.buckets[b.get()]);
+            assert_eq!((a.prefix, a.depth), (b.prefix, b.depth));
+        }
+
+        // another hash builder
+        let err = HashMap::<u64, String, _, _>::load_from(
+            bytes.as_slice(),
+            BitOrder::Lsb,
+            FixedState::with_seed(2),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // truncated
+        assert!(HashMap::<u64, String, _, _>::load_from(
+            &bytes[..bytes.len() - 1],
+            BitOrder::Lsb,
+            FixedState::with_seed(1),
+        )
+        .is_err());
+    }
+}
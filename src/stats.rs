@@ -0,0 +1,247 @@
+//! Structural statistics of a [`HashMap`].
+
+use crate::{
+    hasher::{DirectoryMapper, KeyHasher},
+    map::HashMap,
+};
+use allocator_api2::alloc::Allocator;
+
+/// Return the load factor of `len` entries in `buckets` buckets, which can
+/// hold `bucket_capacity` entries each, i.e., the mean number of entries of
+/// a bucket divided by the bucket capacity.
+fn load_factor(len: usize, buckets: usize, bucket_capacity: usize) -> f64 {
+    len as f64 / (buckets * bucket_capacity) as f64
+}
+
+/// Structural statistics of a [`HashMap`], returned by
+/// [`HashMap::stats()`], for tuning the bucket capacity and the hasher.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    global_depth: usize,
+    bucket_capacity: usize,
+    buckets: usize,
+    min_local_depth: usize,
+    max_local_depth: usize,
+    mean_local_depth: f64,
+    occupancy: Vec<usize>,
+}
+
+impl Stats {
+    /// Return the global depth.
+    #[inline]
+    pub fn global_depth(&self) -> usize {
+        self.global_depth
+    }
+
+    /// Return the number of entries a bucket can hold.
+    #[inline]
+    pub fn bucket_capacity(&self) -> usize {
+        self.bucket_capacity
+    }
+
+    /// Return the number of buckets.
+    #[inline]
+    pub fn buckets(&self) -> usize {
+        self.buckets
+    }
+
+    /// Return the smallest local depth of the buckets.
+    #[inline]
+    pub fn min_local_depth(&self) -> usize {
+        self.min_local_depth
+    }
+
+    /// Return the largest local depth of the buckets.
+    #[inline]
+    pub fn max_local_depth(&self) -> usize {
+        self.max_local_depth
+    }
+
+    /// Return the mean local depth of the buckets.
+    #[inline]
+    pub fn mean_local_depth(&self) -> f64 {
+        self.mean_local_depth
+    }
+
+    /// Return the bucket occupancy histogram, `occupancy()[n]` is the number
+    /// of buckets holding `n` entries.
+    ///
+    /// Buckets holding more entries than the bucket capacity have overflow
+    /// entries, which share a hash value, see
+    /// [`HashMap::bucket_capacity()`].
+    #[inline]
+    pub fn occupancy(&self) -> &[usize] {
+        &self.occupancy
+    }
+
+    /// Return the mean number of directory entries pointing to a bucket,
+    /// which is 1 if every bucket's local depth equals the global depth.
+    ///
+    /// A large factor means the directory is much larger than the number of
+    /// buckets requires, e.g., because of a bad hasher, whose hash values
+    /// share long prefixes.
+    #[inline]
+    pub fn duplication_factor(&self) -> f64 {
+        (1_usize << self.global_depth) as f64 / self.buckets as f64
+    }
+
+    /// Return the mean number of entries of a bucket divided by the bucket
+    /// capacity, which is what [`HashMap::load_factor()`] returns.
+    pub fn load_factor(&self) -> f64 {
+        let entries = self
+            .occupancy
+            .iter()
+            .enumerate()
+            .map(|(n, buckets)| n * buckets)
+            .sum::<usize>();
+
+        load_factor(entries, self.buckets, self.bucket_capacity)
+    }
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> HashMap<K, V, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Collect the structural statistics of the map, see [`Stats`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::HashMap;
+    ///
+    /// let mut map = HashMap::new();
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let stats = map.stats();
+    /// assert!(stats.max_local_depth() <= stats.global_depth());
+    /// assert!(stats.duplication_factor() >= 1.0);
+    /// assert_eq!(stats.occupancy().iter().sum::<usize>(), stats.buckets());
+    /// ```
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            global_depth: self.global_depth,
+            bucket_capacity: self.bucket_capacity,
+            buckets: self.buckets.len(),
+            min_local_depth: usize::MAX,
+            max_local_depth: 0,
+            mean_local_depth: 0.0,
+            occupancy: vec![0; self.bucket_capacity + 1],
+        };
+        let mut local_depths = 0;
+        for (_, bucket) in self.buckets.iter() {
+            let local_depth = bucket.local_depth();
+            stats.min_local_depth = stats.min_local_depth.min(local_depth);
+            stats.max_local_depth = stats.max_local_depth.max(local_depth);
+            local_depths += local_depth;
+
+            if stats.occupancy.len() <= bucket.len() {
+                stats.occupancy.resize(bucket.len() + 1, 0);
+            }
+            stats.occupancy[bucket.len()] += 1;
+        }
+        stats.mean_local_depth = local_depths as f64 / stats.buckets as f64;
+
+        stats
+    }
+
+    /// Return the mean number of entries of a bucket divided by the bucket
+    /// capacity, like [`Stats::load_factor()`], without collecting the
+    /// statistics.
+    ///
+    /// Unlike [`capacity()`](Self::capacity), every bucket is counted once,
+    /// however many directory entries point to it, see
+    /// [`Stats::duplication_factor()`] for how much larger the directory is.
+    pub fn load_factor(&self) -> f64 {
+        load_factor(self.len, self.buckets.len(), self.bucket_capacity)
+    }
+
+    /// Return the number of entries of every bucket, ordered by the bucket
+    /// prefixes.
+    ///
+    /// Many near-empty buckets mean the entries are unevenly distributed,
+    /// e.g., because of a bad hasher, see [`Stats::occupancy()`] for the
+    /// histogram.
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::HashMap;
+    ///
+    /// let mut map = HashMap::with_bucket_capacity(4);
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let occupancy = map.bucket_occupancy();
+    /// assert_eq!(occupancy.iter().sum::<usize>(), 1000);
+    /// assert!(occupancy.iter().all(|n| *n <= 4));
+    /// ```
+    pub fn bucket_occupancy(&self) -> Vec<usize> {
+        self.bucket_infos().map(|info| info.len()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stats_works() {
+        let map = HashMap::<u64, u64>::with_bucket_capacity(4);
+        let stats = map.stats();
+        assert_eq!(stats.global_depth(), 0);
+        assert_eq!(stats.buckets(), 1);
+        assert_eq!(stats.min_local_depth(), 0);
+        assert_eq!(stats.max_local_depth(), 0);
+        assert_eq!(stats.mean_local_depth(), 0.0);
+        assert_eq!(stats.occupancy(), [1, 0, 0, 0, 0]);
+        assert_eq!(stats.duplication_factor(), 1.0);
+        assert_eq!(stats.load_factor(), 0.0);
+
+        let mut map = map;
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        let stats = map.stats();
+        assert_eq!(stats.global_depth(), map.global_depth);
+        assert_eq!(stats.buckets(), map.buckets.len());
+        assert!(stats.min_local_depth() <= stats.max_local_depth());
+        assert_eq!(stats.max_local_depth(), stats.global_depth());
+        assert_eq!(stats.occupancy().len(), 5);
+        let entries = stats
+            .occupancy()
+            .iter()
+            .enumerate()
+            .map(|(n, buckets)| n * buckets)
+            .sum::<usize>();
+        assert_eq!(entries, 1000);
+        assert_eq!(
+            stats.duplication_factor(),
+            map.directories.len() as f64 / map.buckets.len() as f64
+        );
+    }
+
+    #[test]
+    fn load_factor_and_bucket_occupancy() {
+        let mut map = HashMap::with_bucket_capacity(4);
+        assert_eq!(map.load_factor(), 0.0);
+        assert_eq!(map.bucket_occupancy(), [0]);
+
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.load_factor(), map.stats().load_factor());
+        assert_eq!(
+            map.load_factor(),
+            1000.0 / (map.bucket_count() * map.bucket_capacity()) as f64
+        );
+        let occupancy = map.bucket_occupancy();
+        assert_eq!(occupancy.len(), map.buckets.len());
+        assert_eq!(occupancy.iter().sum::<usize>(), map.len());
+        // ordered by the bucket prefixes
+        let first = map.directories[0].get();
+        assert_eq!(occupancy[0], map.buckets[first].len());
+    }
+}
@@ -0,0 +1,601 @@
+//! Storage backends of [`DiskHashMap`](crate::DiskHashMap).
+//!
+//! Only the disk map runs over a [`BucketStore`], the buckets of
+//! [`HashMap`](crate::HashMap) and the concurrent maps always live in memory.
+
+#[cfg(feature = "mmap")]
+use crate::mmap::Mmap;
+use crate::page::is_valid_page_size;
+#[cfg(feature = "direct-io")]
+use crate::page::MIN_PAGE_SIZE;
+use std::{
+    borrow::Cow,
+    fmt::{Debug, Formatter},
+    fs::{File, OpenOptions},
+    io,
+    path::Path,
+};
+
+/// Alignment of the buffers, offsets and lengths of direct I/O, which pages
+/// always are, as the page size is a multiple of it.
+#[cfg(feature = "direct-io")]
+const DIRECT_IO_ALIGN: usize = MIN_PAGE_SIZE;
+
+/// How a [`FileStore`] writes its pages to the file.
+///
+/// The pages are always synced by [`BucketStore::sync()`], the write mode
+/// decides what happens in between.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WriteMode {
+    /// Write the pages through the page cache of the OS, this is the
+    /// default.
+    #[default]
+    Buffered,
+    /// Sync the data of the file after every page write, so a page is
+    /// durable once it is written.
+    Sync,
+    /// Open the file with `O_DIRECT`, so the pages are read and written
+    /// bypassing the page cache of the OS, which requires the `direct-io`
+    /// feature, and is only available on Linux.
+    ///
+    /// A written page is not durable until the file is synced, as the
+    /// device may cache it.
+    #[cfg(feature = "direct-io")]
+    Direct,
+}
+
+/// Set the flags of opening a file written with `write_mode` to `options`.
+///
+/// # Error
+/// An [`Unsupported`](io::ErrorKind::Unsupported) error is returned if the
+/// platform doesn't support `write_mode`.
+#[cfg_attr(not(feature = "direct-io"), allow(unused_variables))]
+pub(crate) fn set_open_flags(
+    options: &mut OpenOptions,
+    write_mode: WriteMode,
+) -> io::Result<()> {
+    #[cfg(feature = "direct-io")]
+    if write_mode == WriteMode::Direct {
+        #[cfg(target_os = "linux")]
+        std::os::unix::fs::OpenOptionsExt::custom_flags(
+            options,
+            libc::O_DIRECT,
+        );
+        #[cfg(not(target_os = "linux"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "direct I/O is only supported on Linux",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Return the part of `buf` which is `len` bytes long and aligned to
+/// [`DIRECT_IO_ALIGN`], `buf` is resized to hold it.
+#[cfg(feature = "direct-io")]
+fn aligned(buf: &mut Vec<u8>, len: usize) -> &mut [u8] {
+    buf.resize(len + DIRECT_IO_ALIGN, 0);
+    let start = buf.as_ptr().align_offset(DIRECT_IO_ALIGN);
+
+    &mut buf[start..start + len]
+}
+
+/// Read `buf.len()` bytes of `file` at `offset` into `buf`.
+pub(crate) fn read_at(
+    file: &File,
+    offset: u64,
+    buf: &mut [u8],
+) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        let mut read = 0;
+        while read < buf.len() {
+            match std::os::windows::fs::FileExt::seek_read(
+                file,
+                &mut buf[read..],
+                offset + read as u64,
+            )? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write `buf` to `file` at `offset`.
+pub(crate) fn write_at(file: &File, offset: u64, buf: &[u8]) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        let mut written = 0;
+        while written < buf.len() {
+            match std::os::windows::fs::FileExt::seek_write(
+                file,
+                &buf[written..],
+                offset + written as u64,
+            )? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => written += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Return the error of reading a page that doesn't exist.
+fn out_of_bounds() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "page out of bounds")
+}
+
+/// A store of pages, where a [`DiskHashMap`](crate::DiskHashMap) keeps its
+/// header and buckets.
+///
+/// Pages are numbered from 0, and are all [`page_size()`](Self::page_size)
+/// bytes long, which should be a power of two from 4 KiB to 64 KiB. Besides
+/// the stores of this crate, the disk map can run over any store users
+/// provide, e.g., object storage or a block device, see
+/// [`DiskHashMap::with_store()`](crate::DiskHashMap::with_store).
+///
+/// The trait stores pages rather than buckets of any map: the disk map
+/// encodes its buckets as pages, and implements extendible hashing over
+/// them itself, separately from [`HashMap`](crate::HashMap), whose
+/// in-memory buckets can't be kept in a store.
+pub trait BucketStore {
+    /// Return the size of a page in bytes.
+    fn page_size(&self) -> usize;
+
+    /// Return the number of pages, including the allocated ones that are
+    /// not written yet.
+    fn page_count(&self) -> u64;
+
+    /// Return the content of page `page_no`.
+    ///
+    /// # Error
+    /// An error is returned if the page doesn't exist, or is allocated but
+    /// not written yet.
+    fn get_page(&self, page_no: u64) -> io::Result<Cow<'_, [u8]>>;
+
+    /// Write `page`, which is a page long, to page `page_no`. Writing beyond
+    /// the last page grows the store, which happens when a map recovers the
+    /// pages it allocated before a crash.
+    fn write_page(&mut self, page_no: u64, page: &[u8]) -> io::Result<()>;
+
+    /// Allocate a page at the end of the store, and return its number.
+    fn allocate_page(&mut self) -> io::Result<u64>;
+
+    /// Make the written pages durable.
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+/// A store keeping the pages in memory, which are lost when it is dropped.
+pub struct MemoryStore {
+    /// Size of a page in bytes
+    page_size: usize,
+    /// The pages
+    pages: Vec<Box<[u8]>>,
+}
+
+impl Debug for MemoryStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryStore")
+            .field("page size", &self.page_size)
+            .field("pages", &self.pages.len())
+            .finish()
+    }
+}
+
+impl MemoryStore {
+    /// Create an empty store with pages of `page_size` bytes.
+    pub fn new(page_size: usize) -> Self {
+        Self {
+            page_size,
+            pages: Vec::new(),
+        }
+    }
+}
+
+impl BucketStore for MemoryStore {
+    #[inline]
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    #[inline]
+    fn page_count(&self) -> u64 {
+        self.pages.len() as u64
+    }
+
+    fn get_page(&self, page_no: u64) -> io::Result<Cow<'_, [u8]>> {
+        usize::try_from(page_no)
+            .ok()
+            .and_then(|page_no| self.pages.get(page_no))
+            .map(|page| Cow::Borrowed(&page[..]))
+            .ok_or_else(out_of_bounds)
+    }
+
+    fn write_page(&mut self, page_no: u64, page: &[u8]) -> io::Result<()> {
+        let page_no = usize::try_from(page_no).map_err(io::Error::other)?;
+        if page_no >= self.pages.len() {
+            self.pages
+                .resize(page_no + 1, vec![0; self.page_size].into());
+        }
+        self.pages[page_no].copy_from_slice(page);
+
+        Ok(())
+    }
+
+    fn allocate_page(&mut self) -> io::Result<u64> {
+        self.pages.push(vec![0; self.page_size].into());
+
+        Ok(self.pages.len() as u64 - 1)
+    }
+
+    #[inline]
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A store keeping the pages in a file, page `i` is at offset
+/// `i * page_size`.
+///
+/// The file grows when an allocated page is written, so it is always made of
+/// whole pages that are written.
+pub struct FileStore {
+    /// The file
+    file: File,
+    /// Size of a page in bytes
+    page_size: usize,
+    /// Number of pages, including the allocated ones
+    page_count: u64,
+    /// How the pages are written
+    write_mode: WriteMode,
+}
+
+impl Debug for FileStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileStore")
+            .field("page size", &self.page_size)
+            .field("pages", &self.page_count)
+            .field("write mode", &self.write_mode)
+            .finish()
+    }
+}
+
+impl FileStore {
+    /// Open the file at `path`, which is created if it doesn't exist, as a
+    /// store with pages of `page_size` bytes.
+    ///
+    /// # Error
+    /// An [`InvalidInput`](io::ErrorKind::InvalidInput) error is returned if
+    /// `page_size` is not a power of two from 4 KiB to 64 KiB, and an
+    /// [`InvalidData`](io::ErrorKind::InvalidData) error if the file is not
+    /// made of whole pages.
+    pub fn open<P: AsRef<Path>>(path: P, page_size: usize) -> io::Result<Self> {
+        Self::open_with_write_mode(path, page_size, WriteMode::default())
+    }
+
+    /// Open the file at `path`, which is created if it doesn't exist, as a
+    /// store with pages of `page_size` bytes, written with `write_mode`.
+    ///
+    /// # Error
+    /// Besides the errors of [`open()`](Self::open), an
+    /// [`Unsupported`](io::ErrorKind::Unsupported) error is returned if the
+    /// platform doesn't support `write_mode`.
+    pub fn open_with_write_mode<P: AsRef<Path>>(
+        path: P,
+        page_size: usize,
+        write_mode: WriteMode,
+    ) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        set_open_flags(&mut options, write_mode)?;
+
+        Self::with_write_mode(options.open(path)?, page_size, write_mode)
+    }
+
+    /// Use `file`, which is opened with the flags of `write_mode`, as a store
+    /// with pages of `page_size` bytes.
+    pub(crate) fn with_write_mode(
+        file: File,
+        page_size: usize,
+        write_mode: WriteMode,
+    ) -> io::Result<Self> {
+        if !is_valid_page_size(page_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "page size should be a power of two from 4 KiB to 64 KiB",
+            ));
+        }
+        let file_len = file.metadata()?.len();
+        if file_len % page_size as u64 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file is not made of whole pages",
+            ));
+        }
+
+        Ok(Self {
+            file,
+            page_size,
+            page_count: file_len / page_size as u64,
+            write_mode,
+        })
+    }
+
+    /// Return the length of the file in bytes.
+    #[cfg(feature = "mmap")]
+    fn file_len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+}
+
+impl BucketStore for FileStore {
+    #[inline]
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    #[inline]
+    fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    fn get_page(&self, page_no: u64) -> io::Result<Cow<'_, [u8]>> {
+        if page_no >= self.page_count {
+            return Err(out_of_bounds());
+        }
+        let offset = page_no * self.page_size as u64;
+        #[cfg(feature = "direct-io")]
+        if self.write_mode == WriteMode::Direct {
+            let mut buf = Vec::new();
+            let page = aligned(&mut buf, self.page_size);
+            read_at(&self.file, offset, page)?;
+            return Ok(Cow::Owned(page.to_vec()));
+        }
+        let mut buf = vec![0; self.page_size];
+        read_at(&self.file, offset, &mut buf)?;
+
+        Ok(Cow::Owned(buf))
+    }
+
+    fn write_page(&mut self, page_no: u64, page: &[u8]) -> io::Result<()> {
+        let offset = page_no * self.page_size as u64;
+        match self.write_mode {
+            WriteMode::Buffered => write_at(&self.file, offset, page)?,
+            WriteMode::Sync => {
+                write_at(&self.file, offset, page)?;
+                self.file.sync_data()?;
+            }
+            #[cfg(feature = "direct-io")]
+            WriteMode::Direct => {
+                let mut buf = Vec::new();
+                let aligned = aligned(&mut buf, page.len());
+                aligned.copy_from_slice(page);
+                write_at(&self.file, offset, aligned)?;
+            }
+        }
+        self.page_count = self.page_count.max(page_no + 1);
+
+        Ok(())
+    }
+
+    fn allocate_page(&mut self) -> io::Result<u64> {
+        self.page_count += 1;
+
+        Ok(self.page_count - 1)
+    }
+
+    #[inline]
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+/// A store keeping the pages in a file like [`FileStore`], but reading them
+/// through a shared memory map of the file rather than read syscalls, which
+/// suits read-mostly workloads.
+///
+/// Writes still go through the file, and the memory map is refreshed when
+/// the file grows.
+///
+/// A page read from the store is borrowed from the memory map, so unlike
+/// reading a file, other writers of the file can change it while it's
+/// borrowed, see [`open()`](Self::open) for what opening a store requires.
+#[cfg(feature = "mmap")]
+pub struct MmapStore {
+    /// The file
+    store: FileStore,
+    /// Memory map of the file, `None` if the file is empty
+    mmap: Option<Mmap>,
+}
+
+#[cfg(feature = "mmap")]
+impl Debug for MmapStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MmapStore")
+            .field("page size", &self.store.page_size)
+            .field("pages", &self.store.page_count)
+            .finish()
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl MmapStore {
+    /// Open the file at `path`, which is created if it doesn't exist, as a
+    /// store with pages of `page_size` bytes.
+    ///
+    /// # Safety
+    /// While the store is open, the file should not be modified other than
+    /// through the store, e.g., by another process, or another store of the
+    /// same file, and should not be truncated. Otherwise a page borrowed
+    /// from the store may change while it's read, which is undefined
+    /// behavior, or reading it may kill the process with `SIGBUS`.
+    ///
+    /// # Error
+    /// See [`FileStore::open()`].
+    pub unsafe fn open<P: AsRef<Path>>(
+        path: P,
+        page_size: usize,
+    ) -> io::Result<Self> {
+        // SAFETY: guaranteed by the caller.
+        FileStore::open(path, page_size)
+            .and_then(|store| unsafe { Self::from_store(store) })
+    }
+
+    /// Use `file` as a store with pages of `page_size` bytes.
+    ///
+    /// # Safety
+    /// See [`open()`](Self::open).
+    pub(crate) unsafe fn new(file: File, page_size: usize) -> io::Result<Self> {
+        // SAFETY: guaranteed by the caller.
+        FileStore::with_write_mode(file, page_size, WriteMode::Buffered)
+            .and_then(|store| unsafe { Self::from_store(store) })
+    }
+
+    /// Map the file of `store`.
+    ///
+    /// # Safety
+    /// See [`open()`](Self::open).
+    unsafe fn from_store(store: FileStore) -> io::Result<Self> {
+        let mut store = Self { store, mmap: None };
+        store.remap()?;
+
+        Ok(store)
+    }
+
+    /// Map the whole file into memory, replacing the old memory map.
+    fn remap(&mut self) -> io::Result<()> {
+        let len = usize::try_from(self.store.file_len()?)
+            .map_err(io::Error::other)?;
+        self.mmap = (len > 0)
+            // SAFETY: the file is only modified through this store, which
+            // writes no page while a page is borrowed, and is not truncated,
+            // as promised by the creator of the store.
+            .then(|| unsafe { Mmap::new(&self.store.file, len) })
+            .transpose()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl BucketStore for MmapStore {
+    #[inline]
+    fn page_size(&self) -> usize {
+        self.store.page_size
+    }
+
+    #[inline]
+    fn page_count(&self) -> u64 {
+        self.store.page_count
+    }
+
+    fn get_page(&self, page_no: u64) -> io::Result<Cow<'_, [u8]>> {
+        let page_size = self.store.page_size;
+        let page = usize::try_from(page_no).ok().and_then(|page_no| {
+            let offset = page_no.checked_mul(page_size)?;
+            self.mmap
+                .as_ref()?
+                .as_slice()
+                .get(offset..offset + page_size)
+        });
+        match page {
+            Some(page) => Ok(Cow::Borrowed(page)),
+            // the page is written after the file is mapped
+            None => self.store.get_page(page_no),
+        }
+    }
+
+    fn write_page(&mut self, page_no: u64, page: &[u8]) -> io::Result<()> {
+        self.store.write_page(page_no, page)?;
+        let mapped = self.mmap.as_ref().map_or(0, |mmap| mmap.as_slice().len());
+        if (page_no as usize + 1) * self.store.page_size > mapped {
+            self.remap()?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn allocate_page(&mut self) -> io::Result<u64> {
+        self.store.allocate_page()
+    }
+
+    #[inline]
+    fn sync(&mut self) -> io::Result<()> {
+        self.store.sync()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Write, read and allocate pages of `store`, which is empty.
+    fn check_store<T: BucketStore>(mut store: T) {
+        let page_size = store.page_size();
+        assert_eq!(store.page_count(), 0);
+        assert!(store.get_page(0).is_err());
+
+        assert_eq!(store.allocate_page().unwrap(), 0);
+        assert_eq!(store.allocate_page().unwrap(), 1);
+        store.write_page(0, &vec![1; page_size]).unwrap();
+        store.write_page(1, &vec![2; page_size]).unwrap();
+        // recovery writes pages beyond the end
+        store.write_page(3, &vec![4; page_size]).unwrap();
+        store.sync().unwrap();
+        assert_eq!(store.page_count(), 4);
+        assert_eq!(store.allocate_page().unwrap(), 4);
+        for (page_no, byte) in [(0, 1), (1, 2), (3, 4)] {
+            assert_eq!(
+                *store.get_page(page_no).unwrap(),
+                vec![byte; page_size]
+            );
+        }
+        store.write_page(1, &vec![5; page_size]).unwrap();
+        assert_eq!(*store.get_page(1).unwrap(), vec![5; page_size]);
+    }
+
+    #[test]
+    fn stores_work() {
+        check_store(MemoryStore::new(4096));
+
+        let path = std::env::temp_dir().join(format!(
+            "extendable_hashmap-{}-stores_work",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        check_store(FileStore::open(&path, 4096).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        check_store(
+            FileStore::open_with_write_mode(&path, 4096, WriteMode::Sync)
+                .unwrap(),
+        );
+        std::fs::remove_file(&path).unwrap();
+        #[cfg(all(feature = "direct-io", target_os = "linux"))]
+        {
+            check_store(
+                FileStore::open_with_write_mode(&path, 4096, WriteMode::Direct)
+                    .unwrap(),
+            );
+            std::fs::remove_file(&path).unwrap();
+        }
+        #[cfg(feature = "mmap")]
+        {
+            // SAFETY: the file is only modified through the store.
+            check_store(unsafe { MmapStore::open(&path, 4096) }.unwrap());
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
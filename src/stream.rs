@@ -0,0 +1,189 @@
+//! Streaming export and import of the entries of a [`HashMap`] or a
+//! [`DiskHashMap`](crate::DiskHashMap), for piping a map into another
+//! process, or converting between the in-memory and disk-backed maps.
+//!
+//! A stream is a sequence of records, one per entry, ending at the end of
+//! the stream:
+//!
+//! | field        | size |
+//! |--------------|------|
+//! | key length   | 4    |
+//! | key          |      |
+//! | value length | 4    |
+//! | value        |      |
+//!
+//! where the lengths are in little endian, and the key and value are
+//! encoded by [`Encode`], or the [`Codec`](crate::codec::Codec) of a
+//! [`DiskHashMap`](crate::DiskHashMap), which is the same record as
+//! [`Codec::encode_entry()`](crate::codec::Codec::encode_entry) appends. The
+//! records are written and read one at a time, the map is never
+//! materialized as bytes.
+
+use crate::{
+    codec::Encode,
+    hasher::{DirectoryMapper, KeyHasher},
+    map::HashMap,
+};
+use allocator_api2::alloc::Allocator;
+use std::{
+    hash::Hash,
+    io::{self, Read, Write},
+};
+
+/// Write a record of the encoded `key` and `value` to `writer`.
+pub(crate) fn write_record<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    value: &[u8],
+) -> io::Result<()> {
+    for bytes in [key, value] {
+        let len = u32::try_from(bytes.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "entry is too large")
+        })?;
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(bytes)?;
+    }
+
+    Ok(())
+}
+
+/// Read a record from `reader` into `key` and `value`, return false if the
+/// stream ends before it.
+///
+/// # Error
+/// An [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error is returned if
+/// the stream ends in the middle of a record.
+pub(crate) fn read_record<R: Read>(
+    reader: &mut R,
+    key: &mut Vec<u8>,
+    value: &mut Vec<u8>,
+) -> io::Result<bool> {
+    let mut len = [0; 4];
+    // the stream may only end at the start of a record
+    let mut read = 0;
+    while read < len.len() {
+        match reader.read(&mut len[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    read_bytes(reader, u32::from_le_bytes(len), key)?;
+    reader.read_exact(&mut len)?;
+    read_bytes(reader, u32::from_le_bytes(len), value)?;
+
+    Ok(true)
+}
+
+/// Read `len` bytes from `reader` into `buf`.
+fn read_bytes<R: Read>(
+    reader: &mut R,
+    len: u32,
+    buf: &mut Vec<u8>,
+) -> io::Result<()> {
+    buf.clear();
+    // `len` is not trusted, the bytes are not allocated up front
+    let read = reader.take(len as u64).read_to_end(buf)?;
+    if read != len as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> HashMap<K, V, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Write the entries of the map to `writer` as a stream of records, see
+    /// the [module documentation](crate::stream), return the number of the
+    /// written entries.
+    ///
+    /// The records are written one at a time, so `writer` should be
+    /// buffered, e.g., by a [`BufWriter`](std::io::BufWriter).
+    pub fn export_entries<W: Write>(&self, mut writer: W) -> io::Result<usize>
+    where
+        K: Encode,
+        V: Encode,
+    {
+        let mut key = Vec::new();
+        let mut value = Vec::new();
+        let mut entries = 0;
+        for (_, k, v) in
+            self.buckets.iter().flat_map(|(_, bucket)| bucket.iter())
+        {
+            key.clear();
+            value.clear();
+            k.encode(&mut key);
+            v.encode(&mut value);
+            write_record(&mut writer, &key, &value)?;
+            entries += 1;
+        }
+        writer.flush()?;
+
+        Ok(entries)
+    }
+
+    /// Insert the entries read from `reader`, a stream of records written by
+    /// [`export_entries()`](Self::export_entries) or
+    /// [`DiskHashMap::export_entries()`](crate::DiskHashMap::export_entries),
+    /// until it ends, return the number of the read entries.
+    ///
+    /// An entry whose key is present updates its value. The records are read
+    /// one at a time, so `reader` should be buffered, e.g., by a
+    /// [`BufReader`](std::io::BufReader).
+    ///
+    /// # Error
+    /// An [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error is returned
+    /// if the stream ends in the middle of a record, and the errors of
+    /// [`Encode::decode()`] are returned as they are. The entries read
+    /// before the error stay in the map.
+    pub fn import_entries<R: Read>(
+        &mut self,
+        mut reader: R,
+    ) -> io::Result<usize>
+    where
+        K: Encode + Hash + Eq,
+        V: Encode,
+    {
+        let mut key = Vec::new();
+        let mut value = Vec::new();
+        let mut entries = 0;
+        while read_record(&mut reader, &mut key, &mut value)? {
+            self.insert(K::decode(&key)?, V::decode(&value)?);
+            entries += 1;
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip() {
+        let mut map = HashMap::new();
+        for i in 0..1000_u64 {
+            map.insert(i, i.to_string());
+        }
+        let mut bytes = Vec::new();
+        assert_eq!(map.export_entries(&mut bytes).unwrap(), 1000);
+
+        let mut imported = HashMap::new();
+        assert_eq!(imported.import_entries(bytes.as_slice()).unwrap(), 1000);
+        assert_eq!(imported, map);
+
+        // a stream ending in the middle of a record
+        let mut imported = HashMap::<u64, String>::new();
+        let err = imported
+            .import_entries(&bytes[..bytes.len() - 1])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(imported.len(), 999);
+    }
+}
@@ -0,0 +1,1141 @@
+use crate::{
+    bucket::{
+        Bucket, BucketPolicy,
+        BucketValue::{EqualTo, Range},
+        BUCKET_CAP,
+    },
+    guard::{Ref, RefMut, SyncEntry, SyncVacantEntry},
+    hasher::{DefaultHashBuilder, HashValue, KeyHasher},
+    primitives::{
+        ArcMutexGuard, ArcSwap, AtomicBool, AtomicUsize, Mutex, RawMutex,
+        RwLock, RwLockWriteGuard,
+    },
+};
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Formatter},
+    hash::Hash,
+    sync::{atomic::Ordering, Arc},
+};
+
+/// The write lock of a [`BucketCell`], held by value.
+pub(crate) type WriteLock = ArcMutexGuard<RawMutex, ()>;
+
+/// How the buckets of a [`SyncHashMap`] share their write locks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockStriping {
+    /// Every bucket has a lock of its own, this is the default.
+    #[default]
+    PerBucket,
+    /// The buckets share the given number of locks, which should be a power
+    /// of 2.
+    ///
+    /// A bucket uses the lock picked by the first bits of its bucket bits, so
+    /// neighboring buckets share a lock, and every lock covers more buckets
+    /// as the map grows. This saves memory, while writers to buckets sharing
+    /// a lock block each other.
+    Stripes(usize),
+}
+
+/// A bucket of a [`SyncHashMap`].
+///
+/// Readers load the latest snapshot of the bucket without locking, writers
+/// lock the cell, modify a copy of the snapshot, and then publish the copy.
+pub(crate) struct BucketCell<K, V, H, const B: usize = BUCKET_CAP> {
+    /// Latest snapshot of the bucket
+    snapshot: ArcSwap<Bucket<K, V, H, B>>,
+    /// True if the bucket is retired, i.e., it has been split or merged and
+    /// is no longer in the directory, only set while the write lock is held.
+    retired: AtomicBool,
+    /// True if the bucket is in the queue of the under-full buckets
+    queued: AtomicBool,
+    /// Write lock, which may be shared with other buckets
+    lock: Arc<Mutex<()>>,
+}
+
+impl<K, V, H, const B: usize> BucketCell<K, V, H, B> {
+    fn new(bucket: Bucket<K, V, H, B>, lock: Arc<Mutex<()>>) -> Self {
+        Self {
+            snapshot: ArcSwap::from_pointee(bucket),
+            retired: AtomicBool::new(false),
+            queued: AtomicBool::new(false),
+            lock,
+        }
+    }
+
+    /// Return the latest snapshot of the bucket.
+    pub(crate) fn snapshot(&self) -> Arc<Bucket<K, V, H, B>> {
+        self.snapshot.load_full()
+    }
+
+    /// Replace the snapshot with `bucket`, the write lock should be held.
+    pub(crate) fn publish(&self, bucket: Arc<Bucket<K, V, H, B>>) {
+        debug_assert!(self.lock.is_locked());
+        self.snapshot.store(bucket);
+    }
+}
+
+/// A directory entry of a [`SyncHashMap`], the atomic pointer to a bucket.
+type BucketHandle<K, V, H> = ArcSwap<BucketCell<K, V, H>>;
+
+/// The directory of a [`SyncHashMap`].
+///
+/// A directory is never resized, doubling the directory builds a new one,
+/// which is swapped in atomically, and the old one is freed once the readers
+/// still holding it are done, like RCU. Readers load the latest directory
+/// through a single atomic pointer, and never lock it.
+///
+/// The entries are atomic pointers, which are updated in place when a bucket
+/// gets split, so a split doesn't copy the whole directory.
+struct Directory<K, V, H> {
+    /// Global depth
+    global_depth: usize,
+    /// Directory entries, `entries[i]` is the bucket that entry `i` points
+    /// to, several entries can point to the same bucket.
+    entries: Box<[BucketHandle<K, V, H>]>,
+}
+
+impl<K, V, H: HashValue> Directory<K, V, H> {
+    /// Return the entry pointing to the bucket where a key whose hash value
+    /// is `hash` will go.
+    fn locate(&self, hash: H) -> &BucketHandle<K, V, H> {
+        &self.entries[hash.prefix(self.global_depth as u32)]
+    }
+
+    /// Return a copy of this directory, with the directory doubled and the
+    /// global depth incremented.
+    ///
+    /// With the global depth incremented, entry `idx` is covered by the new
+    /// entries `2 * idx` and `2 * idx + 1`, both of them point to the bucket
+    /// entry `idx` points to.
+    fn double(&self) -> Self {
+        Self {
+            global_depth: self.global_depth + 1,
+            entries: self
+                .entries
+                .iter()
+                .flat_map(|entry| {
+                    let cell = entry.load_full();
+                    [ArcSwap::new(Arc::clone(&cell)), ArcSwap::new(cell)]
+                })
+                .collect(),
+        }
+    }
+
+    /// Point the entries covering the bucket of `cell` to `cell`.
+    fn point_to(&self, cell: Arc<BucketCell<K, V, H>>) {
+        let value = cell.snapshot.load().value(self.global_depth);
+        match value {
+            EqualTo(idx) => self.entries[idx].store(cell),
+            Range(range) => {
+                for idx in range {
+                    self.entries[idx].store(Arc::clone(&cell));
+                }
+            }
+        }
+    }
+}
+
+/// The pointer to the latest directory of a [`SyncHashMap`].
+///
+/// A directory replaced by a doubled one may still be read by the threads
+/// that loaded it before, it is freed once they are done. By default, this
+/// is tracked by the reference count of [`ArcSwap`], with the `epoch`
+/// feature, the replaced directory is handed to the epoch-based garbage
+/// collector of `crossbeam-epoch`, so readers never touch a reference count.
+struct DirectoryPtr<K, V, H> {
+    #[cfg(not(feature = "epoch"))]
+    ptr: ArcSwap<Directory<K, V, H>>,
+    #[cfg(feature = "epoch")]
+    ptr: crossbeam_epoch::Atomic<Directory<K, V, H>>,
+}
+
+#[cfg(not(feature = "epoch"))]
+impl<K, V, H> DirectoryPtr<K, V, H> {
+    fn new(directory: Directory<K, V, H>) -> Self {
+        Self {
+            ptr: ArcSwap::from_pointee(directory),
+        }
+    }
+
+    /// Call `f` with the latest directory, return what `f` returns.
+    fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Directory<K, V, H>) -> R,
+    {
+        f(&self.ptr.load())
+    }
+
+    /// Publish `directory`, the doubling lock should be held exclusively.
+    fn replace(&self, directory: Directory<K, V, H>) {
+        self.ptr.store(Arc::new(directory));
+    }
+}
+
+#[cfg(feature = "epoch")]
+impl<K, V, H> DirectoryPtr<K, V, H> {
+    fn new(directory: Directory<K, V, H>) -> Self {
+        Self {
+            ptr: crossbeam_epoch::Atomic::new(directory),
+        }
+    }
+
+    /// Call `f` with the latest directory, return what `f` returns.
+    fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Directory<K, V, H>) -> R,
+    {
+        let guard = crossbeam_epoch::pin();
+        let directory = self.ptr.load(Ordering::Acquire, &guard);
+        // SAFETY: the pointer is never null, and a replaced directory is
+        // destroyed after every thread pinned before its replacement unpins.
+        f(unsafe { directory.deref() })
+    }
+
+    /// Publish `directory`, the doubling lock should be held exclusively.
+    fn replace(&self, directory: Directory<K, V, H>) {
+        let guard = crossbeam_epoch::pin();
+        let old = self.ptr.swap(
+            crossbeam_epoch::Owned::new(directory),
+            Ordering::AcqRel,
+            &guard,
+        );
+        // SAFETY: `old` is no longer reachable from the map, threads still
+        // reading it are pinned.
+        unsafe { guard.defer_destroy(old) };
+    }
+}
+
+#[cfg(feature = "epoch")]
+impl<K, V, H> Drop for DirectoryPtr<K, V, H> {
+    fn drop(&mut self) {
+        // SAFETY: no thread can be reading the map while it is dropped.
+        unsafe {
+            let guard = crossbeam_epoch::unprotected();
+            drop(self.ptr.load(Ordering::Relaxed, guard).into_owned());
+        }
+    }
+}
+
+/// Return the lock for `bucket`, which is a new one if `stripes` is empty,
+/// otherwise the stripe picked by the first bits of its prefix, padded with
+/// 0s.
+fn stripe_lock<K, V, H, const B: usize>(
+    stripes: &[Arc<Mutex<()>>],
+    bucket: &Bucket<K, V, H, B>,
+) -> Arc<Mutex<()>> {
+    if stripes.is_empty() {
+        return Arc::new(Mutex::new(()));
+    }
+
+    let stripe_bits = stripes.len().trailing_zeros() as usize;
+    let local_depth = bucket.local_depth();
+    let idx = if local_depth >= stripe_bits {
+        bucket.prefix >> (local_depth - stripe_bits)
+    } else {
+        bucket.prefix << (stripe_bits - local_depth)
+    } as usize;
+
+    Arc::clone(&stripes[idx])
+}
+
+/// A concurrent extendible hash map, whose reads never block.
+///
+/// The directory and the buckets are published through atomic pointers,
+/// lookups load them without locking. Writers lock the bucket they modify,
+/// modify a copy of it, and then publish the copy, so writes to different
+/// buckets can run in parallel. A split only locks the bucket it splits, and
+/// updates the directory entries pointing to it, so splits of different
+/// buckets can run in parallel too, unless the directory gets doubled. The
+/// doubled directory is a copy, which is swapped in as a whole.
+///
+/// As every write copies a bucket, writes require the keys and values to be
+/// [`Clone`].
+///
+/// # Deadlock
+/// Writing to the map while holding a [`RefMut`] returned by it may deadlock,
+/// e.g., inserting into the bucket that the [`RefMut`] holds.
+///
+/// # Coalescence
+/// Removals never merge buckets, as that would lock the sibling buckets as
+/// well. A bucket left at most half full is queued instead, and merged with
+/// its sibling bucket by [`compact()`](Self::compact), which can be called
+/// from time to time, e.g., by a background thread.
+pub struct SyncHashMap<K, V, S: KeyHasher = DefaultHashBuilder> {
+    /// Number of entries
+    len: AtomicUsize,
+    /// Number of entries a bucket can hold
+    bucket_capacity: usize,
+    /// Latest directory
+    directory: DirectoryPtr<K, V, S::Hash>,
+    /// Held shared while a split or a merge updates the directory entries,
+    /// and held exclusively while the directory is doubled, so the entries
+    /// are never updated in a directory which is being copied.
+    doubling_lock: RwLock<()>,
+    /// Locks shared by the buckets, empty if every bucket has its own lock
+    stripes: Box<[Arc<Mutex<()>>]>,
+    /// Hash values of the keys removed from the under-full buckets, each of
+    /// them locates a bucket to be merged.
+    underfull: Mutex<Vec<S::Hash>>,
+    /// Held while compacting, so the compactions don't lock the same
+    /// buckets in different orders.
+    compaction_lock: Mutex<()>,
+    /// Hash builder
+    hash_builder: S,
+}
+
+impl<K, V, S: KeyHasher> Debug for SyncHashMap<K, V, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncHashMap")
+            .field("len", &self.len())
+            .field("global depth", &self.directory.with(|d| d.global_depth))
+            .finish()
+    }
+}
+
+impl<K, V, S: KeyHasher + Default> Default for SyncHashMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V> SyncHashMap<K, V, DefaultHashBuilder> {
+    /// Create an empty `SyncHashMap`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty `SyncHashMap` whose buckets share their locks as
+    /// `lock_striping` specifies.
+    ///
+    /// # Panic
+    /// The number of stripes should be a power of 2.
+    pub fn with_lock_striping(lock_striping: LockStriping) -> Self {
+        Self::with_lock_striping_and_hasher(
+            lock_striping,
+            DefaultHashBuilder::default(),
+        )
+    }
+}
+
+impl<K, V, S: KeyHasher> SyncHashMap<K, V, S> {
+    /// Create an empty `SyncHashMap` which will use the given hash builder to
+    /// hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_bucket_capacity_and_hasher(BUCKET_CAP, hash_builder)
+    }
+
+    /// Create an empty `SyncHashMap` whose buckets can hold `bucket_capacity`
+    /// entries, `hash_builder` will be used to hash keys.
+    ///
+    /// # Panic
+    /// `bucket_capacity` should be greater than 0.
+    pub fn with_bucket_capacity_and_hasher(
+        bucket_capacity: usize,
+        hash_builder: S,
+    ) -> Self {
+        Self::with_options(
+            bucket_capacity,
+            LockStriping::default(),
+            hash_builder,
+        )
+    }
+
+    /// Create an empty `SyncHashMap` whose buckets share their locks as
+    /// `lock_striping` specifies, `hash_builder` will be used to hash keys.
+    ///
+    /// # Panic
+    /// The number of stripes should be a power of 2.
+    pub fn with_lock_striping_and_hasher(
+        lock_striping: LockStriping,
+        hash_builder: S,
+    ) -> Self {
+        Self::with_options(BUCKET_CAP, lock_striping, hash_builder)
+    }
+
+    /// Create an empty `SyncHashMap` with the given options.
+    fn with_options(
+        bucket_capacity: usize,
+        lock_striping: LockStriping,
+        hash_builder: S,
+    ) -> Self {
+        assert!(bucket_capacity > 0);
+        let stripes = match lock_striping {
+            LockStriping::PerBucket => Box::default(),
+            LockStriping::Stripes(amount) => {
+                assert!(amount.is_power_of_two());
+                (0..amount).map(|_| Arc::new(Mutex::new(()))).collect()
+            }
+        };
+
+        let entries = [0, 1]
+            .map(|bit| {
+                let bucket = Bucket::new(bit, 1, bucket_capacity);
+                let lock = stripe_lock(&stripes, &bucket);
+                ArcSwap::from_pointee(BucketCell::new(bucket, lock))
+            })
+            .into();
+
+        Self {
+            len: AtomicUsize::new(0),
+            bucket_capacity,
+            directory: DirectoryPtr::new(Directory {
+                global_depth: 1,
+                entries,
+            }),
+            doubling_lock: RwLock::new(()),
+            stripes,
+            underfull: Mutex::new(Vec::new()),
+            compaction_lock: Mutex::new(()),
+            hash_builder,
+        }
+    }
+
+    /// Return how the buckets share their locks.
+    pub fn lock_striping(&self) -> LockStriping {
+        if self.stripes.is_empty() {
+            LockStriping::PerBucket
+        } else {
+            LockStriping::Stripes(self.stripes.len())
+        }
+    }
+
+    /// Wrap `bucket` in a cell, with the lock picked for it.
+    fn new_cell(
+        &self,
+        bucket: Bucket<K, V, S::Hash>,
+    ) -> BucketCell<K, V, S::Hash> {
+        let lock = stripe_lock(&self.stripes, &bucket);
+
+        BucketCell::new(bucket, lock)
+    }
+
+    /// Return a reference to the map's hash builder.
+    #[inline]
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Return the number of elements in the map.
+    ///
+    /// The value is exact once the modifications made by other threads have
+    /// completed, otherwise it can be outdated.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Return the number of elements in the map without synchronizing with
+    /// other threads, which is cheap enough for monitoring, but the value may
+    /// lag behind [`len()`](Self::len).
+    #[inline]
+    pub fn approx_len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Return true if the map contains no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lock the bucket where a key whose hash value is `hash` will go, return
+    /// the bucket and its lock.
+    fn lock_bucket(
+        &self,
+        hash: S::Hash,
+    ) -> (Arc<BucketCell<K, V, S::Hash>>, WriteLock) {
+        loop {
+            let cell = self
+                .directory
+                .with(|directory| directory.locate(hash).load_full());
+            let lock = cell.lock.lock_arc();
+            // A retired bucket has been replaced in the latest directory
+            // before it gets retired.
+            if !cell.retired.load(Ordering::Acquire) {
+                return (cell, lock);
+            }
+        }
+    }
+}
+
+impl<K: Clone, V: Clone, S: KeyHasher> SyncHashMap<K, V, S> {
+    /// Split the bucket where a key whose hash value is `hash` will go.
+    ///
+    /// The bucket is left untouched if it is no longer full, as other threads
+    /// can remove entries or split it before it gets locked.
+    fn split(&self, hash: S::Hash) {
+        let (cell, _lock) = self.lock_bucket(hash);
+        let bucket = cell.snapshot();
+        if !bucket.is_full(self.bucket_capacity) || !bucket.splittable(hash) {
+            return;
+        }
+
+        // The global depth never decreases, once it is greater than the
+        // local depth, no doubling is needed.
+        let old_local_depth = bucket.local_depth();
+        let _doubling_lock =
+            if old_local_depth == self.directory.with(|d| d.global_depth) {
+                let doubling_lock = self.doubling_lock.write();
+                // another split may have doubled the directory
+                if old_local_depth == self.directory.with(|d| d.global_depth) {
+                    let doubled = self.directory.with(Directory::double);
+                    self.directory.replace(doubled);
+                }
+                RwLockWriteGuard::downgrade(doubling_lock)
+            } else {
+                self.doubling_lock.read()
+            };
+
+        let low_prefix = bucket.prefix << 1;
+        let mut low =
+            Bucket::new(low_prefix, old_local_depth + 1, self.bucket_capacity);
+        let mut high = Bucket::new(
+            low_prefix | 1,
+            old_local_depth + 1,
+            self.bucket_capacity,
+        );
+
+        // redistribute the entries by the bit after the old bits
+        for (h, k, v) in bucket.iter() {
+            let entry = (*h, k.clone(), v.clone());
+            if h.bit(old_local_depth as u32) == 1 {
+                high.push(entry, BucketPolicy::Unsorted);
+            } else {
+                low.push(entry, BucketPolicy::Unsorted);
+            }
+        }
+
+        // Lock the new buckets until all the entries point to them, or they
+        // could be split by other threads in the meantime, and the entries
+        // pointing to their halves would get overwritten. The locks shared
+        // with the old bucket are held already.
+        let low = Arc::new(self.new_cell(low));
+        let high = Arc::new(self.new_cell(high));
+        let _low_lock =
+            (!Arc::ptr_eq(&low.lock, &cell.lock)).then(|| low.lock.lock_arc());
+        let _high_lock = (!Arc::ptr_eq(&high.lock, &cell.lock)
+            && !Arc::ptr_eq(&high.lock, &low.lock))
+        .then(|| high.lock.lock_arc());
+        self.directory.with(|directory| {
+            directory.point_to(low);
+            directory.point_to(high);
+        });
+        cell.retired.store(true, Ordering::Release);
+    }
+
+    /// Merge the under-full buckets queued by the removals with their sibling
+    /// buckets, return the number of merges.
+    ///
+    /// A merged bucket is merged again if it is still under-full. The
+    /// directory is never halved.
+    ///
+    /// Writers of the two buckets being merged block, while readers don't.
+    pub fn compact(&self) -> usize {
+        let _compaction_lock = self.compaction_lock.lock();
+        let queue = std::mem::take(&mut *self.underfull.lock());
+
+        let mut merges = 0;
+        for hash in queue {
+            while self.coalesce(hash) {
+                merges += 1;
+            }
+        }
+
+        merges
+    }
+
+    /// Try to merge the bucket where a key whose hash value is `hash` will go
+    /// and its sibling bucket, return true if they are merged.
+    ///
+    /// As in [`HashMap`](crate::HashMap), two buckets can be merged if they
+    /// have the same local depth and their entries fit into one bucket, the
+    /// local depth should be greater than 1, as this map starts with 2
+    /// buckets.
+    fn coalesce(&self, hash: S::Hash) -> bool {
+        let (cell, _lock) = self.lock_bucket(hash);
+        cell.queued.store(false, Ordering::Relaxed);
+        let bucket = cell.snapshot();
+        let local_depth = bucket.local_depth();
+        if local_depth < 2 {
+            return false;
+        }
+
+        let sibling_prefix = (bucket.prefix ^ 1) as usize;
+        let (sibling, _sibling_lock) = loop {
+            let sibling = self.directory.with(|directory| {
+                let shift = directory.global_depth - local_depth;
+                directory.entries[sibling_prefix << shift].load_full()
+            });
+            // the lock may be shared with the bucket
+            let lock = (!Arc::ptr_eq(&sibling.lock, &cell.lock))
+                .then(|| sibling.lock.lock_arc());
+            if !sibling.retired.load(Ordering::Acquire) {
+                break (sibling, lock);
+            }
+        };
+        let sibling_bucket = sibling.snapshot();
+        if sibling_bucket.local_depth() != local_depth
+            || bucket.len() + sibling_bucket.len() >= self.bucket_capacity
+        {
+            return false;
+        }
+
+        // Lock the buckets before the directory, in the order splits do.
+        let _doubling_lock = self.doubling_lock.read();
+        let mut merged = Bucket::new(
+            bucket.prefix >> 1,
+            local_depth - 1,
+            self.bucket_capacity,
+        );
+        for (h, k, v) in bucket.iter().chain(sibling_bucket.iter()) {
+            merged.push((*h, k.clone(), v.clone()), BucketPolicy::Unsorted);
+        }
+
+        // As with a split, the merged bucket stays locked until all the
+        // entries point to it.
+        let merged = Arc::new(self.new_cell(merged));
+        let _merged_lock = (!Arc::ptr_eq(&merged.lock, &cell.lock)
+            && !Arc::ptr_eq(&merged.lock, &sibling.lock))
+        .then(|| merged.lock.lock_arc());
+        self.directory.with(|directory| directory.point_to(merged));
+        cell.retired.store(true, Ordering::Release);
+        sibling.retired.store(true, Ordering::Release);
+
+        true
+    }
+}
+
+impl<K: Hash + Eq, V, S: KeyHasher> SyncHashMap<K, V, S> {
+    /// Return a reference to the entry corresponding to the key.
+    ///
+    /// This never blocks, the returned [`Ref`] holds a snapshot of the
+    /// entry's bucket, which won't see the later updates.
+    pub fn get<Q>(&self, key: &Q) -> Option<Ref<K, V, S::Hash>>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let bucket = self.directory.with(|directory| {
+            directory.locate(hash).load().snapshot.load_full()
+        });
+        let idx = bucket.position(hash, key, BucketPolicy::Unsorted)?;
+
+        Some(Ref::new(bucket, idx))
+    }
+
+    /// Return true if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: KeyHasher> SyncHashMap<K, V, S> {
+    /// Insert a key-value pair into the map.
+    ///
+    /// If the map did not have this key present, `None` is returned.
+    ///
+    /// If the map did have this key present, the value is updated, and the
+    /// old value is returned. The key is not updated, though.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let hash = self.hash_builder.hash_key(&key);
+        loop {
+            let (cell, lock) = self.lock_bucket(hash);
+            let mut bucket = Bucket::clone(&cell.snapshot.load());
+            if let Some(idx) =
+                bucket.position(hash, &key, BucketPolicy::Unsorted)
+            {
+                let old = std::mem::replace(&mut bucket.values[idx], value);
+                cell.publish(Arc::new(bucket));
+                return Some(old);
+            }
+
+            if !bucket.is_full(self.bucket_capacity) || !bucket.splittable(hash)
+            {
+                bucket.push((hash, key, value), BucketPolicy::Unsorted);
+                cell.publish(Arc::new(bucket));
+                self.len.fetch_add(1, Ordering::Release);
+                return None;
+            }
+
+            // Splitting locks the bucket again, release it first.
+            drop(lock);
+            self.split(hash);
+        }
+    }
+
+    /// Return a guard mutably referencing the entry corresponding to the key.
+    ///
+    /// The bucket of the entry is write-locked until the guard is dropped,
+    /// when the modifications get published.
+    pub fn get_mut<Q>(&self, key: &Q) -> Option<RefMut<K, V, S::Hash>>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let (cell, lock) = self.lock_bucket(hash);
+        let snapshot = cell.snapshot();
+        let idx = snapshot.position(hash, key, BucketPolicy::Unsorted)?;
+        let bucket = Bucket::clone(&snapshot);
+
+        Some(RefMut::new(cell, lock, bucket, idx))
+    }
+
+    /// Get the entry of `key` for in-place manipulation.
+    ///
+    /// The bucket of the entry is write-locked until the entry, or the guard
+    /// it gets converted to, is dropped.
+    pub fn entry(&self, key: K) -> SyncEntry<'_, K, V, S::Hash> {
+        let hash = self.hash_builder.hash_key(&key);
+        loop {
+            let (cell, lock) = self.lock_bucket(hash);
+            let snapshot = cell.snapshot();
+            if let Some(idx) =
+                snapshot.position(hash, &key, BucketPolicy::Unsorted)
+            {
+                let bucket = Bucket::clone(&snapshot);
+                return SyncEntry::Occupied(RefMut::new(
+                    cell, lock, bucket, idx,
+                ));
+            }
+
+            // Make sure a vacant entry has room, as it cannot split the
+            // bucket when being inserted.
+            if !snapshot.is_full(self.bucket_capacity)
+                || !snapshot.splittable(hash)
+            {
+                return SyncEntry::Vacant(SyncVacantEntry::new(
+                    cell, lock, &self.len, hash, key,
+                ));
+            }
+
+            drop(lock);
+            self.split(hash);
+        }
+    }
+
+    /// Remove `key` from the map, return the stored key and its value if it
+    /// was previously in the map.
+    pub fn remove_entry<Q>(&self, key: &Q) -> Option<(K, V)>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_builder.hash_key(key);
+        let (cell, _lock) = self.lock_bucket(hash);
+        let mut bucket = Bucket::clone(&cell.snapshot.load());
+        let idx = bucket.position(hash, key, BucketPolicy::Unsorted)?;
+        let (_, k, v) = bucket.remove(idx, BucketPolicy::Unsorted);
+        let underfull = bucket.len() <= self.bucket_capacity / 2
+            && bucket.local_depth() >= 2;
+        cell.publish(Arc::new(bucket));
+        self.len.fetch_sub(1, Ordering::Release);
+        if underfull && !cell.queued.swap(true, Ordering::Relaxed) {
+            self.underfull.lock().push(hash);
+        }
+
+        Some((k, v))
+    }
+
+    /// Remove `key` from the map, return its value if it was previously in the
+    /// map.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: Eq + Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+}
+
+impl<K, V, S> SyncHashMap<K, V, S>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+    S: KeyHasher + Sync,
+    S::Hash: Send + Sync,
+{
+    /// Insert all the key-value pairs of `iter` into the map.
+    ///
+    /// The pairs are sorted by their hash values, so that the ones going to
+    /// the same bucket are adjacent, and split into chunks filled by worker
+    /// threads concurrently. Each bucket is locked and published once for all
+    /// its pairs, pairs that don't fit into a full bucket fall back to
+    /// [`insert()`](Self::insert), which splits it.
+    ///
+    /// As with `insert()`, values of the existing keys are updated, if a key
+    /// appears more than once in `iter`, its last value wins.
+    pub fn insert_batch<I>(&self, iter: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut entries = iter
+            .into_iter()
+            .map(|(k, v)| (self.hash_builder.hash_key(&k), k, v))
+            .collect::<Vec<_>>();
+        // stable, so that the duplicate keys stay in order
+        entries.sort_by_key(|(hash, _, _)| *hash);
+
+        let workers = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(entries.len() / self.bucket_capacity.max(1))
+            .max(1);
+        let mut chunks = Vec::with_capacity(workers);
+        let mut rest = entries;
+        for remaining in (1..workers).rev() {
+            let mut at = rest.len() - rest.len() / (remaining + 1);
+            // equal hash values, which may be the same key, stay in a chunk
+            while at < rest.len() && at > 0 && rest[at].0 == rest[at - 1].0 {
+                at += 1;
+            }
+            let tail = rest.split_off(at);
+            chunks.push(rest);
+            rest = tail;
+        }
+        chunks.push(rest);
+
+        let mut chunks = chunks.into_iter();
+        let first = chunks.next().unwrap_or_default();
+        std::thread::scope(|s| {
+            for chunk in chunks {
+                s.spawn(move || self.fill(chunk));
+            }
+            self.fill(first);
+        });
+    }
+
+    /// Insert the `entries`, which are sorted by their hash values, bucket by
+    /// bucket.
+    fn fill(&self, entries: Vec<(S::Hash, K, V)>) {
+        let mut entries = entries.into_iter().peekable();
+        while let Some(&(hash, _, _)) = entries.peek() {
+            let (cell, lock) = self.lock_bucket(hash);
+            let mut bucket = Bucket::clone(&cell.snapshot.load());
+            let mut added = 0;
+            let mut overflow = None;
+            while let Some((hash, key, value)) =
+                entries.next_if(|(hash, _, _)| bucket.covers(*hash))
+            {
+                if let Some(idx) =
+                    bucket.position(hash, &key, BucketPolicy::Unsorted)
+                {
+                    bucket.values[idx] = value;
+                } else if !bucket.is_full(self.bucket_capacity)
+                    || !bucket.splittable(hash)
+                {
+                    bucket.push((hash, key, value), BucketPolicy::Unsorted);
+                    added += 1;
+                } else {
+                    overflow = Some((key, value));
+                    break;
+                }
+            }
+            cell.publish(Arc::new(bucket));
+            self.len.fetch_add(added, Ordering::Release);
+            drop(lock);
+
+            if let Some((key, value)) = overflow {
+                self.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn concurrent_types_are_send_sync() {
+        // the auto traits follow the keys and values, no unsafe impl needed
+        assert_send_sync::<SyncHashMap<String, Vec<u8>>>();
+        assert_send_sync::<Ref<String, Vec<u8>>>();
+        assert_send_sync::<crate::ShardedHashMap<String, Vec<u8>>>();
+        #[cfg(feature = "async")]
+        assert_send_sync::<crate::AsyncHashMap<String, Vec<u8>>>();
+    }
+
+    #[test]
+    fn sync_map_works() {
+        let map = SyncHashMap::new();
+        assert_eq!(map.insert(1, 1), None);
+        assert_eq!(map.insert(1, 2), Some(1));
+        assert_eq!(map.len(), 1);
+
+        let entry = map.get(&1).unwrap();
+        assert_eq!(entry.pair(), (&1, &2));
+        assert_eq!(format!("{:?}", entry), "Ref { key: 1, value: 2 }");
+        drop(entry);
+
+        *map.get_mut(&1).unwrap() += 1;
+        assert_eq!(*map.get(&1).unwrap(), 3);
+        assert!(map.get(&2).is_none());
+
+        assert_eq!(map.remove(&1), Some(3));
+        assert_eq!(map.remove(&1), None);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn sync_map_reads_never_block() {
+        let map = SyncHashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let old = map.get(&0).unwrap();
+
+        let mut entry = map.get_mut(&0).unwrap();
+        *entry += 1;
+        // readers see the old value until the guard gets dropped
+        assert_eq!(*map.get(&0).unwrap(), 0);
+        let new = entry.downgrade();
+        assert_eq!(*map.get(&0).unwrap(), 1);
+        assert_eq!((*old, *new), (0, 1));
+
+        // a snapshot survives the split of its bucket
+        let snapshot = map.get(&1).unwrap();
+        for i in 100..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(snapshot.pair(), (&1, &1));
+        assert_eq!(snapshot.clone().pair(), (&1, &1));
+        assert!((0..1000).all(|i| map.contains_key(&i)));
+    }
+
+    #[test]
+    fn sync_map_entry() {
+        let map = SyncHashMap::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..1000 {
+                        *map.entry(i % 100).or_insert(0) += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.len(), 100);
+        assert!((0..100).all(|i| *map.get(&i).unwrap() == 40));
+
+        let entry = map.entry(0).and_modify(|v| *v = 0);
+        assert_eq!(entry.key(), &0);
+        assert_eq!(*entry.or_default(), 0);
+        assert!(matches!(map.entry(100), SyncEntry::Vacant(_)));
+        assert!(map.get(&100).is_none());
+    }
+
+    #[test]
+    fn sync_map_lock_striping() {
+        for stripes in [1, 4] {
+            let map =
+                SyncHashMap::with_lock_striping(LockStriping::Stripes(stripes));
+            assert_eq!(map.lock_striping(), LockStriping::Stripes(stripes));
+            thread::scope(|s| {
+                for t in 0..4 {
+                    let map = &map;
+                    s.spawn(move || {
+                        for i in (t * 500)..((t + 1) * 500) {
+                            assert_eq!(map.insert(i, i), None);
+                        }
+                    });
+                }
+            });
+
+            assert_eq!(map.len(), 2000);
+            for i in 0..2000 {
+                assert_eq!(*map.get(&i).unwrap(), i);
+            }
+        }
+    }
+
+    #[test]
+    fn sync_map_insert_batch() {
+        let map = SyncHashMap::new();
+        map.insert(0, 1);
+        map.insert_batch((0..4000).map(|i| (i, i)));
+        // duplicate keys, the last value wins
+        map.insert_batch((0..100).map(|i| (i % 10, i)));
+
+        assert_eq!(map.len(), 4000);
+        for i in 0..4000 {
+            let expected = if i < 10 { 90 + i } else { i };
+            assert_eq!(*map.get(&i).unwrap(), expected);
+        }
+    }
+
+    /// Return the number of buckets in the latest directory.
+    fn bucket_count<K, V, S: KeyHasher>(map: &SyncHashMap<K, V, S>) -> usize {
+        map.directory.with(|directory| {
+            let mut cells = directory
+                .entries
+                .iter()
+                .map(|entry| Arc::as_ptr(&entry.load()))
+                .collect::<Vec<_>>();
+            cells.dedup();
+            cells.len()
+        })
+    }
+
+    #[test]
+    fn sync_map_compact() {
+        let map = SyncHashMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        let buckets = bucket_count(&map);
+        for i in 10..1000 {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+
+        // removals never merge buckets
+        assert_eq!(bucket_count(&map), buckets);
+        let merges = map.compact();
+        assert!(merges > 0);
+        assert_eq!(bucket_count(&map), buckets - merges);
+        // the queue is drained
+        assert_eq!(map.compact(), 0);
+
+        assert_eq!(map.len(), 10);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i).map(|v| *v), (i < 10).then_some(i));
+        }
+        for i in 10..1000 {
+            assert_eq!(map.insert(i, i), None);
+        }
+        assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn sync_map_concurrent_insert() {
+        let map = SyncHashMap::new();
+        thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                s.spawn(move || {
+                    for i in (t * 1000)..((t + 1) * 1000) {
+                        assert_eq!(map.insert(i, i), None);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.len(), 4000);
+        assert_eq!(map.approx_len(), 4000);
+        for i in 0..4000 {
+            assert_eq!(*map.get(&i).unwrap(), i);
+        }
+
+        thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                s.spawn(move || {
+                    for i in (t * 1000)..((t + 1) * 1000) {
+                        if i % 2 == 0 {
+                            assert_eq!(map.remove(&i), Some(i));
+                        } else {
+                            *map.get_mut(&i).unwrap() += 1;
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.len(), 2000);
+        for i in 0..4000 {
+            assert_eq!(map.get(&i).map(|v| *v), (i % 2 == 1).then_some(i + 1));
+        }
+    }
+}
+
+/// The interleavings of the insertions, lookups and splits of
+/// [`SyncHashMap`], checked by loom, run them with
+/// `RUSTFLAGS="--cfg loom" cargo test --release loom`.
+///
+/// Under `cfg(loom)`, the atomics and locks of the map are replaced by models
+/// built on loom's primitives, see the `primitives` module, so these tests
+/// run the real code.
+#[cfg(all(test, loom))]
+mod loom_test {
+    use super::*;
+    use crate::FixedState;
+    use loom::thread;
+
+    /// Buckets hold a single entry, so that the second key of a bucket
+    /// splits it.
+    type Map = SyncHashMap<u32, u32, FixedState>;
+
+    fn new_map() -> Arc<Map> {
+        Arc::new(SyncHashMap::with_bucket_capacity_and_hasher(
+            1,
+            FixedState::with_seed(0),
+        ))
+    }
+
+    /// Return `n` keys whose hash values start with the same bit, i.e.,
+    /// which go to the same bucket of the initial 2.
+    fn colliding_keys(n: usize) -> Vec<u32> {
+        let hash_builder = FixedState::with_seed(0);
+        let bit = |key: &u32| hash_builder.hash_key(key).bit(0);
+        (0..).filter(|key| bit(key) == bit(&0)).take(n).collect()
+    }
+
+    /// Check `f` with the interleavings of at most 2 preemptions, which
+    /// keeps the number of them tractable.
+    fn model<F: Fn() + Sync + Send + 'static>(f: F) {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(f);
+    }
+
+    #[test]
+    fn loom_insert_during_split_is_not_lost() {
+        model(|| {
+            let keys = colliding_keys(3);
+            let map = new_map();
+            map.insert(keys[0], 0);
+
+            // both insertions split the bucket of `keys[0]`
+            let inserter = {
+                let map = Arc::clone(&map);
+                let key = keys[1];
+                thread::spawn(move || map.insert(key, 1))
+            };
+            assert_eq!(map.insert(keys[2], 2), None);
+            assert_eq!(inserter.join().unwrap(), None);
+
+            assert_eq!(map.len(), 3);
+            for (value, key) in keys.iter().enumerate() {
+                assert_eq!(map.get(key).map(|v| *v), Some(value as u32));
+            }
+        });
+    }
+
+    #[test]
+    fn loom_get_during_split_finds_entry() {
+        model(|| {
+            let keys = colliding_keys(2);
+            let map = new_map();
+            map.insert(keys[0], 0);
+
+            let inserter = {
+                let map = Arc::clone(&map);
+                let key = keys[1];
+                thread::spawn(move || map.insert(key, 1))
+            };
+            // the entry is either in the split bucket or in one of its halves
+            assert_eq!(map.get(&keys[0]).map(|v| *v), Some(0));
+            inserter.join().unwrap();
+            assert_eq!(map.get(&keys[1]).map(|v| *v), Some(1));
+        });
+    }
+}
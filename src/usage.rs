@@ -0,0 +1,158 @@
+//! Estimation of the memory used by a [`HashMap`].
+
+use crate::{
+    arena::Arena,
+    bucket::{Bucket, BucketIdx},
+    hasher::{DirectoryMapper, KeyHasher},
+    map::HashMap,
+};
+use allocator_api2::alloc::Allocator;
+use std::mem::size_of;
+
+/// Estimated memory usage of a [`HashMap`] in bytes, returned by
+/// [`HashMap::memory_usage()`].
+///
+/// The estimation counts the allocated capacities, which may be larger than
+/// the used ones, and ignores the overhead of the allocator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    directory: usize,
+    buckets: usize,
+    entries: usize,
+    deep: usize,
+}
+
+impl MemoryUsage {
+    /// Return the bytes used by the directory.
+    #[inline]
+    pub fn directory(&self) -> usize {
+        self.directory
+    }
+
+    /// Return the bytes used by the buckets, including their metadata and the
+    /// entries stored inline, as well as the slots of the removed buckets.
+    #[inline]
+    pub fn buckets(&self) -> usize {
+        self.buckets
+    }
+
+    /// Return the bytes used by the entries spilled to the heap, i.e., the
+    /// chunks of the arena the buckets that can hold more than `B` entries
+    /// allocate from, including the blocks freed by coalescences, which are
+    /// kept for the next splits.
+    #[inline]
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    /// Return the bytes reported by the callback of
+    /// [`HashMap::memory_usage_with()`], 0 for [`HashMap::memory_usage()`].
+    #[inline]
+    pub fn deep(&self) -> usize {
+        self.deep
+    }
+
+    /// Return the total bytes.
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.directory + self.buckets + self.entries + self.deep
+    }
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> HashMap<K, V, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Estimate the memory used by the map, see [`MemoryUsage`].
+    ///
+    /// Only the memory of the keys and values themselves is counted, the
+    /// memory they own, e.g., the bytes of a `String`, is not, use
+    /// [`memory_usage_with()`](Self::memory_usage_with) to count it.
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::HashMap;
+    ///
+    /// let mut map = HashMap::new();
+    /// for i in 0..1000_u64 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let usage = map.memory_usage();
+    /// assert!(usage.total() >= 1000 * 2 * std::mem::size_of::<u64>());
+    /// ```
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory_usage_with(|_, _| 0)
+    }
+
+    /// Estimate the memory used by the map like
+    /// [`memory_usage()`](Self::memory_usage) does, `deep_size` is called
+    /// with every entry to return the bytes owned by its key and value,
+    /// which are reported by [`MemoryUsage::deep()`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use extendable_hashmap::HashMap;
+    ///
+    /// let mut map = HashMap::new();
+    /// map.insert(1, "a".repeat(100));
+    ///
+    /// let usage = map.memory_usage_with(|_, v: &String| v.capacity());
+    /// assert_eq!(usage.deep(), 100);
+    /// ```
+    pub fn memory_usage_with<F>(&self, mut deep_size: F) -> MemoryUsage
+    where
+        F: FnMut(&K, &V) -> usize,
+    {
+        let mut usage = MemoryUsage {
+            directory: self.directories.capacity() * size_of::<BucketIdx>(),
+            buckets: self.buckets.capacity()
+                * size_of::<Bucket<K, V, S::Hash, B, Arena<A>>>(),
+            entries: self.arena.allocated(),
+            ..Default::default()
+        };
+        for (_, bucket) in self.buckets.iter() {
+            usage.deep += bucket
+                .iter()
+                .map(|(_, k, v)| deep_size(k, v))
+                .sum::<usize>();
+        }
+
+        usage
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_usage_works() {
+        let empty = HashMap::<u64, String>::new().memory_usage();
+        assert_eq!(empty.entries(), 0);
+        assert_eq!(empty.deep(), 0);
+        // the single directory entry is stored inline
+        assert_eq!(empty.directory(), 0);
+        assert!(empty.buckets() > 0);
+
+        let mut map = HashMap::<u64, String>::with_bucket_capacity(64);
+        for i in 0..1000 {
+            map.insert(i, i.to_string());
+        }
+        let usage = map.memory_usage();
+        // buckets of 64 entries spill to the heap
+        let entry_size =
+            size_of::<u64>() * 2 + size_of::<String>() + size_of::<u8>();
+        assert!(usage.entries() >= 1000 * entry_size);
+        assert_eq!(usage.deep(), 0);
+        assert_eq!(
+            usage.total(),
+            usage.directory() + usage.buckets() + usage.entries()
+        );
+
+        let usage = map.memory_usage_with(|_, v| v.len());
+        let len = (0..1000).map(|i| i.to_string().len()).sum::<usize>();
+        assert_eq!(usage.deep(), len);
+    }
+}
@@ -1,21 +1,45 @@
-/// Get the first `n` bits of `num`
-pub(crate) fn get_first_n_bits(n: usize, mut num: u64) -> Vec<usize> {
-    let mut ret = Vec::with_capacity(n);
-    let val_msb = 2_u64.pow(u64::BITS - 1);
+#[cfg(test)]
+use crate::hasher::HashValue;
 
-    for _ in 0..n {
-        ret.push(if num >= val_msb { 1 } else { 0 });
-        num <<= 1;
-    }
+/// Get the first `n` bits of `num`, the directory is indexed by
+/// [`HashValue::prefix()`] instead.
+///
+/// # Panic
+/// `n` should not be greater than the width of `num`.
+#[cfg(test)]
+pub(crate) fn get_first_n_bits<H: HashValue>(n: usize, num: H) -> Vec<usize> {
+    assert!(n <= H::BITS as usize);
 
-    ret
+    (0..n as u32).map(|idx| num.bit(idx)).collect()
 }
 
-/// Convert `bits` to its value
+/// Number of tags probed at once by [`match_tag()`].
+pub(crate) const GROUP_WIDTH: usize = 8;
+
+/// A tag that never matches, used to pad a group.
+pub(crate) const EMPTY_TAG: u8 = 0x80;
+
+/// Return a mask with the highest bit set for every byte of `group` that may
+/// equal to `tag`, `tag` should be smaller than 0x80.
+///
+/// This is a SWAR (SIMD within a register) comparison of 8 bytes at once, it
+/// may report false positives for the bytes following a true match, which are
+/// ruled out by the callers by comparing the hash values.
+#[inline]
+pub(crate) fn match_tag(group: [u8; GROUP_WIDTH], tag: u8) -> u64 {
+    const LSBS: u64 = 0x0101_0101_0101_0101;
+    const MSBS: u64 = 0x8080_8080_8080_8080;
+
+    let cmp = u64::from_le_bytes(group) ^ (LSBS * tag as u64);
+    cmp.wrapping_sub(LSBS) & !cmp & MSBS
+}
+
+/// Convert `bits` to its value.
 ///
 /// # Example
 ///
 /// [0, 0] => (0 * 2^0) + (0*2^1) = 0
+#[cfg(test)]
 pub(crate) fn bits_to_value(bits: &[usize]) -> usize {
     bits.iter()
         .rev()
@@ -23,6 +47,50 @@ pub(crate) fn bits_to_value(bits: &[usize]) -> usize {
         .fold(0, |acc, (idx, bit)| acc + (bit * 2_usize.pow(idx as _)))
 }
 
+/// Format the first `depth` bits of `prefix`, the most significant one
+/// first.
+pub(crate) fn format_bits(prefix: u64, depth: usize) -> String {
+    if depth == 0 {
+        String::new()
+    } else {
+        format!("{prefix:0depth$b}")
+    }
+}
+
+/// Lookup table of CRC-32C, entry `i` is the CRC of byte `i`.
+#[cfg(feature = "disk")]
+const CRC32C_TABLE: [u32; 256] = {
+    // bit-reversed Castagnoli polynomial
+    const POLY: u32 = 0x82F6_3B78;
+
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+/// Return the CRC-32C (Castagnoli) checksum of `bytes`.
+#[cfg(feature = "disk")]
+pub(crate) fn crc32c(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, byte| {
+        CRC32C_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -36,6 +104,26 @@ mod test {
         let bits = get_first_n_bits(u64::BITS as _, u64::MIN);
 
         bits.iter().for_each(|bit| assert_eq!(*bit, 0));
+
+        let bits = get_first_n_bits(3, 0b101_u128 << 125);
+        assert_eq!(bits, [1, 0, 1]);
+    }
+
+    #[test]
+    fn match_tag_works() {
+        let group = [1, 2, 5, 2, EMPTY_TAG, EMPTY_TAG, EMPTY_TAG, EMPTY_TAG];
+        let mut mask = match_tag(group, 2);
+        let mut matches = Vec::new();
+        while mask != 0 {
+            matches.push(mask.trailing_zeros() as usize / 8);
+            mask &= mask - 1;
+        }
+        assert_eq!(matches, [1, 3]);
+
+        assert_eq!(match_tag(group, 4), 0);
+        // a false positive: byte 2 follows a true match
+        assert_eq!(match_tag([2, 3, 0, 0, 0, 0, 0, 0], 2), 0x8080);
+        assert_eq!(match_tag([EMPTY_TAG; GROUP_WIDTH], 0), 0);
     }
 
     #[test]
@@ -43,4 +131,30 @@ mod test {
         let bits = [1, 1, 0];
         assert_eq!(bits_to_value(&bits), 6);
     }
+
+    #[test]
+    fn prefix_matches_bits() {
+        for hash in [0, 1, u64::MAX, 1 << 63, 0x1234_5678_9ABC_DEF0] {
+            for n in [0, 1, 3, 17, 63] {
+                let bits = get_first_n_bits(n, hash);
+                assert_eq!(hash.prefix(n as u32), bits_to_value(&bits));
+                let wide = (hash as u128) << 64;
+                assert_eq!(wide.prefix(n as u32), hash.prefix(n as u32));
+            }
+        }
+    }
+
+    #[test]
+    fn format_bits_works() {
+        assert_eq!(format_bits(0b101, 5), "00101");
+        assert_eq!(format_bits(0b101, 3), "101");
+        assert_eq!(format_bits(0, 0), "");
+    }
+
+    #[cfg(feature = "disk")]
+    #[test]
+    fn crc32c_works() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    }
 }
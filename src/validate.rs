@@ -0,0 +1,195 @@
+//! Checking the structural invariants of a [`HashMap`].
+
+use crate::{
+    bucket::BucketPolicy,
+    hasher::{DirectoryMapper, KeyHasher},
+    map::{count_depths, HashMap},
+};
+use allocator_api2::alloc::Allocator;
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+};
+
+/// The error returned by [`HashMap::validate()`], describing the first
+/// violated invariant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantViolation(String);
+
+impl InvariantViolation {
+    /// Return the description of the violated invariant.
+    #[inline]
+    pub fn invariant(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invariant violated: {}", self.0)
+    }
+}
+
+impl Error for InvariantViolation {}
+
+/// Return an error saying `invariant` is violated.
+fn violated(invariant: String) -> InvariantViolation {
+    InvariantViolation(invariant)
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> HashMap<K, V, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Check the structural invariants of the map:
+    ///
+    /// 1. The directory has `2^global_depth` slots, every slot points to a
+    ///    bucket whose local depth is not greater than the global depth, and
+    ///    whose prefix is the first bits of the slot index.
+    /// 2. A bucket of local depth `l` is pointed to by
+    ///    `2^(global_depth - l)` slots.
+    /// 3. Every entry is in the bucket whose prefix is the first bits of its
+    ///    mapped hash value.
+    /// 4. A bucket holds no more entries than the bucket capacity, unless all
+    ///    its entries have the same hash value, or its local depth reaches
+    ///    the maximum global depth.
+    /// 5. The entries of a bucket are sorted by their hash values under
+    ///    [`BucketPolicy::Sorted`].
+    /// 6. [`len()`](Self::len) is the number of the entries of the buckets.
+    ///
+    /// This walks the whole map, it is meant for tests and debugging, e.g.,
+    /// `debug_assert!(map.validate().is_ok())`.
+    ///
+    /// # Error
+    /// An [`InvariantViolation`] describing the first violated invariant is
+    /// returned.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        if self.directories.len() != 1 << self.global_depth {
+            return Err(violated(format!(
+                "directory has {} slots at global depth {}",
+                self.directories.len(),
+                self.global_depth
+            )));
+        }
+
+        let mut references = vec![0_usize; self.buckets.capacity()];
+        for (idx, bucket_idx) in self.directories.iter().enumerate() {
+            let Some(bucket) = self.buckets.get(bucket_idx.get()) else {
+                return Err(violated(format!(
+                    "slot {idx} points to removed bucket {bucket_idx:?}"
+                )));
+            };
+            let local_depth = bucket.local_depth();
+            if local_depth > self.global_depth {
+                return Err(violated(format!(
+                    "bucket {bucket_idx:?} has local depth {local_depth} \
+                     beyond global depth {}",
+                    self.global_depth
+                )));
+            }
+            if (idx >> (self.global_depth - local_depth)) as u64
+                != bucket.prefix
+            {
+                return Err(violated(format!(
+                    "slot {idx} points to bucket {bucket_idx:?} of prefix \
+                     {:#b}",
+                    bucket.prefix
+                )));
+            }
+            references[bucket_idx.get()] += 1;
+        }
+
+        let mut len = 0;
+        for (bucket_idx, bucket) in self.buckets.iter() {
+            let local_depth = bucket.local_depth();
+            if references[bucket_idx] != 1 << (self.global_depth - local_depth)
+            {
+                return Err(violated(format!(
+                    "bucket {bucket_idx} of local depth {local_depth} is \
+                     pointed to by {} slots",
+                    references[bucket_idx]
+                )));
+            }
+            if bucket
+                .hashes
+                .iter()
+                .any(|hash| !bucket.covers(self.mapper.map(*hash)))
+            {
+                return Err(violated(format!(
+                    "bucket {bucket_idx} holds an entry of another prefix"
+                )));
+            }
+            if bucket.len() > self.bucket_capacity
+                && local_depth < self.max_global_depth
+                && bucket.hashes.windows(2).any(|w| w[0] != w[1])
+            {
+                return Err(violated(format!(
+                    "bucket {bucket_idx} holds {} entries beyond capacity {}",
+                    bucket.len(),
+                    self.bucket_capacity
+                )));
+            }
+            if self.bucket_policy == BucketPolicy::Sorted
+                && bucket.hashes.windows(2).any(|w| w[0] > w[1])
+            {
+                return Err(violated(format!(
+                    "entries of bucket {bucket_idx} are not sorted"
+                )));
+            }
+            len += bucket.len();
+        }
+        if len != self.len {
+            return Err(violated(format!(
+                "len is {} while the buckets hold {len} entries",
+                self.len
+            )));
+        }
+
+        let depth_counts = count_depths(&self.buckets, self.global_depth);
+        if self.depth_counts.get(..=self.global_depth)
+            != Some(depth_counts.as_slice())
+            || self.depth_counts[self.global_depth + 1..]
+                .iter()
+                .any(|count| *count != 0)
+        {
+            return Err(violated(format!(
+                "depth counts are {:?} while the buckets have {depth_counts:?}",
+                self.depth_counts
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bucket::BucketIdx;
+
+    #[test]
+    fn validate_detects_violations() {
+        let mut map = HashMap::with_bucket_capacity(4);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        map.validate().unwrap();
+
+        let mut broken = map.clone();
+        broken.len += 1;
+        let err = broken.validate().unwrap_err();
+        assert!(err.invariant().starts_with("len is 1001"));
+        assert!(err.to_string().starts_with("invariant violated: len is"));
+
+        // a slot pointing to the bucket of another slot
+        let mut broken = map.clone();
+        let idx = broken.directories.len() - 1;
+        broken.directories[idx] = BucketIdx::new(broken.directories[0].get());
+        assert!(broken.validate().is_err());
+
+        let mut broken = map.clone();
+        broken.global_depth += 1;
+        assert!(broken.validate().is_err());
+    }
+}
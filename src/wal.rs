@@ -0,0 +1,262 @@
+//! Write-ahead log of a [`DiskHashMap`](crate::DiskHashMap).
+//!
+//! Before a modification writes the pages of the map, the new images of the
+//! pages are appended to the log as an entry:
+//!
+//! | field    | size          |
+//! |----------|---------------|
+//! | length   | 4             |
+//! | checksum | 4             |
+//! | kind     | 1             |
+//! | pages    | 4             |
+//! | page     | 8 + page size |
+//! | ...      |               |
+//!
+//! where the length and the CRC-32C checksum cover the rest of the entry,
+//! and every page is its page number followed by its image. All the integers
+//! are in little endian.
+//!
+//! Replaying an entry writes its page images again, which is idempotent, so
+//! recovery replays every complete entry, and stops at the first torn one.
+
+use crate::util::crc32c;
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Size of an entry header, i.e., the length and the checksum, in bytes.
+const ENTRY_HEADER_LEN: usize = 8;
+
+/// How often a [`DiskHashMap`](crate::DiskHashMap) syncs its write-ahead
+/// log, which bounds the modifications lost on a crash.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Sync the log after every modification, so a modification survives a
+    /// crash once it returns, this is the default.
+    #[default]
+    Always,
+    /// Sync the log after every `n` modifications, up to the last `n - 1`
+    /// modifications are lost on a crash.
+    Batched(u32),
+    /// Sync the log only in
+    /// [`DiskHashMap::sync()`](crate::DiskHashMap::sync), or when it grows
+    /// too large.
+    Manual,
+}
+
+/// The modification an entry is appended for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum EntryKind {
+    Insert = 1,
+    Remove = 2,
+    Split = 3,
+}
+
+impl EntryKind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            1 => Some(Self::Insert),
+            2 => Some(Self::Remove),
+            3 => Some(Self::Split),
+            _ => None,
+        }
+    }
+}
+
+/// Return the path of the log of the map stored at `path`, which is `path`
+/// with `.wal` appended.
+pub(crate) fn wal_path(path: &Path) -> PathBuf {
+    let mut wal_path = OsString::from(path.as_os_str());
+    wal_path.push(".wal");
+
+    wal_path.into()
+}
+
+/// A write-ahead log.
+pub(crate) struct Wal {
+    /// The log file, opened for appending
+    file: File,
+    /// Length of the log in bytes
+    len: u64,
+    /// Number of entries appended since the last sync
+    unsynced: u32,
+}
+
+impl Wal {
+    /// Open the log at `path`, which is created if it doesn't exist.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let len = file.metadata()?.len();
+
+        Ok(Self {
+            file,
+            len,
+            unsynced: 0,
+        })
+    }
+
+    /// Return the length of the log in bytes.
+    #[inline]
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Append an entry with the page images `pages`, and sync it following
+    /// `durability`, return true if the log is synced.
+    pub(crate) fn append(
+        &mut self,
+        kind: EntryKind,
+        pages: &[(u64, &[u8])],
+        durability: Durability,
+    ) -> io::Result<bool> {
+        let mut entry = vec![0; ENTRY_HEADER_LEN];
+        entry.push(kind as u8);
+        entry.extend_from_slice(&(pages.len() as u32).to_le_bytes());
+        for (page_no, image) in pages {
+            entry.extend_from_slice(&page_no.to_le_bytes());
+            entry.extend_from_slice(image);
+        }
+        let body_len = (entry.len() - ENTRY_HEADER_LEN) as u32;
+        let checksum = crc32c(&entry[ENTRY_HEADER_LEN..]);
+        entry[0..4].copy_from_slice(&body_len.to_le_bytes());
+        entry[4..8].copy_from_slice(&checksum.to_le_bytes());
+
+        self.file.write_all(&entry)?;
+        self.len += entry.len() as u64;
+        self.unsynced += 1;
+        match durability {
+            Durability::Always => self.sync().map(|_| true),
+            Durability::Batched(n) if self.unsynced >= n => {
+                self.sync().map(|_| true)
+            }
+            Durability::Batched(_) | Durability::Manual => Ok(false),
+        }
+    }
+
+    /// Sync the appended entries to the disk.
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+
+        Ok(())
+    }
+
+    /// Empty the log, once the pages it logs are synced.
+    pub(crate) fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.len = 0;
+        self.unsynced = 0;
+
+        Ok(())
+    }
+
+    /// Call `f` with the page number and image of every page logged by the
+    /// complete entries, in the order they are appended, return the number
+    /// of the complete entries, the pages are `page_size` bytes long.
+    ///
+    /// Reading stops at the first entry which is torn or corrupted, as it
+    /// was being appended when the map crashed, and the entries after it,
+    /// if any, were never synced.
+    pub(crate) fn replay<F>(
+        &self,
+        page_size: usize,
+        mut f: F,
+    ) -> io::Result<usize>
+    where
+        F: FnMut(u64, &[u8]) -> io::Result<()>,
+    {
+        let mut bytes = Vec::new();
+        (&self.file).seek(SeekFrom::Start(0))?;
+        (&self.file).read_to_end(&mut bytes)?;
+
+        let mut entries = 0;
+        let mut rest = bytes.as_slice();
+        while rest.len() >= ENTRY_HEADER_LEN {
+            let body_len =
+                u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+            let checksum = u32::from_le_bytes(rest[4..8].try_into().unwrap());
+            let Some(body) =
+                rest.get(ENTRY_HEADER_LEN..ENTRY_HEADER_LEN + body_len)
+            else {
+                break;
+            };
+            if body.len() < 5
+                || crc32c(body) != checksum
+                || EntryKind::from_u8(body[0]).is_none()
+            {
+                break;
+            }
+            let n_pages =
+                u32::from_le_bytes(body[1..5].try_into().unwrap()) as usize;
+            let pages = &body[5..];
+            if pages.len() != n_pages * (8 + page_size) {
+                break;
+            }
+            for page in pages.chunks_exact(8 + page_size) {
+                let page_no = u64::from_le_bytes(page[..8].try_into().unwrap());
+                f(page_no, &page[8..])?;
+            }
+
+            entries += 1;
+            rest = &rest[ENTRY_HEADER_LEN + body_len..];
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wal_replay_stops_at_torn_entry() {
+        let path = std::env::temp_dir().join(format!(
+            "extendable_hashmap-{}-wal_replay_stops_at_torn_entry",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut wal = Wal::open(&path).unwrap();
+        let image = vec![7; 4096];
+        assert!(wal
+            .append(EntryKind::Insert, &[(1, &image)], Durability::Always)
+            .unwrap());
+        assert!(!wal
+            .append(
+                EntryKind::Split,
+                &[(2, &image), (1, &image)],
+                Durability::Manual,
+            )
+            .unwrap());
+        let len = wal.len();
+        wal.append(EntryKind::Remove, &[(2, &image)], Durability::Always)
+            .unwrap();
+        // tear the last entry
+        wal.file.set_len(len + 100).unwrap();
+
+        let mut pages = Vec::new();
+        let entries = wal
+            .replay(4096, |page_no, image| {
+                assert!(image.iter().all(|byte| *byte == 7));
+                pages.push(page_no);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(entries, 2);
+        assert_eq!(pages, [1, 2, 1]);
+
+        wal.truncate().unwrap();
+        assert_eq!(wal.replay(4096, |_, _| Ok(())).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
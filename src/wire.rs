@@ -0,0 +1,170 @@
+//! Byte round trips of [`HashMap`] with [`bincode`] or [`postcard`], for
+//! shipping a map across a network boundary, or embedding it in another
+//! binary blob.
+//!
+//! The bytes are an envelope:
+//!
+//! | field   | size |
+//! |---------|------|
+//! | magic   | 8    |
+//! | version | 4    |
+//! | format  | 1    |
+//! | payload |      |
+//!
+//! where the format is 1 for bincode and 2 for postcard, and the payload is
+//! the map serialized by the format with its [`Serialize`] implementation,
+//! i.e., as a map of its entries. The version is in little endian.
+
+use crate::{
+    hasher::{DirectoryMapper, KeyHasher},
+    map::HashMap,
+};
+use allocator_api2::alloc::Allocator;
+use serde::{Deserialize, Serialize};
+use std::{hash::Hash, io};
+
+/// Magic bytes at the start of an envelope.
+const MAGIC: &[u8; 8] = b"EXTHWIRE";
+/// Version of the envelope.
+const VERSION: u32 = 1;
+/// Length of the envelope header in bytes.
+const HEADER_LEN: usize = 13;
+/// Format of a payload serialized by bincode.
+#[cfg(feature = "bincode")]
+const BINCODE: u8 = 1;
+/// Format of a payload serialized by postcard.
+#[cfg(feature = "postcard")]
+const POSTCARD: u8 = 2;
+
+/// Return the error of malformed bytes.
+fn malformed<E: std::fmt::Display>(reason: E) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed envelope: {}", reason),
+    )
+}
+
+/// Return the bincode options of the payloads.
+#[cfg(feature = "bincode")]
+fn bincode_options() -> impl bincode::Options {
+    // the default options of `bincode::options()`, which reject trailing
+    // bytes
+    bincode::options()
+}
+
+impl<K, V, S, M, const B: usize, A: Allocator + Clone> HashMap<K, V, S, M, B, A>
+where
+    S: KeyHasher,
+    M: DirectoryMapper<S::Hash>,
+{
+    /// Serialize the map into bytes, wrapped in a versioned envelope, which
+    /// can be restored by [`from_bytes()`](Self::from_bytes).
+    ///
+    /// The map is serialized by postcard if the `postcard` feature is
+    /// enabled, and by bincode otherwise. Only the entries are serialized,
+    /// not the layout of the map.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        #[cfg(feature = "postcard")]
+        {
+            bytes.push(POSTCARD);
+            postcard::to_extend(self, bytes).map_err(io::Error::other)
+        }
+        #[cfg(not(feature = "postcard"))]
+        {
+            use bincode::Options;
+
+            bytes.push(BINCODE);
+            bincode_options()
+                .serialize_into(&mut bytes, self)
+                .map_err(io::Error::other)?;
+            Ok(bytes)
+        }
+    }
+
+    /// Restore a map from the bytes written by [`to_bytes()`](Self::to_bytes).
+    ///
+    /// # Error
+    /// An [`InvalidData`](io::ErrorKind::InvalidData) error is returned if
+    /// the bytes are malformed, or serialized by a format whose feature is
+    /// not enabled.
+    pub fn from_bytes<'de>(bytes: &'de [u8]) -> io::Result<Self>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        S: Default,
+        M: Default,
+        A: Default,
+    {
+        if bytes.len() < HEADER_LEN || &bytes[0..8] != MAGIC {
+            return Err(malformed("bad magic bytes"));
+        }
+        if bytes[8..12] != VERSION.to_le_bytes() {
+            return Err(malformed("unsupported version"));
+        }
+
+        let payload = &bytes[HEADER_LEN..];
+        match bytes[12] {
+            #[cfg(feature = "bincode")]
+            BINCODE => {
+                use bincode::Options;
+
+                bincode_options().deserialize(payload).map_err(malformed)
+            }
+            #[cfg(feature = "postcard")]
+            POSTCARD => match postcard::take_from_bytes(payload) {
+                Ok((map, [])) => Ok(map),
+                Ok(_) => Err(malformed("trailing bytes")),
+                Err(err) => Err(malformed(err)),
+            },
+            _ => Err(malformed("unsupported format")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut map = HashMap::new();
+        for i in 0..1000_u64 {
+            map.insert(i.to_string(), i);
+        }
+
+        let bytes = map.to_bytes().unwrap();
+        assert_eq!(&bytes[0..8], MAGIC);
+        let de = HashMap::<String, u64>::from_bytes(&bytes).unwrap();
+        assert_eq!(de, map);
+
+        // every enabled format can be read
+        #[cfg(feature = "bincode")]
+        {
+            use bincode::Options;
+
+            let mut bytes = bytes[..HEADER_LEN - 1].to_vec();
+            bytes.push(BINCODE);
+            bincode_options().serialize_into(&mut bytes, &map).unwrap();
+            assert_eq!(
+                HashMap::<String, u64>::from_bytes(&bytes).unwrap(),
+                map
+            );
+        }
+
+        for bad in [&bytes[..bytes.len() - 1], &bytes[1..], &[]] {
+            let err = HashMap::<String, u64>::from_bytes(bad).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        let mut unknown = bytes.clone();
+        unknown[12] = 0;
+        assert!(HashMap::<String, u64>::from_bytes(&unknown).is_err());
+    }
+}